@@ -0,0 +1,17 @@
+//! Builds the pure math modules of rusty-flame as `no_std` + `alloc`,
+//! ensuring they stay reusable in constrained environments.
+//! Tests use std (for the test harness), and run the modules' own tests.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+extern crate nalgebra as na;
+
+#[path = "../../src/fixed_point.rs"]
+pub mod fixed_point;
+#[path = "../../src/flame.rs"]
+pub mod flame;
+#[path = "../../src/geometry.rs"]
+pub mod geometry;
+#[path = "../../src/mobius.rs"]
+pub mod mobius;
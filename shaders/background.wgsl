@@ -0,0 +1,11 @@
+//!include quad.wgsl
+
+@group(0) @binding(0)
+var r_image: texture_2d<f32>;
+@group(0) @binding(1)
+var r_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(r_image, r_sampler, in.tex_coord);
+}
@@ -0,0 +1,66 @@
+//!include quad.wgsl
+
+struct Params {
+    // Luminance above which pixels bloom.
+    threshold: f32,
+    // Brightness of the blurred bright areas added back.
+    intensity: f32,
+    // Distance between blur taps, in texels of the bloom textures.
+    spacing: f32,
+}
+
+@group(0) @binding(0)
+var r_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var r_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> r_params: Params;
+// Only used by fs_composite.
+@group(1) @binding(0)
+var r_bloom: texture_2d<f32>;
+@group(1) @binding(1)
+var r_bloom_sampler: sampler;
+
+fn luminance(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Keeps the part of each pixel brighter than the threshold, with its color.
+// Drawn at half resolution, so the linear filtering averages 2x2 pixels.
+@fragment
+fn fs_threshold(in: VertexOutput) -> @location(0) vec4<f32> {
+    let c = textureSample(r_texture, r_sampler, in.tex_coord).rgb;
+    let l = luminance(c);
+    return vec4<f32>(c * (max(l - r_params.threshold, 0.0) / max(l, 0.0001)), 1.0);
+}
+
+// One direction of a separable Gaussian blur (sigma of 2 taps).
+fn blur(coord: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    var weights = array<f32, 5>(0.2042, 0.1802, 0.1238, 0.0663, 0.0276);
+    let step = direction * r_params.spacing / vec2<f32>(textureDimensions(r_texture));
+    var sum = textureSample(r_texture, r_sampler, coord).rgb * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = step * f32(i);
+        let pair = textureSample(r_texture, r_sampler, coord + offset).rgb
+            + textureSample(r_texture, r_sampler, coord - offset).rgb;
+        sum += pair * weights[i];
+    }
+    return vec4<f32>(sum, 1.0);
+}
+
+@fragment
+fn fs_blur_x(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.tex_coord, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_y(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.tex_coord, vec2<f32>(0.0, 1.0));
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let scene = textureSample(r_texture, r_sampler, in.tex_coord);
+    let bloom = textureSample(r_bloom, r_bloom_sampler, in.tex_coord).rgb;
+    return vec4<f32>(scene.rgb + bloom * r_params.intensity, scene.a);
+}
@@ -0,0 +1,29 @@
+//!include quad.wgsl
+
+struct Params {
+    // 0 shows the flame, 1 shows the difference.
+    blend: f32,
+}
+
+@group(0) @binding(0)
+var r_flame: texture_2d<f32>;
+@group(0) @binding(1)
+var r_sampler: sampler;
+@group(1) @binding(0)
+var r_reference: texture_2d<f32>;
+@group(1) @binding(1)
+var<uniform> r_params: Params;
+
+fn luminance(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let flame = textureSample(r_flame, r_sampler, in.tex_coord).rgb;
+    let reference = textureSample(r_reference, r_sampler, in.tex_coord).rgb;
+    // Red where the flame is brighter than the reference, blue where it is darker.
+    let d = luminance(flame) - luminance(reference);
+    let heatmap = vec3<f32>(max(d, 0.0), 0.0, max(-d, 0.0));
+    return vec4<f32>(mix(flame, heatmap, r_params.blend), 1.0);
+}
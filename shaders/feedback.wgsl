@@ -0,0 +1,12 @@
+//!include quad.wgsl
+
+@group(0) @binding(0)
+var r_color: texture_2d<f32>;
+@group(0) @binding(1)
+var r_sampler: sampler;
+
+// Density of the new frame. Blending fades and adds the previous contents.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(r_color, r_sampler, in.tex_coord).x, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,22 @@
+// Vertex stage for drawing a texture with a quad from `build_quad`.
+// Textures are stored top row first, but quad texture coordinates are y up, so y is flipped.
+
+struct VertexOutput {
+    @location(0)
+    tex_coord: vec2<f32>,
+    @builtin(position)
+    position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    @location(0)
+    in_pos_vs: vec2<f32>,
+    @location(1)
+    in_tex_coord_vs: vec2<f32>
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coord = vec2<f32>(in_tex_coord_vs.x, 1.0 - in_tex_coord_vs.y);
+    out.position = vec4<f32>(in_pos_vs, 0.0, 1.0);
+    return out;
+}
@@ -25,17 +25,68 @@ var r_color: texture_2d<f32>;
 @group(0) @binding(1)
 var r_sampler: sampler;
 
+struct Params {
+    // Row of the palette to use.
+    color: f32,
+    // See `highlight_rolloff`.
+    rolloff: f32,
+    // Applied to the final linear color: simulates a color vision deficiency, or the identity (see `ColorVision`).
+    color_vision: mat3x3<f32>,
+}
+
+// x is density, y is color.
 @group(1) @binding(0)
-var r_gradient: texture_1d<f32>;
+var r_gradient: texture_2d<f32>;
 @group(1) @binding(1)
 var r_gradient_sampler: sampler;
+@group(1) @binding(2)
+var r_curve: texture_1d<f32>;
+@group(1) @binding(3)
+var<uniform> r_params: Params;
+// Contrast curve, remapping the position along the gradient. Usually the identity.
+@group(1) @binding(4)
+var r_contrast: texture_1d<f32>;
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+// Reinhard shoulder compressing positions above 1 - rolloff, so the densest areas don't reach the end of the gradient.
+// Matches `histogram::highlight_rolloff`.
+fn highlight_rolloff(t: f32, rolloff: f32) -> f32 {
+    let knee = 1.0 - rolloff;
+    let x = max(t - knee, 0.0) / max(rolloff, 1e-6);
+    return select(t, knee + rolloff * x / (1.0 + x), rolloff > 0.0 && t > knee);
+}
+
+// Position along the gradient for the density at tex_coord.
+fn tone_map(tex_coord: vec2<f32>) -> f32 {
     // TODO: non-filtered interger sampler?
-    var tex: vec4<f32> = textureSample(r_color, r_sampler, in.tex_coord);
+    var tex: vec4<f32> = textureSample(r_color, r_sampler, tex_coord);
     var v: f32 = tex.x;
     var l: f32 = log2(v);
+    // Tone curve is the identity for plain log mapping.
+    var t: f32 = textureSample(r_curve, r_gradient_sampler, l / 100.0).x;
+    t = textureSample(r_contrast, r_gradient_sampler, t).x;
+    t = highlight_rolloff(t, r_params.rolloff);
+    return select(t, -1.0, v <= 0.0);
+}
 
-    return textureSample(r_gradient, r_gradient_sampler, l / 100.0);
+fn shade(t: f32) -> vec4<f32> {
+    var color: vec4<f32> = textureSample(r_gradient, r_gradient_sampler, vec2<f32>(max(t, 0.0), r_params.color));
+    // Zero density is transparent. This only matters when blending over a background.
+    color.a = select(1.0, 0.0, t < 0.0);
+    return vec4<f32>(max(r_params.color_vision * color.rgb, vec3<f32>(0.0)), color.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return shade(tone_map(in.tex_coord));
+}
+
+// Brightness of the densest areas relative to SDR white.
+const HDR_PEAK: f32 = 4.0;
+
+// For extended range (scRGB) outputs: outputs linear values, with dense areas brighter than SDR white.
+@fragment
+fn fs_main_hdr(in: VertexOutput) -> @location(0) vec4<f32> {
+    var t: f32 = tone_map(in.tex_coord);
+    var color: vec4<f32> = shade(t);
+    return vec4<f32>(color.rgb * mix(1.0, HDR_PEAK, max(t, 0.0)), color.a);
 }
@@ -0,0 +1,39 @@
+//!include quad.wgsl
+
+struct Params {
+    // Source pixel where the footprint of the first output pixel starts.
+    offset: vec2<u32>,
+    // Source pixels per output pixel, in each direction.
+    factor: u32,
+    // Kernel taps in each direction.
+    taps: u32,
+    // Source pixels sampled before the start of each output pixel's footprint.
+    margin: u32,
+    // Separable kernel weights, packed 4 per element.
+    weights: array<vec4<f32>, 16>,
+}
+
+@group(0) @binding(0)
+var r_source: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> r_params: Params;
+
+fn weight(i: u32) -> f32 {
+    return r_params.weights[i / 4u][i % 4u];
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let last = vec2<i32>(textureDimensions(r_source)) - 1;
+    let start = vec2<i32>(r_params.offset + vec2<u32>(in.position.xy) * r_params.factor) - i32(r_params.margin);
+    var sum = vec4<f32>(0.0);
+    for (var y = 0u; y < r_params.taps; y++) {
+        var row = vec4<f32>(0.0);
+        for (var x = 0u; x < r_params.taps; x++) {
+            let p = clamp(start + vec2<i32>(i32(x), i32(y)), vec2<i32>(0), last);
+            row += textureLoad(r_source, p, 0) * weight(x);
+        }
+        sum += row * weight(y);
+    }
+    return sum;
+}
@@ -2,6 +2,8 @@
 struct VertexOutput {
     @location(0)
     tex_coord: vec2<f32>,
+    @location(1)
+    opacity: f32,
     @builtin(position)
     position: vec4<f32>,
 }
@@ -9,18 +11,23 @@ struct VertexOutput {
 @vertex
 fn vs_main(
     @location(0)
-    instance_matrix_row_0: vec4<f32>,
+    instance_matrix_row_0: vec3<f32>,
     @location(1)
-    instance_matrix_row_1: vec4<f32>,
+    instance_matrix_row_1: vec3<f32>,
     @location(2)
     in_pos_vs: vec2<f32>,
     @location(3)
     in_tex_coord_vs: vec2<f32>,
+    @location(4)
+    instance_opacity: f32,
+    @location(5)
+    in_opacity_vs: f32,
 ) -> VertexOutput {
-    var instance_matrix: mat2x3<f32> = mat2x3<f32>(instance_matrix_row_0.xyz, instance_matrix_row_1.xyz);
+    var instance_matrix: mat2x3<f32> = mat2x3<f32>(instance_matrix_row_0, instance_matrix_row_1);
 
     var out: VertexOutput;
     out.tex_coord = in_tex_coord_vs;
+    out.opacity = instance_opacity * in_opacity_vs;
     out.position = vec4<f32>((vec3<f32>(in_pos_vs, 1.0) * instance_matrix), 0.0, 1.0);
     return out;
 }
@@ -29,7 +36,7 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+    return vec4<f32>(in.opacity, 0.0, 0.0, 1.0);
 }
 
 
@@ -39,9 +46,55 @@ var r_color: texture_2d<f32>;
 var r_sampler: sampler;
 
 
+// Density is linear (not gamma encoded) and per unit area, so sampling the smaller pass at each covered pixel
+// (with or without filtering) conserves its integral, scaled by the transform's area scale factor.
 @fragment
 fn fs_main_textured(
     in: VertexOutput
 ) -> @location(0) vec4<f32> {
-    return textureSample(r_color, r_sampler, in.tex_coord);
+    return textureSample(r_color, r_sampler, in.tex_coord) * in.opacity;
+}
+
+struct SplatParams {
+    // Standard deviation of the splat, in texture coordinates (the quad is 1 across).
+    sigma: f32,
+}
+
+@group(0) @binding(2)
+var<uniform> r_splat: SplatParams;
+
+// Gaussian falloff from the center of the quad,
+// normalized so the splat (if not truncated by the quad's edges) adds the same density as the solid quad.
+fn splat(tex_coord: vec2<f32>) -> f32 {
+    var d: vec2<f32> = tex_coord - vec2<f32>(0.5, 0.5);
+    var s2: f32 = r_splat.sigma * r_splat.sigma;
+    return exp(-dot(d, d) / (2.0 * s2)) / (6.28318530718 * s2);
+}
+
+@fragment
+fn fs_main_splat(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.opacity * splat(in.tex_coord), 0.0, 0.0, 1.0);
+}
+
+// Log accumulation: each contribution is compressed before being summed.
+fn compress(v: f32) -> f32 {
+    return log2(1.0 + v);
+}
+
+@fragment
+fn fs_main_log(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(compress(in.opacity), 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main_textured_log(
+    in: VertexOutput
+) -> @location(0) vec4<f32> {
+    var v: vec4<f32> = textureSample(r_color, r_sampler, in.tex_coord) * in.opacity;
+    return vec4<f32>(compress(v.x), 0.0, 0.0, v.a);
+}
+
+@fragment
+fn fs_main_splat_log(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(compress(in.opacity * splat(in.tex_coord)), 0.0, 0.0, 1.0);
 }
@@ -1,69 +1,212 @@
 use num::rational::Ratio;
-use std::borrow::Cow;
+use serde::{Deserialize, Serialize};
+use std::mem;
 use wgpu::{
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry,
     BindingResource, BindingType, Extent3d, FilterMode, PipelineLayoutDescriptor,
-    SamplerDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages,
-    TextureDescriptor, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor,
-    TextureViewDimension,
+    SamplerDescriptor, ShaderModule, ShaderStages, TextureDescriptor, TextureFormat,
+    TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension,
 };
 use winit::dpi::PhysicalSize;
 
 use crate::{
-    flame::{BoundedState, State},
-    geometry::{self, box_to_box, letter_box_scale, Bounds, Rect},
-    mesh::{build_instances, build_mesh},
-    render_common::MeshData,
+    flame::{expansion_factor, BoundedState, State},
+    geometry::{self, box_to_box, Bounds, Disk, OrientedRect, Rect},
+    mesh::{build_instances, build_mesh, build_mobius_mesh, Instance, Vertex, MOBIUS_DIVISIONS},
+    mobius::MobiusState,
+    plan,
+    render_common::{begin_render_pass, float_sampling, shader_module, MeshData},
     util_types::PtrRc,
-    wgpu_render::Renderer,
+    wgpu_render::{Inputs2, Renderer},
 };
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
-struct Accumulate {
-    pub levels: u32,
-    pub size: PhysicalSize<u32>,
-    pub name: String,
+/// Part of a larger image, in pixels with y up.
+/// Used to render outputs too large for a single texture.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Tile {
+    /// Size of the whole image. The flame is letter boxed into this.
+    pub full: PhysicalSize<u32>,
+    /// Offset of the tile within the image.
+    pub min: [u32; 2],
 }
 
-impl Accumulate {
-    fn mesh_levels(&self) -> u32 {
-        self.levels - self.instance_levels()
-    }
+/// How contributions are added into the on screen pass's accumulation texture.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Accumulation {
+    /// Store density.
+    Linear,
+    /// Store the sum of `log2(1 + contribution)` for each instance's contribution.
+    /// This compresses the densest contributions so cores keep more detail after tone mapping,
+    /// but the stored values are no longer proportional to density.
+    /// Only applies to the on screen pass (see [`Accumulation::for_pass`]).
+    Log,
+}
 
-    fn instance_levels(&self) -> u32 {
-        self.levels / 2
+impl Accumulation {
+    /// The accumulation used by the pass with `key`: the smaller passes always store linear density,
+    /// as the on screen pass samples them as density, and compressing them too would compound.
+    pub fn for_pass(self, key: &PassKey) -> Accumulation {
+        if key.axis_aligned {
+            self
+        } else {
+            Accumulation::Linear
+        }
     }
 }
 
+/// Selects the fragment shader of an accumulation pipeline.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct PipelineKey {
+    /// Sample from a smaller pass.
+    pub textured: bool,
+    /// Draw Gaussian splats instead of solid quads. Must be false when `textured`.
+    pub splat: bool,
+    pub accumulation: Accumulation,
+}
+
+/// Size of the uniform buffer holding the splat size.
+const SPLAT_PARAMS_SIZE: u64 = 16;
+
 #[salsa::query_group(AccumulateStorage)]
-pub trait Accumulator: Renderer {
+pub trait Accumulator: Renderer + Inputs2 {
     fn data(&self, key: ()) -> PtrRc<DeviceData>;
     fn pass(&self, key: PassKey) -> PtrRc<Pass>;
+    fn pipeline(&self, key: PipelineKey) -> PtrRc<wgpu::RenderPipeline>;
     fn mesh(&self, key: u32) -> PtrRc<MeshData>;
     fn instance(&self, key: InstanceKey) -> PtrRc<MeshData>;
-    fn bounds(&self, key: ()) -> Rect;
+    fn bounds(&self, key: ()) -> OrientedRect;
+    /// Convex hull of the flame, before the final transform. See `BoundedState::convex_hull`.
+    fn hull(&self, key: ()) -> PtrRc<Vec<na::Point2<f64>>>;
+    /// Bounds of each level drawn from `bounds`, before the final transform. See `BoundedState::level_bounds`.
+    fn level_bounds(&self, key: ()) -> PtrRc<Vec<OrientedRect>>;
+    fn scale_analysis(&self, key: ()) -> ScaleAnalysis;
+    /// Number of vertices (instances × mesh vertices) drawn by the pass, including smaller passes it depends on.
+    fn vertex_count(&self, key: PassKey) -> u64;
+}
+
+fn vertex_count(db: &dyn Accumulator, key: PassKey) -> u64 {
+    let pass = db.pass(key);
+    let (vertexes, instances) = pass.buffers(db);
+    let smaller = pass.spec.smaller.clone().map_or(0, |k| db.vertex_count(k));
+    u64::from(vertexes.count) * u64::from(instances.count) + smaller
 }
 
-fn bounds(db: &dyn Accumulator, (): ()) -> Rect {
+fn bounds(db: &dyn Accumulator, (): ()) -> OrientedRect {
     let root = db.root(());
-    let levels = 5;
+    let config = db.config(());
 
     // This can be expensive, so cache it.
-    let bounds = root.get_state().get_bounds(levels);
+    let bounds = match &config.mobius {
+        // Bounded by a disk, which has no orientation.
+        Some(maps) => {
+            let (disk, _levels) = MobiusState::new(maps)
+                .get_bounds_adaptive(config.max_bounds_levels, config.bounds_samples);
+            OrientedRect::axis_aligned(disk.aabb())
+        }
+        None => {
+            let angle = if config.oriented_bounds {
+                root.dominant_angle()
+            } else {
+                0.0
+            };
+            let (bounds, _levels) = root
+                .get_oriented_state(angle)
+                .get_bounds_adaptive(config.max_bounds_levels, config.bounds_samples);
+            bounds
+        }
+    };
     if bounds.is_infinite() {
-        panic!("infinite bounds")
+        // Non-contractive flames (which can be made while editing) have no finite bounds.
+        // Draw them into a fixed region instead, so the UI stays usable to fix them.
+        if config.mobius.is_some() {
+            log::warn!(
+                "infinite bounds: the Möbius transforms do not contract away from their poles"
+            );
+        } else {
+            log::warn!("infinite bounds: {:?}", db.contraction(()));
+        }
+        return OrientedRect {
+            rotation: bounds.rotation,
+            rect: Rect {
+                min: na::Point2::new(-1.0, -1.0),
+                max: na::Point2::new(1.0, 1.0),
+            },
+        };
+    }
+
+    let rect = bounds.rect;
+    OrientedRect {
+        rotation: bounds.rotation,
+        rect: rect.with_min_size(f64::max(1e-6, 0.01 * f64::max(rect.width(), rect.height()))),
+    }
+}
+
+/// Upper limit on the states `hull` maps the bounds' corners by.
+const HULL_STATES: usize = 1 << 14;
+
+fn hull(db: &dyn Accumulator, (): ()) -> PtrRc<Vec<na::Point2<f64>>> {
+    let bounds = db.bounds(());
+    // Möbius transforms curve the bounds' edges, so the hull of their mapped corners would not contain the flame.
+    if db.config(()).mobius.is_some() {
+        return bounds.corners().to_vec().into();
+    }
+    let root = db.root(());
+    let n = root.transforms().len().max(2);
+    let mut levels = 0;
+    while n.pow(levels + 1) <= HULL_STATES {
+        levels += 1;
+    }
+    root.get_state()
+        .convex_hull(levels, &bounds.corners())
+        .into()
+}
+
+/// Most levels `level_bounds` computes: deeper levels are too close together to tell apart.
+const MAX_OVERLAY_LEVELS: u32 = 6;
+
+fn level_bounds(db: &dyn Accumulator, (): ()) -> PtrRc<Vec<OrientedRect>> {
+    let root = db.root(());
+    let config = db.config(());
+    let n = config
+        .mobius
+        .as_ref()
+        .map_or(root.transforms().len(), Vec::len)
+        .max(2);
+    let mut levels = 0;
+    while levels < MAX_OVERLAY_LEVELS && n.pow(levels + 1) <= HULL_STATES {
+        levels += 1;
+    }
+    let bounds = db.bounds(());
+    match &config.mobius {
+        Some(maps) => MobiusState::new(maps)
+            .level_bounds(&inscribed_disk(&bounds), levels)
+            .iter()
+            .map(|disk| OrientedRect::axis_aligned(disk.aabb()))
+            .collect::<Vec<_>>()
+            .into(),
+        None => root
+            .get_oriented_state(bounds.rotation.angle())
+            .level_bounds(&bounds, levels)
+            .into(),
+    }
+}
+
+/// The disk a Möbius flame's bounds were computed as (see `bounds`): the largest one inside them.
+fn inscribed_disk(bounds: &OrientedRect) -> Disk {
+    let rect = bounds.aabb();
+    Disk {
+        center: na::center(&rect.min, &rect.max),
+        radius: f64::min(rect.width(), rect.height()) / 2.0,
     }
-    bounds
 }
 
 pub fn mesh(db: &dyn Accumulator, levels: u32) -> PtrRc<MeshData> {
     let bounds = db.bounds(());
-    MeshData::new(
-        &*db.device(()),
-        &build_mesh(&db.root(()), bounds, levels),
-        "Vertex Buffer",
-    )
-    .into()
+    let vertexes = match &db.config(()).mobius {
+        Some(maps) => build_mobius_mesh(maps, bounds, levels, MOBIUS_DIVISIONS),
+        None => build_mesh(&db.root(()), bounds, levels),
+    };
+    MeshData::new(&db.device(()), &vertexes, "Vertex Buffer").into()
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -72,10 +215,27 @@ pub struct InstanceKey {
     // width / height
     // TODO: use a uniform buffer for root transformation for better caching and to allow non-letterbox positioning of final pass.
     aspect_ratio: Ratio<u32>,
+    axis_aligned: bool,
+    /// If set, the instances are positioned for this tile, which has the provided size.
+    tile: Option<(Tile, PhysicalSize<u32>)>,
+    view: Option<ViewTransform>,
 }
 
 pub fn instance(db: &dyn Accumulator, key: InstanceKey) -> PtrRc<MeshData> {
     let bounds = db.bounds(());
+    let root = db.root(());
+
+    // The final transform and camera only affect the output, so are applied by the on screen pass,
+    // and the smaller passes it samples from are unaffected.
+    let (final_transform, output_bounds) = if key.axis_aligned {
+        (output_transform(db), output_bounds(db))
+    } else {
+        (na::Affine2::identity(), bounds.aabb())
+    };
+    let output_bounds = match db.config(()).crop {
+        Some(crop) if key.axis_aligned => output_bounds.sub_rect(&crop),
+        _ => output_bounds,
+    };
 
     let window_rect = geometry::Rect {
         min: na::Point2::new(0.0, 0.0),
@@ -85,56 +245,185 @@ pub fn instance(db: &dyn Accumulator, key: InstanceKey) -> PtrRc<MeshData> {
         ),
     };
 
-    let root_mat = geometry::letter_box(window_rect, bounds);
+    // Passes sampled by larger ones are stretched to exactly fill their texture, since the quads sampling them
+    // map texture coordinates 0 to 1 across the bounds.
+    // Letter boxing would leave margins (wherever rounding changed the aspect ratio) which those quads include,
+    // shrinking the contents and losing density.
+    let root_mat = if key.axis_aligned {
+        geometry::letter_box(window_rect, output_bounds)
+    } else {
+        geometry::box_to_box_oriented(window_rect, bounds)
+    };
+
+    let (root_mat, output_rect) = match key.tile {
+        Some((tile, size)) => {
+            let full_rect = geometry::Rect {
+                min: na::Point2::new(0.0, 0.0),
+                max: na::Point2::new(tile.full.width as f64, tile.full.height as f64),
+            };
+            let min = na::Point2::new(tile.min[0] as f64, tile.min[1] as f64);
+            (
+                geometry::letter_box_pixel_aspect(
+                    full_rect,
+                    output_bounds,
+                    db.config(()).pixel_aspect,
+                ),
+                geometry::Rect {
+                    min,
+                    max: min + na::Vector2::new(size.width as f64, size.height as f64),
+                },
+            )
+        }
+        None => (root_mat, window_rect),
+    };
 
     let rebox = box_to_box(
         geometry::Rect {
             min: na::Point2::new(-1.0, -1.0),
             max: na::Point2::new(1.0, 1.0),
         },
-        window_rect,
+        output_rect,
     );
 
     MeshData::new(
-        &*db.device(()),
-        &build_instances(&db.root(()), rebox * root_mat, key.levels),
+        &db.device(()),
+        &build_instances(
+            &root,
+            key.view.map_or(na::Affine2::identity(), |v| v.get())
+                * rebox
+                * root_mat
+                * final_transform,
+            key.levels,
+        ),
         "Instance Buffer",
     )
     .into()
 }
 
+/// Applied to the flame by the on screen pass: the final transform (if any),
+/// then the camera rotation about the center of the result's bounds.
+pub fn output_transform(db: &dyn Accumulator) -> na::Affine2<f64> {
+    let t = db
+        .root(())
+        .final_transform()
+        .copied()
+        .unwrap_or(na::Affine2::identity());
+    let angle = db.config(()).camera_rotation;
+    if angle == 0.0 {
+        return t;
+    }
+    let rect = db.bounds(()).transformed_aabb(&t);
+    let center = na::center(&rect.min, &rect.max).coords;
+    let rotation = na::Translation2::from(center).to_homogeneous()
+        * na::Rotation2::new(angle).to_homogeneous()
+        * na::Translation2::from(-center).to_homogeneous();
+    na::Affine2::from_matrix_unchecked(rotation) * t
+}
+
+/// Axis-aligned bounds of the output (after `output_transform`), which the on screen pass fits to its target.
+/// This is before cropping.
+pub fn output_bounds(db: &dyn Accumulator) -> geometry::Rect {
+    let rect = db.bounds(()).transformed_aabb(&output_transform(db));
+    rect.with_min_size(f64::max(1e-6, 0.01 * f64::max(rect.width(), rect.height())))
+}
+
 /// Device dependant, but otherwise constant data.
 #[derive(Debug)]
 pub struct DeviceData {
     shader: ShaderModule,
     pub accumulation_bind_group_layout: BindGroupLayout,
+    splat_bind_group_layout: BindGroupLayout,
+    splat_params: wgpu::Buffer,
+    splat_bind_group: BindGroup,
     accumulation_sampler: wgpu::Sampler,
     nearest_sampler: wgpu::Sampler,
 }
 
 #[derive(Debug)]
 pub struct Pass {
-    pipeline: wgpu::RenderPipeline,
+    pipeline: PtrRc<wgpu::RenderPipeline>,
     output_bind_group: wgpu::BindGroup,
+    texture: wgpu::Texture,
     view: wgpu::TextureView,
-    spec: Accumulate,
-    smaller: Option<PassKey>,
+    spec: plan::Accumulate,
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct PassKey {
     pub resolution: PhysicalSize<u32>,
     pub filter: bool,
+    /// Fit the axis-aligned bounds (used for the final, on screen pass) instead of the oriented bounds.
+    pub axis_aligned: bool,
+    /// If set, render just this tile of a larger image (which has this pass's resolution). Requires `axis_aligned`.
+    pub tile: Option<Tile>,
+    /// Applied to the output (ex: to zoom in). Requires `axis_aligned`.
+    pub view: Option<ViewTransform>,
+    /// Number of larger passes sampling from this one (0 for the output pass).
+    pub depth: u32,
+}
+
+/// Transform applied after fitting the flame to the output, in normalized device coordinates.
+/// Stored as bits so it can be used in keys.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct ViewTransform([u64; 6]);
+
+impl ViewTransform {
+    pub fn new(t: &na::Affine2<f64>) -> Self {
+        let m = t.matrix();
+        Self(
+            [
+                m[(0, 0)],
+                m[(0, 1)],
+                m[(0, 2)],
+                m[(1, 0)],
+                m[(1, 1)],
+                m[(1, 2)],
+            ]
+            .map(f64::to_bits),
+        )
+    }
+
+    pub fn get(&self) -> na::Affine2<f64> {
+        let [a, b, c, d, e, f] = self.0.map(f64::from_bits);
+        na::Affine2::from_matrix_unchecked(na::Matrix3::new(a, b, c, d, e, f, 0.0, 0.0, 1.0))
+    }
 }
 
 pub fn data(db: &dyn Accumulator, (): ()) -> PtrRc<DeviceData> {
     let device = db.device(());
+    let float_filtering = db.float_filtering(());
+    let (sampler_binding, filter) = float_sampling(float_filtering);
+    let splat_bind_group_layout =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("splat"),
+        });
+    let splat_params = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("splat params"),
+        size: SPLAT_PARAMS_SIZE,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let splat_bind_group = device.create_bind_group(&BindGroupDescriptor {
+        layout: &splat_bind_group_layout,
+        entries: &[BindGroupEntry {
+            binding: 2,
+            resource: splat_params.as_entire_binding(),
+        }],
+        label: Some("splat"),
+    });
     DeviceData {
         // Load the shaders from disk
-        shader: device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("wgpu.wgsl"),
-            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("../shaders/wgpu.wgsl"))),
-        }),
+        shader: shader_module(&device, "wgpu.wgsl", include_str!("../shaders/wgpu.wgsl")),
 
         accumulation_bind_group_layout: device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
@@ -145,7 +434,9 @@ pub fn data(db: &dyn Accumulator, (): ()) -> PtrRc<DeviceData> {
                         ty: BindingType::Texture {
                             multisampled: false,
                             // R32Float textures to not support filtering be default: requires native feature opt-in.
-                            sample_type: TextureSampleType::Float { filterable: true },
+                            sample_type: TextureSampleType::Float {
+                                filterable: float_filtering,
+                            },
                             view_dimension: TextureViewDimension::D2,
                         },
                         count: None,
@@ -153,7 +444,7 @@ pub fn data(db: &dyn Accumulator, (): ()) -> PtrRc<DeviceData> {
                     BindGroupLayoutEntry {
                         binding: 1,
                         visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        ty: BindingType::Sampler(sampler_binding),
                         count: None,
                     },
                 ],
@@ -161,11 +452,16 @@ pub fn data(db: &dyn Accumulator, (): ()) -> PtrRc<DeviceData> {
             },
         ),
 
+        splat_bind_group_layout,
+        splat_params,
+        splat_bind_group,
+
         // TODO: mipmap filtering and generation
+        // Without float filtering, the smaller passes are magnified blocky instead of smooth.
         accumulation_sampler: device.create_sampler(&SamplerDescriptor {
             label: Some("accumulation sampler"),
-            mag_filter: FilterMode::Linear,
-            min_filter: FilterMode::Linear,
+            mag_filter: filter,
+            min_filter: filter,
             ..Default::default()
         }),
 
@@ -180,15 +476,32 @@ pub fn data(db: &dyn Accumulator, (): ()) -> PtrRc<DeviceData> {
 }
 
 impl Pass {
-    pub fn render(&self, db: &dyn Accumulator, encoder: &mut wgpu::CommandEncoder) -> &BindGroup {
+    /// Returns the mesh and instance buffers.
+    fn buffers(&self, db: &dyn Accumulator) -> (PtrRc<MeshData>, PtrRc<MeshData>) {
         let vertexes = db.mesh(self.spec.mesh_levels());
         let instances = db.instance(InstanceKey {
             levels: self.spec.instance_levels(),
-            aspect_ratio: Ratio::new(self.spec.size.width, self.spec.size.height),
+            aspect_ratio: Ratio::new(
+                self.spec.key.resolution.width,
+                self.spec.key.resolution.height,
+            ),
+            axis_aligned: self.spec.key.axis_aligned,
+            tile: self
+                .spec
+                .key
+                .tile
+                .map(|tile| (tile, self.spec.key.resolution)),
+            view: self.spec.key.view,
         });
+        (vertexes, instances)
+    }
+
+    pub fn render(&self, db: &dyn Accumulator, encoder: &mut wgpu::CommandEncoder) -> &BindGroup {
+        let (vertexes, instances) = self.buffers(db);
+        let data = db.data(());
 
         // TODO: avoid having 3 "if let"s for this.
-        let smaller_pass = if let Some(b) = &self.smaller {
+        let smaller_pass = if let Some(b) = &self.spec.smaller {
             let inner = db.pass(b.clone());
             Some(inner)
         } else {
@@ -197,123 +510,128 @@ impl Pass {
 
         let smaller = smaller_pass.as_ref().map(|b| b.render(db, encoder));
 
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Accumulate"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &self.view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: None,
-            occlusion_query_set: None,
-            timestamp_writes: None,
-        });
+        let mut render_pass = begin_render_pass(
+            encoder,
+            &wgpu::RenderPassDescriptor {
+                label: Some("Accumulate"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            },
+        );
         render_pass.set_pipeline(&self.pipeline);
         if let Some(b) = &smaller {
             render_pass.set_bind_group(0, b, &[])
+        } else if let Some(sigma) = db.config(()).splat_size {
+            let mut params = [0u8; SPLAT_PARAMS_SIZE as usize];
+            params[..4].copy_from_slice(&sigma.to_le_bytes());
+            db.queue(()).write_buffer(&data.splat_params, 0, &params);
+            render_pass.set_bind_group(0, &data.splat_bind_group, &[])
         };
 
-        render_pass.set_vertex_buffer(0, instances.buffer.slice(..));
-        render_pass.set_vertex_buffer(1, vertexes.buffer.slice(..));
-        render_pass.draw(0..(vertexes.count), 0..(instances.count));
+        // Without transforms there is nothing to draw (and empty buffers can not be bound), so the pass is just cleared.
+        if vertexes.count > 0 && instances.count > 0 {
+            render_pass.set_vertex_buffer(0, instances.buffer.slice(..));
+            render_pass.set_vertex_buffer(1, vertexes.buffer.slice(..));
+            render_pass.draw(0..(vertexes.count), 0..(instances.count));
+        }
         &self.output_bind_group
     }
-}
 
-fn area_sf(t: &na::Affine2<f64>) -> f64 {
-    let mat = t.matrix();
-    // get the upper 2x2 (as that is what effects scaling). TODO: better way to get scale factor.
-    let m2 = nalgebra::Matrix2::from_fn(|a, b| mat.row(a)[b]);
-    m2.determinant()
+    /// The accumulation texture this pass renders into.
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
 }
 
-fn texture_size(s: f64) -> u32 {
-    u32::max(1, (s / 8.0) as u32 * 8)
+/// How much the first level transforms scale area, used to size passes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScaleAnalysis {
+    /// Smallest linear scale factor (using each transform's largest singular value).
+    pub sf_min: f64,
+    /// Sum of the area scale factors.
+    pub fill_ratio: f64,
 }
 
-/// Returns a BindGroup for reading from the the output from the pass
-pub fn pass(db: &dyn Accumulator, key: PassKey) -> PtrRc<Pass> {
-    let b = db.bounds(());
-    let root = db.root(());
+/// NaN is invalid in all the floats here, so Eq is fine.
+impl Eq for ScaleAnalysis {}
+
+fn scale_analysis(db: &dyn Accumulator, (): ()) -> ScaleAnalysis {
     let mut sf_min = f64::INFINITY;
-    let mut sf_max = f64::NEG_INFINITY;
     let mut fill_ratio = 0.0;
-    let mut count = 0;
+    if let Some(maps) = &db.config(()).mobius {
+        // The most each map stretches anywhere in the bounds, so passes have enough resolution where they stretch most.
+        let disk = inscribed_disk(&db.bounds(()));
+        for m in maps {
+            let stretch = m
+                .max_stretch(na::Complex::new(disk.center.x, disk.center.y), disk.radius)
+                .unwrap_or(f64::INFINITY);
+            sf_min = f64::min(sf_min, stretch);
+            fill_ratio += stretch * stretch;
+        }
+        return ScaleAnalysis { sf_min, fill_ratio };
+    }
     // TODO: should render variable number of iterations of different functions to get more uniform scale instead of fixed level (recurse if it helps)
-    // TODO: avoid redoing this analysis for every pass
-    root.get_state().process_levels(1, &mut |x| {
-        let sf = area_sf(&x.mat);
-        sf_min = f64::min(sf_min, sf);
-        sf_max = f64::max(sf_max, sf);
-        fill_ratio += sf;
-        count += 1;
+    db.root(()).get_state().process_levels(1, &mut |x| {
+        // The largest singular value, so non-uniform transforms get enough resolution in their most stretched direction.
+        sf_min = f64::min(sf_min, expansion_factor(&x.mat));
+        fill_ratio += area_sf(&x.mat).abs();
     });
-    let sf_min = f64::sqrt(sf_min);
-    let _sf_max = f64::sqrt(sf_max);
-
-    let lb_scale = letter_box_scale(
-        Rect {
-            min: na::Point2::origin(),
-            max: na::Point2::new(key.resolution.width as f64, key.resolution.height as f64),
-        },
-        b,
-    );
-
-    let width_to_fill = lb_scale * b.width();
-    let height_to_fill = lb_scale * b.height();
-
-    let fill_area = fill_ratio * width_to_fill * height_to_fill;
+    ScaleAnalysis { sf_min, fill_ratio }
+}
 
-    // TODO: reasonable cost function
-    let mut passes: u32 = if fill_area > 1024.0 * 1024.0 {
-        2
-    } else if fill_area > 256.0 * 256.0 {
-        6
-    } else {
-        8
-    };
+fn area_sf(t: &na::Affine2<f64>) -> f64 {
+    let mat = t.matrix();
+    // get the upper 2x2 (as that is what effects scaling). TODO: better way to get scale factor.
+    let m2 = nalgebra::Matrix2::from_fn(|a, b| mat.row(a)[b]);
+    m2.determinant()
+}
 
-    // Avoid buffers being too large
-    const BUFFER_LIMIT: usize = 512;
-    while passes > 2 && db.config(()).n.pow(passes / 2) > BUFFER_LIMIT {
-        passes -= 1;
+/// Inputs for planning passes for the current flame and device.
+pub fn plan_inputs(db: &dyn Accumulator) -> plan::Inputs {
+    plan::Inputs {
+        bounds: db.bounds(()),
+        output_bounds: output_bounds(db),
+        scale: db.scale_analysis(()),
+        // Symmetry adds copies of the points' transforms, so count the root's transforms rather than the points.
+        n: db
+            .config(())
+            .mobius
+            .as_ref()
+            .map_or(db.root(()).transforms().len(), Vec::len),
+        max_texture_size: db.device(()).limits().max_texture_dimension_2d,
+        rounding: db.config(()).size_rounding,
+        locked: db.config(()).locked_resolution,
+        crop: db.config(()).crop,
+        min_pass_size: db.config(()).min_pass_size,
+        depth: db.config(()).pass_depth,
+        levels: db.config(()).step_levels,
+        samples_per_pixel: db.config(()).samples_per_pixel,
+        subdivisions: db.config(()).mobius.as_ref().map(|_| MOBIUS_DIVISIONS),
     }
+}
 
-    let sf = sf_min.powi(passes as i32);
-
-    let width = texture_size(width_to_fill * sf);
-    let height = texture_size(height_to_fill * sf);
-
-    let smaller = if width > 16 || height > 16 {
-        Some(PassKey {
-            filter: true,
-            resolution: [width, height].into(),
-        })
-    } else {
-        None
-    };
-    make_pass(
-        db,
-        Accumulate {
-            levels: passes,
-            size: key.resolution,
-            name: "AutoSized".to_owned(),
-        },
-        smaller,
-        key.filter,
-    )
-    .into()
+/// Returns a BindGroup for reading from the the output from the pass
+pub fn pass(db: &dyn Accumulator, key: PassKey) -> PtrRc<Pass> {
+    make_pass(db, plan::step(&plan_inputs(db), &key)).into()
 }
 
-fn make_pass(
-    db: &dyn Accumulator,
-    accumulate: Accumulate,
-    smaller: Option<PassKey>,
-    filter: bool,
-) -> Pass {
+/// Pipeline for accumulation passes, which sample from a smaller pass if `key.textured`.
+/// These are shared by all passes, so changing the pass structure does not require compiling new pipelines.
+pub fn pipeline(db: &dyn Accumulator, key: PipelineKey) -> PtrRc<wgpu::RenderPipeline> {
+    let PipelineKey {
+        textured,
+        splat,
+        accumulation,
+    } = key;
     let device = db.device(());
     let data = db.data(());
 
@@ -328,10 +646,16 @@ fn make_pass(
         alpha: blend_add,
     };
 
-    let groups = &[&data.accumulation_bind_group_layout];
+    let bind_group_layouts: &[&BindGroupLayout] = if textured {
+        &[&data.accumulation_bind_group_layout]
+    } else if splat {
+        &[&data.splat_bind_group_layout]
+    } else {
+        &[]
+    };
     let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
         label: Some("accumulation pipeline"),
-        bind_group_layouts: if smaller.is_some() { groups } else { &[] },
+        bind_group_layouts,
         push_constant_ranges: &[],
     });
 
@@ -340,54 +664,95 @@ fn make_pass(
         entry_point: "vs_main",
         buffers: &[
             wgpu::VertexBufferLayout {
-                array_stride: 2 * 4 * 4,
+                array_stride: mem::size_of::<Instance>() as u64,
                 step_mode: wgpu::VertexStepMode::Instance,
-                attributes: &wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4], // Rows of matrix
+                // Rows of matrix, then opacity
+                attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 4 => Float32],
             },
             wgpu::VertexBufferLayout {
-                array_stride: 2 * 2 * 4,
+                array_stride: mem::size_of::<Vertex>() as u64,
                 step_mode: wgpu::VertexStepMode::Vertex,
-                attributes: &wgpu::vertex_attr_array![2 => Float32x2, 3 => Float32x2],
+                attributes: &wgpu::vertex_attr_array![2 => Float32x2, 3 => Float32x2, 5 => Float32],
             },
         ],
     };
 
-    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some(&accumulate.name),
-        layout: Some(&pipeline_layout),
-        vertex: vertex_shader,
-        fragment: Some(wgpu::FragmentState {
-            module: &data.shader,
-            entry_point: if smaller.is_some() {
-                "fs_main_textured"
-            } else {
-                "fs_main"
-            },
-            targets: &[Some(wgpu::ColorTargetState {
-                format: TextureFormat::R32Float,
-                blend: Some(blend_state_add),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
-        }),
-        primitive: wgpu::PrimitiveState::default(),
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
-        multiview: None,
+    device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("accumulate"),
+            layout: Some(&pipeline_layout),
+            vertex: vertex_shader,
+            fragment: Some(wgpu::FragmentState {
+                module: &data.shader,
+                entry_point: match (textured, splat, accumulation) {
+                    (true, _, Accumulation::Linear) => "fs_main_textured",
+                    (true, _, Accumulation::Log) => "fs_main_textured_log",
+                    (false, true, Accumulation::Linear) => "fs_main_splat",
+                    (false, true, Accumulation::Log) => "fs_main_splat_log",
+                    (false, false, Accumulation::Linear) => "fs_main",
+                    (false, false, Accumulation::Log) => "fs_main_log",
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: TextureFormat::R32Float,
+                    blend: Some(blend_state_add),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+        .into()
+}
+
+/// Compiles the accumulation pipelines `make_pass` can use with the current config, whatever the pass structure.
+/// This is synchronous (wgpu has no asynchronous pipeline creation), so it only moves the stall to startup:
+/// changing the splats or accumulation later still compiles their pipelines on first use.
+pub fn prebuild_pipelines(db: &dyn Accumulator) {
+    let config = db.config(());
+    // The smaller passes are always linear.
+    let mut accumulations = vec![Accumulation::Linear];
+    if config.accumulation != Accumulation::Linear {
+        accumulations.push(config.accumulation);
+    }
+    for accumulation in accumulations {
+        for textured in [true, false] {
+            db.pipeline(PipelineKey {
+                textured,
+                splat: !textured && config.splat_size.is_some(),
+                accumulation,
+            });
+        }
+    }
+}
+
+fn make_pass(db: &dyn Accumulator, accumulate: plan::Accumulate) -> Pass {
+    let device = db.device(());
+    let data = db.data(());
+    let textured = accumulate.smaller.is_some();
+    let pipeline = db.pipeline(PipelineKey {
+        textured,
+        // Normalized so toggling splats does not invalidate or duplicate the textured pipelines.
+        splat: !textured && db.config(()).splat_size.is_some(),
+        accumulation: db.config(()).accumulation.for_pass(&accumulate.key),
     });
 
     let texture: wgpu::Texture = device.create_texture(&TextureDescriptor {
         size: Extent3d {
-            width: accumulate.size.width,
-            height: accumulate.size.height,
+            width: accumulate.key.resolution.width,
+            height: accumulate.key.resolution.height,
             depth_or_array_layers: 1,
         },
         mip_level_count: 1,
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
         format: TextureFormat::R32Float,
-        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
-        label: Some(&accumulate.name),
-        view_formats: &vec![],
+        usage: TextureUsages::TEXTURE_BINDING
+            | TextureUsages::RENDER_ATTACHMENT
+            | TextureUsages::COPY_SRC,
+        label: Some("accumulation texture"),
+        view_formats: &[],
     });
 
     let view: wgpu::TextureView = texture.create_view(&TextureViewDescriptor::default());
@@ -401,7 +766,7 @@ fn make_pass(
             },
             BindGroupEntry {
                 binding: 1,
-                resource: BindingResource::Sampler(if filter {
+                resource: BindingResource::Sampler(if accumulate.key.filter {
                     &data.nearest_sampler
                 } else {
                     &data.accumulation_sampler
@@ -413,9 +778,9 @@ fn make_pass(
 
     Pass {
         pipeline,
+        texture,
         view,
         output_bind_group,
-        smaller,
         spec: accumulate,
     }
 }
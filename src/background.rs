@@ -0,0 +1,239 @@
+//! Optional image drawn behind the flame.
+
+use std::mem;
+use wgpu::{
+    AddressMode, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutEntry, BindingResource,
+    BindingType, FilterMode, PipelineLayoutDescriptor, SamplerDescriptor, ShaderStages,
+    TextureAspect, TextureDescriptor, TextureFormat, TextureSampleType, TextureUsages,
+    TextureViewDescriptor, TextureViewDimension,
+};
+
+use crate::{
+    geometry::{letter_box_scale, Rect},
+    mesh::{build_quad, Vertex},
+    render_common::{begin_render_pass, shader_module, MeshData},
+    util_types::PtrRc,
+    wgpu_render::Postprocesser,
+};
+
+/// Device and target format dependant, but otherwise constant data.
+#[derive(Debug)]
+pub struct Data {
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    quad: MeshData,
+    pipeline: wgpu::RenderPipeline,
+}
+
+#[derive(Debug)]
+pub struct Background {
+    bind_group: wgpu::BindGroup,
+    width: u32,
+    height: u32,
+}
+
+pub fn data(db: &dyn Postprocesser, format: TextureFormat) -> PtrRc<Data> {
+    let device = db.device(());
+
+    let shader = shader_module(
+        &device,
+        "background.wgsl",
+        include_str!("../shaders/background.wgsl"),
+    );
+
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+        label: None,
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("background pipeline"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("background"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: mem::size_of::<Vertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2],
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    Data {
+        bind_group_layout,
+        sampler: device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        }),
+        quad: MeshData::new(&device, &build_quad(), "Background Quad Vertex Buffer"),
+        pipeline,
+    }
+    .into()
+}
+
+/// Loads the image at `path`. Returns None (and logs) on failure.
+pub fn load(db: &dyn Postprocesser, path: &str) -> Option<PtrRc<Background>> {
+    let image = match image::open(path) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => {
+            log::warn!("Failed to load background image {path}: {e}");
+            return None;
+        }
+    };
+
+    let device = db.device(());
+    let queue = db.queue(());
+    let data = db.background_data(*db.swapchain_format(()));
+
+    let texture_size = wgpu::Extent3d {
+        width: image.width(),
+        height: image.height(),
+        depth_or_array_layers: 1,
+    };
+
+    let texture = device.create_texture(&TextureDescriptor {
+        size: texture_size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TextureFormat::Rgba8UnormSrgb,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        label: Some("background_texture"),
+        view_formats: &[],
+    });
+
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        &image,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * image.width()),
+            rows_per_image: Some(image.height()),
+        },
+        texture_size,
+    );
+
+    let view = texture.create_view(&TextureViewDescriptor::default());
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        layout: &data.bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&data.sampler),
+            },
+        ],
+        label: None,
+    });
+
+    Some(
+        Background {
+            bind_group,
+            width: image.width(),
+            height: image.height(),
+        }
+        .into(),
+    )
+}
+
+/// Clears dst, then draws the background letter boxed into the flame's part of the window.
+pub fn render(
+    db: &dyn Postprocesser,
+    encoder: &mut wgpu::CommandEncoder,
+    background: &Background,
+    dst: &wgpu::TextureView,
+) {
+    let data = db.background_data(*db.swapchain_format(()));
+    let size = db.window_size(());
+    let container = Rect {
+        min: na::Point2::origin(),
+        max: na::Point2::new(size.width as f64, size.height as f64),
+    };
+    let content = Rect {
+        min: na::Point2::origin(),
+        max: na::Point2::new(background.width as f64, background.height as f64),
+    };
+    let scale = letter_box_scale(container, content);
+    let width = content.width() * scale;
+    let height = content.height() * scale;
+
+    let mut pass = begin_render_pass(
+        encoder,
+        &wgpu::RenderPassDescriptor {
+            label: Some("Background render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: dst,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        },
+    );
+
+    pass.set_viewport(
+        ((container.width() - width) / 2.0) as f32,
+        ((container.height() - height) / 2.0) as f32,
+        width as f32,
+        height as f32,
+        0.0,
+        1.0,
+    );
+    pass.set_pipeline(&data.pipeline);
+    pass.set_bind_group(0, &background.bind_group, &[]);
+    pass.set_vertex_buffer(0, data.quad.buffer.slice(..));
+    pass.draw(0..(data.quad.count), 0..1);
+}
@@ -0,0 +1,215 @@
+//! Converting a directory of flam3 `.flame` files to PNGs without opening a window:
+//! `rusty-flame batch --in <directory> --out <directory> --size <width>x<height>`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    flam3, request_adapter, supersample,
+    ui::Settings,
+    util_types::Shared,
+    wgpu_render::{self, DatabaseStruct, Inputs},
+};
+
+const USAGE: &str =
+    "Usage: rusty-flame batch --in <directory> --out <directory> [--size <width>x<height>]";
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    /// Directory of `.flame` files to render.
+    pub input: PathBuf,
+    /// Directory the PNGs are written to, created if needed.
+    pub output: PathBuf,
+    pub size: PhysicalSize<u32>,
+}
+
+/// Parses the arguments after "batch".
+pub fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut size = PhysicalSize::new(1920, 1080);
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {flag}"))?;
+        match flag.as_str() {
+            "--in" => input = Some(PathBuf::from(value)),
+            "--out" => output = Some(PathBuf::from(value)),
+            "--size" => size = parse_size(value)?,
+            _ => return Err(format!("Unknown option \"{flag}\"")),
+        }
+    }
+    Ok(Args {
+        input: input.ok_or("Missing --in")?,
+        output: output.ok_or("Missing --out")?,
+        size,
+    })
+}
+
+fn parse_size(value: &str) -> Result<PhysicalSize<u32>, String> {
+    let invalid = || format!("Invalid size \"{value}\": expected <width>x<height>");
+    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok(PhysicalSize::new(width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Runs the batch command with the arguments after "batch", reporting progress and failures.
+/// Returns the process exit code: nonzero if any file failed.
+pub fn main(args: &[String]) -> i32 {
+    let args = match parse_args(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return 2;
+        }
+    };
+    match run(&args) {
+        Ok(failures) => {
+            for (path, e) in &failures {
+                eprintln!("Failed {}: {e}", path.display());
+            }
+            if failures.is_empty() {
+                0
+            } else {
+                eprintln!("{} file(s) failed", failures.len());
+                1
+            }
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            1
+        }
+    }
+}
+
+/// Renders every `.flame` file in `args.input` to a PNG of the same name in `args.output`.
+/// A file which fails does not stop the rest: returns those which failed and why,
+/// or an error if rendering could not start at all.
+pub fn run(args: &Args) -> Result<Vec<(PathBuf, String)>, String> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(&args.input)
+        .map_err(|e| format!("Failed to read {}: {e}", args.input.display()))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("flame"))
+        })
+        .collect();
+    paths.sort();
+    fs::create_dir_all(&args.output)
+        .map_err(|e| format!("Failed to create {}: {e}", args.output.display()))?;
+
+    let (device, queue, float_filtering) = pollster::block_on(headless_device())?;
+    let mut db = DatabaseStruct::new(
+        Shared::new(device),
+        Shared::new(queue),
+        supersample::FORMAT,
+        args.size,
+        Settings::default(),
+    );
+    db.set_float_filtering_with_durability((), float_filtering, salsa::Durability::HIGH);
+    let mut failures = vec![];
+    for path in paths {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let out = args.output.join(format!("{name}.png"));
+        match render_file(&mut db, &path, &out, args.size) {
+            Ok(()) => println!("Saved {}", out.display()),
+            Err(e) => failures.push((path, e)),
+        }
+    }
+    Ok(failures)
+}
+
+fn render_file(
+    db: &mut DatabaseStruct,
+    path: &Path,
+    out: &Path,
+    size: PhysicalSize<u32>,
+) -> Result<(), String> {
+    let xml = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut settings = Settings::default();
+    let mut scene = settings.to_scene();
+    flam3::apply_flame(&xml, &mut scene)?;
+    settings.apply_scene(&scene)?;
+    db.set_settings(settings);
+    let image = wgpu_render::render_to_image(db, size).map_err(|e| e.to_string());
+    db.release_passes();
+    image?.save(out).map_err(|e| e.to_string())
+}
+
+/// Device and queue for rendering without a window, and if the device can filter R32Float textures.
+async fn headless_device() -> Result<(wgpu::Device, wgpu::Queue, bool), String> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = request_adapter(&instance, None)
+        .await
+        .ok_or("Failed to find a graphics adapter")?;
+    // Like the app, this depends on blending R32Float textures, and renders at reduced quality if it can not filter them.
+    let flags = adapter
+        .get_texture_format_features(wgpu::TextureFormat::R32Float)
+        .flags;
+    if !flags.contains(wgpu::TextureFormatFeatureFlags::BLENDABLE) {
+        return Err(format!(
+            "Blending R32Float textures is not supported by \"{}\"",
+            adapter.get_info().name
+        ));
+    }
+    let float_filtering = flags.contains(wgpu::TextureFormatFeatureFlags::FILTERABLE);
+    if !float_filtering {
+        eprintln!("Filtering R32Float textures is not supported: rendering at reduced quality");
+    }
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                    & adapter.features(),
+                limits: wgpu::Limits::default(),
+            },
+            None,
+        )
+        .await
+        .map_err(|e| format!("Failed to create device: {e}"))?;
+    Ok((device, queue, float_filtering))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::{parse_args, Args};
+    use winit::dpi::PhysicalSize;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(
+            parse_args(&args("--in flames --out pngs --size 640x480")),
+            Ok(Args {
+                input: "flames".into(),
+                output: "pngs".into(),
+                size: PhysicalSize::new(640, 480),
+            })
+        );
+        assert_eq!(
+            parse_args(&args("--out pngs --in flames")).map(|a| a.size),
+            Ok(PhysicalSize::new(1920, 1080))
+        );
+        for bad in [
+            "--in flames",
+            "--out pngs",
+            "--in flames --out pngs --size",
+            "--in flames --out pngs --size 640",
+            "--in flames --out pngs --size 0x480",
+            "--in flames --out pngs --size ax480",
+            "--in flames --out pngs --depth 3",
+        ] {
+            assert!(parse_args(&args(bad)).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! Additive bloom: the bright parts of the rendered flame are blurred and added back, so they glow.
+//! The blur is done at half resolution, so this is cheap enough to leave on while interacting.
+
+use std::mem;
+use wgpu::{
+    AddressMode, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutEntry, BindingResource,
+    BindingType, FilterMode, PipelineLayoutDescriptor, SamplerDescriptor, ShaderStages,
+    TextureDescriptor, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor,
+    TextureViewDimension,
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    mesh::{build_quad, Vertex},
+    render_common::{begin_render_pass, shader_module, MeshData},
+    util_types::PtrRc,
+    wgpu_render::Postprocesser,
+};
+
+/// Size of the uniform buffer holding `Bloom`'s parameters.
+const PARAMS_SIZE: u64 = 16;
+
+/// Taps on each side of the center of the blur (see `bloom.wgsl`).
+const BLUR_TAPS: f32 = 4.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bloom {
+    /// Luminance (0 to 1) above which pixels bloom.
+    pub threshold: f32,
+    /// Brightness of the glow added back.
+    pub intensity: f32,
+    /// How far the glow spreads, in pixels.
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom {
+            threshold: 0.6,
+            intensity: 0.8,
+            radius: 16.0,
+        }
+    }
+}
+
+impl Bloom {
+    /// Distance between blur taps in texels of the half resolution bloom textures, so the outermost tap is `radius` pixels away.
+    fn spacing(&self) -> f32 {
+        self.radius / 2.0 / BLUR_TAPS
+    }
+}
+
+/// Device and target format dependant, but otherwise constant data.
+#[derive(Debug)]
+pub struct Data {
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    params: wgpu::Buffer,
+    quad: MeshData,
+    threshold: wgpu::RenderPipeline,
+    blur_x: wgpu::RenderPipeline,
+    blur_y: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+}
+
+/// A texture and the bind group for sampling it.
+#[derive(Debug)]
+struct Texture {
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Textures the flame is rendered into then bloomed from.
+#[derive(Debug)]
+pub struct Target {
+    /// The flame, before bloom.
+    scene: Texture,
+    /// Half resolution bright areas, blurred in place (via `blurred`).
+    bright: Texture,
+    /// Half resolution, blurred in one direction.
+    blurred: Texture,
+    /// Size of `bright` and `blurred`.
+    half: PhysicalSize<u32>,
+}
+
+impl Target {
+    /// Where to draw the flame, which `render` then blooms.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.scene.view
+    }
+}
+
+pub fn data(db: &dyn Postprocesser, format: TextureFormat) -> PtrRc<Data> {
+    let device = db.device(());
+
+    let shader = shader_module(&device, "bloom.wgsl", include_str!("../shaders/bloom.wgsl"));
+
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+        label: Some("bloom texture"),
+    });
+
+    let single_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("bloom pipeline"),
+        bind_group_layouts: &[&layout],
+        push_constant_ranges: &[],
+    });
+    // The scene, then the bloom.
+    let composite_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("bloom composite pipeline"),
+        bind_group_layouts: &[&layout, &layout],
+        push_constant_ranges: &[],
+    });
+
+    let make_pipeline = |label, layout, entry_point| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: mem::size_of::<Vertex>() as u64,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    };
+
+    Data {
+        sampler: device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        }),
+        params: device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("bloom params"),
+            size: PARAMS_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }),
+        quad: MeshData::new(&device, &build_quad(), "Bloom Quad Vertex Buffer"),
+        threshold: make_pipeline("bloom threshold", &single_layout, "fs_threshold"),
+        blur_x: make_pipeline("bloom blur x", &single_layout, "fs_blur_x"),
+        blur_y: make_pipeline("bloom blur y", &single_layout, "fs_blur_y"),
+        composite: make_pipeline("bloom composite", &composite_layout, "fs_composite"),
+        layout,
+    }
+    .into()
+}
+
+/// Swapchain format textures for blooming a flame of `size`.
+pub fn target(db: &dyn Postprocesser, size: PhysicalSize<u32>) -> PtrRc<Target> {
+    let device = db.device(());
+    let data = db.bloom_data(*db.swapchain_format(()));
+    let texture = |size: PhysicalSize<u32>, label| {
+        let texture = device.create_texture(&TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: *db.swapchain_format(()),
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+            label: Some(label),
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &data.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&data.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: data.params.as_entire_binding(),
+                },
+            ],
+            label: None,
+        });
+        Texture { view, bind_group }
+    };
+    let half = PhysicalSize::new(u32::max(1, size.width / 2), u32::max(1, size.height / 2));
+    Target {
+        scene: texture(size, "bloom scene"),
+        bright: texture(half, "bloom bright"),
+        blurred: texture(half, "bloom blurred"),
+        half,
+    }
+    .into()
+}
+
+/// Draws the flame in `src` into `dst` with `bloom` added.
+pub fn render(
+    db: &dyn Postprocesser,
+    encoder: &mut wgpu::CommandEncoder,
+    src: &Target,
+    bloom: &Bloom,
+    dst: &wgpu::TextureView,
+) {
+    let data = db.bloom_data(*db.swapchain_format(()));
+    let mut params = [0u8; PARAMS_SIZE as usize];
+    params[..4].copy_from_slice(&bloom.threshold.to_le_bytes());
+    params[4..8].copy_from_slice(&bloom.intensity.to_le_bytes());
+    params[8..12].copy_from_slice(&bloom.spacing().to_le_bytes());
+    db.queue(()).write_buffer(&data.params, 0, &params);
+
+    // Each step's label, pipeline, inputs, target, and the size of the region of the target drawn into.
+    let steps: [(
+        &str,
+        &wgpu::RenderPipeline,
+        &[&Texture],
+        &wgpu::TextureView,
+        _,
+    ); 4] = [
+        (
+            "Bloom threshold",
+            &data.threshold,
+            &[&src.scene],
+            &src.bright.view,
+            src.half,
+        ),
+        (
+            "Bloom blur x",
+            &data.blur_x,
+            &[&src.bright],
+            &src.blurred.view,
+            src.half,
+        ),
+        (
+            "Bloom blur y",
+            &data.blur_y,
+            &[&src.blurred],
+            &src.bright.view,
+            src.half,
+        ),
+        (
+            "Bloom composite",
+            &data.composite,
+            &[&src.scene, &src.bright],
+            dst,
+            db.window_size(()),
+        ),
+    ];
+    for (label, pipeline, inputs, target, size) in steps {
+        let mut pass = begin_render_pass(
+            encoder,
+            &wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            },
+        );
+        pass.set_viewport(0.0, 0.0, size.width as f32, size.height as f32, 0.0, 1.0);
+        pass.set_pipeline(pipeline);
+        for (i, input) in inputs.iter().enumerate() {
+            pass.set_bind_group(i as u32, &input.bind_group, &[]);
+        }
+        pass.set_vertex_buffer(0, data.quad.buffer.slice(..));
+        pass.draw(0..(data.quad.count), 0..1);
+    }
+}
@@ -0,0 +1,91 @@
+//! Text commands for changing settings quickly (ex: during live demos).
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::ui::Settings;
+
+const HELP: &str = "n <count>, scale <s>, rot <degrees>, randomize <seed>";
+
+/// Largest `n` accepted by the `n` command.
+const MAX_N: usize = 64;
+
+/// Applies `line` to `settings`. Returns a description of the change, or why it failed (leaving `settings` unchanged).
+pub fn execute(settings: &mut Settings, line: &str) -> Result<String, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().ok_or_else(|| format!("Commands: {HELP}"))?;
+    let args: Vec<&str> = words.collect();
+    let arg = |name: &str| match args[..] {
+        [arg] => Ok(arg),
+        _ => Err(format!("Usage: {command} <{name}>")),
+    };
+    match command {
+        "help" => Ok(format!("Commands: {HELP}")),
+        "n" => {
+            let n: usize = parse(arg("count")?)?;
+            if !(1..=MAX_N).contains(&n) {
+                return Err(format!("n must be from 1 to {MAX_N}"));
+            }
+            settings.set_n(n);
+            Ok(format!("n = {n}"))
+        }
+        "scale" => {
+            let scale: f64 = parse(arg("s")?)?;
+            if !(-1.0 < scale && scale < 1.0) {
+                return Err("scale must be between -1 and 1".to_owned());
+            }
+            settings.set_polygon_scale(scale);
+            Ok(format!("scale = {scale}"))
+        }
+        "rot" => {
+            let degrees: f32 = parse(arg("degrees")?)?;
+            if !degrees.is_finite() {
+                return Err("rot must be finite".to_owned());
+            }
+            settings.set_rotation(degrees.to_radians());
+            Ok(format!("rot = {degrees}°"))
+        }
+        "randomize" => {
+            let seed: u64 = parse(arg("seed")?)?;
+            settings.randomize(seed);
+            Ok(format!("Randomized with seed {seed}"))
+        }
+        _ => Err(format!("Unknown command \"{command}\". Commands: {HELP}")),
+    }
+}
+
+fn parse<T: FromStr>(s: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    s.parse().map_err(|e| format!("Invalid value \"{s}\": {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{commands::execute, ui::Settings};
+
+    #[test]
+    fn commands() {
+        let mut settings = Settings::default();
+        assert_eq!(execute(&mut settings, "n 7"), Ok("n = 7".to_owned()));
+        assert_eq!(settings.n, 7);
+        assert!(execute(&mut settings, "scale 0.45").is_ok());
+        assert!(execute(&mut settings, "rot 30").is_ok());
+        assert!(execute(&mut settings, "randomize 12345").is_ok());
+        assert_eq!(settings.get_state().transforms().len(), 7);
+
+        let mut randomized = Settings::default();
+        execute(&mut randomized, "n 7").unwrap();
+        execute(&mut randomized, "randomize 12345").unwrap();
+        assert_eq!(randomized.get_state(), settings.get_state());
+    }
+
+    #[test]
+    fn invalid_commands() {
+        let mut settings = Settings::default();
+        for line in ["", "n", "n 0", "n x", "n 1 2", "scale 2", "rot inf", "jump"] {
+            assert!(execute(&mut settings, line).is_err(), "{line}");
+        }
+        assert_eq!(settings, Settings::default());
+    }
+}
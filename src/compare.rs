@@ -0,0 +1,291 @@
+//! Per pixel difference between the rendered flame and a reference image, for matching a target.
+
+use std::mem;
+use wgpu::{
+    AddressMode, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutEntry, BindingResource,
+    BindingType, FilterMode, PipelineLayoutDescriptor, SamplerDescriptor, ShaderStages,
+    TextureAspect, TextureDescriptor, TextureFormat, TextureSampleType, TextureUsages,
+    TextureViewDescriptor, TextureViewDimension,
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    mesh::{build_quad, Vertex},
+    render_common::{begin_render_pass, shader_module, MeshData},
+    util_types::PtrRc,
+    wgpu_render::Postprocesser,
+};
+
+/// Size of the uniform buffer holding the blend factor.
+const PARAMS_SIZE: u64 = 16;
+
+/// Device and target format dependant, but otherwise constant data.
+#[derive(Debug)]
+pub struct Data {
+    texture_layout: wgpu::BindGroupLayout,
+    reference_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    params: wgpu::Buffer,
+    quad: MeshData,
+    pipeline: wgpu::RenderPipeline,
+}
+
+/// Texture the flame is rendered into, so it can be compared.
+#[derive(Debug)]
+pub struct Target {
+    pub view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+#[derive(Debug)]
+pub struct Reference {
+    bind_group: wgpu::BindGroup,
+}
+
+pub fn data(db: &dyn Postprocesser, format: TextureFormat) -> PtrRc<Data> {
+    let device = db.device(());
+
+    let shader = shader_module(
+        &device,
+        "compare.wgsl",
+        include_str!("../shaders/compare.wgsl"),
+    );
+
+    let texture_entry = |binding| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Texture {
+            multisampled: false,
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+        },
+        count: None,
+    };
+
+    let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            texture_entry(0),
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+        label: Some("compare flame"),
+    });
+
+    let reference_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            texture_entry(0),
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+        label: Some("compare reference"),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("compare pipeline"),
+        bind_group_layouts: &[&texture_layout, &reference_layout],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("compare"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: mem::size_of::<Vertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2],
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    Data {
+        texture_layout,
+        reference_layout,
+        sampler: device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        }),
+        params: device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("compare params"),
+            size: PARAMS_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }),
+        quad: MeshData::new(&device, &build_quad(), "Compare Quad Vertex Buffer"),
+        pipeline,
+    }
+    .into()
+}
+
+/// A swapchain format texture the size of the flame.
+pub fn target(db: &dyn Postprocesser, size: PhysicalSize<u32>) -> PtrRc<Target> {
+    let device = db.device(());
+    let data = db.compare_data(*db.swapchain_format(()));
+    let texture = device.create_texture(&TextureDescriptor {
+        size: wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: *db.swapchain_format(()),
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+        label: Some("compare target"),
+        view_formats: &[],
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        layout: &data.texture_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&data.sampler),
+            },
+        ],
+        label: None,
+    });
+    Target { view, bind_group }.into()
+}
+
+/// Loads the reference image at `path`. Returns None (and logs) on failure.
+pub fn load_reference(db: &dyn Postprocesser, path: &str) -> Option<PtrRc<Reference>> {
+    let image = match image::open(path) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => {
+            log::warn!("Failed to load reference image {path}: {e}");
+            return None;
+        }
+    };
+
+    let device = db.device(());
+    let data = db.compare_data(*db.swapchain_format(()));
+
+    let texture_size = wgpu::Extent3d {
+        width: image.width(),
+        height: image.height(),
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&TextureDescriptor {
+        size: texture_size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TextureFormat::Rgba8UnormSrgb,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        label: Some("reference_texture"),
+        view_formats: &[],
+    });
+    db.queue(()).write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        &image,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * image.width()),
+            rows_per_image: Some(image.height()),
+        },
+        texture_size,
+    );
+    let view = texture.create_view(&TextureViewDescriptor::default());
+
+    Some(
+        Reference {
+            bind_group: device.create_bind_group(&BindGroupDescriptor {
+                layout: &data.reference_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: data.params.as_entire_binding(),
+                    },
+                ],
+                label: None,
+            }),
+        }
+        .into(),
+    )
+}
+
+/// Draws the flame in `src` into dst, blended with its difference from `reference` (stretched to fit).
+/// `blend` of 0 shows just the flame, and 1 just the difference.
+pub fn render(
+    db: &dyn Postprocesser,
+    encoder: &mut wgpu::CommandEncoder,
+    src: &Target,
+    reference: &Reference,
+    dst: &wgpu::TextureView,
+    blend: f32,
+) {
+    let data = db.compare_data(*db.swapchain_format(()));
+    let mut params = [0u8; PARAMS_SIZE as usize];
+    params[..4].copy_from_slice(&blend.to_le_bytes());
+    db.queue(()).write_buffer(&data.params, 0, &params);
+
+    let size = db.window_size(());
+    let mut pass = begin_render_pass(
+        encoder,
+        &wgpu::RenderPassDescriptor {
+            label: Some("Compare render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: dst,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        },
+    );
+    pass.set_viewport(0.0, 0.0, size.width as f32, size.height as f32, 0.0, 1.0);
+    pass.set_pipeline(&data.pipeline);
+    pass.set_bind_group(0, &src.bind_group, &[]);
+    pass.set_bind_group(1, &reference.bind_group, &[]);
+    pass.set_vertex_buffer(0, data.quad.buffer.slice(..));
+    pass.draw(0..(data.quad.count), 0..1);
+}
@@ -0,0 +1,553 @@
+//! CPU rendering path which accumulates density in f64.
+//! Much slower than the GPU path, but avoids the precision loss of R32Float accumulation, so useful for prints.
+
+use na::{Affine2, Point2};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    accumulate::{self, Accumulator},
+    flame::{expansion_factor, Root, State},
+    geometry::{self, OrientedRect, Rect},
+    histogram::{contrast_curve, highlight_rolloff, lookup, tone_curve, LOG_RANGE},
+    orbit_trap, postprocess,
+    rng::Rng,
+    wgpu_render::{DatabaseStruct, Inputs, Postprocesser, Renderer},
+};
+
+/// Upper limit on the number of quads drawn, to bound the run time.
+const MAX_LEAVES: u64 = 1 << 26;
+
+/// Chaos game points per pixel when coloring with an orbit trap.
+const TRAP_SAMPLES_PER_PIXEL: usize = 64;
+
+/// Density for each pixel (top row first) of the flame's leaf quads.
+/// `view` maps flame space to pixels, with y up.
+/// Transforms are applied until the quads are at most a pixel across (or there would be more than `max_leaves`).
+/// Quads smaller than a pixel deposit their area into the pixel containing their center,
+/// and larger ones add their opacity to every pixel whose center they cover (like the GPU path).
+pub fn accumulate(
+    root: &Root,
+    quad: &OrientedRect,
+    view: &Affine2<f64>,
+    size: PhysicalSize<u32>,
+    max_leaves: u64,
+) -> Vec<f64> {
+    let (width, height) = (size.width as usize, size.height as usize);
+    let mut density = vec![0.0; width * height];
+    if root.transforms().is_empty() {
+        return density;
+    }
+
+    let quad_size = {
+        let b = quad.transformed_aabb(view);
+        f64::max(b.width(), b.height())
+    };
+    let n = root.transforms().len() as u64;
+    let stretch = root
+        .transforms()
+        .iter()
+        .map(expansion_factor)
+        .fold(0.0, f64::max);
+    let mut levels = 0;
+    let mut leaves = 1u64;
+    // A single transform never adds leaves, so is only limited by shrinking (which must terminate).
+    while quad_size * stretch.powi(levels as i32) > 1.0
+        && leaves * n <= max_leaves
+        && (n > 1 || stretch < 1.0)
+    {
+        levels += 1;
+        leaves *= n;
+    }
+
+    let mut deposit = |x: f64, y: f64, amount: f64| {
+        if x >= 0.0 && y >= 0.0 && (x as usize) < width && (y as usize) < height {
+            density[(height - 1 - y as usize) * width + x as usize] += amount;
+        }
+    };
+
+    root.get_state().process_levels(levels, &mut |s| {
+        let m = view * s.mat;
+        let corners = quad.corners().map(|p| m * p);
+        let b = Rect {
+            min: corners[0],
+            max: corners[0],
+        };
+        let b = corners.iter().fold(b, |b, p| Rect {
+            min: Point2::new(f64::min(b.min.x, p.x), f64::min(b.min.y, p.y)),
+            max: Point2::new(f64::max(b.max.x, p.x), f64::max(b.max.y, p.y)),
+        });
+        if f64::max(b.width(), b.height()) <= 1.0 {
+            let center = corners
+                .iter()
+                .fold(Point2::origin(), |c, p| c + p.coords / 4.0);
+            deposit(center.x, center.y, s.opacity * area(&corners));
+        } else {
+            for y in (b.min.y.floor() as i64).max(0)..=(b.max.y.ceil() as i64).min(height as i64) {
+                for x in (b.min.x.floor() as i64).max(0)..=(b.max.x.ceil() as i64).min(width as i64)
+                {
+                    let p = Point2::new(x as f64 + 0.5, y as f64 + 0.5);
+                    if contains(&corners, p) {
+                        deposit(p.x, p.y, s.opacity);
+                    }
+                }
+            }
+        }
+    });
+    density
+}
+
+fn cross(o: Point2<f64>, a: Point2<f64>, b: Point2<f64>) -> f64 {
+    (a - o).perp(&(b - o))
+}
+
+/// Area of a convex polygon.
+fn area(corners: &[Point2<f64>; 4]) -> f64 {
+    (cross(corners[0], corners[1], corners[2]) + cross(corners[0], corners[2], corners[3])).abs()
+        / 2.0
+}
+
+/// If `p` is inside the convex polygon (of either winding).
+fn contains(corners: &[Point2<f64>; 4], p: Point2<f64>) -> bool {
+    let sides = [0, 1, 2, 3].map(|i| cross(corners[i], corners[(i + 1) % 4], p));
+    sides.iter().all(|s| *s >= 0.0) || sides.iter().all(|s| *s <= 0.0)
+}
+
+/// Renders the current flame into an image of the given size, using the same tone mapping and palette as the GPU path.
+/// Density is always accumulated linearly: `Accumulation::Log` only applies to the GPU path.
+/// If an orbit trap is selected, the chaos game is used instead of quads, and the trap picks the color.
+pub fn render_image(db: &DatabaseStruct, size: PhysicalSize<u32>) -> image::RgbaImage {
+    let config = db.config(());
+    let root = db.root(());
+    let bounds = db.bounds(());
+    let output_bounds = accumulate::output_bounds(db);
+    let output_bounds = config
+        .crop
+        .map_or(output_bounds, |crop| output_bounds.sub_rect(&crop));
+    let full = Rect {
+        min: Point2::origin(),
+        max: Point2::new(size.width as f64, size.height as f64),
+    };
+    let view = geometry::letter_box_pixel_aspect(full, output_bounds, config.pixel_aspect)
+        * accumulate::output_transform(db);
+    // Orbit traps color by the trap instead of the density, which is then only used for brightness.
+    let (density, traps) = match config.orbit_trap {
+        Some(trap) => {
+            // Fixed seed, so exports are reproducible.
+            let mut rng = Rng::new(0);
+            // Trap distances are measured in the flame's space, before the final transform.
+            let (density, traps) = orbit_trap::accumulate(
+                &root,
+                trap,
+                &bounds.aabb(),
+                &view,
+                size,
+                TRAP_SAMPLES_PER_PIXEL * (size.width * size.height) as usize,
+                &mut || rng.next_f64(),
+            );
+            let density: Vec<f64> = density
+                .into_iter()
+                .map(|d| d / TRAP_SAMPLES_PER_PIXEL as f64)
+                .collect();
+            (density, Some(traps))
+        }
+        None => (accumulate(&root, &bounds, &view, size, MAX_LEAVES), None),
+    };
+
+    let curve = tone_curve(
+        config.tone_map,
+        config.auto_exposure,
+        density.iter().map(|v| *v as f32),
+    );
+    let contrast = contrast_curve(&config.tone_curve);
+    let palette = db.palette_image(config.palette.clone());
+    let row = postprocess::palette_row(&palette, config.palette_color);
+
+    let mut image = image::RgbaImage::new(size.width, size.height);
+    for (i, (pixel, v)) in image.pixels_mut().zip(&density).enumerate() {
+        if *v <= 0.0 {
+            continue;
+        }
+        let x = (v.log2() as f32 / LOG_RANGE).clamp(0.0, 1.0);
+        let t = highlight_rolloff(
+            lookup(&contrast, lookup(&curve, x)),
+            config.highlight_rolloff,
+        );
+        let column = |t: f32| ((t * palette.width() as f32) as u32).min(palette.width() - 1);
+        *pixel = match &traps {
+            None => {
+                let [r, g, b, _] = palette.get_pixel(column(t), row).0;
+                image::Rgba([r, g, b, 255])
+            }
+            Some(traps) => {
+                // Average trap value of the pixel's points (density is normalized, so this uses the raw count).
+                let trap = (traps[i] / (v * TRAP_SAMPLES_PER_PIXEL as f64)) as f32;
+                let [r, g, b, _] = palette.get_pixel(column(trap.clamp(0.0, 1.0)), row).0;
+                let shade = |c: u8| (f32::from(c) * t).round() as u8;
+                image::Rgba([shade(r), shade(g), shade(b), 255])
+            }
+        };
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu_raster::accumulate,
+        flame::{BoundedState, Root},
+        geometry::{box_to_box_oriented, letter_box, OrientedRect, Rect},
+        histogram::{exposure_curve, highlight_rolloff, lookup, LOG_RANGE},
+    };
+    use na::{Affine2, Matrix3, Point2};
+    use winit::dpi::PhysicalSize;
+
+    /// Four half scale copies of the unit square, which evenly fill it.
+    fn square() -> Root {
+        Root::new(
+            [(0.0, 0.0), (0.5, 0.0), (0.0, 0.5), (0.5, 0.5)]
+                .map(|(x, y)| {
+                    Affine2::from_matrix_unchecked(Matrix3::new(
+                        0.5, 0.0, x, 0.0, 0.5, y, 0.0, 0.0, 1.0,
+                    ))
+                })
+                .to_vec(),
+        )
+    }
+
+    /// Three half scale copies of the unit square (a Sierpinski triangle).
+    fn sierpinski() -> Root {
+        Root::new(
+            [(0.0, 0.0), (0.5, 0.0), (0.25, 0.5)]
+                .map(|(x, y)| {
+                    Affine2::from_matrix_unchecked(Matrix3::new(
+                        0.5, 0.0, x, 0.0, 0.5, y, 0.0, 0.0, 1.0,
+                    ))
+                })
+                .to_vec(),
+        )
+    }
+
+    /// A dense spiral: a high contrast flame, whose density winds into its center.
+    fn spiral() -> Root {
+        let (scale, angle) = (0.9f64, 0.35f64);
+        Root::new(vec![
+            // Most of the density winds into the center.
+            Affine2::from_matrix_unchecked(Matrix3::new(
+                scale * angle.cos(),
+                -scale * angle.sin(),
+                0.0,
+                scale * angle.sin(),
+                scale * angle.cos(),
+                0.0,
+                0.0,
+                0.0,
+                1.0,
+            )),
+            Affine2::from_matrix_unchecked(Matrix3::new(
+                0.15, 0.0, 1.0, 0.0, 0.15, 0.0, 0.0, 0.0, 1.0,
+            )),
+        ])
+    }
+
+    /// Models a textured accumulation pass on the CPU: a smaller pass (with a different aspect ratio than the bounds,
+    /// as rounding can produce) is sampled by one level of quads, using texture coordinates spanning the bounds.
+    /// The total density should scale by the sum of the area scale factors (0.75 here).
+    #[test]
+    fn recursion_conserves_density() {
+        let root = sierpinski();
+        let unit = Rect {
+            min: Point2::new(0.0, 0.0),
+            max: Point2::new(1.0, 1.0),
+        };
+        let quad = OrientedRect::axis_aligned(unit);
+        let small = PhysicalSize::new(96, 64);
+        let small_view = box_to_box_oriented(
+            Rect {
+                min: Point2::origin(),
+                max: Point2::new(small.width as f64, small.height as f64),
+            },
+            quad,
+        );
+        let smaller = accumulate(&root, &quad, &small_view, small, 1 << 20);
+        // Density is per unit area, so the integral is the sum times the area of a pixel in flame space.
+        let pixels_per_area = small_view.matrix().fixed_view::<2, 2>(0, 0).determinant();
+        let smaller_total: f64 = smaller.iter().sum::<f64>() / pixels_per_area;
+
+        // Each copy of the bounds is 192 pixels across, so every smaller pass pixel gets the same number of samples.
+        let size = 384;
+        let view = letter_box(
+            Rect {
+                min: Point2::origin(),
+                max: Point2::new(size as f64, size as f64),
+            },
+            unit,
+        );
+        let to_flame = view.inverse();
+        let mut total = 0.0;
+        for y in 0..size {
+            for x in 0..size {
+                let p = to_flame * Point2::new(x as f64 + 0.5, y as f64 + 0.5);
+                for t in root.transforms() {
+                    let uv = t.inverse() * p;
+                    if unit.contains_point(uv) && uv.x < 1.0 && uv.y < 1.0 {
+                        let sx = (uv.x * small.width as f64) as usize;
+                        let sy = (uv.y * small.height as f64) as usize;
+                        total +=
+                            smaller[(small.height as usize - 1 - sy) * small.width as usize + sx];
+                    }
+                }
+            }
+        }
+        total /= (size * size) as f64;
+        assert!(
+            (total / smaller_total - 0.75).abs() < 1e-3,
+            "{total} {smaller_total}"
+        );
+    }
+
+    /// Models a textured accumulation pass on the CPU: `smaller` (`small` pixels square, holding `quad`),
+    /// bilinearly sampled (like `accumulation_sampler`) by one level of quads, into `size` pixels square through `view`.
+    /// Each sample is mapped by `contribution` before being summed: the identity for `Accumulation::Linear`.
+    fn textured_pass(
+        root: &Root,
+        quad: OrientedRect,
+        view: &Affine2<f64>,
+        size: u32,
+        smaller: &[f64],
+        small: u32,
+        contribution: impl Fn(f64) -> f64,
+    ) -> Vec<f64> {
+        // Clamps to the edge, and flips to y up.
+        let texel = |x: i64, y: i64| {
+            let last = i64::from(small) - 1;
+            let (x, y) = (x.clamp(0, last), last - y.clamp(0, last));
+            smaller[(y * i64::from(small) + x) as usize]
+        };
+        let sample = |uv: Point2<f64>| {
+            let p = uv * f64::from(small) - na::Vector2::new(0.5, 0.5);
+            let (x, y) = (p.x.floor(), p.y.floor());
+            let (fx, fy) = (p.x - x, p.y - y);
+            let (x, y) = (x as i64, y as i64);
+            (texel(x, y) * (1.0 - fx) + texel(x + 1, y) * fx) * (1.0 - fy)
+                + (texel(x, y + 1) * (1.0 - fx) + texel(x + 1, y + 1) * fx) * fy
+        };
+        let unit = Rect {
+            min: Point2::new(0.0, 0.0),
+            max: Point2::new(1.0, 1.0),
+        };
+        let to_uv = box_to_box_oriented(unit, quad);
+        let to_flame = view.inverse();
+        let mut density = vec![0.0; (size * size) as usize];
+        for y in 0..size {
+            for x in 0..size {
+                let p = to_flame * Point2::new(f64::from(x) + 0.5, f64::from(y) + 0.5);
+                for t in root.transforms() {
+                    let uv = to_uv * (t.inverse() * p);
+                    if unit.contains_point(uv) && uv.x < 1.0 && uv.y < 1.0 {
+                        density[((size - 1 - y) * size + x) as usize] += contribution(sample(uv));
+                    }
+                }
+            }
+        }
+        density
+    }
+
+    /// Models a whole textured pass on the CPU: a smaller pass holding all but the last level,
+    /// bilinearly sampled (like `accumulation_sampler`) by one level of quads,
+    /// should match the density field of a single deep pass which draws every level as quads.
+    /// The smaller pass is the size of a copy of the bounds, or rounded up (as the planner does).
+    #[test]
+    fn recursion_matches_deep_pass() {
+        let root = sierpinski();
+        let unit = Rect {
+            min: Point2::new(0.0, 0.0),
+            max: Point2::new(1.0, 1.0),
+        };
+        let quad = OrientedRect::axis_aligned(unit);
+        let pixels = |size: u32| Rect {
+            min: Point2::origin(),
+            max: Point2::new(f64::from(size), f64::from(size)),
+        };
+        for (size, small) in [(96, 48), (120, 64)] {
+            // Both pick their levels so the leaves are at most a pixel: 7 for the deep pass, and 6 for the smaller one.
+            let view = letter_box(pixels(size), unit);
+            let deep = accumulate(&root, &quad, &view, PhysicalSize::new(size, size), 1 << 20);
+            let smaller = accumulate(
+                &root,
+                &quad,
+                &box_to_box_oriented(pixels(small), quad),
+                PhysicalSize::new(small, small),
+                1 << 20,
+            );
+            let textured = textured_pass(&root, quad, &view, size, &smaller, small, |v| v);
+
+            // Individual pixels alias differently, so compare the average density of blocks of pixels.
+            let block = size as usize / 8;
+            let blocks = |density: &[f64]| -> Vec<f64> {
+                (0..64)
+                    .map(|b| {
+                        let (bx, by) = (b % 8 * block, b / 8 * block);
+                        (by..by + block)
+                            .flat_map(|y| (bx..bx + block).map(move |x| (x, y)))
+                            .map(|(x, y)| density[y * size as usize + x])
+                            .sum::<f64>()
+                            / (block * block) as f64
+                    })
+                    .collect()
+            };
+            let (expected, actual) = (blocks(&deep), blocks(&textured));
+            let max = expected.iter().copied().fold(0.0, f64::max);
+            for (e, a) in expected.iter().zip(&actual) {
+                assert!((e - a).abs() < 0.02 * max, "{size}: {e} vs {a}");
+            }
+            let (deep_total, textured_total) =
+                (deep.iter().sum::<f64>(), textured.iter().sum::<f64>());
+            assert!(
+                (textured_total / deep_total - 1.0).abs() < 0.01,
+                "{size}: {deep_total} vs {textured_total}"
+            );
+        }
+    }
+
+    #[test]
+    fn few_transforms() {
+        let quad = OrientedRect::axis_aligned(Rect {
+            min: Point2::new(0.0, 0.0),
+            max: Point2::new(1.0, 1.0),
+        });
+        let view = Affine2::from_matrix_unchecked(Matrix3::new(
+            16.0, 0.0, 0.0, 0.0, 16.0, 0.0, 0.0, 0.0, 1.0,
+        ));
+        let size = PhysicalSize::new(16, 16);
+        let empty = accumulate(&Root::new(vec![]), &quad, &view, size, 1 << 20);
+        assert!(empty.iter().all(|v| *v == 0.0));
+
+        // A single transform shrinks the quad toward its fixed point (the origin), which gets all the density.
+        let single = Root::new(vec![Affine2::from_matrix_unchecked(Matrix3::new(
+            0.5, 0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 1.0,
+        ))]);
+        let density = accumulate(&single, &quad, &view, size, 1 << 20);
+        assert_eq!(density.iter().filter(|v| **v > 0.0).count(), 1);
+    }
+
+    #[test]
+    fn uniform_density() {
+        let quad = OrientedRect::axis_aligned(Rect {
+            min: Point2::new(0.0, 0.0),
+            max: Point2::new(1.0, 1.0),
+        });
+        let view = Affine2::from_matrix_unchecked(Matrix3::new(
+            16.0, 0.0, 0.0, 0.0, 16.0, 0.0, 0.0, 0.0, 1.0,
+        ));
+        let size = PhysicalSize::new(16, 16);
+        // Pixel sized quads (area deposits), and 4 pixel wide quads (coverage).
+        for max_leaves in [1 << 20, 16] {
+            let density = accumulate(&square(), &quad, &view, size, max_leaves);
+            assert_eq!(density.len(), 256);
+            for v in density {
+                assert!((v - 1.0).abs() < 1e-9, "{max_leaves}: {v}");
+            }
+        }
+    }
+
+    /// On a dense spiral, auto exposure's hard mapping takes the densest pixels to the end of the gradient
+    /// (white, with the default palette), while highlight rolloff keeps them below it, still in order.
+    #[test]
+    fn highlight_rolloff_on_spiral() {
+        let root = spiral();
+        let bounds = root.get_state().get_bounds(6);
+        let size = PhysicalSize::new(64, 64);
+        let view = letter_box(
+            Rect {
+                min: Point2::origin(),
+                max: Point2::new(64.0, 64.0),
+            },
+            bounds,
+        );
+        let density = accumulate(
+            &root,
+            &OrientedRect::axis_aligned(bounds),
+            &view,
+            size,
+            1 << 16,
+        );
+        let curve = exposure_curve(density.iter().map(|v| *v as f32), 0.0);
+        let mut hard: Vec<f32> = density
+            .iter()
+            .filter(|v| **v > 0.0)
+            .map(|v| lookup(&curve, (v.log2() as f32 / LOG_RANGE).clamp(0.0, 1.0)))
+            .collect();
+        hard.sort_by(f32::total_cmp);
+        assert_eq!(hard.last(), Some(&1.0));
+
+        let rolloff = 0.25;
+        let soft: Vec<f32> = hard
+            .iter()
+            .map(|t| highlight_rolloff(*t, rolloff))
+            .collect();
+        assert!(soft.iter().all(|t| *t <= 1.0 - rolloff / 2.0 + 1e-6));
+        for (h, s) in hard.iter().zip(&soft) {
+            if *h <= 1.0 - rolloff {
+                assert_eq!(h, s);
+            }
+        }
+        for (h, s) in hard.windows(2).zip(soft.windows(2)) {
+            assert_eq!(h[0] < h[1], s[0] < s[1]);
+        }
+    }
+
+    /// Compares `Accumulation::Log` (modeled as the output pass sampling a linear smaller pass) against linear accumulation,
+    /// which the postprocess log tone map then maps by `log2(density)`, on a high contrast flame.
+    /// Both order the pixels the same (so the tone map still reads the core as densest), but the log pass stores
+    /// a much narrower range, which leaves room in the core for detail instead of saturating the gradient's end.
+    #[test]
+    fn log_accumulation_compresses_core() {
+        let root = spiral();
+        let bounds = root.get_state().get_bounds(6);
+        let quad = OrientedRect::axis_aligned(bounds);
+        let (size, small) = (64, 64);
+        let pixels = |size: u32| Rect {
+            min: Point2::origin(),
+            max: Point2::new(f64::from(size), f64::from(size)),
+        };
+        // Scaled to about a thousand quads per pixel at the core, like a deep GPU pass.
+        let smaller: Vec<f64> = accumulate(
+            &root,
+            &quad,
+            &box_to_box_oriented(pixels(small), quad),
+            PhysicalSize::new(small, small),
+            1 << 16,
+        )
+        .iter()
+        .map(|v| v * 1024.0)
+        .collect();
+        let view = letter_box(pixels(size), bounds);
+        let linear = textured_pass(&root, quad, &view, size, &smaller, small, |v| v);
+        let log = textured_pass(&root, quad, &view, size, &smaller, small, |v| {
+            (1.0 + v).log2()
+        });
+
+        let covered: Vec<usize> = (0..linear.len()).filter(|i| linear[*i] > 0.0).collect();
+        assert!(covered.iter().all(|i| log[*i] > 0.0));
+        for a in &covered {
+            for b in &covered {
+                if linear[*a] < linear[*b] {
+                    assert!(log[*a] <= log[*b], "{a} {b}");
+                }
+            }
+        }
+
+        // Stops from the faint edges (1st percentile) to the core (99.9th percentile).
+        let stops = |density: &[f64]| {
+            let mut v: Vec<f64> = covered.iter().map(|i| density[*i]).collect();
+            v.sort_by(f64::total_cmp);
+            let at = |p: f64| v[((v.len() - 1) as f64 * p) as usize];
+            (at(0.999) / at(0.01)).log2()
+        };
+        let (linear_stops, log_stops) = (stops(&linear), stops(&log));
+        assert!(
+            log_stops < linear_stops / 2.0,
+            "{log_stops} vs {linear_stops}"
+        );
+    }
+}
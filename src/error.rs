@@ -0,0 +1,51 @@
+//! Errors from setting up the renderer and rendering images.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum RenderError {
+    Window(winit::error::OsError),
+    CreateSurface(wgpu::CreateSurfaceError),
+    /// No adapter could render to the window.
+    NoAdapter,
+    RequestDevice(wgpu::RequestDeviceError),
+    /// Reading back a rendered image failed.
+    BufferMap,
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Window(e) => write!(f, "Failed to create window: {e}"),
+            RenderError::CreateSurface(e) => write!(f, "Failed to create surface: {e}"),
+            RenderError::NoAdapter => {
+                write!(
+                    f,
+                    "Failed to find any adapter which can render to the window"
+                )
+            }
+            RenderError::RequestDevice(e) => write!(f, "Failed to create device: {e}"),
+            RenderError::BufferMap => write!(f, "Failed to map readback buffer"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<winit::error::OsError> for RenderError {
+    fn from(e: winit::error::OsError) -> Self {
+        RenderError::Window(e)
+    }
+}
+
+impl From<wgpu::CreateSurfaceError> for RenderError {
+    fn from(e: wgpu::CreateSurfaceError) -> Self {
+        RenderError::CreateSurface(e)
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for RenderError {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        RenderError::RequestDevice(e)
+    }
+}
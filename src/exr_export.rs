@@ -0,0 +1,20 @@
+//! Saves raw density for tone mapping in other tools.
+
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, WritableImage,
+};
+use winit::dpi::PhysicalSize;
+
+/// Writes `density` (rows from the top, as from `wgpu_render::render_density`) as a single channel 32 bit float OpenEXR file.
+pub fn save(path: &str, size: PhysicalSize<u32>, density: Vec<f32>) -> Result<(), String> {
+    let layer = Layer::new(
+        (size.width as usize, size.height as usize),
+        LayerAttributes::named("density"),
+        Encoding::SMALL_LOSSLESS,
+        AnyChannels::sort(vec![AnyChannel::new("Y", FlatSamples::F32(density))].into()),
+    );
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(|e| format!("{path}: {e}"))
+}
@@ -0,0 +1,181 @@
+//! Accumulation across frames, fading the previous density to leave trails as the flame changes.
+
+use std::mem;
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindingResource, FilterMode, PipelineLayoutDescriptor,
+    SamplerDescriptor, TextureDescriptor, TextureFormat, TextureUsages, TextureViewDescriptor,
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    mesh::{build_quad, Vertex},
+    render_common::{begin_render_pass, shader_module, MeshData},
+    util_types::PtrRc,
+    wgpu_render::Postprocesser,
+};
+
+/// Device dependant, but otherwise constant data.
+#[derive(Debug)]
+pub struct Data {
+    sampler: wgpu::Sampler,
+    quad: MeshData,
+    pipeline: wgpu::RenderPipeline,
+}
+
+/// Density persisted between frames.
+/// Unlike accumulation passes, this is only keyed on size, so it survives changes to the flame.
+#[derive(Debug)]
+pub struct Trails {
+    pub texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    /// For reading the trails, in the same layout as accumulation pass outputs.
+    pub bind_group: wgpu::BindGroup,
+}
+
+pub fn data(db: &dyn Postprocesser, (): ()) -> PtrRc<Data> {
+    let device = db.device(());
+    let accumulate_data = db.data(());
+
+    let shader = shader_module(
+        &device,
+        "feedback.wgsl",
+        include_str!("../shaders/feedback.wgsl"),
+    );
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("feedback pipeline"),
+        bind_group_layouts: &[&accumulate_data.accumulation_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    // new + decay * old, with decay as the blend constant.
+    let blend_fade = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::Constant,
+        operation: wgpu::BlendOperation::Add,
+    };
+
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("feedback"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: mem::size_of::<Vertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2],
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: TextureFormat::R32Float,
+                blend: Some(wgpu::BlendState {
+                    color: blend_fade,
+                    alpha: blend_fade,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    Data {
+        // Source and destination are the same size, so no filtering is needed.
+        sampler: device.create_sampler(&SamplerDescriptor {
+            label: Some("feedback sampler"),
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            ..Default::default()
+        }),
+        quad: MeshData::new(&device, &build_quad(), "Feedback Quad Vertex Buffer"),
+        pipeline,
+    }
+    .into()
+}
+
+pub fn trails(db: &dyn Postprocesser, size: PhysicalSize<u32>) -> PtrRc<Trails> {
+    let device = db.device(());
+    let texture = device.create_texture(&TextureDescriptor {
+        size: wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TextureFormat::R32Float,
+        usage: TextureUsages::TEXTURE_BINDING
+            | TextureUsages::RENDER_ATTACHMENT
+            | TextureUsages::COPY_SRC,
+        label: Some("trails texture"),
+        view_formats: &[],
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        layout: &db.data(()).accumulation_bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&db.feedback_data(()).sampler),
+            },
+        ],
+        label: None,
+    });
+    Trails {
+        texture,
+        view,
+        bind_group,
+    }
+    .into()
+}
+
+/// Fades `trails` by `decay`, then adds the density from `src` (an accumulation pass output the same size).
+/// Blending does the fade, so the trails texture is never read and written at once.
+pub fn render(
+    db: &dyn Postprocesser,
+    encoder: &mut wgpu::CommandEncoder,
+    src: &wgpu::BindGroup,
+    trails: &Trails,
+    decay: f32,
+) {
+    let data = db.feedback_data(());
+    let mut pass = begin_render_pass(
+        encoder,
+        &wgpu::RenderPassDescriptor {
+            label: Some("Feedback render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &trails.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        },
+    );
+    let decay = f64::from(decay);
+    pass.set_blend_constant(wgpu::Color {
+        r: decay,
+        g: decay,
+        b: decay,
+        a: decay,
+    });
+    pass.set_pipeline(&data.pipeline);
+    pass.set_bind_group(0, src, &[]);
+    pass.set_vertex_buffer(0, data.quad.buffer.slice(..));
+    pass.draw(0..(data.quad.count), 0..1);
+}
@@ -1,4 +1,4 @@
-use std::mem;
+use core::mem;
 
 pub fn iterate<V: PartialEq, F: Fn(&V) -> V>(initial: V, f: F) -> V {
     let mut v: V = initial;
@@ -0,0 +1,179 @@
+//! Reading data from flam3 `.flame` files.
+
+use crate::{postprocess::PaletteSource, scene};
+
+/// Colors from the first `<palette>` element's hex data (6 hex digits per color, usually 256 colors).
+/// Returns None if there is no palette element or its data is malformed.
+pub fn parse_palette(xml: &str) -> Option<Vec<[u8; 3]>> {
+    let start = xml.find("<palette")?;
+    let element = &xml[start..];
+    let open_end = element.find('>')?;
+    if element[..open_end].ends_with('/') {
+        return None;
+    }
+    let body = &element[open_end + 1..];
+    let body = &body[..body.find("</palette>")?];
+    let digits: Vec<u8> = body
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    if digits.is_empty() || !digits.len().is_multiple_of(6) {
+        return None;
+    }
+    Some(
+        digits
+            .chunks(6)
+            .map(|c| [c[0] << 4 | c[1], c[2] << 4 | c[3], c[4] << 4 | c[5]])
+            .collect(),
+    )
+}
+
+/// Replaces the transforms, final transform and palette of `scene` with those of the first `<flame>` in `xml`.
+/// Only the affine parts of transforms are used: variations and weights have no equivalent here, so are ignored.
+/// Fails (leaving `scene` unchanged) if there are no transforms or any is malformed.
+pub fn apply_flame(xml: &str, scene: &mut scene::Scene) -> Result<(), String> {
+    let start = xml.find("<flame").ok_or("No flame element")?;
+    let flame = &xml[start..];
+    let flame = &flame[..flame.find("</flame>").unwrap_or(flame.len())];
+    let transforms = tags(flame, "xform")
+        .into_iter()
+        .map(|tag| {
+            Ok(scene::Transform {
+                coefs: parse_coefs(attribute(tag, "coefs").ok_or("Transform without coefs")?)?,
+                opacity: attribute(tag, "opacity")
+                    .map_or(Ok(1.0), |value| value.trim().parse())
+                    .map_err(|e| format!("Invalid opacity: {e}"))?,
+                post: attribute(tag, "post").map(parse_coefs).transpose()?,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if transforms.is_empty() {
+        return Err("No transforms".to_owned());
+    }
+    let final_transform = tags(flame, "finalxform")
+        .first()
+        .map(|tag| parse_coefs(attribute(tag, "coefs").ok_or("Final transform without coefs")?))
+        .transpose()?;
+    scene.transforms = transforms;
+    scene.final_transform = final_transform;
+    scene.palette = parse_palette(flame).map_or(PaletteSource::Default, PaletteSource::Colors);
+    Ok(())
+}
+
+/// Start tags of the `name` elements in `xml`, without the closing `>`.
+fn tags<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let open = format!("<{name}");
+    xml.match_indices(&open)
+        .map(|(i, _)| &xml[i + open.len()..])
+        // Skip longer names with this prefix (ex: "xforms").
+        .filter(|rest| rest.starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>'))
+        .map(|rest| &rest[..rest.find('>').unwrap_or(rest.len())])
+        .collect()
+}
+
+/// Value of the `name` attribute in `tag`, if present.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{name}=\"");
+    let mut search = 0;
+    while let Some(i) = tag[search..].find(&pattern) {
+        let start = search + i;
+        let value = start + pattern.len();
+        // Skip longer names with this suffix (ex: "post" in "xpost").
+        if tag[..start].ends_with(char::is_whitespace) {
+            let len = tag[value..].find('"')?;
+            return Some(&tag[value..value + len]);
+        }
+        search = value;
+    }
+    None
+}
+
+/// The 6 numbers of a `coefs` or `post` attribute, which `scene::Transform` stores as is.
+fn parse_coefs(value: &str) -> Result<[f64; 6], String> {
+    let values = value
+        .split_whitespace()
+        .map(|v| {
+            v.parse::<f64>()
+                .map_err(|e| format!("Invalid coefs \"{value}\": {e}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    values
+        .try_into()
+        .map_err(|_| format!("Expected 6 coefs, got \"{value}\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        flam3::{apply_flame, parse_palette},
+        postprocess::PaletteSource,
+        ui::Settings,
+    };
+
+    #[test]
+    fn palette() {
+        let xml = r#"<flame name="test">
+   <xform weight="0.5" color="0" linear="1" coefs="1 0 0 1 0 0"/>
+   <palette count="2" format="RGB">
+      FF000000ff80
+   </palette>
+</flame>"#;
+        assert_eq!(parse_palette(xml), Some(vec![[255, 0, 0], [0, 255, 128]]));
+    }
+
+    #[test]
+    fn palette_malformed() {
+        assert_eq!(parse_palette("<flame></flame>"), None);
+        assert_eq!(parse_palette("<palette/>"), None);
+        assert_eq!(parse_palette("<palette>FF00</palette>"), None);
+        assert_eq!(parse_palette("<palette>GG0000</palette>"), None);
+        assert_eq!(parse_palette("<palette>FF0000"), None);
+    }
+
+    #[test]
+    fn flame() {
+        let xml = r#"<flames>
+<flame name="test" size="640 480">
+   <xform weight="0.5" color="0" linear="1" coefs="0.5 0 0 0.5 0 0"/>
+   <xform weight="0.5" color="1" spherical="1" coefs="0.5 0.1 -0.1 0.5 0.5 -0.25"
+      post="1 0 0 1 0.1 0" opacity="0.25"/>
+   <finalxform color="0" linear="1" coefs="1 0 0 -1 0 0"/>
+   <palette count="1" format="RGB">FF0000</palette>
+</flame>
+<flame name="ignored"><xform coefs="1 0 0 1 0 0"/></flame>
+</flames>"#;
+        let mut scene = Settings::default().to_scene();
+        apply_flame(xml, &mut scene).unwrap();
+        assert_eq!(scene.transforms.len(), 2);
+        assert_eq!(scene.transforms[0].coefs, [0.5, 0.0, 0.0, 0.5, 0.0, 0.0]);
+        assert_eq!(scene.transforms[0].opacity, 1.0);
+        assert_eq!(scene.transforms[0].post, None);
+        assert_eq!(scene.transforms[1].coefs, [0.5, 0.1, -0.1, 0.5, 0.5, -0.25]);
+        assert_eq!(scene.transforms[1].opacity, 0.25);
+        assert_eq!(
+            scene.transforms[1].post,
+            Some([1.0, 0.0, 0.0, 1.0, 0.1, 0.0])
+        );
+        assert_eq!(scene.final_transform, Some([1.0, 0.0, 0.0, -1.0, 0.0, 0.0]));
+        assert_eq!(scene.palette, PaletteSource::Colors(vec![[255, 0, 0]]));
+        Settings::default().apply_scene(&scene).unwrap();
+    }
+
+    #[test]
+    fn flame_malformed() {
+        let mut scene = Settings::default().to_scene();
+        let original = scene.clone();
+        for xml in [
+            "<flames></flames>",
+            "<flame></flame>",
+            r#"<flame><xform linear="1"/></flame>"#,
+            r#"<flame><xform coefs="1 0 0 1 0"/></flame>"#,
+            r#"<flame><xform coefs="1 0 0 1 0 x"/></flame>"#,
+            r#"<flame><xform coefs="1 0 0 1 0 0" opacity="most"/></flame>"#,
+        ] {
+            assert!(apply_flame(xml, &mut scene).is_err(), "{xml}");
+            assert_eq!(scene, original);
+        }
+    }
+}
@@ -1,7 +1,11 @@
+//! IFS state and bounds computation.
+//! Only depends on `core` and `alloc` (see `no_std_check`).
+
 use crate::fixed_point;
-use crate::geometry::{Bounds, Rect};
-use nalgebra::Affine2;
-use std::fmt::Debug;
+use crate::geometry::{Bounds, OrientedRect, Rect, Transform};
+use alloc::{vec, vec::Vec};
+use core::fmt::Debug;
+use nalgebra::{Affine2, ComplexField, Matrix2, Matrix3, Point2, RealField, Rotation2};
 
 pub trait State<'a> {
     fn visit_level<F: FnMut(&Self)>(&self, callback: &mut F);
@@ -17,17 +21,79 @@ pub trait State<'a> {
     }
 }
 
-pub trait BoundedState<'a>: State<'a> {
+pub trait BoundedState<'a>: State<'a> + Clone {
     type B: Bounds + Debug;
 
+    /// How much this state can stretch distances. Used to pick which leaves to sample when computing bounds.
+    fn scale_factor(&self) -> f64;
+
+    /// Bounds to start the fixed point iteration from.
+    fn initial_bounds(&self) -> Self::B {
+        Self::B::origin()
+    }
+
+    #[cfg(test)]
     fn get_bounds(&self, levels: u32) -> Self::B {
-        let mut b = Self::B::origin();
+        self.get_bounds_sampled(levels, None)
+    }
+
+    /// Union of `b` transformed by every state `level` levels down.
+    /// Without any transforms there are no such states (and the attractor is empty), so `b` is returned unchanged.
+    fn union_transformed(&self, level: u32, b: &Self::B) -> Self::B {
+        let mut b2: Option<Self::B> = None;
+        self.process_levels(level, &mut |s| {
+            let b3 = s.transform_bounds(b);
+            b2 = Some(match &b2 {
+                None => b3,
+                Some(b4) => Self::B::union(b4, &b3),
+            })
+        });
+
+        b2.unwrap_or_else(|| b.clone())
+    }
+
+    /// The (at most) `samples` states `level` levels down with the largest scale factors,
+    /// or None if there are no more than `samples` states.
+    fn sample_leaves(&self, level: u32, samples: usize) -> Option<Vec<Self>> {
+        let mut leaves = Vec::new();
+        self.process_levels(level, &mut |s| leaves.push(s.clone()));
+        if leaves.len() <= samples {
+            return None;
+        }
+        leaves.sort_by(|a, b| b.scale_factor().total_cmp(&a.scale_factor()));
+        leaves.truncate(usize::max(1, samples));
+        Some(leaves)
+    }
+
+    /// Like `get_bounds`, but if `samples` is provided, the fixed point iteration only uses that many states per level
+    /// (the ones with the largest scale factor), which is much faster for flames with many transforms.
+    /// The result is then checked against all states, and grown until it contains them, so it still contains the attractor.
+    fn get_bounds_sampled(&self, levels: u32, samples: Option<usize>) -> Self::B {
+        self.get_bounds_seeded(levels, samples, self.initial_bounds())
+            .0
+    }
+
+    /// Like `get_bounds_sampled`, but starts the fixed point iteration from `seed` instead of `initial_bounds`
+    /// (ex: a caller's guess near the attractor). Any finite seed gives correct bounds,
+    /// but seeds closer to the attractor's bounds take fewer iterations.
+    /// Returns the bounds and the total number of iterations.
+    fn get_bounds_seeded(
+        &self,
+        levels: u32,
+        samples: Option<usize>,
+        seed: Self::B,
+    ) -> (Self::B, u32) {
+        let mut b = seed.clone();
+        let mut sampled = false;
+        let mut iterations = 0;
         // Starting with too few levels can diverge to infinity for large scale factors
         for level in 0..=levels {
-            let mut count = 0;
+            let mut count: u32 = 0;
             if b.is_infinite() {
-                b = Self::B::origin();
+                b = seed.clone();
             }
+            let leaves = samples.and_then(|samples| self.sample_leaves(level, samples));
+            sampled = leaves.is_some();
             let b_new = fixed_point::iterate_cmp(
                 b,
                 |before, after| {
@@ -52,68 +118,341 @@ pub trait BoundedState<'a>: State<'a> {
                         (after.grow(0.0001), false)
                     }
                 },
-                |input_bounds: &Self::B| {
-                    let mut b2: Option<Self::B> = None;
-                    self.process_levels(level, &mut |s| {
-                        let b3 = s.transform_bounds(input_bounds);
-                        b2 = Some(match &b2 {
-                            None => b3,
-                            Some(b4) => Self::B::union(b4, &b3),
-                        })
-                    });
-
-                    b2.unwrap()
+                |input_bounds: &Self::B| match &leaves {
+                    Some(leaves) => leaves
+                        .iter()
+                        .map(|s| s.transform_bounds(input_bounds))
+                        .reduce(|a, b| Self::B::union(&a, &b))
+                        .unwrap(),
+                    None => self.union_transformed(level, input_bounds),
                 },
             );
             b = b_new;
+            iterations += count;
         }
-        b
+        if sampled {
+            // Skipped states may map outside the bounds: add margin until they don't.
+            // This can fail to settle (ex: non-contractive flames, whose bounds become infinite or NaN),
+            // so give up after a while and compute the bounds from all states instead.
+            for _ in 0..SAMPLED_GROW_LIMIT {
+                let all = self.union_transformed(levels, &b);
+                if b.contains(&all) {
+                    return (b, iterations);
+                }
+                b = Self::B::union(&b, &all).grow(0.01);
+            }
+            let (b, full_iterations) = self.get_bounds_seeded(levels, None, seed);
+            return (b, iterations + full_iterations);
+        }
+        (b, iterations)
+    }
+
+    /// Like `get_bounds`, but picks the number of levels:
+    /// levels are added until the bounds stop shrinking meaningfully, or `max_levels` is reached.
+    /// Returns the bounds and the number of levels used.
+    fn get_bounds_adaptive(&self, max_levels: u32, samples: Option<usize>) -> (Self::B, u32) {
+        let mut levels = u32::min(1, max_levels);
+        let mut b = self.get_bounds_sampled(levels, samples);
+        while levels < max_levels {
+            let b_new = self.get_bounds_sampled(levels + 1, samples);
+            levels += 1;
+            // Too few levels can diverge to infinity, which would otherwise look stable.
+            let stable = !b_new.is_infinite() && b_new.grow(0.01).contains(&b);
+
+            b = b_new;
+            if stable {
+                break;
+            }
+        }
+        (b, levels)
+    }
+
+    /// Bounds of each level drawn from `seed` (ex: bounds containing the attractor), for showing how the flame fills space:
+    /// level `i` is the union of `seed` mapped by every state `i` levels down, so level 0 is `seed` (mapped by this state),
+    /// and level 1 the union of the first children. If `seed` contains its own images these nest, closing in on the attractor.
+    fn level_bounds(&self, seed: &Self::B, levels: u32) -> Vec<Self::B> {
+        (0..=levels)
+            .map(|level| self.union_transformed(level, seed))
+            .collect()
     }
 
     fn transform_bounds(&self, b: &Self::B) -> Self::B;
+
+    /// Maps `p` (in world space) by this state.
+    fn transform_point(&self, p: Point2<f64>) -> Point2<f64>;
+
+    /// Convex hull (counterclockwise) of `points` mapped by every state `levels` levels down.
+    /// If `points` are the corners of bounds containing the attractor, this contains it too,
+    /// but fits diagonal or triangular flames much more tightly than the bounds (more so with more levels).
+    fn convex_hull(&self, levels: u32, points: &[Point2<f64>]) -> Vec<Point2<f64>> {
+        let mut mapped = vec![];
+        self.process_levels(levels, &mut |s| {
+            mapped.extend(points.iter().map(|p| s.transform_point(*p)))
+        });
+        convex_hull(mapped)
+    }
+}
+
+/// Convex hull of `points`, counterclockwise, without repeated or collinear points.
+pub fn convex_hull(mut points: Vec<Point2<f64>>) -> Vec<Point2<f64>> {
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    // Andrew's monotone chain: the lower hull left to right, then the upper hull right to left.
+    let half = |points: &mut dyn Iterator<Item = &Point2<f64>>| {
+        let mut hull: Vec<Point2<f64>> = vec![];
+        for p in points {
+            while hull.len() >= 2
+                && (hull[hull.len() - 1] - hull[hull.len() - 2]).perp(&(*p - hull[hull.len() - 2]))
+                    <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(*p);
+        }
+        // The last point starts the other half.
+        hull.pop();
+        hull
+    };
+    let mut hull = half(&mut points.iter());
+    hull.extend(half(&mut points.iter().rev()));
+    hull
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct AffineState<'a> {
     pub mat: Affine2<f64>,
+    /// Product of the opacities of the transforms applied to reach this state.
+    pub opacity: f64,
     mats: &'a [Affine2<f64>],
+    /// Opacity of each transform in `mats`. All 1 if None.
+    opacities: Option<&'a [f64]>,
 }
 
 impl<'a> AffineState<'a> {
     pub fn new(mat_root: Affine2<f64>, transforms: &'a [Affine2<f64>]) -> AffineState<'a> {
         AffineState {
             mat: mat_root,
+            opacity: 1.0,
             mats: transforms,
+            opacities: None,
         }
     }
+
+    /// Fixed points of the transforms (not including `mat`), skipping any which don't have a unique one.
+    pub fn fixed_points(&self) -> Vec<Point2<f64>> {
+        self.mats.iter().filter_map(fixed_point).collect()
+    }
 }
 
 impl<'a> BoundedState<'a> for AffineState<'a> {
     type B = Rect;
+
+    fn scale_factor(&self) -> f64 {
+        expansion_factor(&self.mat)
+    }
+
+    /// Bounds of the fixed points, which are all in the attractor, so this is a good starting point.
+    fn initial_bounds(&self) -> Self::B {
+        self.fixed_points()
+            .into_iter()
+            .map(Rect::point)
+            .reduce(|a, b| Rect::union(&a, &b))
+            .unwrap_or_else(Rect::origin)
+    }
+
+    /// Exact for affine maps (see `Transform::transform_bounds`).
     fn transform_bounds(&self, b: &Self::B) -> Self::B {
-        let corners = b.corners();
-        let points = corners
-            .iter()
-            .map(|p| Rect::point(self.mat.transform_point(p)));
-        points.reduce(|a, b| Rect::union(&a, &b)).unwrap()
+        self.mat.transform_bounds(b)
+    }
+
+    fn transform_point(&self, p: Point2<f64>) -> Point2<f64> {
+        self.mat.transform_point(&p)
     }
 }
 
 impl<'a> State<'a> for AffineState<'a> {
     fn visit_level<F: FnMut(&Self)>(&self, callback: &mut F) {
-        for t in self.mats.iter().map(|m| m * self.mat) {
+        for (i, t) in self.mats.iter().map(|m| m * self.mat).enumerate() {
             let s = Self {
                 mat: t,
+                opacity: self.opacity * self.opacities.map_or(1.0, |o| o[i]),
                 mats: self.mats,
+                opacities: self.opacities,
             };
             callback(&s);
         }
     }
 }
 
+/// `AffineState` whose bounds are computed in a rotated frame.
+#[derive(Copy, Clone, Debug)]
+pub struct OrientedAffineState<'a> {
+    pub inner: AffineState<'a>,
+    rotation: Rotation2<f64>,
+}
+
+impl<'a> BoundedState<'a> for OrientedAffineState<'a> {
+    type B = OrientedRect;
+
+    fn scale_factor(&self) -> f64 {
+        self.inner.scale_factor()
+    }
+
+    fn initial_bounds(&self) -> Self::B {
+        let to_local = self.rotation.inverse();
+        OrientedRect {
+            rotation: self.rotation,
+            rect: self
+                .inner
+                .fixed_points()
+                .into_iter()
+                .map(|p| Rect::point(to_local * p))
+                .reduce(|a, b| Rect::union(&a, &b))
+                .unwrap_or_else(Rect::origin),
+        }
+    }
+
+    fn transform_bounds(&self, b: &Self::B) -> Self::B {
+        let to_local = self.rotation.inverse();
+        let corners = b.corners();
+        let points = corners
+            .iter()
+            .map(|p| Rect::point(to_local * self.inner.mat.transform_point(p)));
+        OrientedRect {
+            rotation: self.rotation,
+            rect: points.reduce(|a, b| Rect::union(&a, &b)).unwrap(),
+        }
+    }
+
+    fn transform_point(&self, p: Point2<f64>) -> Point2<f64> {
+        self.inner.transform_point(p)
+    }
+}
+
+impl<'a> State<'a> for OrientedAffineState<'a> {
+    fn visit_level<F: FnMut(&Self)>(&self, callback: &mut F) {
+        self.inner.visit_level(&mut |inner| {
+            callback(&Self {
+                inner: *inner,
+                rotation: self.rotation,
+            })
+        });
+    }
+}
+
+/// The upper 2x2 of the matrix: the transform without its translation.
+pub fn linear_part(t: &Affine2<f64>) -> Matrix2<f64> {
+    t.matrix().fixed_view::<2, 2>(0, 0).into_owned()
+}
+
+/// The point `t` maps to itself, if there is exactly one.
+pub fn fixed_point(t: &Affine2<f64>) -> Option<Point2<f64>> {
+    let m = t.matrix();
+    let offset = na::Vector2::new(m[(0, 2)], m[(1, 2)]);
+    (Matrix2::identity() - linear_part(t))
+        .try_inverse()
+        .map(|inv| Point2::from(inv * offset))
+}
+
+/// Largest singular value of the linear part of `t`: the most it can stretch any distance.
+/// Values of 1 or more mean the transform is not contractive, which can make the flame diverge.
+pub fn expansion_factor(t: &Affine2<f64>) -> f64 {
+    linear_part(t).singular_values().max()
+}
+
+/// Transform from flam3's coefs: the columns of the matrix (x axis, y axis, then offset),
+/// so `[a, b, c, d, e, f]` maps (x, y) to (ax + cy + e, bx + dy + f).
+pub fn affine_from_coefs([a, b, c, d, e, f]: [f64; 6]) -> Affine2<f64> {
+    Affine2::from_matrix_unchecked(Matrix3::new(a, c, e, b, d, f, 0.0, 0.0, 1.0))
+}
+
+/// `t` as flam3's coefs: the inverse of `affine_from_coefs`.
+pub fn affine_to_coefs(t: &Affine2<f64>) -> [f64; 6] {
+    let m = t.matrix();
+    [
+        m[(0, 0)],
+        m[(1, 0)],
+        m[(0, 1)],
+        m[(1, 1)],
+        m[(0, 2)],
+        m[(1, 2)],
+    ]
+}
+
+/// Most times `BoundedState::get_bounds_seeded` grows sampled bounds to contain the skipped states,
+/// before falling back to using all states.
+const SAMPLED_GROW_LIMIT: u32 = 32;
+
+/// Deepest products of transforms `Root::contraction_report` checks.
+const CONTRACTION_LEVELS: u32 = 8;
+
+/// Most products of transforms `Root::contraction_report` checks on one level (beyond the first).
+const CONTRACTION_MAX_PRODUCTS: usize = 1 << 12;
+
+/// Whether an IFS contracts (and so has a finite attractor), from `Root::contraction_report`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContractionReport {
+    /// Indexes of transforms which are not contractive on their own (see `expansion_factor`).
+    pub expanding: Vec<usize>,
+    /// Largest `expansion_factor` of any transform. Below 1, the IFS certainly contracts.
+    pub max_expansion: f64,
+    /// Upper bound on the joint spectral radius: the most products of the transforms stretch, per level.
+    /// Below 1, the IFS contracts over enough levels, even if some transforms expand on their own.
+    pub joint_spectral_radius: f64,
+}
+
+/// NaN is invalid in all the floats here, so Eq is fine.
+impl Eq for ContractionReport {}
+
+impl ContractionReport {
+    pub fn is_contractive(&self) -> bool {
+        self.joint_spectral_radius < 1.0
+    }
+}
+
+/// Symmetry group about the origin, which `Root::with_symmetry` gives the flame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symmetry {
+    /// Rotation by multiples of 1/n turn.
+    Cyclic(u32),
+    /// Like `Cyclic`, plus mirroring across the x axis (and thus across n lines through the origin).
+    Dihedral(u32),
+}
+
+impl Symmetry {
+    /// The isometries in the group, starting with the identity.
+    pub fn elements(self) -> Vec<Affine2<f64>> {
+        let (n, mirror) = match self {
+            Symmetry::Cyclic(n) => (n.max(1), false),
+            Symmetry::Dihedral(n) => (n.max(1), true),
+        };
+        let rotations = (0..n).map(move |i| {
+            Affine2::from_matrix_unchecked(
+                Rotation2::new(core::f64::consts::TAU * f64::from(i) / f64::from(n))
+                    .to_homogeneous(),
+            )
+        });
+        let reflection = Affine2::from_matrix_unchecked(na::Matrix3::new_nonuniform_scaling(
+            &na::Vector2::new(1.0, -1.0),
+        ));
+        let mut elements: Vec<Affine2<f64>> = rotations.clone().collect();
+        if mirror {
+            elements.extend(rotations.map(|r| r * reflection));
+        }
+        elements
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Root {
     storage: Vec<Affine2<f64>>,
+    /// Scales the density contributed by each transform.
+    opacities: Vec<f64>,
+    /// Applied to the output, but not fed back into the iteration.
+    final_transform: Option<Affine2<f64>>,
 }
 
 /// NaN is invalid in all the floats here, so Eq is fine.
@@ -121,18 +460,353 @@ impl Eq for Root {}
 
 impl Root {
     pub fn new(storage: Vec<Affine2<f64>>) -> Root {
-        Root { storage }
+        let opacities = vec![1.0; storage.len()];
+        Root::with_opacities(storage, opacities)
+    }
+
+    /// Transforms from flam3's coefs. See `affine_from_coefs`.
+    pub fn from_coefs(coefs: &[[f64; 6]]) -> Root {
+        Root::new(coefs.iter().copied().map(affine_from_coefs).collect())
+    }
+
+    /// The transforms as flam3's coefs (the inverse of `from_coefs`). Opacities and the final transform are not included.
+    pub fn to_coefs(&self) -> Vec<[f64; 6]> {
+        self.storage.iter().map(affine_to_coefs).collect()
+    }
+
+    /// `opacities` must be the same length as `storage`.
+    pub fn with_opacities(storage: Vec<Affine2<f64>>, opacities: Vec<f64>) -> Root {
+        assert_eq!(storage.len(), opacities.len());
+        Root {
+            storage,
+            opacities,
+            final_transform: None,
+        }
+    }
+
+    pub fn with_final_transform(self, final_transform: Option<Affine2<f64>>) -> Root {
+        Root {
+            final_transform,
+            ..self
+        }
+    }
+
+    /// Applies each transform's post transform (flam3's "post" affine) after it.
+    /// Transforms whose entry in `post` is None (or missing) are unchanged.
+    /// There are no nonlinear variations, so a transform followed by its post transform is itself affine:
+    /// the compositions replace the transforms, so iteration, point mapping and bounds all include the post transforms.
+    pub fn with_post_transforms(mut self, post: &[Option<Affine2<f64>>]) -> Root {
+        for (t, post) in self.storage.iter_mut().zip(post) {
+            if let Some(post) = post {
+                *t = post * *t;
+            }
+        }
+        self
+    }
+
+    /// Adds copies of each transform followed by each (non identity) element of `symmetry`,
+    /// with the same opacity. Unlike polygon mode, which places copies of one transform around a circle,
+    /// this makes any set of transforms (including asymmetric ones) symmetric: the group maps the new attractor onto itself.
+    /// The elements are isometries, so each copy stretches exactly as much as the original,
+    /// keeping the flame contractive (and its bounds convergent) if it was.
+    /// The final transform is unchanged, so is only symmetric if it commutes with the group (ex: a uniform scale).
+    pub fn with_symmetry(self, symmetry: Symmetry) -> Root {
+        let elements = symmetry.elements();
+        Root {
+            storage: elements
+                .iter()
+                .flat_map(|g| self.storage.iter().map(move |t| g * t))
+                .collect(),
+            opacities: elements
+                .iter()
+                .flat_map(|_| self.opacities.iter().copied())
+                .collect(),
+            ..self
+        }
+    }
+
+    pub fn final_transform(&self) -> Option<&Affine2<f64>> {
+        self.final_transform.as_ref()
+    }
+
+    pub fn transforms(&self) -> &[Affine2<f64>] {
+        &self.storage
+    }
+
+    /// Chaos game weights matching the rendered density: each transform's opacity times how much it scales area.
+    pub fn chaos_weights(&self) -> Vec<f64> {
+        self.storage
+            .iter()
+            .zip(&self.opacities)
+            .map(|(t, opacity)| {
+                let det = linear_part(t).determinant();
+                // `f64::abs` is not in `core`.
+                opacity * f64::max(det, -det)
+            })
+            .collect()
+    }
+
+    /// Points from the chaos game: starting at `start`, each step applies a transform picked with probability
+    /// proportional to its entry in `weights` (ex: `chaos_weights`), using `random` (uniform in [0, 1)).
+    /// The first `skip` points, from before the orbit settles onto the attractor, are discarded.
+    pub fn iterate_points(
+        &self,
+        start: Point2<f64>,
+        count: usize,
+        skip: usize,
+        weights: &[f64],
+        random: &mut impl FnMut() -> f64,
+    ) -> Vec<Point2<f64>> {
+        assert_eq!(weights.len(), self.storage.len());
+        if self.storage.is_empty() {
+            return vec![];
+        }
+        let total: f64 = weights.iter().sum();
+        let mut p = start;
+        let mut points = Vec::with_capacity(count);
+        for i in 0..skip + count {
+            let mut r = random() * total;
+            let mut chosen = self.storage.len() - 1;
+            for (j, w) in weights.iter().enumerate() {
+                if r < *w {
+                    chosen = j;
+                    break;
+                }
+                r -= w;
+            }
+            p = self.storage[chosen] * p;
+            if i >= skip {
+                points.push(p);
+            }
+        }
+        points
+    }
+
+    pub fn get_state(&self) -> AffineState<'_> {
+        AffineState {
+            opacities: Some(&self.opacities),
+            ..AffineState::new(Affine2::<f64>::identity(), &self.storage)
+        }
+    }
+
+    pub fn get_oriented_state(&self, angle: f64) -> OrientedAffineState<'_> {
+        OrientedAffineState {
+            inner: self.get_state(),
+            rotation: Rotation2::new(angle),
+        }
+    }
+
+    /// The fixed point of each transform. These are on the attractor.
+    /// Transforms without a unique fixed point (ex: identity) are skipped.
+    pub fn fixed_points(&self) -> Vec<Point2<f64>> {
+        self.get_state().fixed_points()
+    }
+
+    /// Indexes of transforms which are not contractive (see `expansion_factor`).
+    pub fn expanding_transforms(&self) -> Vec<usize> {
+        self.storage
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| expansion_factor(t) >= 1.0)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Checks how the IFS contracts. Flames which don't contract have infinite bounds, so can't be rendered.
+    /// The joint spectral radius is estimated from the products of up to `CONTRACTION_LEVELS` transforms
+    /// (fewer when there are many transforms).
+    pub fn contraction_report(&self) -> ContractionReport {
+        let n = self.storage.len();
+        let state = self.get_state();
+        let mut joint_spectral_radius = f64::INFINITY;
+        for level in 1..=CONTRACTION_LEVELS {
+            if level > 1
+                && !matches!(n.checked_pow(level), Some(count) if count <= CONTRACTION_MAX_PRODUCTS)
+            {
+                break;
+            }
+            let mut max: f64 = 0.0;
+            state.process_levels(level, &mut |s| {
+                max = f64::max(max, expansion_factor(&s.mat))
+            });
+            // Every product's stretch bounds the joint spectral radius from above.
+            joint_spectral_radius = f64::min(
+                joint_spectral_radius,
+                ComplexField::powf(max, 1.0 / f64::from(level)),
+            );
+        }
+        ContractionReport {
+            expanding: self.expanding_transforms(),
+            max_expansion: self
+                .storage
+                .iter()
+                .map(expansion_factor)
+                .fold(0.0, f64::max),
+            joint_spectral_radius,
+        }
+    }
+
+    /// If the IFS contracts (see `contraction_report`), and so can be rendered.
+    pub fn is_contractive(&self) -> bool {
+        self.contraction_report().is_contractive()
+    }
+
+    /// Hash identifying the flame, for caching renders and finding duplicate flames.
+    /// Stable across runs and platforms: it is FNV-1a over each value rounded to a multiple of `HASH_PRECISION`
+    /// (so tiny floating point differences, ex: from a round trip through text, hash the same),
+    /// rather than `core::hash`, whose output is not guaranteed to be stable.
+    /// Transforms (with their opacities) are unordered, since their order does not change the flame.
+    pub fn content_hash(&self) -> u64 {
+        let mut transforms: Vec<[i64; 7]> = self
+            .storage
+            .iter()
+            .zip(&self.opacities)
+            .map(|(t, opacity)| {
+                let [a, b, c, d, e, f] = quantized_coefs(t);
+                [a, b, c, d, e, f, quantize(*opacity)]
+            })
+            .collect();
+        transforms.sort_unstable();
+        let mut hash = Fnv1a::default();
+        hash.write(transforms.len() as i64);
+        for value in transforms.iter().flatten() {
+            hash.write(*value);
+        }
+        match &self.final_transform {
+            Some(t) => {
+                hash.write(1);
+                for value in quantized_coefs(t) {
+                    hash.write(value);
+                }
+            }
+            None => hash.write(0),
+        }
+        hash.0
+    }
+
+    /// Angle of the principal axis of the fixed points of the first level transforms.
+    /// Since these points are on the attractor, this approximates the direction the flame is longest in.
+    pub fn dominant_angle(&self) -> f64 {
+        let points = self.fixed_points();
+        if points.len() < 2 {
+            return 0.0;
+        }
+        let mean = points
+            .iter()
+            .fold(na::Vector2::zeros(), |a, p| a + p.coords)
+            / points.len() as f64;
+        let (mut xx, mut yy, mut xy) = (0.0, 0.0, 0.0);
+        for p in &points {
+            let d = p.coords - mean;
+            xx += d.x * d.x;
+            yy += d.y * d.y;
+            xy += d.x * d.y;
+        }
+        0.5 * RealField::atan2(2.0 * xy, xx - yy)
+    }
+}
+
+/// Values are rounded to multiples of this by `Root::content_hash`.
+pub const HASH_PRECISION: f64 = 1e-9;
+
+fn quantize(value: f64) -> i64 {
+    let scaled = value / HASH_PRECISION;
+    // `f64::round` is not in `core`. The cast saturates, and maps NaN to 0.
+    (if scaled < 0.0 {
+        scaled - 0.5
+    } else {
+        scaled + 0.5
+    }) as i64
+}
+
+/// Top two rows of the matrix, quantized.
+fn quantized_coefs(t: &Affine2<f64>) -> [i64; 6] {
+    let m = t.matrix();
+    [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2)].map(|i| quantize(m[i]))
+}
+
+/// 64 bit FNV-1a hash, over little endian bytes.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
     }
+}
 
-    pub fn get_state(&self) -> AffineState {
-        AffineState::new(Affine2::<f64>::identity(), &self.storage)
+impl Fnv1a {
+    fn write(&mut self, value: i64) {
+        for byte in value.to_le_bytes() {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::flame::{fixed_point, AffineState, BoundedState, Bounds, Rect, Root, State};
-    use na::{Affine2, Point2, Rotation2, Similarity2, Translation2};
+    use crate::flame::{
+        convex_hull, expansion_factor, fixed_point, AffineState, BoundedState, Bounds, Rect, Root,
+        State, Symmetry, HASH_PRECISION,
+    };
+    use crate::geometry::OrientedRect;
+    use na::{Affine2, Point2, Rotation2, Similarity2, Translation2, UnitComplex};
+
+    /// Deterministic stand in for a random number generator: a linear congruential generator.
+    fn lcg(seed: u64) -> impl FnMut() -> f64 {
+        let mut state = seed;
+        move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        }
+    }
+
+    #[test]
+    fn iterate_points_burn_in() {
+        let root = Root::new(
+            [(0.0, 0.0), (1.0, 0.0), (0.5, 1.0)]
+                .iter()
+                .map(|(x, y)| {
+                    na::convert(Translation2::new(*x, *y) * Similarity2::from_scaling(0.5))
+                })
+                .collect(),
+        );
+        let bounds = root.get_state().get_bounds(5).grow(1e-9);
+        let start = Point2::new(100.0, -100.0);
+        let weights = root.chaos_weights();
+
+        let unsettled = root.iterate_points(start, 10, 0, &weights, &mut lcg(1));
+        assert!(!bounds.contains_point(unsettled[0]));
+
+        let points = root.iterate_points(start, 1000, 50, &weights, &mut lcg(1));
+        assert_eq!(points.len(), 1000);
+        // Same sequence, minus the burn in.
+        assert_eq!(
+            root.iterate_points(start, 10, 0, &weights, &mut lcg(1))[5..],
+            root.iterate_points(start, 5, 5, &weights, &mut lcg(1))[..]
+        );
+        assert!(points.iter().all(|p| bounds.contains_point(*p)));
+    }
+
+    #[test]
+    fn coefs() {
+        // flam3's order: x axis, y axis, offset.
+        let coefs = [
+            [0.5, 0.1, -0.1, 0.5, 0.5, -0.25],
+            [1.0, 0.0, 0.0, -1.0, 0.0, 0.0],
+        ];
+        let root = Root::from_coefs(&coefs);
+        let maps = |t: usize, from: (f64, f64), to: (f64, f64)| {
+            let p = root.transforms()[t].transform_point(&Point2::new(from.0, from.1));
+            assert!((p - Point2::new(to.0, to.1)).norm() < 1e-12, "{p}");
+        };
+        maps(0, (0.0, 0.0), (0.5, -0.25));
+        maps(0, (1.0, 0.0), (1.0, -0.15));
+        maps(0, (0.0, 1.0), (0.4, 0.25));
+        maps(1, (2.0, 3.0), (2.0, -3.0));
+        assert_eq!(root.to_coefs(), coefs);
+    }
 
     fn checked_bounds(s: &AffineState) -> Rect {
         let b = s.get_bounds(3);
@@ -231,4 +905,413 @@ mod tests {
             .contains(&bounds));
         }
     }
+
+    fn polygon(n: u32, scale: f64) -> Vec<Affine2<f64>> {
+        (0..n)
+            .map(|i| {
+                let offset =
+                    Rotation2::new(std::f64::consts::PI * 2.0 * f64::from(i) / f64::from(n))
+                        * Point2::new(1.0, 0.0);
+                na::convert::<_, Affine2<f64>>(
+                    Similarity2::from_scaling(scale) * Translation2::new(offset.x, offset.y),
+                ) * Rotation2::new(0.3)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sampled_bounds() {
+        let storage = polygon(8, 0.4);
+        let state = AffineState::new(Affine2::identity(), &storage);
+        let full = state.get_bounds(2);
+        let sampled = state.get_bounds_sampled(2, Some(4));
+        assert!(sampled.contains(&state.union_transformed(2, &sampled)));
+        assert!(sampled.grow(0.01).contains(&full));
+
+        let mut points = vec![];
+        state.process_levels(4, &mut |s| {
+            points.push(s.mat.transform_point(&Point2::new(0.0, 0.0)))
+        });
+        assert!(points
+            .iter()
+            .all(|p| sampled.grow(0.001).contains_point(*p)));
+    }
+
+    #[test]
+    fn sampled_bounds_diverging_growth() {
+        // Axis aligned bounds of these rotated copies grow faster than the transforms shrink them,
+        // so growing the sampled bounds diverges (taking thousands of steps to reach infinity).
+        // It should give up quickly and use the full bounds instead.
+        let storage: Vec<_> = polygon(3, 0.9)
+            .into_iter()
+            .enumerate()
+            .map(|(i, a)| a * Rotation2::new(0.3 * i as f64))
+            .collect();
+        let state = AffineState::new(Affine2::identity(), &storage);
+        let sampled = state.get_bounds_sampled(3, Some(2));
+        assert_eq!(sampled, state.get_bounds(3));
+    }
+
+    #[test]
+    fn seeded_bounds() {
+        // Sierpinski triangle far from the origin: iterating from the origin takes a while to reach it,
+        // but its fixed points (the default seed) are its corners.
+        let storage = [(100.0, 50.0), (101.0, 50.0), (100.5, 51.0)]
+            .map(|(x, y)| {
+                na::convert::<_, Affine2<f64>>(
+                    Translation2::new(x / 2.0, y / 2.0) * Similarity2::from_scaling(0.5),
+                )
+            })
+            .to_vec();
+        let state = AffineState::new(Affine2::identity(), &storage);
+        let (from_origin, origin_iterations) = state.get_bounds_seeded(3, None, Rect::origin());
+        let (from_hull, hull_iterations) = state.get_bounds_seeded(3, None, state.initial_bounds());
+        assert!(
+            hull_iterations < origin_iterations,
+            "{hull_iterations} vs {origin_iterations}"
+        );
+        assert!(from_origin.grow(0.01).contains(&from_hull));
+        assert!(from_hull.grow(0.01).contains(&from_origin));
+        assert_eq!(state.get_bounds(3), from_hull);
+    }
+
+    #[test]
+    fn hull() {
+        let square =
+            [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(x, y)| Point2::new(x, y));
+        // Interior, repeated, and collinear points are dropped.
+        let mut points = square.to_vec();
+        points.extend([(0.5, 0.5), (0.0, 0.0), (0.5, 0.0)].map(|(x, y)| Point2::new(x, y)));
+        assert_eq!(convex_hull(points), square.to_vec());
+        assert_eq!(convex_hull(vec![]), vec![]);
+
+        // Sierpinski triangle: the hull approaches the triangle, which has half the area of its bounds.
+        let storage = [(0.0, 0.0), (0.5, 0.0), (0.25, 0.5)]
+            .map(|(x, y)| {
+                na::convert::<_, Affine2<f64>>(
+                    Translation2::new(x, y) * Similarity2::from_scaling(0.5),
+                )
+            })
+            .to_vec();
+        let state = AffineState::new(Affine2::identity(), &storage);
+        let bounds = state.get_bounds(4);
+        let hull = state.convex_hull(6, &bounds.corners());
+        let area = (0..hull.len())
+            .map(|i| hull[i].coords.perp(&hull[(i + 1) % hull.len()].coords))
+            .sum::<f64>()
+            / 2.0;
+        assert!(area > 0.0);
+        assert!(area < bounds.width() * bounds.height() * 0.55, "{area}");
+        let mut inside = true;
+        state.process_levels(6, &mut |s| {
+            let p = s.mat.transform_point(&Point2::origin());
+            inside &= (0..hull.len())
+                .all(|i| (hull[(i + 1) % hull.len()] - hull[i]).perp(&(p - hull[i])) >= -1e-9);
+        });
+        assert!(inside);
+    }
+
+    #[test]
+    fn level_bounds() {
+        let storage = [(0.0, 0.0), (0.5, 0.0), (0.25, 0.5)]
+            .map(|(x, y)| {
+                na::convert::<_, Affine2<f64>>(
+                    Translation2::new(x, y) * Similarity2::from_scaling(0.5),
+                )
+            })
+            .to_vec();
+        let state = AffineState::new(Affine2::identity(), &storage);
+        let seed = state.get_bounds(4).grow(1.0);
+        let levels = state.level_bounds(&seed, 5);
+        assert_eq!(levels.len(), 6);
+        assert_eq!(levels[0], seed);
+        for pair in levels.windows(2) {
+            assert!(pair[0].contains(&pair[1]));
+        }
+        // Closes in on the attractor's bounds, at half the seed's size.
+        assert!(levels[5].width() < seed.width() * 0.55);
+        assert!(levels[5].width() > seed.width() * 0.5);
+    }
+
+    #[test]
+    fn few_transforms() {
+        // No transforms: the bounds stay at the initial bounds, and nothing is drawn.
+        let root = Root::new(vec![]);
+        assert_eq!(root.get_state().get_bounds(4), Rect::origin());
+        let mut count = 0;
+        root.get_state().process_levels(2, &mut |_| count += 1);
+        assert_eq!(count, 0);
+
+        // A single transform: the attractor is its fixed point.
+        let root = Root::new(vec![na::convert(
+            Similarity2::from_scaling(0.5) * Translation2::new(2.0, 0.0),
+        )]);
+        let bounds = root.get_state().get_bounds(4);
+        assert!(bounds.grow(0.01).contains_point(Point2::new(2.0, 0.0)));
+        assert!(bounds.width() < 0.1 && bounds.height() < 0.1, "{bounds:?}");
+    }
+
+    #[test]
+    fn fixed_points() {
+        let root = Root::new(vec![
+            na::convert(Similarity2::from_scaling(0.5) * Translation2::new(5.0, 6.0)),
+            na::convert(Similarity2::from_scaling(0.5) * UnitComplex::new(1.0)),
+            // No unique fixed point.
+            Affine2::identity(),
+        ]);
+        let points = root.fixed_points();
+        assert_eq!(points.len(), 2);
+        assert!((points[0] - Point2::new(5.0, 6.0)).norm() < 1e-12);
+        for (p, t) in points.iter().zip(&root.storage) {
+            assert!((t.transform_point(p) - p).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn opacity() {
+        let root = Root::with_opacities(
+            vec![
+                na::convert(Similarity2::from_scaling(0.5)),
+                na::convert(Similarity2::from_scaling(0.5) * Translation2::new(1.0, 0.0)),
+            ],
+            vec![0.5, 0.25],
+        );
+        let mut opacities = vec![];
+        root.get_state()
+            .process_levels(2, &mut |s| opacities.push(s.opacity));
+        assert_eq!(opacities, vec![0.25, 0.125, 0.125, 0.0625]);
+    }
+
+    #[test]
+    fn post_transforms() {
+        let pre: Vec<Affine2<f64>> = vec![
+            na::convert(Similarity2::from_scaling(0.5)),
+            na::convert(Similarity2::from_scaling(0.5) * Translation2::new(1.0, 0.0)),
+        ];
+        let post: Affine2<f64> = na::convert(Translation2::new(0.0, 2.0) * Rotation2::new(0.5));
+        let root = Root::new(pre.clone()).with_post_transforms(&[None, Some(post)]);
+        // Applied after the transform.
+        let p = Point2::new(0.25, 0.5);
+        let mut mapped = vec![];
+        root.get_state()
+            .process_levels(1, &mut |s| mapped.push(s.transform_point(p)));
+        assert_eq!(mapped[0], pre[0] * p);
+        assert!((mapped[1] - post * (pre[1] * p)).norm() < 1e-12);
+
+        // Bounds contain the attractor of the composed transforms, not the original ones.
+        let bounds = root.get_state().get_bounds(5);
+        let original = Root::new(pre).get_state().get_bounds(5);
+        assert!(!original.contains(&bounds));
+        let points = root.iterate_points(Point2::origin(), 1000, 50, &[1.0, 1.0], &mut lcg(2));
+        assert!(points.iter().all(|p| bounds.grow(1e-9).contains_point(*p)));
+    }
+
+    #[test]
+    fn symmetry() {
+        assert_eq!(Symmetry::Cyclic(5).elements().len(), 5);
+        assert_eq!(Symmetry::Dihedral(5).elements().len(), 10);
+        assert_eq!(Symmetry::Cyclic(0).elements(), vec![Affine2::identity()]);
+
+        // Asymmetric seed.
+        let seed = Root::with_opacities(
+            vec![
+                na::convert(Similarity2::from_scaling(0.4) * Translation2::new(1.0, 0.3)),
+                na::convert(
+                    Translation2::new(-0.2, 0.5)
+                        * UnitComplex::new(0.2)
+                        * Similarity2::from_scaling(0.3),
+                ),
+            ],
+            vec![1.0, 0.5],
+        );
+        let root = seed.clone().with_symmetry(Symmetry::Dihedral(4));
+        assert_eq!(root.transforms().len(), 16);
+        assert_eq!(&root.transforms()[..2], seed.transforms());
+        assert_eq!(root.opacities[15], 0.5);
+        for (t, original) in root
+            .transforms()
+            .iter()
+            .zip(seed.transforms().iter().cycle())
+        {
+            assert!((expansion_factor(t) - expansion_factor(original)).abs() < 1e-12);
+        }
+        assert!(root.is_contractive());
+
+        // D4 maps axis aligned rects to axis aligned rects, so the bounds are exactly symmetric.
+        let bounds = root.get_state().get_bounds(3);
+        assert!((bounds.min.x + bounds.max.x).abs() < 1e-9, "{bounds:?}");
+        assert!((bounds.min.y + bounds.max.y).abs() < 1e-9, "{bounds:?}");
+        assert!(
+            (bounds.width() - bounds.height()).abs() < 1e-9,
+            "{bounds:?}"
+        );
+        // The bounds converge: they contain the attractor.
+        let points = root.iterate_points(
+            Point2::origin(),
+            2000,
+            50,
+            &root.chaos_weights(),
+            &mut lcg(3),
+        );
+        assert!(points.iter().all(|p| bounds.grow(1e-9).contains_point(*p)));
+    }
+
+    #[test]
+    fn content_hash() {
+        let a: Affine2<f64> = na::convert(Similarity2::from_scaling(0.5));
+        let b: Affine2<f64> =
+            na::convert(Similarity2::from_scaling(0.5) * Translation2::new(1.0, 0.0));
+        let root = Root::with_opacities(vec![a, b], vec![1.0, 0.5]);
+        // Pinned, since the hash must not change between versions.
+        assert_eq!(root.content_hash(), 0x3ee7_168e_c7e9_ebea);
+        // Order independent, but opacities stay with their transforms.
+        assert_eq!(
+            Root::with_opacities(vec![b, a], vec![0.5, 1.0]).content_hash(),
+            root.content_hash()
+        );
+        assert_ne!(
+            Root::with_opacities(vec![b, a], vec![1.0, 0.5]).content_hash(),
+            root.content_hash()
+        );
+        // Differences below the precision are ignored.
+        let nudged: Affine2<f64> =
+            na::convert(Similarity2::from_scaling(0.5 + HASH_PRECISION * 1e-3));
+        assert_eq!(
+            Root::with_opacities(vec![nudged, b], vec![1.0, 0.5]).content_hash(),
+            root.content_hash()
+        );
+        let moved: Affine2<f64> = na::convert(Similarity2::from_scaling(0.5001));
+        assert_ne!(
+            Root::with_opacities(vec![moved, b], vec![1.0, 0.5]).content_hash(),
+            root.content_hash()
+        );
+        assert_ne!(
+            root.clone().with_final_transform(Some(a)).content_hash(),
+            root.content_hash()
+        );
+    }
+
+    #[test]
+    fn expanding() {
+        let root = Root::new(vec![
+            na::convert(Similarity2::from_scaling(0.5)),
+            // Contracts one axis, but stretches the other.
+            Affine2::from_matrix_unchecked(na::Matrix3::new(
+                0.1, 0.0, 0.0, //
+                0.0, 1.5, 0.0, //
+                0.0, 0.0, 1.0,
+            )),
+            na::convert(Similarity2::from_scaling(-0.9)),
+        ]);
+        assert_eq!(root.expanding_transforms(), vec![1]);
+        assert!((expansion_factor(&root.storage[2]) - 0.9).abs() < 1e-9);
+        assert!(!root.is_contractive());
+    }
+
+    #[test]
+    fn contraction() {
+        let report =
+            Root::new(vec![na::convert(Similarity2::from_scaling(0.5))]).contraction_report();
+        assert_eq!(report.expanding, Vec::<usize>::new());
+        assert!((report.max_expansion - 0.5).abs() < 1e-9);
+        assert!(report.is_contractive());
+        assert!(Root::new(vec![]).is_contractive());
+
+        // Stretches x into y and shrinks y into x: expands on its own, but applying it twice scales by 0.6.
+        let swap = Affine2::from_matrix_unchecked(na::Matrix3::new(
+            0.0, 1.5, 0.0, //
+            0.4, 0.0, 0.0, //
+            0.0, 0.0, 1.0,
+        ));
+        let report =
+            Root::new(vec![swap, na::convert(Similarity2::from_scaling(0.5))]).contraction_report();
+        assert_eq!(report.expanding, vec![0]);
+        assert!((report.max_expansion - 1.5).abs() < 1e-9);
+        assert!(report.is_contractive(), "{report:?}");
+
+        let report =
+            Root::new(vec![na::convert(Similarity2::from_scaling(1.1))]).contraction_report();
+        assert!(!report.is_contractive());
+        assert!((report.joint_spectral_radius - 1.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn adaptive_levels() {
+        let max_levels = 7;
+        let strong = polygon(3, 0.1);
+        let weak = polygon(3, 0.9);
+        let (_, strong_levels) =
+            AffineState::new(Affine2::identity(), &strong).get_bounds_adaptive(max_levels, None);
+        let (_, weak_levels) =
+            AffineState::new(Affine2::identity(), &weak).get_bounds_adaptive(max_levels, None);
+        assert!(strong_levels < weak_levels);
+        assert!(weak_levels <= max_levels);
+    }
+
+    #[test]
+    fn negative_scale_bounds() {
+        for n in 2..6 {
+            let storage = polygon(n, -0.5);
+            let state = AffineState::new(Affine2::identity(), &storage);
+            let bounds = state.get_bounds(3);
+            assert!(!bounds.is_infinite());
+            assert!(bounds.width() > 0.0 && bounds.height() > 0.0);
+            let mut points = vec![];
+            state.process_levels(5, &mut |s| {
+                points.push(s.mat.transform_point(&Point2::new(0.0, 0.0)))
+            });
+            assert!(points.iter().all(|p| bounds.grow(0.001).contains_point(*p)));
+        }
+    }
+
+    #[test]
+    fn adaptive_bounds_contain_fractal() {
+        let storage = polygon(4, 0.5);
+        let state = AffineState::new(Affine2::identity(), &storage);
+        let (bounds, levels) = state.get_bounds_adaptive(6, None);
+        assert!(levels >= 1);
+        let mut points = vec![];
+        state.process_levels(5, &mut |s| {
+            points.push(s.mat.transform_point(&Point2::new(0.0, 0.0)))
+        });
+        assert!(points.iter().all(|p| bounds.grow(0.001).contains_point(*p)));
+    }
+
+    #[test]
+    fn oriented_bounds() {
+        // Two contractions towards the ends of a diagonal line.
+        let root = Root::new(vec![
+            na::convert(Similarity2::from_scaling(0.5)),
+            na::convert(Translation2::new(0.5, 0.5) * Similarity2::from_scaling(0.5)),
+        ]);
+        let angle = root.dominant_angle();
+        assert!((angle - std::f64::consts::FRAC_PI_4).abs() < 1e-9);
+
+        let aligned = root.get_state().get_bounds(3);
+        let oriented: OrientedRect = root.get_oriented_state(angle).get_bounds(3);
+        let area = |r: &Rect| r.width() * r.height();
+        assert!(area(&oriented.rect) < area(&aligned) * 0.1);
+
+        let mut points = vec![];
+        root.get_state().process_levels(5, &mut |s| {
+            points.push(s.mat.transform_point(&Point2::new(0.0, 0.0)))
+        });
+        let to_local = oriented.rotation.inverse();
+        let (min, max) = (oriented.rect.min, oriented.rect.max);
+        assert!(points.iter().all(|p| {
+            let l = to_local * *p;
+            l.x > min.x - 1e-9 && l.x < max.x + 1e-9 && l.y > min.y - 1e-9 && l.y < max.y + 1e-9
+        }));
+    }
+
+    #[test]
+    fn oriented_bounds_unrotated() {
+        let v = Root::new(vec![na::convert(
+            Similarity2::from_scaling(0.5) * Translation2::new(5.0, 6.0),
+        )]);
+        assert_eq!(
+            v.get_oriented_state(0.0).get_bounds(3),
+            OrientedRect::axis_aligned(v.get_state().get_bounds(3))
+        );
+    }
 }
@@ -1,7 +1,10 @@
-use na::Vector2;
+//! Bounding boxes and fitting.
+//! Only depends on `core` (see `no_std_check`).
+
+use na::{ComplexField, Rotation2, Vector2};
 use nalgebra::Point2;
 
-pub trait Bounds: PartialEq + Sized {
+pub trait Bounds: PartialEq + Clone + Sized {
     fn union(a: &Self, b: &Self) -> Self;
     fn origin() -> Self;
     fn is_infinite(&self) -> bool;
@@ -46,6 +49,34 @@ impl Rect {
     pub fn height(&self) -> f64 {
         (self.max - self.min).y
     }
+
+    /// The part of this covered by `fraction`, where (0, 0) is `min` and (1, 1) is `max`.
+    pub fn sub_rect(&self, fraction: &Rect) -> Self {
+        let at = |p: Point2<f64>| {
+            Point2::new(
+                self.min.x + p.x * self.width(),
+                self.min.y + p.y * self.height(),
+            )
+        };
+        Rect {
+            min: at(fraction.min),
+            max: at(fraction.max),
+        }
+    }
+
+    /// Expands (around the center) any axis smaller than `size`.
+    /// Useful for degenerate bounds (ex: from a zero scale transform), which would otherwise produce NaN scales.
+    pub fn with_min_size(&self, size: f64) -> Self {
+        let center = na::center(&self.min, &self.max);
+        let half = Vector2::new(
+            f64::max(self.width(), size) / 2.0,
+            f64::max(self.height(), size) / 2.0,
+        );
+        Rect {
+            min: center - half,
+            max: center + half,
+        }
+    }
 }
 
 impl Bounds for Rect {
@@ -76,6 +107,171 @@ impl Bounds for Rect {
     }
 }
 
+/// A map of the plane, which can bound where it takes a `Rect`.
+pub trait Transform {
+    fn transform_point(&self, p: Point2<f64>) -> Point2<f64>;
+
+    /// A `Rect` containing the image of `b`.
+    /// By default the bounds of the mapped corners, which is exact for affine maps (they take `b` to the parallelogram
+    /// spanned by the mapped corners). Nonlinear maps can bulge outside the corners, so must override this with a conservative bound.
+    fn transform_bounds(&self, b: &Rect) -> Rect {
+        b.corners()
+            .into_iter()
+            .map(|p| Rect::point(self.transform_point(p)))
+            .reduce(|a, b| Rect::union(&a, &b))
+            .unwrap()
+    }
+}
+
+impl Transform for na::Affine2<f64> {
+    fn transform_point(&self, p: Point2<f64>) -> Point2<f64> {
+        self * p
+    }
+}
+
+/// A `Rect` in a rotated coordinate frame.
+/// `rect` is expressed in the local frame, which is `rotation` applied to the world frame.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct OrientedRect {
+    pub rotation: Rotation2<f64>,
+    pub rect: Rect,
+}
+
+impl Eq for OrientedRect {}
+
+impl OrientedRect {
+    pub fn new(angle: f64, rect: Rect) -> Self {
+        Self {
+            rotation: Rotation2::new(angle),
+            rect,
+        }
+    }
+
+    pub fn axis_aligned(rect: Rect) -> Self {
+        Self::new(0.0, rect)
+    }
+
+    /// Corners in world space, in the same order as `Rect::corners`.
+    pub fn corners(&self) -> [Point2<f64>; 4] {
+        self.rect.corners().map(|p| self.rotation * p)
+    }
+
+    /// Maps world space into the local frame of `rect`.
+    pub fn to_local(&self) -> na::Affine2<f64> {
+        na::convert(self.rotation.inverse())
+    }
+
+    /// Smallest axis-aligned `Rect` containing this one.
+    pub fn aabb(&self) -> Rect {
+        self.transformed_aabb(&na::Affine2::identity())
+    }
+
+    /// Smallest axis-aligned `Rect` containing this one after applying `t`.
+    pub fn transformed_aabb(&self, t: &na::Affine2<f64>) -> Rect {
+        self.corners()
+            .iter()
+            .map(|p| Rect::point(t * p))
+            .reduce(|a, b| Rect::union(&a, &b))
+            .unwrap()
+    }
+
+    pub fn contains_point(&self, p: Point2<f64>) -> bool {
+        self.rect.contains_point(self.rotation.inverse() * p)
+    }
+}
+
+/// Only meaningful for `OrientedRect`s sharing the same rotation.
+impl Bounds for OrientedRect {
+    fn origin() -> Self {
+        Self::axis_aligned(Rect::origin())
+    }
+
+    fn union(a: &Self, b: &Self) -> Self {
+        debug_assert_eq!(a.rotation, b.rotation);
+        Self {
+            rotation: a.rotation,
+            rect: Rect::union(&a.rect, &b.rect),
+        }
+    }
+
+    fn grow(&self, portion: f64) -> Self {
+        Self {
+            rotation: self.rotation,
+            rect: self.rect.grow(portion),
+        }
+    }
+
+    fn is_infinite(&self) -> bool {
+        self.rect.is_infinite()
+    }
+}
+
+/// A disk, for bounding flames whose maps take circles to circles (see `mobius`), so map disks exactly.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Disk {
+    pub center: Point2<f64>,
+    pub radius: f64,
+}
+
+impl Eq for Disk {}
+
+impl Disk {
+    /// Unbounded: contains every disk.
+    pub fn infinite() -> Self {
+        Disk {
+            center: Point2::origin(),
+            radius: f64::INFINITY,
+        }
+    }
+
+    /// Smallest axis-aligned `Rect` containing this.
+    pub fn aabb(&self) -> Rect {
+        let r = Vector2::new(self.radius, self.radius);
+        Rect {
+            min: self.center - r,
+            max: self.center + r,
+        }
+    }
+}
+
+impl Bounds for Disk {
+    fn origin() -> Self {
+        Disk {
+            center: Point2::origin(),
+            radius: 0.0,
+        }
+    }
+
+    /// Smallest disk containing both.
+    fn union(a: &Self, b: &Self) -> Self {
+        // `Vector2::norm` needs `std`.
+        let offset = b.center - a.center;
+        let distance = ComplexField::sqrt(offset.norm_squared());
+        if a.is_infinite() || distance + b.radius <= a.radius {
+            return *a;
+        }
+        if b.is_infinite() || distance + a.radius <= b.radius {
+            return *b;
+        }
+        let radius = (distance + a.radius + b.radius) / 2.0;
+        Disk {
+            center: a.center + offset * ((radius - a.radius) / distance),
+            radius,
+        }
+    }
+
+    fn grow(&self, portion: f64) -> Self {
+        Disk {
+            center: self.center,
+            radius: self.radius * (1.0 + portion),
+        }
+    }
+
+    fn is_infinite(&self) -> bool {
+        self.radius == f64::INFINITY
+    }
+}
+
 pub fn letter_box_scale(container: Rect, content: Rect) -> f64 {
     f64::min(
         container.width() / content.width(),
@@ -99,6 +295,37 @@ pub fn letter_box(container: Rect, content: Rect) -> na::Affine2<f64> {
     )
 }
 
+/// Like `letter_box`, but for a container whose pixels are `pixel_aspect` (width / height) times as wide as they are tall
+/// (ex: anamorphic video). The content is squeezed horizontally so it has its original shape once displayed.
+/// With square pixels (`pixel_aspect` of 1), this is the same as `letter_box`.
+pub fn letter_box_pixel_aspect(
+    container: Rect,
+    content: Rect,
+    pixel_aspect: f64,
+) -> na::Affine2<f64> {
+    let to_display = na::Vector2::new(pixel_aspect, 1.0);
+    let display = Rect {
+        min: Point2::from(container.min.coords.component_mul(&to_display)),
+        max: Point2::from(container.max.coords.component_mul(&to_display)),
+    };
+    let to_pixels = na::Matrix3::new_nonuniform_scaling(&na::Vector2::new(1.0 / pixel_aspect, 1.0));
+    na::Affine2::from_matrix_unchecked(to_pixels) * letter_box(display, content)
+}
+
+/// The largest rect with the given `aspect_ratio` (width / height) centered in `container`,
+/// like the area `letter_box` would fill with content of that shape.
+pub fn fit_aspect(container: Rect, aspect_ratio: f64) -> Rect {
+    let content = Rect {
+        min: Point2::origin(),
+        max: Point2::new(aspect_ratio, 1.0),
+    };
+    let m = letter_box(container, content);
+    Rect {
+        min: m * content.min,
+        max: m * content.max,
+    }
+}
+
 pub fn box_to_box(container: Rect, content: Rect) -> na::Affine2<f64> {
     let scale_x = container.width() / content.width();
     let scale_y = container.height() / content.height();
@@ -111,3 +338,65 @@ pub fn box_to_box(container: Rect, content: Rect) -> na::Affine2<f64> {
 
     na::Affine2::from_matrix_unchecked(m)
 }
+
+/// Like `box_to_box`, but stretches the rotated `content` over `container` using its local frame.
+pub fn box_to_box_oriented(container: Rect, content: OrientedRect) -> na::Affine2<f64> {
+    box_to_box(container, content.rect) * content.to_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::{letter_box, letter_box_pixel_aspect, Bounds, Disk, Rect};
+    use nalgebra::Point2;
+
+    #[test]
+    fn pixel_aspect() {
+        let container = Rect {
+            min: Point2::new(0.0, 0.0),
+            max: Point2::new(200.0, 100.0),
+        };
+        let content = Rect {
+            min: Point2::new(-1.0, -1.0),
+            max: Point2::new(1.0, 1.0),
+        };
+        assert_eq!(
+            letter_box_pixel_aspect(container, content, 1.0),
+            letter_box(container, content)
+        );
+
+        // Pixels twice as wide as they are tall: the square content is half as many pixels wide as it is tall,
+        // and still centered.
+        let m = letter_box_pixel_aspect(container, content, 2.0);
+        let (min, max) = (m * content.min, m * content.max);
+        assert_eq!(max.y - min.y, 100.0);
+        assert_eq!(max.x - min.x, 50.0);
+        assert_eq!((min.x + max.x) / 2.0, 100.0);
+    }
+
+    #[test]
+    fn disk_union() {
+        let disk = |x: f64, y: f64, radius: f64| Disk {
+            center: Point2::new(x, y),
+            radius,
+        };
+        let (a, b) = (disk(0.0, 0.0, 1.0), disk(4.0, 0.0, 2.0));
+        let union = Disk::union(&a, &b);
+        assert_eq!(union, disk(2.5, 0.0, 3.5));
+        assert!(union.contains(&a) && union.contains(&b));
+        // Disks inside each other.
+        assert_eq!(Disk::union(&union, &a), union);
+        assert_eq!(Disk::union(&a, &union), union);
+        assert!(!a.contains(&b));
+
+        let infinite = Disk::infinite();
+        assert!(infinite.is_infinite() && !union.is_infinite());
+        assert_eq!(Disk::union(&a, &infinite), infinite);
+        assert_eq!(
+            b.aabb(),
+            Rect {
+                min: Point2::new(2.0, -2.0),
+                max: Point2::new(6.0, 2.0),
+            }
+        );
+    }
+}
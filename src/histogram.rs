@@ -0,0 +1,434 @@
+//! Tone curves built from an asynchronous readback of the accumulation buffer.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use wgpu::{BufferAsyncError, Device};
+use winit::dpi::PhysicalSize;
+
+use crate::{render_common::padded_bytes_per_row, util_types::PtrRc, wgpu_render::Postprocesser};
+
+/// Number of entries in the tone curve.
+pub const CURVE_SIZE: usize = 1024;
+
+/// Log2 density which maps to the end of the gradient with the plain log tone map.
+pub const LOG_RANGE: f32 = 100.0;
+
+/// Stops (factors of 2) of density below the reference density which `exposure_curve` spreads over the gradient.
+pub const EXPOSURE_STOPS: f32 = 16.0;
+
+/// Largest change in any entry of the tone curve which `curve_changed` ignores.
+/// Well below one step of an 8 bit output, so ignored changes are not visible.
+const CURVE_TOLERANCE: f32 = 1.0e-3;
+
+/// Portion of the (nonzero) densities below the reference density `exposure_curve` maps to the end of the gradient.
+/// Slightly below 1 so a few very dense pixels (ex: at fixed points) don't darken everything else.
+const EXPOSURE_PERCENTILE: f32 = 0.99;
+
+/// Control points of the contrast curve which leaves the tone map unchanged.
+pub const IDENTITY_CONTRAST: [[f32; 2]; 2] = [[0.0, 0.0], [1.0, 1.0]];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMap {
+    Log,
+    HistogramEq,
+    /// Density proportional to brightness, normalized to the max density. Useful for debugging.
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ReadbackState {
+    Idle,
+    /// A copy into the buffer has been recorded, but not yet submitted.
+    Copied,
+    /// Waiting for the buffer to be mapped.
+    Mapping,
+}
+
+/// Staging buffer for reading back an accumulation texture.
+#[derive(Debug)]
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    size: PhysicalSize<u32>,
+    padded_bytes_per_row: u32,
+    state: Mutex<ReadbackState>,
+    mapped: Arc<Mutex<Option<Result<(), BufferAsyncError>>>>,
+}
+
+pub fn readback(db: &dyn Postprocesser, size: PhysicalSize<u32>) -> PtrRc<Readback> {
+    let padded_bytes_per_row = padded_bytes_per_row(size.width, 4);
+    Readback {
+        buffer: db.device(()).create_buffer(&wgpu::BufferDescriptor {
+            label: Some("histogram readback"),
+            size: u64::from(padded_bytes_per_row) * u64::from(size.height),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        }),
+        size,
+        padded_bytes_per_row,
+        state: Mutex::new(ReadbackState::Idle),
+        mapped: Arc::new(Mutex::new(None)),
+    }
+    .into()
+}
+
+impl Readback {
+    /// Records a copy of `texture` (R32Float, matching size) into the buffer, unless a previous readback is still in flight.
+    pub fn copy(&self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        if *self.state.lock().unwrap() != ReadbackState::Idle {
+            return;
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.size.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.size.width,
+                height: self.size.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        *self.state.lock().unwrap() = ReadbackState::Copied;
+    }
+
+    /// Must be called after the encoder passed to `copy` was submitted.
+    /// Returns a new tone curve (see `tone_curve`) if a previous readback has completed.
+    pub fn after_submit(
+        &self,
+        device: &Device,
+        tone_map: ToneMap,
+        auto_exposure: Option<f32>,
+    ) -> Option<Vec<f32>> {
+        device.poll(wgpu::Maintain::Poll);
+        let mut curve = None;
+        if *self.state.lock().unwrap() == ReadbackState::Mapping {
+            let result = self.mapped.lock().unwrap().take();
+            match result {
+                None => {}
+                Some(Ok(())) => {
+                    {
+                        let bytes = self.buffer.slice(..).get_mapped_range();
+                        let row_len = self.size.width as usize * 4;
+                        let densities = bytes
+                            .chunks(self.padded_bytes_per_row as usize)
+                            .flat_map(|row| bytemuck::cast_slice::<u8, f32>(&row[..row_len]))
+                            .copied();
+                        curve = Some(tone_curve(tone_map, auto_exposure, densities));
+                    }
+                    self.buffer.unmap();
+                    *self.state.lock().unwrap() = ReadbackState::Idle;
+                }
+                Some(Err(_)) => *self.state.lock().unwrap() = ReadbackState::Idle,
+            }
+        }
+        if *self.state.lock().unwrap() == ReadbackState::Copied {
+            let mapped = self.mapped.clone();
+            self.buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    *mapped.lock().unwrap() = Some(result);
+                });
+            *self.state.lock().unwrap() = ReadbackState::Mapping;
+        }
+        curve
+    }
+
+    /// If a readback has been copied but its tone curve not yet returned by `after_submit`.
+    pub fn in_flight(&self) -> bool {
+        *self.state.lock().unwrap() != ReadbackState::Idle
+    }
+}
+
+/// If tone mapping with these settings needs the densities read back (see `tone_curve`).
+pub fn needs_readback(tone_map: ToneMap, auto_exposure: Option<f32>) -> bool {
+    tone_map != ToneMap::Log || auto_exposure.is_some()
+}
+
+/// Tone curve for `tone_map` from the densities.
+/// If `auto_exposure` is set, the log tone map is exposed for the densities (see `exposure_curve`), with that compensation.
+/// The other tone maps already adapt to the densities, so ignore it.
+pub fn tone_curve(
+    tone_map: ToneMap,
+    auto_exposure: Option<f32>,
+    densities: impl Iterator<Item = f32>,
+) -> Vec<f32> {
+    match (tone_map, auto_exposure) {
+        (ToneMap::Log, None) => identity_curve(),
+        (ToneMap::Log, Some(compensation)) => exposure_curve(densities, compensation),
+        (ToneMap::HistogramEq, _) => equalization_curve(densities),
+        (ToneMap::Linear, _) => linear_curve(densities.fold(0.0, f32::max)),
+    }
+}
+
+/// Curve which leaves the log mapping unchanged.
+pub fn identity_curve() -> Vec<f32> {
+    (0..CURVE_SIZE)
+        .map(|i| (i as f32 + 0.5) / CURVE_SIZE as f32)
+        .collect()
+}
+
+/// Maps (scaled) log densities to their position in the cumulative distribution of the densities,
+/// so that the full range of the gradient is used.
+/// Zero densities (the background) are ignored.
+pub fn equalization_curve(densities: impl Iterator<Item = f32>) -> Vec<f32> {
+    let mut histogram = vec![0u64; CURVE_SIZE];
+    for v in densities.filter(|v| *v > 0.0) {
+        let x = v.log2() / LOG_RANGE;
+        let bin = (x * CURVE_SIZE as f32).clamp(0.0, (CURVE_SIZE - 1) as f32) as usize;
+        histogram[bin] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return identity_curve();
+    }
+    let mut sum = 0;
+    histogram
+        .iter()
+        .map(|count| {
+            sum += count;
+            sum as f32 / total as f32
+        })
+        .collect()
+}
+
+/// Log tone curve which is the same for densities scaled by any factor, like flam3's scaling by sample count,
+/// so flames with very different total densities are exposed alike.
+/// The densities' `EXPOSURE_PERCENTILE` maps to the end of the gradient, and `EXPOSURE_STOPS` below it to the start.
+/// `compensation` brightens (or if negative, darkens) by that many stops.
+/// Zero densities (the background) are ignored.
+pub fn exposure_curve(densities: impl Iterator<Item = f32>, compensation: f32) -> Vec<f32> {
+    let mut logs: Vec<f32> = densities.filter(|v| *v > 0.0).map(f32::log2).collect();
+    if logs.is_empty() {
+        return identity_curve();
+    }
+    let index = ((logs.len() - 1) as f32 * EXPOSURE_PERCENTILE) as usize;
+    let (_, reference, _) = logs.select_nth_unstable_by(index, f32::total_cmp);
+    let reference = *reference;
+    (0..CURVE_SIZE)
+        .map(|i| {
+            let log_density = (i as f32 + 0.5) / CURVE_SIZE as f32 * LOG_RANGE;
+            ((log_density - reference + compensation) / EXPOSURE_STOPS + 1.0).clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+/// Maps density linearly onto the gradient, with `max_density` at the end.
+pub fn linear_curve(max_density: f32) -> Vec<f32> {
+    if max_density <= 0.0 || max_density.is_nan() {
+        return identity_curve();
+    }
+    (0..CURVE_SIZE)
+        .map(|i| {
+            let log_density = (i as f32 + 0.5) / CURVE_SIZE as f32 * LOG_RANGE;
+            f32::min(1.0, log_density.exp2() / max_density)
+        })
+        .collect()
+}
+
+/// Softly compresses the top of the gradient position `t` (in [0, 1]) with a Reinhard shoulder,
+/// so the densest areas keep the gradient's colors instead of reaching its end (often white).
+/// Positions below `1 - rolloff` are unchanged, and the slope is continuous there; the end maps to `1 - rolloff / 2`.
+/// A `rolloff` of 0 leaves the hard mapping unchanged. Mirrored by `highlight_rolloff` in `postprocess.wgsl`.
+pub fn highlight_rolloff(t: f32, rolloff: f32) -> f32 {
+    let knee = 1.0 - rolloff;
+    if rolloff <= 0.0 || t <= knee {
+        return t;
+    }
+    let x = (t - knee) / rolloff;
+    knee + rolloff * x / (1.0 + x)
+}
+
+/// If tone curve `new` differs visibly from `old` (by more than `CURVE_TOLERANCE` anywhere).
+pub fn curve_changed(old: &[f32], new: &[f32]) -> bool {
+    old.len() != new.len()
+        || old
+            .iter()
+            .zip(new)
+            .any(|(a, b)| (a - b).abs() > CURVE_TOLERANCE)
+}
+
+/// Value of `curve` (ex: a tone curve) at `x` in [0, 1], without interpolation.
+pub fn lookup(curve: &[f32], x: f32) -> f32 {
+    curve[((x * curve.len() as f32) as usize).min(curve.len() - 1)]
+}
+
+/// Samples a smooth curve through `points` (`[x, y]`), which remaps positions along the gradient after tone mapping.
+/// Uses monotone cubic (Fritsch-Carlson) interpolation, so the curve does not overshoot the points.
+/// Outside the points the curve is flat, and with fewer than 2 points it is the identity.
+pub fn contrast_curve(points: &[[f32; 2]]) -> Vec<f32> {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+    // Points with the same x would make a vertical segment: keep the last.
+    points.dedup_by(|next, prev| {
+        let same = next[0] <= prev[0];
+        if same {
+            *prev = *next;
+        }
+        same
+    });
+    if points.len() < 2 {
+        return identity_curve();
+    }
+
+    let secants: Vec<f32> = points
+        .windows(2)
+        .map(|w| (w[1][1] - w[0][1]) / (w[1][0] - w[0][0]))
+        .collect();
+    let mut tangents: Vec<f32> = (0..points.len())
+        .map(|i| {
+            if i == 0 {
+                secants[0]
+            } else if i == secants.len() {
+                secants[i - 1]
+            } else if secants[i - 1] * secants[i] <= 0.0 {
+                0.0
+            } else {
+                (secants[i - 1] + secants[i]) / 2.0
+            }
+        })
+        .collect();
+    // Limit the tangents so each segment is monotone.
+    for (i, d) in secants.iter().enumerate() {
+        if *d == 0.0 {
+            tangents[i] = 0.0;
+            tangents[i + 1] = 0.0;
+            continue;
+        }
+        let (a, b) = (tangents[i] / d, tangents[i + 1] / d);
+        let s = a * a + b * b;
+        if s > 9.0 {
+            let t = 3.0 / s.sqrt();
+            tangents[i] = t * a * d;
+            tangents[i + 1] = t * b * d;
+        }
+    }
+
+    (0..CURVE_SIZE)
+        .map(|i| {
+            let x = (i as f32 + 0.5) / CURVE_SIZE as f32;
+            let k = points.partition_point(|p| p[0] <= x);
+            let y = if k == 0 {
+                points[0][1]
+            } else if k == points.len() {
+                points[k - 1][1]
+            } else {
+                let ([x0, y0], [x1, y1]) = (points[k - 1], points[k]);
+                let (m0, m1) = (tangents[k - 1], tangents[k]);
+                let h = x1 - x0;
+                let t = (x - x0) / h;
+                let (t2, t3) = (t * t, t * t * t);
+                (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+                    + (t3 - 2.0 * t2 + t) * h * m0
+                    + (-2.0 * t3 + 3.0 * t2) * y1
+                    + (t3 - t2) * h * m1
+            };
+            y.clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::histogram::{
+        contrast_curve, curve_changed, equalization_curve, exposure_curve, highlight_rolloff,
+        identity_curve, linear_curve, lookup, CURVE_SIZE, IDENTITY_CONTRAST, LOG_RANGE,
+    };
+
+    #[test]
+    fn equalization() {
+        assert_eq!(equalization_curve([0.0, 0.0].into_iter()), identity_curve());
+
+        // Two clusters of densities far apart in log space should be spread to use the full range.
+        let curve = equalization_curve([1.5, 1.5, 1.0e20, 1.0e20].into_iter());
+        assert_eq!(curve.len(), CURVE_SIZE);
+        assert_eq!(curve[CURVE_SIZE - 1], 1.0);
+        assert_eq!(curve[CURVE_SIZE / 200], 0.5);
+        assert!(curve.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn changed() {
+        let curve = linear_curve(1024.0);
+        assert!(!curve_changed(&curve, &curve));
+        assert!(!curve_changed(&curve, &linear_curve(1024.5)));
+        assert!(curve_changed(&curve, &linear_curve(2048.0)));
+        assert!(curve_changed(&curve, &identity_curve()));
+        assert!(curve_changed(&curve, &curve[1..]));
+    }
+
+    #[test]
+    fn rolloff() {
+        assert_eq!(highlight_rolloff(1.0, 0.0), 1.0);
+        assert_eq!(highlight_rolloff(0.5, 0.4), 0.5);
+        assert!((highlight_rolloff(1.0, 0.4) - 0.8).abs() < 1e-6);
+        // Smooth at the knee: the slope just above it is about 1.
+        let slope = (highlight_rolloff(0.6 + 1e-3, 0.4) - 0.6) / 1e-3;
+        assert!((slope - 1.0).abs() < 0.01, "{slope}");
+        assert!(highlight_rolloff(0.9, 0.4) < highlight_rolloff(0.95, 0.4));
+    }
+
+    #[test]
+    fn linear() {
+        assert_eq!(linear_curve(0.0), identity_curve());
+
+        let curve = linear_curve(1024.0);
+        assert_eq!(curve.len(), CURVE_SIZE);
+        assert_eq!(curve[CURVE_SIZE - 1], 1.0);
+        assert!(curve[0] > 0.0 && curve[0] < 0.01);
+        assert!(curve.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn exposure() {
+        assert_eq!(exposure_curve([0.0].into_iter(), 0.0), identity_curve());
+
+        let densities: Vec<f32> = (1..1000).map(|i| i as f32 * 1.5).collect();
+        let scaled = || densities.iter().map(|v| v * 1024.0);
+        let curve = exposure_curve(densities.iter().copied(), 0.0);
+        let scaled_curve = exposure_curve(scaled(), 0.0);
+        let t = |curve: &[f32], v: f32| lookup(curve, v.log2() / LOG_RANGE);
+        // Scaling every density leaves the image unchanged.
+        for v in [3.0, 100.0, 1400.0] {
+            assert!((t(&curve, v) - t(&scaled_curve, v * 1024.0)).abs() < 0.01);
+        }
+        assert!(t(&curve, 1400.0) > 0.95);
+        assert!(curve.windows(2).all(|w| w[0] <= w[1]));
+
+        let brighter = exposure_curve(densities.iter().copied(), 2.0);
+        assert!(t(&brighter, 100.0) > t(&curve, 100.0) + 0.1);
+    }
+
+    #[test]
+    fn contrast() {
+        let identity = identity_curve();
+        for curve in [
+            contrast_curve(&IDENTITY_CONTRAST),
+            contrast_curve(&[[0.5, 0.2]]),
+        ] {
+            assert_eq!(curve.len(), CURVE_SIZE);
+            assert!(curve
+                .iter()
+                .zip(&identity)
+                .all(|(a, b)| (a - b).abs() < 1.0e-5));
+        }
+
+        // S curve: passes through the points without overshooting, and is flat outside them.
+        let points = [[0.1, 0.0], [0.3, 0.1], [0.7, 0.9], [0.9, 1.0]];
+        let curve = contrast_curve(&points);
+        assert!(curve.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(lookup(&curve, 0.05), 0.0);
+        assert_eq!(lookup(&curve, 0.95), 1.0);
+        assert!((lookup(&curve, 0.5) - 0.5).abs() < 0.01);
+        assert!(lookup(&curve, 0.2) < 0.1);
+    }
+}
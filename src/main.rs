@@ -1,37 +1,61 @@
 #![warn(unused_extern_crates)]
 
+extern crate alloc;
 extern crate nalgebra as na;
-use egui::{FontDefinitions, Style};
+use egui::FontDefinitions;
 use egui_wgpu_backend::{RenderPass, ScreenDescriptor};
 use egui_winit_platform::{Platform, PlatformDescriptor};
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-use std::rc::Rc;
-
-use util_types::DebugIt;
-use wgpu_render::{render, Inputs, Inputs2};
+use wgpu_render::{Inputs, Inputs2, Postprocesser, Renderer};
 use winit::{
-    dpi::{PhysicalSize, Size},
-    event::Event,
+    dpi::{LogicalSize, PhysicalSize},
+    event::{ElementState, Event, KeyboardInput, ModifiersState, MouseScrollDelta, VirtualKeyCode},
     event_loop::{ControlFlow, EventLoop},
-    window::Window,
     window::WindowBuilder,
+    window::{Fullscreen, Window},
 };
 #[cfg(target_arch = "wasm32")]
 extern crate console_error_panic_hook;
-use std::panic;
+use std::time::Duration;
 
 mod accumulate;
+mod background;
+#[cfg(not(target_arch = "wasm32"))]
+mod batch;
+mod bloom;
+mod commands;
+mod compare;
+mod cpu_raster;
+mod error;
+mod exr_export;
+mod feedback;
 pub mod fixed_point;
-mod flame;
+mod flam3;
+pub mod flame;
 pub mod geometry;
-mod mesh;
+mod histogram;
+pub mod mesh;
+pub mod mobius;
+mod orbit_trap;
+mod plan;
 mod postprocess;
 mod render_common;
+mod rng;
+mod scene;
+mod screensaver;
+mod slideshow;
+mod supersample;
+mod sweep;
+mod templates;
+mod timing;
+mod tutorial;
 mod ui;
 mod util_types;
 mod wgpu_render;
+#[cfg(not(target_arch = "wasm32"))]
+mod window_state;
 
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
@@ -39,19 +63,72 @@ pub fn wasm_run() {
     main();
 }
 
+/// Uses the saved size and position (desktop only) if there is one.
+fn window_builder(event_loop: &EventLoop<()>) -> WindowBuilder {
+    let builder = WindowBuilder::new()
+        .with_inner_size(LogicalSize::new(1280.0, 720.0))
+        .with_title("Rusty Flame");
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(state) = window_state::load() {
+        // Keep the window on the monitor it was on, if it is still connected.
+        let monitor = event_loop
+            .available_monitors()
+            .find(|m| {
+                let (p, s) = (m.position(), m.size());
+                (p.x..p.x + s.width as i32).contains(&state.position.x)
+                    && (p.y..p.y + s.height as i32).contains(&state.position.y)
+            })
+            .or_else(|| event_loop.primary_monitor());
+        let state = monitor.map_or(state, |m| state.clamp_to(&m));
+        return builder
+            .with_inner_size(state.size)
+            .with_position(state.position);
+    }
+    builder
+}
+
+pub use error::RenderError;
+pub use ui::Settings;
+pub use util_types::Shared;
+pub use wgpu_render::{after_submit, render, render_fade, DatabaseStruct};
+
+/// Per frame callback for procedural animation: given the frame index and seconds since starting,
+/// returns the settings to render with.
+pub type Script = Box<dyn FnMut(u64, f64) -> Settings>;
+
+/// Options for `run_with`.
+#[derive(Default)]
+pub struct RunConfig {
+    /// If set, this drives the settings every frame, overriding the UI.
+    pub script: Option<Script>,
+}
+
 pub fn main() {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if args.first().map(String::as_str) == Some("batch") {
+            std::process::exit(batch::main(&args[1..]));
+        }
+    }
+    if let Err(e) = run_with(RunConfig::default()) {
+        // Setup failed before there was a window or device to show the error with.
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+/// Runs the app. Only returns if setup fails: otherwise the event loop takes over the thread.
+/// On the web, setup happens asynchronously, so its errors are logged instead.
+pub fn run_with(config: RunConfig) -> Result<(), RenderError> {
     let event_loop = winit::event_loop::EventLoopBuilder::with_user_event().build();
-    let window = WindowBuilder::new()
-        .with_inner_size(Size::Physical((3000, 2000).into()))
-        .with_title("Rusty Flame")
-        .build(&event_loop)
-        .unwrap();
+    let window = window_builder(&event_loop).build(&event_loop)?;
 
     #[cfg(not(target_arch = "wasm32"))]
     {
         // wgpu_subscriber::initialize_default_subscriber(None);
         // Temporarily avoid srgb formats for the swapchain on the web
-        pollster::block_on(run(event_loop, window));
+        pollster::block_on(run(event_loop, window, config))
     }
     #[cfg(target_arch = "wasm32")]
     {
@@ -67,39 +144,129 @@ pub fn main() {
                     .ok()
             })
             .expect("couldn't append canvas to document body");
-        wasm_bindgen_futures::spawn_local(run(event_loop, window));
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(e) = run(event_loop, window, config).await {
+                log::error!("{e}");
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Pixels of scrolling (ex: from a touchpad) treated as one line of mouse wheel scrolling.
+const PIXELS_PER_LINE: f64 = 20.0;
+
+/// Size of the part of the window not covered by the settings panel.
+fn flame_size(window_size: PhysicalSize<u32>, panel_width: f64) -> PhysicalSize<u32> {
+    PhysicalSize::new(
+        u32::max(
+            1,
+            window_size.width.saturating_sub(panel_width.ceil() as u32),
+        ),
+        window_size.height,
+    )
+}
+
+/// Time left until the next frame is due under `fps_cap`, or None if it is due now.
+fn frame_delay(since_last_frame: Duration, fps_cap: Option<u32>) -> Option<Duration> {
+    let interval = Duration::from_secs_f64(1.0 / f64::from(fps_cap?.max(1)));
+    interval
+        .checked_sub(since_last_frame)
+        .filter(|delay| !delay.is_zero())
+}
+
+/// Tries progressively less demanding adapters: high performance, then low power, then the fallback (software) adapter.
+/// If `surface` is set, only adapters which can render to it are used.
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface>,
+) -> Option<wgpu::Adapter> {
+    for (power_preference, force_fallback_adapter) in [
+        (wgpu::PowerPreference::HighPerformance, false),
+        (wgpu::PowerPreference::LowPower, false),
+        (wgpu::PowerPreference::LowPower, true),
+    ] {
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference,
+                // If set, only adapters which can render to the surface
+                compatible_surface: surface,
+                force_fallback_adapter,
+            })
+            .await;
+        if adapter.is_some() {
+            return adapter;
+        }
+        log::warn!(
+            "No adapter found for {power_preference:?} (fallback: {force_fallback_adapter})"
+        );
     }
+    None
 }
 
-async fn run(event_loop: EventLoop<()>, window: Window) {
+async fn run(
+    event_loop: EventLoop<()>,
+    window: Window,
+    mut config: RunConfig,
+) -> Result<(), RenderError> {
     let mut started = wasm_timer::Instant::now();
     let mut frame_count = 0u64;
     let mut recent_frme_rate: f64 = 0.0;
+    // CPU seconds spent recording and submitting the flame's commands, summed over the frames since `started`,
+    // and averaged per frame over the last measurement. Unlike the frame rate, this is not limited by vsync.
+    let mut render_seconds = 0.0;
+    let mut recent_render_time: f64 = 0.0;
+    // Start of the last frame, for `Settings::fps_cap`.
+    let mut last_frame = wasm_timer::Instant::now();
 
     let size: PhysicalSize<u32> = window.inner_size();
     // Backend "all" does not appear to be preferring VULKAN in wgpu 0.13, so use VULKAN explicitly for now.
     let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default()); //  backend: wgpu::InstanceDescriptor::VULKAN
     dbg!(&instance);
-    let surface = unsafe { instance.create_surface(&window) }.unwrap();
-    let adapter = instance
-        .request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            // Request an adapter which can render to our surface
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
-        })
+    let surface = unsafe { instance.create_surface(&window) }?;
+    let adapter = request_adapter(&instance, Some(&surface))
         .await
-        .expect("Failed to find an appropriate adapter");
+        .ok_or(RenderError::NoAdapter)?;
 
-    dbg!(&adapter.get_info());
+    let adapter_info = adapter.get_info();
+    dbg!(&adapter_info);
+    let adapter_name = format!("{} ({:?})", adapter_info.name, adapter_info.backend);
 
     // List features for R32Float (This app depends on R32Float blending)
     let r32features = adapter.get_texture_format_features(wgpu::TextureFormat::R32Float);
-    if !r32features
+    // If set, the flame can't be rendered, so this message is displayed instead.
+    let fatal_error = if !r32features
         .flags
-        .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE)
+        .contains(wgpu::TextureFormatFeatureFlags::BLENDABLE)
     {
-        panic!("This app depends on R32Float blending which is not supported")
+        Some(format!(
+            "This app depends on blending R32Float textures, \
+            which is not supported by the graphics adapter \"{adapter_name}\"."
+        ))
+    } else {
+        None
+    };
+    // Without filtering, R32Float textures are sampled nearest only, which still renders, just less smoothly.
+    let float_filtering = r32features
+        .flags
+        .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE);
+    let quality_notice = (!float_filtering).then_some(
+        "Reduced quality: this adapter can not filter R32Float textures, \
+            so the flame is sampled without smoothing.",
+    );
+    if let Some(notice) = quality_notice {
+        log::warn!("{notice}");
+    }
+
+    // WebGPU (including in browsers) supports the default limits, but WebGL2 has no compute or storage support.
+    let webgl = adapter_info.backend == wgpu::Backend::Gl;
+    // On the web, recommend WebGPU when the browser only provides WebGL2.
+    let backend_notice = (cfg!(target_arch = "wasm32") && webgl).then_some(
+        "Running on WebGL2, which is more limited. \
+            For better performance, use a browser with WebGPU support.",
+    );
+    if let Some(notice) = backend_notice {
+        log::warn!("{notice}");
     }
 
     let mut limits: wgpu::Limits = wgpu::Limits::default();
@@ -137,7 +304,7 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
     // };
 
     // Lower limits to work on webgl based on testing in Firefox
-    {
+    if webgl {
         limits.max_compute_workgroups_per_dimension = 0; // 65535,
         limits.max_compute_workgroup_size_z = 0; // 64,
         limits.max_compute_workgroup_size_y = 0; // 256,
@@ -156,13 +323,14 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
             &wgpu::DeviceDescriptor {
                 label: None,
                 // Enable nonstandard features
-                features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+                features: (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                    | wgpu::Features::TIMESTAMP_QUERY)
+                    & adapter.features(),
                 limits,
             },
             None,
         )
-        .await
-        .expect("Failed to create device");
+        .await?;
 
     let surface_caps = surface.get_capabilities(&adapter);
     let surface_format = surface_caps
@@ -171,6 +339,12 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
         .copied()
         .find(|f| f.is_srgb())
         .unwrap_or(surface_caps.formats[0]);
+    // Extended range linear format, used for HDR output when supported.
+    let hdr_format = surface_caps
+        .formats
+        .iter()
+        .copied()
+        .find(|f| *f == wgpu::TextureFormat::Rgba16Float);
 
     let mut surface_config = wgpu::SurfaceConfiguration {
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -187,13 +361,16 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
 
     surface.configure(&device, &surface_config);
 
+    let ui_scale = ui::default_ui_scale(window.scale_factor());
+    let mut applied_ui_scale = ui_scale;
+
     // We use the `egui_winit_platform` crate to handle integration with wgpu, and create the runtime context
     let mut egui_platform = Platform::new(PlatformDescriptor {
         physical_width: window.inner_size().width,
         physical_height: window.inner_size().height,
         scale_factor: window.scale_factor(),
         font_definitions: FontDefinitions::default(),
-        style: Style::default(),
+        style: ui::scaled_style(ui_scale),
     });
 
     // We use the egui_wgpu_backend crate as the render backend.
@@ -202,16 +379,50 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
     // Display the demo application that ships with egui.
     // let mut demo_app = egui_demo_lib::ColorTest::default();
 
-    // let start_time = Instant::now();
+    let start_time = wasm_timer::Instant::now();
+    let mut frame_index = 0u64;
 
     let mut ui_settings = ui::Settings::default();
-
-    let mut db = wgpu_render::DatabaseStruct::default();
-    db.set_config((), ui_settings.clone());
-    db.set_window_size_with_durability((), size, salsa::Durability::MEDIUM);
-    db.set_device_with_durability((), Rc::new(device), salsa::Durability::HIGH);
-    db.set_queue_with_durability((), Rc::new(queue), salsa::Durability::HIGH);
-    db.set_swapchain_format_with_durability((), DebugIt(surface_format), salsa::Durability::HIGH);
+    ui_settings.ui_scale = ui_scale;
+    ui_settings.tutorial = tutorial::Tutorial::first_launch();
+    let mut show_ui = true;
+    let mut export = ui::Export::default();
+    let mut modifiers = ModifiersState::empty();
+    // Set while the window has zero area, during which nothing is rendered.
+    let mut minimized = false;
+    // Size from the latest `Resized` event, applied at the start of the next redraw.
+    // Resizes can arrive much faster than frames (ex: dragging the window's corner), so this reconfigures the surface once per frame,
+    // right before acquiring its texture, instead of for each event.
+    let mut pending_resize: Option<PhysicalSize<u32>> = None;
+    // Milliseconds per stage (see `timing::STAGES`), when timestamp queries are supported.
+    let mut gpu_timings: Option<Vec<f64>> = None;
+    // In physical pixels.
+    let mut panel_width = 0.0;
+
+    let mut db = wgpu_render::DatabaseStruct::new(
+        Shared::new(device),
+        Shared::new(queue),
+        surface_format,
+        size,
+        ui_settings.clone(),
+    );
+    db.set_float_filtering_with_durability((), float_filtering, salsa::Durability::HIGH);
+    // The first frame switches to the HDR format if the settings use it.
+    let hdr_formats: Vec<_> = hdr_format.filter(|_| ui_settings.hdr).into_iter().collect();
+    db.prebuild_pipelines(&hdr_formats);
+    // The playing slideshow, and a database (with its own caches) for the scene it is fading in.
+    // The fade database is made up front, so starting a slideshow does not stall on compiling the pipelines the current settings use.
+    let mut slideshow: Option<slideshow::Slideshow> = None;
+    let mut fade_db = wgpu_render::DatabaseStruct::new(
+        db.device(()),
+        db.queue(()),
+        surface_format,
+        size,
+        ui_settings.clone(),
+    );
+    fade_db.set_float_filtering_with_durability((), float_filtering, salsa::Durability::HIGH);
+    fade_db.prebuild_pipelines(&hdr_formats);
+    let mut screensaver: Option<screensaver::Screensaver> = None;
 
     event_loop.run(move |event, _, control_flow| {
         // Have the closure take ownership of the resources.
@@ -242,34 +453,169 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
             //     window.request_redraw();
             // }
             Event::RedrawRequested(_) => {
-                db.set_config((), ui_settings.clone());
+                // There is nothing to draw to (see `Resized`). Restoring resizes, which resumes drawing.
+                if minimized {
+                    return;
+                }
+                if let Some(size) = pending_resize.take() {
+                    if [size.width, size.height] != [surface_config.width, surface_config.height] {
+                        surface_config.width = size.width;
+                        surface_config.height = size.height;
+                        surface.configure(&db.device(()), &surface_config);
+                    }
+                    let flame = flame_size(size, panel_width);
+                    if flame != db.window_size(()) {
+                        db.set_window_size_with_durability((), flame, salsa::Durability::MEDIUM);
+                    }
+                }
+                last_frame = wasm_timer::Instant::now();
+                if let Some(script) = &mut config.script {
+                    ui_settings = script(frame_index, start_time.elapsed().as_secs_f64());
+                }
+                if ui_settings.screensaver && screensaver.is_none() {
+                    screensaver = Some(screensaver::Screensaver::new(ui_settings.clone()));
+                    show_ui = false;
+                }
+                if let Some(screensaver) = &screensaver {
+                    ui_settings = screensaver.settings();
+                }
+                let fade = if ui_settings.slideshow {
+                    if slideshow.is_none() {
+                        match slideshow::Slideshow::load(&ui_settings.slideshow_dir) {
+                            Ok(loaded) => slideshow = Some(loaded),
+                            Err(e) => {
+                                ui_settings.slideshow = false;
+                                ui_settings.slideshow_error = Some(e);
+                            }
+                        }
+                    }
+                    let (dwell, fade) = (ui_settings.slideshow_dwell, ui_settings.slideshow_fade);
+                    slideshow
+                        .as_mut()
+                        .and_then(|slideshow| slideshow.update(&mut ui_settings, dwell, fade))
+                } else {
+                    if slideshow.take().is_some() {
+                        fade_db.release_passes();
+                    }
+                    None
+                };
+                frame_index += 1;
+                db.set_settings(ui_settings.clone());
+
+                let format = match (ui_settings.hdr, hdr_format) {
+                    (true, Some(hdr_format)) => hdr_format,
+                    _ => surface_format,
+                };
+                if format != surface_config.format {
+                    surface_config.format = format;
+                    surface.configure(&db.device(()), &surface_config);
+                    db.set_target_format(format);
+                    egui_rpass = RenderPass::new(&db.device(()), format, 1);
+                }
+                let fade_opacity = fade.map(|(settings, opacity)| {
+                    fade_db.set_target_size(db.window_size(()));
+                    fade_db.set_target_format(*db.swapchain_format(()));
+                    fade_db.set_settings(settings);
+                    opacity
+                });
 
                 let device = &mut db.device(());
                 let queue = &mut db.queue(());
-                let output_texture = surface
-                    .get_current_texture()
-                    .expect("Failed to acquire next swap chain texture");
+                let acquired = match surface.get_current_texture() {
+                    // The window changed since the surface was configured (ex: a resize not yet delivered):
+                    // reconfigure to its current size and retry.
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        let size = window.inner_size();
+                        if size.width > 0 && size.height > 0 {
+                            surface_config.width = size.width;
+                            surface_config.height = size.height;
+                        }
+                        surface.configure(device, &surface_config);
+                        db.set_window_size_with_durability(
+                            (),
+                            flame_size(
+                                PhysicalSize::new(surface_config.width, surface_config.height),
+                                panel_width,
+                            ),
+                            salsa::Durability::MEDIUM,
+                        );
+                        surface.get_current_texture()
+                    }
+                    acquired => acquired,
+                };
+                let output_texture = match acquired {
+                    Ok(texture) => texture,
+                    Err(e) => {
+                        // Usually transient, so skip this frame and try again.
+                        log::warn!("Failed to acquire next swap chain texture: {e}");
+                        window.request_redraw();
+                        return;
+                    }
+                };
+                let output_view = output_texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
                 let mut encoder =
                     device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
                 {
-                    render(&db, &output_texture, &mut encoder);
-
-                    frame_count += 1;
-                    let elapsed = started.elapsed();
-                    if elapsed.as_secs_f64() > 0.25 {
-                        recent_frme_rate = elapsed.as_secs_f64() / frame_count as f64;
-                        started = wasm_timer::Instant::now();
-                        frame_count = 0;
+                    // Discard passes from outside the frame (ex: exports).
+                    render_common::take_pass_count();
+                    let render_start = wasm_timer::Instant::now();
+                    if fatal_error.is_none() {
+                        render(&db, &output_view, &mut encoder);
+                        if let Some(opacity) = fade_opacity {
+                            wgpu_render::render_fade(&fade_db, &output_view, &mut encoder, opacity);
+                        }
                     }
-
-                    let output_view = output_texture
-                        .texture
-                        .create_view(&wgpu::TextureViewDescriptor::default());
+                    render_seconds += render_start.elapsed().as_secs_f64();
 
                     // Draw UI
+                    if ui_settings.ui_scale != applied_ui_scale {
+                        applied_ui_scale = ui_settings.ui_scale;
+                        egui_platform
+                            .context()
+                            .set_style(ui::scaled_style(applied_ui_scale));
+                    }
                     egui_platform.begin_frame();
 
-                    ui::update(&egui_platform.context(), &mut ui_settings, recent_frme_rate);
+                    panel_width = if let Some(error) = &fatal_error {
+                        ui::error_screen(&egui_platform.context(), error);
+                        0.0
+                    } else if show_ui {
+                        let hull = ui_settings
+                            .show_hull
+                            .then(|| wgpu_render::hull_overlay(&db));
+                        let level_bounds = ui_settings
+                            .show_level_bounds
+                            .then(|| wgpu_render::level_bounds_overlay(&db));
+                        let palette = db.palette_image(ui_settings.palette.clone());
+                        f64::from(ui::update(
+                            &egui_platform.context(),
+                            &mut ui_settings,
+                            &mut show_ui,
+                            &mut export,
+                            &ui::Stats {
+                                frame_time: recent_frme_rate,
+                                render_time: recent_render_time,
+                                points_per_frame: wgpu_render::points_per_frame(&db),
+                                // Plus one for the UI, which is drawn after this.
+                                render_passes: render_common::take_pass_count() + 1,
+                                adapter: &adapter_name,
+                                backend_notice,
+                                quality_notice,
+                                hdr_supported: hdr_format.is_some(),
+                                plan: wgpu_render::plan(&db),
+                                output_aspect: wgpu_render::output_aspect(&db),
+                                hull,
+                                level_bounds,
+                                contraction: db.contraction(()),
+                                palette: &palette,
+                                gpu_timings: gpu_timings.as_deref(),
+                            },
+                        )) * window.scale_factor()
+                    } else {
+                        0.0
+                    };
 
                     // End the UI frame. We could now handle the output and draw the UI with the backend.
                     let output = egui_platform.end_frame(Some(&window));
@@ -282,32 +628,119 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                         scale_factor: window.scale_factor() as f32,
                     };
 
-                    egui_rpass
+                    let drawn = egui_rpass
                         .add_textures(device, queue, &output.textures_delta)
-                        .unwrap();
-                    egui_rpass.remove_textures(output.textures_delta).unwrap();
-
-                    egui_rpass.update_buffers(device, queue, &paint_jobs, &screen_descriptor);
-
-                    // Record all render passes.
-                    egui_rpass
-                        .execute(
-                            &mut encoder,
-                            &output_view,
-                            &paint_jobs,
-                            &screen_descriptor,
-                            None,
-                        )
-                        .unwrap();
+                        .and_then(|()| egui_rpass.remove_textures(output.textures_delta))
+                        .and_then(|()| {
+                            egui_rpass.update_buffers(
+                                device,
+                                queue,
+                                &paint_jobs,
+                                &screen_descriptor,
+                            );
+                            // Record all render passes.
+                            egui_rpass.execute(
+                                &mut encoder,
+                                &output_view,
+                                &paint_jobs,
+                                &screen_descriptor,
+                                // Nothing else clears the screen when showing an error.
+                                fatal_error.as_ref().map(|_| wgpu::Color::BLACK),
+                            )
+                        });
+                    if let Err(e) = drawn {
+                        // Skip this frame rather than submitting it without the UI, and try again.
+                        log::error!("Failed to draw the UI: {e}");
+                        window.request_redraw();
+                        return;
+                    }
+                    if fatal_error.is_none() {
+                        wgpu_render::finish_timing(&db, &mut encoder);
+                    }
                 }
 
+                let submit_start = wasm_timer::Instant::now();
                 db.queue(()).submit(Some(encoder.finish()));
+                render_seconds += submit_start.elapsed().as_secs_f64();
+
+                frame_count += 1;
+                let elapsed = started.elapsed();
+                if elapsed.as_secs_f64() > 0.25 {
+                    recent_frme_rate = elapsed.as_secs_f64() / frame_count as f64;
+                    recent_render_time = render_seconds / frame_count as f64;
+                    started = wasm_timer::Instant::now();
+                    frame_count = 0;
+                    render_seconds = 0.0;
+                }
 
-                output_texture.present()
+                output_texture.present();
+
+                if fatal_error.is_none() && wgpu_render::after_submit(&db) {
+                    window.request_redraw();
+                }
+                if fade_opacity.is_some() {
+                    wgpu_render::after_submit(&fade_db);
+                }
+                if let Some(timings) = db.timer(()).and_then(|t| t.after_submit(&db.device(()))) {
+                    gpu_timings = Some(timings);
+                }
+
+                if export.requested {
+                    export.requested = false;
+                    let size = PhysicalSize::new(export.width, export.height);
+                    if export.raw_density {
+                        match wgpu_render::render_density(&db, size) {
+                            Ok(density) => match exr_export::save(&export.path, size, density) {
+                                Ok(()) => log::info!("Saved {}", export.path),
+                                Err(e) => log::error!("Failed to save {e}"),
+                            },
+                            Err(e) => log::error!("Failed to export {}: {e}", export.path),
+                        }
+                    } else if let Some(sweep) = &export.sweep {
+                        match sweep::export(&db, &ui_settings, sweep, size, &export.path) {
+                            Ok(()) => log::info!("Saved {}", export.path),
+                            Err(e) => log::error!("Failed to export {e}"),
+                        }
+                    } else {
+                        // Orbit traps are only computed on the CPU, which only draws affine flames.
+                        let cpu = export.high_precision || ui_settings.orbit_trap.is_some();
+                        let image = if cpu && ui_settings.mobius.is_none() {
+                            Ok(cpu_raster::render_image(&db, size))
+                        } else {
+                            wgpu_render::render_to_image(&db, size)
+                        };
+                        match image.map(|image| image.save(&export.path)) {
+                            Ok(Ok(())) => log::info!("Saved {}", export.path),
+                            Ok(Err(e)) => log::error!("Failed to save {}: {e}", export.path),
+                            Err(e) => log::error!("Failed to export {}: {e}", export.path),
+                        }
+                    }
+                    db.release_passes();
+                }
+
+                // Fit the flame to the area not covered by the UI.
+                let size = flame_size(
+                    PhysicalSize::new(surface_config.width, surface_config.height),
+                    panel_width,
+                );
+                if size != db.window_size(()) {
+                    db.set_window_size_with_durability((), size, salsa::Durability::MEDIUM);
+                    window.request_redraw();
+                }
             }
-            Event::MainEventsCleared => {
-                if ui_settings.busy_loop {
-                    window.request_redraw(); // Enable to busy loop
+            // Scripts, slideshows and the screensaver animate, so need continuous redraws.
+            Event::MainEventsCleared
+                if (ui_settings.busy_loop
+                    || config.script.is_some()
+                    || ui_settings.slideshow
+                    || screensaver.is_some())
+                    && !minimized =>
+            {
+                match frame_delay(last_frame.elapsed(), ui_settings.fps_cap) {
+                    None => window.request_redraw(), // Enable to busy loop
+                    // This event is sent again once the wait is over.
+                    // The web event loop uses its own `Instant` (and waits with a timeout), which `set_wait_timeout` handles.
+                    Some(delay) => control_flow.set_wait_timeout(delay),
                 }
             }
             Event::WindowEvent { event, .. } => {
@@ -315,6 +748,15 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                 // but its unclear how to tell when its safe to skip this.
                 window.request_redraw();
 
+                if screensaver
+                    .as_ref()
+                    .is_some_and(|s| s.interrupted_by(&event))
+                {
+                    ui_settings = screensaver.take().unwrap().stop();
+                    show_ui = true;
+                    return;
+                }
+
                 // Pass the winit events to the platform integration.
                 // state.on_event returns true when the event has already been handled by egui and shouldn't be passed further
                 if !exclusive {
@@ -323,18 +765,55 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                             // Resize with 0 width and height is used by winit to signal a minimize event on Windows.
                             // See: https://github.com/rust-windowing/winit/issues/208
                             // This solves an issue where the app would panic when minimizing on Windows.
-                            if size.width > 0 && size.height > 0 {
-                                surface_config.width = size.width;
-                                surface_config.height = size.height;
-                                surface.configure(&db.device(()), &surface_config);
-                                db.set_window_size_with_durability(
-                                    (),
-                                    size,
-                                    salsa::Durability::MEDIUM,
-                                );
+                            minimized = size.width == 0 || size.height == 0;
+                            if !minimized {
+                                // The flame's size follows once the surface is reconfigured (see `pending_resize`).
+                                pending_resize = Some(size);
+                                window.request_redraw();
+                            }
+                        }
+                        winit::event::WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(key),
+                                    ..
+                                },
+                            ..
+                        } => match key {
+                            VirtualKeyCode::F11 => show_ui = !show_ui,
+                            VirtualKeyCode::Tab => ui_settings.cycle_selection(modifiers.shift()),
+                            VirtualKeyCode::Escape => ui_settings.clear_selection(),
+                            VirtualKeyCode::L => ui_settings.step_level(modifiers.shift()),
+                            VirtualKeyCode::Left => {
+                                ui_settings.nudge_selected(na::Vector2::new(-1.0, 0.0))
+                            }
+                            VirtualKeyCode::Right => {
+                                ui_settings.nudge_selected(na::Vector2::new(1.0, 0.0))
+                            }
+                            VirtualKeyCode::Up => {
+                                ui_settings.nudge_selected(na::Vector2::new(0.0, 1.0))
+                            }
+                            VirtualKeyCode::Down => {
+                                ui_settings.nudge_selected(na::Vector2::new(0.0, -1.0))
                             }
+                            VirtualKeyCode::F => window.set_fullscreen(match window.fullscreen() {
+                                Some(_) => None,
+                                None => Some(Fullscreen::Borderless(None)),
+                            }),
+                            _ => (),
+                        },
+                        winit::event::WindowEvent::MouseWheel { delta, .. } => {
+                            let lines = match delta {
+                                MouseScrollDelta::LineDelta(_, y) => y,
+                                MouseScrollDelta::PixelDelta(p) => (p.y / PIXELS_PER_LINE) as f32,
+                            };
+                            ui_settings.scroll_polygon(lines, modifiers.ctrl());
                         }
+                        winit::event::WindowEvent::ModifiersChanged(state) => modifiers = state,
                         winit::event::WindowEvent::CloseRequested => {
+                            #[cfg(not(target_arch = "wasm32"))]
+                            window_state::save(&window);
                             *control_flow = ControlFlow::Exit;
                         }
                         _ => (),
@@ -1,27 +1,73 @@
-use bytemuck::{Pod, Zeroable};
-use nalgebra::{Affine2, Matrix3};
+//! Tessellation of an IFS into quads, for drawing with instanced rendering.
+//! Independent of the rest of the renderer, so custom renderers can reuse it.
+//!
+//! Drawing `levels` levels of an IFS takes `n^levels` copies of a seed quad (ex: the flame's bounds).
+//! These are split between a mesh (`build_mesh`, the inner `m` levels) and instances of it (`build_instances`, the outer `levels - m`),
+//! which keeps both buffers small. Each instance draws the whole mesh transformed by its matrix:
+//! `clip_position = instance * vertex.position`, and the density added is `instance.opacity * vertex.opacity`.
+//! Drawn with additive blending into a single channel float target, this accumulates the flame's density.
+//!
+//! All the types here are `#[repr(C)]` and `Pod`, so can be uploaded directly (ex: with `bytemuck::cast_slice`).
+
+use nalgebra::{Affine2, Matrix3, Point2};
 
 use crate::{
-    flame::{Root, State},
-    geometry::{self, Rect},
+    flame::{AffineState, Root, State},
+    geometry::{self, OrientedRect},
+    mobius::{Mobius, MobiusState},
 };
 
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-pub struct Vertex {
-    position: Position,
-    texture_coordinate: TextureCoordinate,
-}
+pub use pod::{Instance, Vertex};
 
 pub type TextureCoordinate = [f32; 2];
 
 pub type Position = [f32; 2];
 
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct Instance {
-    row0: [f32; 4],
-    row1: [f32; 4],
+/// The types uploaded to the GPU.
+mod pod {
+    use super::{Position, TextureCoordinate};
+    use bytemuck::{Pod, Zeroable};
+
+    /// Corner of a triangle, 20 bytes: `position` (2 x f32 at offset 0), `texture_coordinate` (2 x f32 at offset 8)
+    /// then `opacity` (f32 at offset 16). As a wgpu vertex buffer: `Float32x2, Float32x2, Float32`.
+    /// Triangles are a list (not a strip): each quad is 6 vertexes.
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Vertex {
+        /// `x, y` in the space the instance matrices map from:
+        /// for `build_mesh`, the flame's coordinates (the seed quad's corners mapped by the mesh levels' transforms),
+        /// and for `build_quad`, clip space.
+        pub position: Position,
+        /// Corner of the seed quad this came from: `[0, 0]` at its first corner (`Rect::min`),
+        /// `[0, 1]` at the second, `[1, 1]` at the third and `[1, 0]` at the fourth (see `Rect::corners`).
+        /// Used to sample a smaller pass (which holds the whole flame) across the quad.
+        pub texture_coordinate: TextureCoordinate,
+        /// Multiplier for the accumulated density: the product of the opacities of the mesh levels' transforms.
+        pub opacity: f32,
+    }
+
+    /// Per instance transform and density, 28 bytes: `row0` (3 x f32 at offset 0), `row1` (3 x f32 at offset 12)
+    /// then `opacity` (f32 at offset 24). As a wgpu instance buffer: `Float32x3, Float32x3, Float32`.
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct Instance {
+        /// Top row of the affine matrix, `[a, b, tx]`: `x' = a * x + b * y + tx`.
+        /// The bottom row is always `[0, 0, 1]`, so is left implicit.
+        pub row0: [f32; 3],
+        /// Second row of the affine matrix, `[c, d, ty]`: `y' = c * x + d * y + ty`.
+        pub row1: [f32; 3],
+        /// Multiplier for the accumulated density: the product of the opacities of the instance levels' transforms.
+        pub opacity: f32,
+    }
+
+    // Implemented by hand since the checks `bytemuck`'s derives generate trip dead code lints on newer compilers.
+    // SAFETY: these are `repr(C)` and only contain `f32`s, and the size checks below ensure there is no padding.
+    unsafe impl Zeroable for Vertex {}
+    unsafe impl Pod for Vertex {}
+    unsafe impl Zeroable for Instance {}
+    unsafe impl Pod for Instance {}
+    const _: () = assert!(std::mem::size_of::<Vertex>() == 5 * 4);
+    const _: () = assert!(std::mem::size_of::<Instance>() == 7 * 4);
 }
 
 fn convert_point(p: &na::Point2<f64>) -> [f32; 2] {
@@ -31,37 +77,151 @@ fn convert_point(p: &na::Point2<f64>) -> [f32; 2] {
 const TRIANGLE_INDEXES_FOR_QUAD: [usize; 6] = [0, 1, 2, 0, 2, 3];
 const UV_QUAD: [TextureCoordinate; 4] = [[0.0, 0.0], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]];
 
-pub(crate) fn build_mesh(root: &Root, quad: Rect, levels: u32) -> Vec<Vertex> {
-    let corners = quad.corners();
+/// Fewest states worth splitting across threads.
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+const PARALLEL_MIN_STATES: usize = 1 << 12;
 
-    let mut vertexes = vec![];
-    root.get_state().process_levels(levels, &mut |state| {
+/// Calls `build` for each state `levels` levels down, in the same order as `process_levels`, collecting what it pushes.
+/// With the `parallel` feature, large trees are built on multiple threads (see `collect_levels_parallel`).
+fn collect_levels<T: Copy + Send>(
+    root: &Root,
+    levels: u32,
+    build: impl Fn(&AffineState, &mut Vec<T>) + Sync,
+) -> Vec<T> {
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    if levels > 0 && root.transforms().len().saturating_pow(levels) >= PARALLEL_MIN_STATES {
+        return collect_levels_parallel(root, levels, build);
+    }
+    collect_levels_sequential(root, levels, build)
+}
+
+fn collect_levels_sequential<T>(
+    root: &Root,
+    levels: u32,
+    build: impl Fn(&AffineState, &mut Vec<T>),
+) -> Vec<T> {
+    let mut out = vec![];
+    root.get_state()
+        .process_levels(levels, &mut |state| build(state, &mut out));
+    out
+}
+
+/// Like `collect_levels_sequential`, but with one task per top level transform, whose results are concatenated in order.
+/// `levels` must be at least 1.
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+fn collect_levels_parallel<T: Copy + Send>(
+    root: &Root,
+    levels: u32,
+    build: impl Fn(&AffineState, &mut Vec<T>) + Sync,
+) -> Vec<T> {
+    use rayon::prelude::*;
+    let mut children = vec![];
+    root.get_state()
+        .visit_level(&mut |state| children.push(*state));
+    let parts: Vec<Vec<T>> = children
+        .par_iter()
+        .map(|child| {
+            let mut out = vec![];
+            child.process_levels(levels - 1, &mut |state| build(state, &mut out));
+            out
+        })
+        .collect();
+    parts.concat()
+}
+
+/// Two triangles (see `Vertex`) for each of the `n^levels` states `levels` levels down (see `State::process_levels`):
+/// `quad` (usually the flame's bounds) mapped by the state, in the same order.
+pub fn build_mesh(root: &Root, quad: OrientedRect, levels: u32) -> Vec<Vertex> {
+    let corners = quad.corners();
+    collect_levels(root, levels, |state, vertexes| {
         for i in &TRIANGLE_INDEXES_FOR_QUAD {
             let t2 = state.mat * corners[*i];
             vertexes.push(Vertex {
                 position: convert_point(&t2),
                 texture_coordinate: UV_QUAD[*i],
+                opacity: state.opacity as f32,
             })
         }
+    })
+}
+
+/// Quads per side of the grid `build_mobius_mesh` maps for each state.
+pub const MOBIUS_DIVISIONS: u32 = 8;
+
+/// Like `build_mesh`, for a flame of Möbius maps, which curve lines, so a quad's image is not a quad:
+/// `quad` is split into a `divisions` by `divisions` grid, whose corners are mapped by each state.
+/// Texture coordinates are those of the grid's corners in `quad`, so smaller passes are sampled along the curves.
+/// Opacities are all 1. Möbius maps are not affine, so can not be instanced: every level is in the mesh,
+/// which is drawn by a single instance (`build_instances` with 0 levels).
+pub fn build_mobius_mesh(
+    maps: &[Mobius],
+    quad: OrientedRect,
+    levels: u32,
+    divisions: u32,
+) -> Vec<Vertex> {
+    let [origin, up, _, right] = quad.corners();
+    let grid: Vec<(Point2<f64>, TextureCoordinate)> = (0..=divisions)
+        .flat_map(|x| (0..=divisions).map(move |y| (x, y)))
+        .map(|(x, y)| {
+            let (u, v) = (
+                f64::from(x) / f64::from(divisions),
+                f64::from(y) / f64::from(divisions),
+            );
+            (
+                origin + (right - origin) * u + (up - origin) * v,
+                [u as f32, v as f32],
+            )
+        })
+        .collect();
+    let at = |x: u32, y: u32| grid[(x * (divisions + 1) + y) as usize];
+    let mut vertexes = vec![];
+    MobiusState::new(maps).process_levels(levels, &mut |state| {
+        for x in 0..divisions {
+            for y in 0..divisions {
+                // Same order as `UV_QUAD`.
+                let corners = [at(x, y), at(x, y + 1), at(x + 1, y + 1), at(x + 1, y)];
+                let mapped: Option<Vec<Point2<f64>>> = corners
+                    .iter()
+                    .map(|(p, _)| state.map.map_point(*p))
+                    .collect();
+                // Cells containing a pole are unbounded, so can not be drawn.
+                if let Some(mapped) = mapped {
+                    for i in &TRIANGLE_INDEXES_FOR_QUAD {
+                        vertexes.push(Vertex {
+                            position: convert_point(&mapped[*i]),
+                            texture_coordinate: corners[*i].1,
+                            opacity: 1.0,
+                        })
+                    }
+                }
+            }
+        }
     });
     vertexes
 }
 
-pub(crate) fn build_instances(root: &Root, root_mat: Affine2<f64>, levels: u32) -> Vec<Instance> {
-    let mut instances: Vec<Instance> = vec![];
-    root.get_state().process_levels(levels, &mut |state| {
-        let m: Matrix3<f64> = (root_mat * state.mat).to_homogeneous();
-        let s = m.as_slice();
-        instances.push(Instance {
-            row0: [s[0] as f32, s[3] as f32, s[6] as f32, 0f32],
-            row1: [s[1] as f32, s[4] as f32, s[7] as f32, 0f32],
-        });
-    });
+/// One instance for each of the `n^levels` states `levels` levels down (see `State::process_levels`), in the same order:
+/// the state's transform, followed by `root_mat` (ex: mapping the flame's bounds to clip space).
+/// Drawing a mesh from `build_mesh` with these draws `levels` more levels.
+pub fn build_instances(root: &Root, root_mat: Affine2<f64>, levels: u32) -> Vec<Instance> {
+    collect_levels(root, levels, |state, instances| {
+        instances.push(instance(state, root_mat))
+    })
+}
 
-    instances
+fn instance(state: &AffineState, root_mat: Affine2<f64>) -> Instance {
+    let m: Matrix3<f64> = (root_mat * state.mat).to_homogeneous();
+    let s = m.as_slice();
+    Instance {
+        row0: [s[0] as f32, s[3] as f32, s[6] as f32],
+        row1: [s[1] as f32, s[4] as f32, s[7] as f32],
+        opacity: state.opacity as f32,
+    }
 }
 
-pub(crate) fn build_quad() -> Vec<Vertex> {
+/// Two triangles covering clip space (-1 to 1 on both axes), with opacity 1, for drawing full screen passes.
+/// Texture coordinates are y up: `[0, 0]` at the bottom left.
+pub fn build_quad() -> Vec<Vertex> {
     let corners: Vec<Position> = geometry::Rect {
         min: na::Point2::new(-1.0, -1.0),
         max: na::Point2::new(1.0, 1.0),
@@ -77,7 +237,180 @@ pub(crate) fn build_quad() -> Vec<Vertex> {
             Vertex {
                 position: corners[*index],
                 texture_coordinate: UV_QUAD[*index],
+                opacity: 1.0,
             }
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    use crate::{
+        flame::AffineState,
+        mesh::{collect_levels_parallel, collect_levels_sequential, instance},
+    };
+    use crate::{
+        flame::{Root, State},
+        geometry::{OrientedRect, Rect},
+        mesh::{build_instances, build_mobius_mesh, Instance, Vertex},
+        mobius::{Mobius, MobiusState},
+    };
+    use na::{Affine2, Complex, Matrix3, Point2, Similarity2, Vector2};
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    use std::time::Instant;
+
+    /// External shaders rely on the documented layouts.
+    #[test]
+    fn layouts() {
+        assert_eq!(std::mem::size_of::<Vertex>(), 20);
+        assert_eq!(std::mem::size_of::<Instance>(), 28);
+    }
+
+    /// Instances must be in `process_levels` order, whether or not they were built in parallel.
+    #[test]
+    fn instance_order() {
+        let root = Root::new(
+            (0..4)
+                .map(|i| {
+                    Affine2::from_matrix_unchecked(Matrix3::new(
+                        0.5,
+                        0.0,
+                        f64::from(i),
+                        0.0,
+                        0.5,
+                        0.0,
+                        0.0,
+                        0.0,
+                        1.0,
+                    ))
+                })
+                .collect(),
+        );
+        // Enough states to be split across threads with the `parallel` feature.
+        let levels = 6;
+        let instances = build_instances(&root, Affine2::identity(), levels);
+        let mut expected = vec![];
+        root.get_state().process_levels(levels, &mut |s| {
+            expected.push(s.mat.matrix()[(0, 2)] as f32)
+        });
+        assert_eq!(instances.len(), expected.len());
+        for (instance, x) in instances.iter().zip(expected) {
+            assert_eq!(instance.row0[2], x);
+        }
+    }
+
+    /// Each grid corner is the state's map applied to the point of the quad at its texture coordinate,
+    /// in `process_levels` order, and cells containing a pole are skipped.
+    #[test]
+    fn mobius_mesh() {
+        let c = Complex::new;
+        let maps = [
+            Mobius::new(c(0.5, 0.1), c(0.3, 0.0), c(0.2, -0.1), c(1.0, 0.0)),
+            Mobius::new(c(0.4, 0.0), c(-0.4, 0.0), c(0.0, 0.0), c(1.0, 0.0)),
+        ];
+        let quad = OrientedRect::new(
+            0.3,
+            Rect {
+                min: Point2::new(-1.0, -0.5),
+                max: Point2::new(1.0, 1.0),
+            },
+        );
+        let [origin, up, _, right] = quad.corners();
+        let (levels, divisions) = (2, 4);
+        let vertexes = build_mobius_mesh(&maps, quad, levels, divisions);
+        let cell_vertexes = (divisions * divisions * 6) as usize;
+        let mut states = vec![];
+        MobiusState::new(&maps).process_levels(levels, &mut |s| states.push(s.map));
+        assert_eq!(vertexes.len(), states.len() * cell_vertexes);
+        for (i, v) in vertexes.iter().enumerate() {
+            let [u, w] = v.texture_coordinate.map(f64::from);
+            let expected = states[i / cell_vertexes]
+                .map_point(origin + (right - origin) * u + (up - origin) * w)
+                .unwrap();
+            let [x, y] = v.position;
+            assert!((f64::from(x) - expected.x).abs() < 1e-5);
+            assert!((f64::from(y) - expected.y).abs() < 1e-5);
+        }
+
+        // The pole of 1 / z is at the origin, which is a corner of 4 cells.
+        let inversion = [Mobius::new(
+            c(0.0, 0.0),
+            c(1.0, 0.0),
+            c(1.0, 0.0),
+            c(0.0, 0.0),
+        )];
+        let centered = OrientedRect::axis_aligned(Rect {
+            min: Point2::new(-1.0, -1.0),
+            max: Point2::new(1.0, 1.0),
+        });
+        let vertexes = build_mobius_mesh(&inversion, centered, 1, divisions);
+        assert_eq!(vertexes.len(), (divisions * divisions - 4) as usize * 6);
+    }
+
+    /// Eight transforms, which multiply the states quickly for benchmarks.
+    fn octagon() -> Root {
+        Root::new(
+            (0..8)
+                .map(|i| {
+                    let angle = f64::from(i);
+                    na::convert(Similarity2::new(
+                        Vector2::new(angle.cos(), angle.sin()),
+                        angle,
+                        0.4,
+                    ))
+                })
+                .collect(),
+        )
+    }
+
+    /// Bytes uploaded per frame for a high count of instances (n = 8, levels = 5: 32768),
+    /// compared to the previous layout which padded each row to 4 floats (36 bytes per instance).
+    #[test]
+    fn instance_bandwidth() {
+        let instances = build_instances(&octagon(), Affine2::identity(), 5);
+        assert_eq!(instances.len(), 8usize.pow(5));
+        let packed = bytemuck::cast_slice::<Instance, u8>(&instances).len();
+        let padded: Vec<[f32; 9]> = instances
+            .iter()
+            .map(|i| {
+                let [a, b, c] = i.row0;
+                let [d, e, f] = i.row1;
+                [a, b, c, 0.0, d, e, f, 0.0, i.opacity]
+            })
+            .collect();
+        let padded = bytemuck::cast_slice::<[f32; 9], u8>(&padded).len();
+        assert_eq!(packed, instances.len() * 28);
+        assert_eq!(padded, instances.len() * 36);
+        // 22% less to upload and for the vertex stage to read.
+        assert_eq!(packed * 9, padded * 7);
+    }
+
+    /// Timing of building instances for n = 8, levels = 8 (16.7 million states) with and without the `parallel` feature's threads.
+    /// Run with `cargo test --release --features parallel -- --ignored --nocapture bench_collect_levels`.
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    #[test]
+    #[ignore]
+    fn bench_collect_levels() {
+        let root = octagon();
+        let levels = 8;
+        let build = |state: &AffineState, out: &mut Vec<Instance>| {
+            out.push(instance(state, Affine2::identity()))
+        };
+        let time = |collect: &dyn Fn() -> Vec<Instance>| {
+            let start = Instant::now();
+            let instances = collect();
+            (start.elapsed(), instances)
+        };
+        let (sequential_time, sequential) =
+            time(&|| collect_levels_sequential(&root, levels, build));
+        let (parallel_time, parallel) = time(&|| collect_levels_parallel(&root, levels, build));
+        assert_eq!(sequential, parallel);
+        println!(
+            "{} instances on {} threads: sequential {sequential_time:?}, parallel {parallel_time:?} ({:.2}x)",
+            sequential.len(),
+            rayon::current_num_threads(),
+            sequential_time.as_secs_f64() / parallel_time.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,447 @@
+//! Möbius transformations of the complex plane: z -> (az + b) / (cz + d), as an alternative to affine transforms.
+//! Only depends on `core` and `alloc` (see `no_std_check`).
+//!
+//! Möbius maps curve lines, so their states are drawn as subdivided grids (see `mesh::build_mobius_mesh`)
+//! instead of instanced quads. They take circles to circles, so bounds are disks, which they map exactly.
+
+use crate::{
+    flame::{BoundedState, State},
+    geometry::{Bounds, Disk, Rect, Transform},
+};
+use nalgebra::{Complex, ComplexField, Point2};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mobius {
+    pub a: Complex<f64>,
+    pub b: Complex<f64>,
+    pub c: Complex<f64>,
+    pub d: Complex<f64>,
+}
+
+/// NaN is invalid in all the floats here, so Eq is fine.
+impl Eq for Mobius {}
+
+fn modulus(z: Complex<f64>) -> f64 {
+    // `Complex::norm` needs `std`.
+    ComplexField::sqrt(z.norm_sqr())
+}
+
+/// Principal square root.
+fn sqrt(z: Complex<f64>) -> Complex<f64> {
+    // `Complex::sqrt` needs `std`.
+    let r = modulus(z);
+    let re = ComplexField::sqrt(f64::max(0.0, (r + z.re) / 2.0));
+    let im = ComplexField::sqrt(f64::max(0.0, (r - z.re) / 2.0));
+    Complex::new(re, if z.im < 0.0 { -im } else { im })
+}
+
+fn point(z: Complex<f64>) -> Point2<f64> {
+    Point2::new(z.re, z.im)
+}
+
+fn complex(p: Point2<f64>) -> Complex<f64> {
+    Complex::new(p.x, p.y)
+}
+
+impl Mobius {
+    pub fn new(a: Complex<f64>, b: Complex<f64>, c: Complex<f64>, d: Complex<f64>) -> Self {
+        Mobius { a, b, c, d }
+    }
+
+    pub fn identity() -> Self {
+        let (zero, one) = (Complex::new(0.0, 0.0), Complex::new(1.0, 0.0));
+        Mobius::new(one, zero, zero, one)
+    }
+
+    /// From `[a, b, c, d]`, each `[re, im]`.
+    pub fn from_coefs(coefs: [[f64; 2]; 4]) -> Self {
+        let [a, b, c, d] = coefs.map(|[re, im]| Complex::new(re, im));
+        Mobius::new(a, b, c, d)
+    }
+
+    /// `[a, b, c, d]`, each `[re, im]` (the inverse of `from_coefs`).
+    pub fn to_coefs(&self) -> [[f64; 2]; 4] {
+        [self.a, self.b, self.c, self.d].map(|z| [z.re, z.im])
+    }
+
+    /// Zero if the map is degenerate (constant).
+    pub fn determinant(&self) -> Complex<f64> {
+        self.a * self.d - self.b * self.c
+    }
+
+    /// Where `z` maps to, or None for the pole (which maps to infinity).
+    pub fn apply(&self, z: Complex<f64>) -> Option<Complex<f64>> {
+        let denominator = self.c * z + self.d;
+        (denominator.norm_sqr() > 0.0).then(|| (self.a * z + self.b) / denominator)
+    }
+
+    /// `apply`, treating points as complex numbers (x + yi).
+    pub fn map_point(&self, p: Point2<f64>) -> Option<Point2<f64>> {
+        self.apply(complex(p)).map(point)
+    }
+
+    /// The map which applies `other`, then `self`.
+    pub fn compose(&self, other: &Mobius) -> Mobius {
+        Mobius::new(
+            self.a * other.a + self.b * other.c,
+            self.a * other.b + self.b * other.d,
+            self.c * other.a + self.d * other.c,
+            self.c * other.b + self.d * other.d,
+        )
+    }
+
+    /// Undoes `self`, if it is not degenerate.
+    pub fn inverse(&self) -> Mobius {
+        Mobius::new(self.d, -self.b, -self.c, self.a)
+    }
+
+    /// The same map, with its coefficients scaled so the determinant has modulus 1.
+    /// Composing many maps otherwise overflows or underflows the coefficients.
+    pub fn normalized(&self) -> Mobius {
+        let scale = ComplexField::sqrt(modulus(self.determinant()));
+        if scale == 0.0 {
+            return *self;
+        }
+        Mobius::new(
+            self.a / scale,
+            self.b / scale,
+            self.c / scale,
+            self.d / scale,
+        )
+    }
+
+    /// The fixed point which nearby points converge to, if there is one:
+    /// iterating the map alone converges to it from almost anywhere, so it is on the attractor.
+    pub fn attracting_fixed_point(&self) -> Option<Complex<f64>> {
+        // Fixed points solve cz^2 + (d - a)z - b = 0.
+        let roots = if self.c.norm_sqr() == 0.0 {
+            let slope = self.d - self.a;
+            if slope.norm_sqr() == 0.0 {
+                return None;
+            }
+            [self.b / slope; 2]
+        } else {
+            let root = sqrt((self.d - self.a) * (self.d - self.a) + 4.0 * self.b * self.c);
+            [1.0, -1.0].map(|sign| (self.a - self.d + root * sign) / (2.0 * self.c))
+        };
+        let stretch = modulus(self.determinant());
+        // The derivative is det / (cz + d)^2.
+        roots
+            .into_iter()
+            .find(|z| (self.c * z + self.d).norm_sqr() > stretch)
+    }
+
+    /// Image of the disk with `center` and `radius`, as a center and radius:
+    /// Möbius maps take circles to circles, so this is exact rather than conservative.
+    /// None if the disk contains the pole, making its image unbounded.
+    pub fn map_disk(&self, center: Complex<f64>, radius: f64) -> Option<(Complex<f64>, f64)> {
+        if self.c.norm_sqr() == 0.0 {
+            let scale = self.a / self.d;
+            return Some((scale * center + self.b / self.d, modulus(scale) * radius));
+        }
+        let to_pole = -self.d / self.c - center;
+        if modulus(to_pole) <= radius {
+            return None;
+        }
+        // The reflection of the pole in the circle maps to the center of the image,
+        // since the pole maps to infinity, and reflections in the circle map to reflections in its image.
+        let image_center = self.apply(center + radius * radius / to_pole.conj())?;
+        let on_circle = self.apply(center + Complex::new(radius, 0.0))?;
+        Some((image_center, modulus(on_circle - image_center)))
+    }
+
+    /// Most this stretches distances within the disk with `center` and `radius`
+    /// (the largest modulus of its derivative there), or None if the disk contains the pole.
+    pub fn max_stretch(&self, center: Complex<f64>, radius: f64) -> Option<f64> {
+        let determinant = modulus(self.determinant());
+        if self.c.norm_sqr() == 0.0 {
+            return Some(determinant / self.d.norm_sqr());
+        }
+        // The derivative is det / (cz + d)^2, which is largest closest to the pole.
+        let to_pole = modulus(-self.d / self.c - center);
+        let closest = (to_pole - radius) * modulus(self.c);
+        (to_pole > radius).then(|| determinant / (closest * closest))
+    }
+}
+
+impl Transform for Mobius {
+    /// The pole maps to infinity.
+    fn transform_point(&self, p: Point2<f64>) -> Point2<f64> {
+        self.map_point(p)
+            .unwrap_or(Point2::new(f64::INFINITY, f64::INFINITY))
+    }
+
+    /// Conservative: bounds the exact image (see `map_disk`) of the disk circumscribing `b`,
+    /// so includes where the edges of `b` curve outside its mapped corners.
+    /// Infinite if that disk contains the pole.
+    fn transform_bounds(&self, b: &Rect) -> Rect {
+        let center = nalgebra::center(&b.min, &b.max);
+        let radius = modulus(complex(b.max) - complex(center));
+        match self.map_disk(complex(center), radius) {
+            Some((center, radius)) => Disk {
+                center: point(center),
+                radius,
+            },
+            None => Disk::infinite(),
+        }
+        .aabb()
+    }
+}
+
+/// If every map takes the disk with `center` and `radius` into itself, so the disk contains the attractor of `maps`.
+pub fn contains_attractor(maps: &[Mobius], center: Complex<f64>, radius: f64) -> bool {
+    maps.iter().all(|m| {
+        m.map_disk(center, radius)
+            .is_some_and(|(c, r)| modulus(c - center) + r <= radius)
+    })
+}
+
+/// Like `flame::AffineState`, for a flame of Möbius maps.
+#[derive(Copy, Clone, Debug)]
+pub struct MobiusState<'a> {
+    /// The maps applied to reach this state, composed.
+    pub map: Mobius,
+    maps: &'a [Mobius],
+}
+
+impl<'a> MobiusState<'a> {
+    pub fn new(maps: &'a [Mobius]) -> MobiusState<'a> {
+        MobiusState {
+            map: Mobius::identity(),
+            maps,
+        }
+    }
+}
+
+impl<'a> State<'a> for MobiusState<'a> {
+    fn visit_level<F: FnMut(&Self)>(&self, callback: &mut F) {
+        for m in self.maps {
+            callback(&Self {
+                map: m.compose(&self.map).normalized(),
+                maps: self.maps,
+            });
+        }
+    }
+}
+
+impl<'a> BoundedState<'a> for MobiusState<'a> {
+    type B = Disk;
+
+    /// Stretch at the origin: only used to pick which states to sample, so does not need to bound the stretch.
+    fn scale_factor(&self) -> f64 {
+        self.map
+            .max_stretch(Complex::new(0.0, 0.0), 0.0)
+            .unwrap_or(f64::INFINITY)
+    }
+
+    /// Bounds of the attracting fixed points, which are all in the attractor, so this is a good starting point.
+    /// Unlike the affine case, the origin can be a pole (ex: of an inversion), so is a poor fallback.
+    fn initial_bounds(&self) -> Self::B {
+        self.maps
+            .iter()
+            .filter_map(Mobius::attracting_fixed_point)
+            .map(|z| Disk {
+                center: point(z),
+                radius: 0.0,
+            })
+            .reduce(|a, b| Disk::union(&a, &b))
+            .unwrap_or_else(Disk::origin)
+    }
+
+    /// Exact (see `Mobius::map_disk`), or infinite if the disk contains the pole.
+    fn transform_bounds(&self, b: &Self::B) -> Self::B {
+        if b.is_infinite() {
+            return *b;
+        }
+        match self.map.map_disk(complex(b.center), b.radius) {
+            Some((center, radius)) => Disk {
+                center: point(center),
+                radius,
+            },
+            None => Disk::infinite(),
+        }
+    }
+
+    /// The pole maps to infinity.
+    fn transform_point(&self, p: Point2<f64>) -> Point2<f64> {
+        self.map.transform_point(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        flame::{BoundedState, State},
+        geometry::{Bounds, Disk, Rect, Transform},
+        mobius::{contains_attractor, modulus, Mobius, MobiusState},
+    };
+    use nalgebra::{Complex, Point2};
+
+    fn c(re: f64, im: f64) -> Complex<f64> {
+        Complex::new(re, im)
+    }
+
+    fn example() -> Mobius {
+        Mobius::new(c(0.5, 0.1), c(0.3, 0.0), c(0.2, -0.1), c(1.0, 0.0))
+    }
+
+    /// Two maps which take the unit disk into itself.
+    fn pair() -> [Mobius; 2] {
+        [
+            example(),
+            Mobius::new(c(0.4, 0.0), c(-0.4, 0.0), c(0.0, 0.0), c(1.0, 0.0)),
+        ]
+    }
+
+    #[test]
+    fn apply() {
+        let m = example();
+        assert_eq!(Mobius::identity().apply(c(2.0, 3.0)), Some(c(2.0, 3.0)));
+        assert_eq!(m.apply(c(0.0, 0.0)), Some(c(0.3, 0.0)));
+        // The pole.
+        let inversion = Mobius::new(c(1.0, 0.0), c(0.0, 0.0), c(0.5, 0.0), c(1.0, 0.0));
+        assert_eq!(inversion.apply(c(-2.0, 0.0)), None);
+        assert_eq!(
+            m.map_point(Point2::new(0.0, 0.0)),
+            Some(Point2::new(0.3, 0.0))
+        );
+        assert_eq!(Mobius::from_coefs(m.to_coefs()), m);
+
+        let z = c(0.25, -0.5);
+        let round_trip = m.inverse().apply(m.apply(z).unwrap()).unwrap();
+        assert!(modulus(round_trip - z) < 1e-12);
+        let other = Mobius::new(c(0.0, 1.0), c(1.0, 0.0), c(0.5, 0.0), c(2.0, 0.0));
+        let composed = m.compose(&other).apply(z).unwrap();
+        assert!(modulus(composed - m.apply(other.apply(z).unwrap()).unwrap()) < 1e-12);
+        let normalized = m.compose(&other).normalized();
+        assert!((modulus(normalized.determinant()) - 1.0).abs() < 1e-12);
+        assert!(modulus(normalized.apply(z).unwrap() - composed) < 1e-12);
+    }
+
+    #[test]
+    fn fixed_point() {
+        for m in pair() {
+            let z = m.attracting_fixed_point().unwrap();
+            assert!(modulus(m.apply(z).unwrap() - z) < 1e-12);
+            // Iterating converges to it.
+            let mut w = c(0.1, 0.1);
+            for _ in 0..100 {
+                w = m.apply(w).unwrap();
+            }
+            assert!(modulus(w - z) < 1e-9);
+        }
+        // Translations and rotations about a point attract nothing.
+        let translation = Mobius::new(c(1.0, 0.0), c(1.0, 0.0), c(0.0, 0.0), c(1.0, 0.0));
+        assert_eq!(translation.attracting_fixed_point(), None);
+        let rotation = Mobius::new(c(0.0, 1.0), c(0.0, 0.0), c(0.0, 0.0), c(1.0, 0.0));
+        assert_eq!(rotation.attracting_fixed_point(), None);
+    }
+
+    #[test]
+    fn disk() {
+        let m = example();
+        let (center, radius) = (c(0.1, 0.2), 0.75);
+        let (image_center, image_radius) = m.map_disk(center, radius).unwrap();
+        let stretch = m.max_stretch(center, radius).unwrap();
+        for i in 0..64 {
+            let angle = core::f64::consts::TAU * f64::from(i) / 64.0;
+            let z = center + c(angle.cos(), angle.sin()) * radius;
+            let w = m.apply(z).unwrap();
+            assert!((modulus(w - image_center) - image_radius).abs() < 1e-9);
+            // Bounds the derivative, checked with a finite difference.
+            let step = c(-angle.sin(), angle.cos()) * 1e-6;
+            let (a, b) = (z - step * 0.5, z + step * 0.5);
+            let stretched = modulus(m.apply(b).unwrap() - m.apply(a).unwrap()) / modulus(b - a);
+            assert!(stretched <= stretch * (1.0 + 1e-6));
+        }
+        // Containing the pole.
+        assert_eq!(m.map_disk(-m.d / m.c, 0.1), None);
+        assert_eq!(m.max_stretch(-m.d / m.c, 0.1), None);
+    }
+
+    /// The image of a rect's edges curves outside its mapped corners, but not outside `transform_bounds`.
+    #[test]
+    fn rect_bounds() {
+        let inversion = Mobius::new(c(0.0, 0.0), c(1.0, 0.0), c(1.0, 0.0), c(0.0, 0.0));
+        let rect = Rect {
+            min: Point2::new(1.0, -1.0),
+            max: Point2::new(2.0, 1.0),
+        };
+        let bounds = inversion.transform_bounds(&rect);
+        let corners = rect
+            .corners()
+            .map(|p| Rect::point(inversion.transform_point(p)))
+            .into_iter()
+            .reduce(|a, b| Rect::union(&a, &b))
+            .unwrap();
+        let mut outside_corners = false;
+        for i in 0..=64 {
+            let t = f64::from(i) / 64.0;
+            for p in [
+                Point2::new(1.0 + t, -1.0),
+                Point2::new(1.0 + t, 1.0),
+                Point2::new(1.0, 2.0 * t - 1.0),
+                Point2::new(2.0, 2.0 * t - 1.0),
+            ] {
+                let image = inversion.transform_point(p);
+                assert!(bounds.contains_point(image));
+                outside_corners |= !corners.contains_point(image);
+            }
+        }
+        assert!(outside_corners);
+        // Unbounded around the pole.
+        let around_pole = Rect {
+            min: Point2::new(-1.0, -1.0),
+            max: Point2::new(1.0, 1.0),
+        };
+        assert!(inversion.transform_bounds(&around_pole).is_infinite());
+    }
+
+    #[test]
+    fn attractor() {
+        let maps = pair();
+        let (center, radius) = (c(0.0, 0.0), 1.0);
+        assert!(contains_attractor(&maps, center, radius));
+        assert!(maps
+            .iter()
+            .all(|m| m.max_stretch(center, radius).unwrap() < 1.0));
+        assert!(!contains_attractor(&maps, c(5.0, 0.0), 0.5));
+    }
+
+    /// The bounds contain every state's image of themselves, and the points of the chaos game.
+    #[test]
+    fn bounds() {
+        let maps = pair();
+        let state = MobiusState::new(&maps);
+        let bounds = state.get_bounds(4);
+        assert!(!bounds.is_infinite());
+        assert!(bounds.radius < 1.0);
+        assert!(bounds.contains(&state.union_transformed(4, &bounds)));
+        let mut z = c(0.0, 0.0);
+        for i in 0..1000 {
+            // The Thue-Morse sequence: an aperiodic choice of maps.
+            z = maps[(i as u32).count_ones() as usize % 2].apply(z).unwrap();
+            let p = Point2::new(z.re, z.im);
+            assert!(bounds.contains(&Disk {
+                center: p,
+                radius: 0.0
+            }));
+        }
+
+        // States are the maps composed in the same order as `AffineState`: the first level's map is applied first.
+        let mut states = vec![];
+        state.process_levels(2, &mut |s| states.push(s.map));
+        let z = c(0.2, 0.1);
+        let expected = maps[1].apply(maps[0].apply(z).unwrap()).unwrap();
+        assert!(modulus(states[1].apply(z).unwrap() - expected) < 1e-12);
+
+        // A map with a pole near its attractor: the origin maps to the attractor, but the bounds must exclude the pole.
+        let inversion = [Mobius::new(
+            c(0.0, 0.0),
+            c(0.5, 0.0),
+            c(1.0, 0.0),
+            c(1.5, 0.0),
+        )];
+        assert!(!MobiusState::new(&inversion).get_bounds(3).is_infinite());
+    }
+}
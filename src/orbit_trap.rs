@@ -0,0 +1,151 @@
+//! Orbit trap coloring: chaos game points colored by how close their recent orbit came to a shape.
+//! The accumulation buffers only hold density, so this is only computed on the CPU (see `cpu_raster`).
+
+use na::{Affine2, Point2};
+use winit::dpi::PhysicalSize;
+
+use crate::{flame::Root, geometry::Rect};
+
+/// Shape points are trapped by. Centered in (and sized relative to) the output bounds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trap {
+    Point,
+    /// Horizontal line.
+    Line,
+    /// Circle with a diameter of half the bounds' larger side.
+    Circle,
+}
+
+impl Trap {
+    /// Distance from `p` to the trap, as a fraction of the larger side of `bounds`.
+    pub fn distance(&self, bounds: &Rect, p: Point2<f64>) -> f64 {
+        let center = na::center(&bounds.min, &bounds.max);
+        let size = f64::max(bounds.width(), bounds.height());
+        let d = match self {
+            Trap::Point => (p - center).norm(),
+            Trap::Line => (p.y - center.y).abs(),
+            Trap::Circle => ((p - center).norm() - size / 4.0).abs(),
+        };
+        d / size
+    }
+}
+
+/// Number of points (each point and its predecessors) checked against the trap.
+const ORBIT: usize = 4;
+
+/// Points discarded before the orbit settles onto the attractor.
+const BURN_IN: usize = 32;
+
+/// Points iterated at once, to bound memory use.
+const BATCH: usize = 1 << 16;
+
+/// Chaos game density (in points) and the sum of the points' trap values for each pixel (top row first).
+/// A point's trap value is the smallest distance to `trap` of it and its `ORBIT - 1` predecessors.
+/// `view` maps flame space to pixels, with y up.
+pub fn accumulate(
+    root: &Root,
+    trap: Trap,
+    bounds: &Rect,
+    view: &Affine2<f64>,
+    size: PhysicalSize<u32>,
+    samples: usize,
+    random: &mut impl FnMut() -> f64,
+) -> (Vec<f64>, Vec<f64>) {
+    let (width, height) = (size.width as usize, size.height as usize);
+    let mut density = vec![0.0; width * height];
+    let mut traps = vec![0.0; width * height];
+
+    let weights = root.chaos_weights();
+    let mut p = root
+        .fixed_points()
+        .first()
+        .copied()
+        .unwrap_or(Point2::origin());
+    let mut recent = [f64::INFINITY; ORBIT];
+    let mut skip = BURN_IN;
+    let mut remaining = samples;
+    while remaining > 0 {
+        let count = usize::min(remaining, BATCH);
+        let points = root.iterate_points(p, count, skip, &weights, random);
+        let Some(last) = points.last() else {
+            break;
+        };
+        p = *last;
+        skip = 0;
+        remaining -= count;
+        for (i, point) in points.iter().enumerate() {
+            recent[i % ORBIT] = trap.distance(bounds, *point);
+            let pixel = view * point;
+            if pixel.x >= 0.0 && pixel.y >= 0.0 {
+                let (x, y) = (pixel.x as usize, pixel.y as usize);
+                if x < width && y < height {
+                    let index = (height - 1 - y) * width + x;
+                    density[index] += 1.0;
+                    traps[index] += recent.iter().copied().fold(f64::INFINITY, f64::min);
+                }
+            }
+        }
+    }
+    (density, traps)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        flame::Root,
+        geometry::Rect,
+        orbit_trap::{accumulate, Trap},
+        rng::Rng,
+    };
+    use na::{Affine2, Matrix3, Point2};
+    use winit::dpi::PhysicalSize;
+
+    #[test]
+    fn distances() {
+        let bounds = Rect {
+            min: Point2::new(-1.0, -1.0),
+            max: Point2::new(1.0, 1.0),
+        };
+        let p = Point2::new(0.0, 1.0);
+        assert_eq!(Trap::Point.distance(&bounds, p), 0.5);
+        assert_eq!(Trap::Line.distance(&bounds, p), 0.5);
+        assert_eq!(Trap::Circle.distance(&bounds, p), 0.25);
+        assert_eq!(Trap::Circle.distance(&bounds, Point2::new(0.5, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn every_sample_lands() {
+        // Two maps onto the halves of the bottom edge of the unit square, so every point is inside it.
+        let root = Root::new(
+            [0.0, 0.5]
+                .map(|x| {
+                    Affine2::from_matrix_unchecked(Matrix3::new(
+                        0.5, 0.0, x, 0.0, 0.5, 0.0, 0.0, 0.0, 1.0,
+                    ))
+                })
+                .to_vec(),
+        );
+        let bounds = Rect {
+            min: Point2::new(0.0, 0.0),
+            max: Point2::new(1.0, 1.0),
+        };
+        let view = Affine2::from_matrix_unchecked(Matrix3::new(
+            8.0, 0.0, 0.0, 0.0, 8.0, 0.0, 0.0, 0.0, 1.0,
+        ));
+        let mut rng = Rng::new(1);
+        let samples = 100_000;
+        let (density, traps) = accumulate(
+            &root,
+            Trap::Point,
+            &bounds,
+            &view,
+            PhysicalSize::new(8, 8),
+            samples,
+            &mut || rng.next_f64(),
+        );
+        assert_eq!(density.iter().sum::<f64>(), samples as f64);
+        for (d, t) in density.iter().zip(traps) {
+            assert!((0.0..=*d).contains(&t));
+        }
+    }
+}
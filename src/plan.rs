@@ -0,0 +1,598 @@
+//! Chooses the chain of accumulation passes used to render a flame.
+//! This is independent of the GPU, so it can be inspected and tested on its own.
+
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    accumulate::{PassKey, ScaleAnalysis},
+    flame::expansion_factor,
+    geometry::{letter_box_scale, OrientedRect, Rect},
+};
+
+/// Default for `Inputs::min_pass_size`.
+pub const DEFAULT_MIN_PASS_SIZE: u32 = 16;
+
+/// Avoid instance and mesh buffers being too large.
+const BUFFER_LIMIT: usize = 512;
+
+/// Most quads `max_levels` allows a single pass to draw.
+const LEVELS_QUAD_LIMIT: usize = 1 << 20;
+
+/// Most quads in the mesh of a pass drawing subdivided states (see `Inputs::subdivisions`).
+const SUBDIVIDED_QUAD_LIMIT: usize = 1 << 16;
+
+/// How the size of smaller passes is rounded.
+/// Either way the size is never less than what is needed to preserve resolution.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum SizeRounding {
+    /// Round up to a multiple of 8, which improves the odds of reusing textures as the flame changes.
+    MultipleOf8,
+    /// Round up to the next whole pixel.
+    Exact,
+}
+
+/// Everything about the flame and device which planning depends on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Inputs {
+    pub bounds: OrientedRect,
+    /// Bounds fit to the output pass (after the final transform and camera rotation).
+    pub output_bounds: Rect,
+    pub scale: ScaleAnalysis,
+    /// Number of transforms drawn, including any symmetric copies (see `Root::with_symmetry`).
+    pub n: usize,
+    pub max_texture_size: u32,
+    pub rounding: SizeRounding,
+    /// If set, the output pass is planned as if it had this size (but still rendered at its own),
+    /// so the smaller passes do not depend on the output size, and exports match the preview.
+    pub locked: Option<PhysicalSize<u32>>,
+    /// Part of the output bounds (as fractions, see `Rect::sub_rect`) fit to the output pass.
+    pub crop: Option<Rect>,
+    /// Passes smaller than this (in both dimensions, see `min_pass_size`) are not worth recursing into.
+    pub min_pass_size: u32,
+    /// If set, overrides `min_pass_size` to use this many passes, where possible (smaller passes must still shrink).
+    pub depth: Option<u32>,
+    /// If set, the output pass draws exactly this many levels of solid quads (with no smaller passes),
+    /// so the levels drawn are exactly those of `State::process_levels`. For watching the flame emerge level by level.
+    pub levels: Option<u32>,
+    /// If set, each pass draws the number of levels whose quads (`n.pow(levels)`) come closest to averaging
+    /// this many per pixel of the area they fill, instead of choosing levels from fixed pass size thresholds.
+    pub samples_per_pixel: Option<f64>,
+    /// If set, each state is drawn as a grid of this many quads per side, all in the mesh, without instancing.
+    /// For states which are not affine (see `mesh::build_mobius_mesh`).
+    pub subdivisions: Option<u32>,
+}
+
+/// Most levels a pass can draw with `Inputs::levels` before it has too many quads.
+pub fn max_levels(n: usize) -> u32 {
+    (0..64)
+        .take_while(|levels| {
+            n.checked_pow(*levels)
+                .is_some_and(|quads| quads <= LEVELS_QUAD_LIMIT)
+        })
+        .last()
+        .unwrap_or(0)
+}
+
+/// Levels (at least 2) for which the quads a pass draws come closest to averaging `samples_per_pixel`
+/// over `fill_area` pixels. Compared by ratio, since each level multiplies the quads by `n`.
+fn density_levels(n: usize, fill_area: f64, samples_per_pixel: f64) -> u32 {
+    let wanted = f64::max(1.0, samples_per_pixel * fill_area).ln();
+    let error = |levels: u32| (levels as f64 * (n as f64).ln() - wanted).abs();
+    (2..=u32::max(2, max_levels(n)))
+        .min_by(|a, b| error(*a).total_cmp(&error(*b)))
+        .unwrap_or(2)
+}
+
+/// Size below which passes are not recursed into: `inputs.min_pass_size`, scaled up with the number of transforms.
+/// More transforms multiply the quads drawn per level faster, so the smallest pass can be larger
+/// (covering the same detail in fewer levels).
+fn min_pass_size(inputs: &Inputs) -> u32 {
+    let scale = f64::sqrt(inputs.n.max(4) as f64 / 4.0);
+    (f64::from(inputs.min_pass_size) * scale) as u32
+}
+
+/// A single accumulation pass.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Accumulate {
+    pub key: PassKey,
+    /// Number of levels of transforms drawn by this pass.
+    pub levels: u32,
+    /// Pass sampled from by this one, or None if this pass draws solid quads.
+    pub smaller: Option<PassKey>,
+    /// If false, every level is in the mesh (see `Inputs::subdivisions`).
+    pub instanced: bool,
+}
+
+impl Accumulate {
+    pub fn mesh_levels(&self) -> u32 {
+        self.levels - self.instance_levels()
+    }
+
+    pub fn instance_levels(&self) -> u32 {
+        if self.instanced {
+            self.levels / 2
+        } else {
+            0
+        }
+    }
+}
+
+/// Every pass needed to render a `PassKey`, largest first.
+/// Each pass samples from the one after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Plan {
+    pub passes: Vec<Accumulate>,
+}
+
+impl Plan {
+    /// Total levels of transforms applied to the smallest pass's quads.
+    pub fn levels(&self) -> u32 {
+        self.passes.iter().map(|p| p.levels).sum()
+    }
+}
+
+/// Size in pixels for a texture which needs at least `s`.
+fn texture_size(s: f64, rounding: SizeRounding) -> u32 {
+    let exact = u32::max(1, s.ceil() as u32);
+    match rounding {
+        SizeRounding::MultipleOf8 => exact.div_ceil(8) * 8,
+        SizeRounding::Exact => exact,
+    }
+}
+
+/// Lowers `levels` (to no less than 1) until the mesh of subdivided states fits in `SUBDIVIDED_QUAD_LIMIT`.
+/// Unchanged unless `inputs.subdivisions` is set.
+fn fit_subdivided(inputs: &Inputs, mut levels: u32) -> u32 {
+    if let Some(divisions) = inputs.subdivisions {
+        let quads = |levels: u32| {
+            inputs
+                .n
+                .checked_pow(levels)
+                .and_then(|states| states.checked_mul((divisions * divisions) as usize))
+        };
+        while levels > 1 && !matches!(quads(levels), Some(quads) if quads <= SUBDIVIDED_QUAD_LIMIT)
+        {
+            levels -= 1;
+        }
+    }
+    levels
+}
+
+/// Chooses how many levels the pass for `key` draws, and the smaller pass (if any) it samples.
+pub fn step(inputs: &Inputs, key: &PassKey) -> Accumulate {
+    let instanced = inputs.subdivisions.is_none();
+    if let Some(levels) = inputs.levels {
+        return Accumulate {
+            key: key.clone(),
+            levels: fit_subdivided(inputs, u32::min(levels, max_levels(inputs.n))),
+            smaller: None,
+            instanced,
+        };
+    }
+    let b = inputs.bounds;
+    // Tiles use the pixel density of the whole image.
+    let full = key.tile.map_or(key.resolution, |tile| tile.full);
+    let full = match inputs.locked {
+        Some(locked) if key.axis_aligned => locked,
+        _ => full,
+    };
+    let lb_scale = letter_box_scale(
+        Rect {
+            min: na::Point2::origin(),
+            max: na::Point2::new(full.width as f64, full.height as f64),
+        },
+        if key.axis_aligned {
+            inputs.crop.map_or(inputs.output_bounds, |crop| {
+                inputs.output_bounds.sub_rect(&crop)
+            })
+        } else {
+            b.rect
+        },
+    );
+    // Zooming the view in magnifies the output pass, so the smaller passes need more pixels to keep up.
+    let lb_scale = lb_scale * key.view.map_or(1.0, |view| expansion_factor(&view.get()));
+
+    // Smaller passes always hold the oriented bounds.
+    let width_to_fill = lb_scale * b.rect.width();
+    let height_to_fill = lb_scale * b.rect.height();
+
+    let fill_area = inputs.scale.fill_ratio * width_to_fill * height_to_fill;
+
+    let mut levels: u32 = match inputs.samples_per_pixel {
+        Some(samples) => density_levels(inputs.n, fill_area, samples),
+        None if fill_area > 1024.0 * 1024.0 => 2,
+        None if fill_area > 256.0 * 256.0 => 6,
+        None => 8,
+    };
+
+    while levels > 2 && inputs.n.pow(levels / 2) > BUFFER_LIMIT {
+        levels -= 1;
+    }
+    levels = fit_subdivided(inputs, levels);
+
+    let sf = inputs.scale.sf_min.powi(levels as i32);
+
+    let width = u32::min(
+        inputs.max_texture_size,
+        texture_size(width_to_fill * sf, inputs.rounding),
+    );
+    let height = u32::min(
+        inputs.max_texture_size,
+        texture_size(height_to_fill * sf, inputs.rounding),
+    );
+
+    // Smaller passes must actually shrink, or the recursion might never end
+    // (ex: for non-contractive transforms, or sizes held up by rounding).
+    // The output pass has a different frame (axis-aligned bounds), so can not be compared this way.
+    let shrinks = key.axis_aligned
+        || (width <= key.resolution.width
+            && height <= key.resolution.height
+            && [width, height] != [key.resolution.width, key.resolution.height]);
+    let wanted = match inputs.depth {
+        Some(depth) => key.depth + 1 < depth,
+        None => {
+            let cutoff = min_pass_size(inputs);
+            width > cutoff || height > cutoff
+        }
+    };
+    let smaller = if sf < 1.0 && shrinks && wanted {
+        Some(PassKey {
+            filter: true,
+            resolution: [width, height].into(),
+            axis_aligned: false,
+            tile: None,
+            view: None,
+            depth: key.depth + 1,
+        })
+    } else {
+        None
+    };
+    Accumulate {
+        key: key.clone(),
+        levels,
+        smaller,
+        instanced,
+    }
+}
+
+/// Plans all the passes needed to render `key`.
+pub fn plan(inputs: &Inputs, key: PassKey) -> Plan {
+    let mut passes = vec![];
+    let mut next = Some(key);
+    while let Some(key) = next {
+        let pass = step(inputs, &key);
+        next = pass.smaller.clone();
+        passes.push(pass);
+    }
+    Plan { passes }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accumulate::{Accumulation, PassKey, ScaleAnalysis, ViewTransform},
+        flame::{Root, Symmetry},
+        geometry::{OrientedRect, Rect},
+        plan::{
+            density_levels, max_levels, plan, texture_size, Inputs, Plan, SizeRounding,
+            BUFFER_LIMIT, DEFAULT_MIN_PASS_SIZE, LEVELS_QUAD_LIMIT, SUBDIVIDED_QUAD_LIMIT,
+        },
+    };
+    use winit::dpi::PhysicalSize;
+
+    fn inputs(n: usize, sf: f64) -> Inputs {
+        let bounds = Rect {
+            min: na::Point2::new(-1.0, -1.0),
+            max: na::Point2::new(1.0, 1.0),
+        };
+        Inputs {
+            bounds: OrientedRect::axis_aligned(bounds),
+            output_bounds: bounds,
+            scale: ScaleAnalysis {
+                sf_min: sf,
+                fill_ratio: n as f64 * sf * sf,
+            },
+            n,
+            max_texture_size: 8192,
+            rounding: SizeRounding::MultipleOf8,
+            locked: None,
+            crop: None,
+            min_pass_size: DEFAULT_MIN_PASS_SIZE,
+            depth: None,
+            levels: None,
+            samples_per_pixel: None,
+            subdivisions: None,
+        }
+    }
+
+    fn root_key(width: u32, height: u32) -> PassKey {
+        PassKey {
+            resolution: PhysicalSize::new(width, height),
+            filter: false,
+            axis_aligned: true,
+            tile: None,
+            view: None,
+            depth: 0,
+        }
+    }
+
+    #[test]
+    fn passes_shrink() {
+        for n in [2, 3, 5, 12] {
+            let p = plan(&inputs(n, 0.5), root_key(1920, 1080));
+            assert_eq!(p.passes[0].key, root_key(1920, 1080));
+            for pair in p.passes.windows(2) {
+                assert_eq!(pair[0].smaller.as_ref(), Some(&pair[1].key));
+                let (a, b) = (pair[0].key.resolution, pair[1].key.resolution);
+                assert!(b.width <= a.width && b.height <= a.height);
+                assert!(n.pow(pair[0].instance_levels()) <= 512);
+            }
+            let last = p.passes.last().unwrap();
+            assert_eq!(last.smaller, None);
+            assert!(p.levels() > 0);
+        }
+    }
+
+    #[test]
+    fn explicit_levels() {
+        for levels in 0..4 {
+            let stepped = Inputs {
+                levels: Some(levels),
+                ..inputs(5, 0.5)
+            };
+            let p = plan(&stepped, root_key(640, 480));
+            assert_eq!(p.passes.len(), 1);
+            assert_eq!(p.levels(), levels);
+        }
+        let too_many = Inputs {
+            levels: Some(100),
+            ..inputs(5, 0.5)
+        };
+        assert_eq!(plan(&too_many, root_key(640, 480)).levels(), max_levels(5));
+        assert_eq!(max_levels(2), 20);
+        assert_eq!(max_levels(1), 63);
+    }
+
+    /// Symmetry multiplies the transforms (by up to 24 for `Dihedral(12)`), and the plan must use the expanded count:
+    /// planning for the points alone would choose levels whose buffers hold hundreds of times more quads.
+    #[test]
+    fn symmetric_root() {
+        let points = 12;
+        let root = Root::new(vec![
+            na::convert(na::Similarity2::from_scaling(0.3));
+            points
+        ])
+        .with_symmetry(Symmetry::Dihedral(12));
+        let n = root.transforms().len();
+        assert_eq!(n, points * 24);
+        for levels in [None, Some(20)] {
+            let inputs = Inputs {
+                levels,
+                ..inputs(n, 0.5)
+            };
+            let p = plan(&inputs, root_key(1920, 1080));
+            for pass in &p.passes {
+                assert!(n.pow(pass.instance_levels()) <= BUFFER_LIMIT);
+                assert!(n.pow(pass.mesh_levels()) <= LEVELS_QUAD_LIMIT);
+            }
+        }
+        let undercounted = plan(&inputs(points, 0.5), root_key(1920, 1080));
+        assert!(undercounted
+            .passes
+            .iter()
+            .any(|pass| n.pow(pass.instance_levels()) > BUFFER_LIMIT));
+    }
+
+    #[test]
+    fn texture_sizes() {
+        use SizeRounding::*;
+        for (s, multiple, exact) in [
+            (0.0, 8, 1),
+            (0.5, 8, 1),
+            (7.9, 8, 8),
+            (8.0, 8, 8),
+            (8.1, 16, 9),
+            (1000.5, 1008, 1001),
+        ] {
+            assert_eq!(texture_size(s, MultipleOf8), multiple, "{s}");
+            assert_eq!(texture_size(s, Exact), exact, "{s}");
+            assert!(f64::from(texture_size(s, Exact)) >= s);
+        }
+    }
+
+    #[test]
+    fn locked_resolution() {
+        let locked = Inputs {
+            locked: Some(PhysicalSize::new(1920, 1080)),
+            ..inputs(5, 0.5)
+        };
+        let preview = plan(&locked, root_key(640, 480));
+        let export = plan(&locked, root_key(7680, 4320));
+        assert_eq!(preview.passes[0].levels, export.passes[0].levels);
+        assert_eq!(preview.passes[1..], export.passes[1..]);
+        assert_eq!(
+            plan(&locked, root_key(1920, 1080)),
+            plan(&inputs(5, 0.5), root_key(1920, 1080))
+        );
+    }
+
+    #[test]
+    fn crop_increases_resolution() {
+        let cropped = Inputs {
+            crop: Some(Rect {
+                min: na::Point2::new(0.25, 0.25),
+                max: na::Point2::new(0.75, 0.75),
+            }),
+            ..inputs(5, 0.5)
+        };
+        let full = plan(&inputs(5, 0.5), root_key(640, 480));
+        let cropped = plan(&cropped, root_key(640, 480));
+        // Without a crop this is small enough to need no smaller pass at all.
+        let resolution = |p: &Plan| p.passes.get(1).map_or(0, |p| p.key.resolution.width);
+        assert!(resolution(&cropped) > resolution(&full));
+    }
+
+    #[test]
+    fn zoom_increases_resolution() {
+        let zoomed = |zoom: f64| PassKey {
+            view: Some(ViewTransform::new(&na::convert(
+                na::Similarity2::from_scaling(zoom),
+            ))),
+            ..root_key(640, 480)
+        };
+        let resolution = |p: &Plan| p.passes.get(1).map_or(0, |p| p.key.resolution.width);
+        let full = plan(&inputs(5, 0.5), root_key(640, 480));
+        assert_eq!(
+            plan(&inputs(5, 0.5), zoomed(1.0)).passes[1..],
+            full.passes[1..]
+        );
+        assert!(resolution(&plan(&inputs(5, 0.5), zoomed(4.0))) > resolution(&full));
+    }
+
+    #[test]
+    fn log_accumulation_only_on_screen() {
+        let p = plan(&inputs(5, 0.5), root_key(1920, 1080));
+        assert!(p.passes.len() > 1);
+        for (i, pass) in p.passes.iter().enumerate() {
+            let expected = if i == 0 {
+                Accumulation::Log
+            } else {
+                Accumulation::Linear
+            };
+            assert_eq!(Accumulation::Log.for_pass(&pass.key), expected);
+            assert_eq!(
+                Accumulation::Linear.for_pass(&pass.key),
+                Accumulation::Linear
+            );
+        }
+    }
+
+    #[test]
+    fn subdivided() {
+        for n in [2, 3, 5, 12] {
+            for levels in [None, Some(20)] {
+                let inputs = Inputs {
+                    subdivisions: Some(8),
+                    levels,
+                    ..inputs(n, 0.5)
+                };
+                let p = plan(&inputs, root_key(1920, 1080));
+                for pass in &p.passes {
+                    assert_eq!(pass.instance_levels(), 0);
+                    assert_eq!(pass.mesh_levels(), pass.levels);
+                    assert!(n.pow(pass.levels) * 64 <= SUBDIVIDED_QUAD_LIMIT);
+                }
+            }
+        }
+        let p = plan(&inputs(5, 0.5), root_key(1920, 1080));
+        assert!(p.passes.iter().any(|pass| pass.instance_levels() > 0));
+    }
+
+    #[test]
+    fn tiny_output_single_pass() {
+        let p = plan(
+            &inputs(5, 0.5),
+            root_key(DEFAULT_MIN_PASS_SIZE, DEFAULT_MIN_PASS_SIZE),
+        );
+        assert_eq!(p.passes.len(), 1);
+    }
+
+    #[test]
+    fn depth_override() {
+        let key = root_key(1920, 1080);
+        let natural = plan(&inputs(5, 0.5), key.clone()).passes.len() as u32;
+        for depth in [1, natural, natural + 1] {
+            let p = plan(
+                &Inputs {
+                    depth: Some(depth),
+                    ..inputs(5, 0.5)
+                },
+                key.clone(),
+            );
+            assert_eq!(p.passes.len() as u32, depth);
+            for (i, pass) in p.passes.iter().enumerate() {
+                assert_eq!(pass.key.depth, i as u32);
+            }
+        }
+    }
+
+    #[test]
+    fn extreme_aspect_ratios_terminate() {
+        for (width, height) in [(1000.0, 1.0), (1.0, 1000.0), (1000.0, 0.0), (0.0, 0.0)] {
+            for sf in [0.1, 0.5, 0.99, 1.0, 2.0] {
+                for min_pass_size in [1, 16, 256] {
+                    let bounds = Rect {
+                        min: na::Point2::new(0.0, 0.0),
+                        max: na::Point2::new(width, height),
+                    };
+                    let inputs = Inputs {
+                        bounds: OrientedRect::axis_aligned(bounds),
+                        output_bounds: bounds,
+                        min_pass_size,
+                        ..inputs(3, sf)
+                    };
+                    let p = plan(&inputs, root_key(1920, 1080));
+                    assert!(p.passes.len() < 64, "{width}x{height} {sf}");
+                    for pass in &p.passes[1..] {
+                        let size = pass.key.resolution;
+                        assert!(size.width >= 1 && size.width <= inputs.max_texture_size);
+                        assert!(size.height >= 1 && size.height <= inputs.max_texture_size);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn min_pass_size_setting() {
+        let coarse = Inputs {
+            min_pass_size: 256,
+            ..inputs(5, 0.5)
+        };
+        let fine = Inputs {
+            min_pass_size: 1,
+            ..inputs(5, 0.5)
+        };
+        let key = root_key(1920, 1080);
+        assert!(plan(&coarse, key.clone()).passes.len() < plan(&fine, key).passes.len());
+    }
+
+    #[test]
+    fn sample_density() {
+        assert_eq!(density_levels(3, 256.0, 1.0), 5);
+        assert_eq!(density_levels(3, 256.0, 0.001), 2);
+        assert_eq!(density_levels(2, 1e12, 1.0), max_levels(2));
+        for n in [2, 3, 5, 12] {
+            for fill_area in [100.0, 1e4, 1e6] {
+                for samples in [0.25, 1.0, 4.0] {
+                    let levels = density_levels(n, fill_area, samples);
+                    if levels > 2 && levels < max_levels(n) {
+                        let achieved = n.pow(levels) as f64 / fill_area;
+                        let within = (n as f64).sqrt();
+                        assert!(achieved <= samples * within && achieved >= samples / within);
+                    }
+                }
+            }
+        }
+
+        let key = root_key(1920, 1080);
+        let levels = |samples| {
+            let targeted = Inputs {
+                samples_per_pixel: Some(samples),
+                ..inputs(3, 0.5)
+            };
+            let p = plan(&targeted, key.clone());
+            assert!(p.passes.len() < 64);
+            for pair in p.passes.windows(2) {
+                let (a, b) = (pair[0].key.resolution, pair[1].key.resolution);
+                assert!(b.width <= a.width && b.height <= a.height);
+                assert!(3usize.pow(pair[0].instance_levels()) <= 512);
+            }
+            p.passes[0].levels
+        };
+        assert!(levels(0.01) < levels(1.0));
+        assert!(levels(1.0) <= levels(100.0));
+        levels(1e-9);
+        levels(1e9);
+    }
+}
@@ -1,21 +1,70 @@
-use std::borrow::Cow;
+use serde::{Deserialize, Serialize};
+use std::{mem, sync::Mutex};
 use wgpu::{
     AddressMode, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutEntry, BindingResource,
-    BindingType, FilterMode, PipelineLayoutDescriptor, SamplerDescriptor, ShaderModuleDescriptor,
-    ShaderSource, ShaderStages, TextureAspect, TextureDescriptor, TextureFormat, TextureSampleType,
-    TextureUsages, TextureViewDescriptor, TextureViewDimension,
+    BindingType, PipelineLayoutDescriptor, SamplerDescriptor, ShaderStages, TextureAspect,
+    TextureDescriptor, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor,
+    TextureViewDimension,
 };
+use winit::dpi::PhysicalSize;
 
 use crate::{
-    mesh::build_quad, render_common::MeshData, util_types::PtrRc, wgpu_render::Postprocesser,
+    histogram::{contrast_curve, curve_changed, identity_curve, CURVE_SIZE, IDENTITY_CONTRAST},
+    mesh::{build_quad, Vertex},
+    render_common::{begin_render_pass, float_sampling, shader_module, MeshData},
+    util_types::PtrRc,
+    wgpu_render::Postprocesser,
 };
 
 /// Device dependant, but otherwise constant data.
 #[derive(Debug)]
 pub struct Data {
-    gradient_bind_group: wgpu::BindGroup,
+    gradient_bind_group_layout: wgpu::BindGroupLayout,
+    gradient_sampler: wgpu::Sampler,
+    curve_view: wgpu::TextureView,
+    /// Uniforms for the fragment shader: the palette color coordinate, highlight rolloff and color vision simulation.
+    params: wgpu::Buffer,
+    /// Tone curve applied to the log density before looking up the gradient.
+    curve_texture: wgpu::Texture,
+    /// Tone curve `curve_texture` was last written with.
+    curve: Mutex<Vec<f32>>,
+    contrast_view: wgpu::TextureView,
+    /// The user's contrast curve, applied after the tone curve.
+    contrast_texture: wgpu::Texture,
+    /// Control points `contrast_texture` was last sampled from.
+    contrast_points: Mutex<Vec<[f32; 2]>>,
     quad: MeshData,
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+}
+
+/// Size of the uniform buffer holding `Data::params`: see `Params` in `postprocess.wgsl`.
+const PARAMS_SIZE: u64 = 64;
+
+/// 2D gradient: x is the (tone mapped) density, and y the color coordinate.
+#[derive(Debug)]
+pub struct Palette {
+    bind_group: wgpu::BindGroup,
+}
+
+/// Pipelines for rendering to a specific format.
+#[derive(Debug)]
+pub struct Pipelines {
     pipeline: wgpu::RenderPipeline,
+    /// Alpha blends over the existing contents of the target, with zero density being transparent.
+    transparent_pipeline: wgpu::RenderPipeline,
+    /// Blends over the existing contents of the target by the blend constant (the opacity).
+    fade_pipeline: wgpu::RenderPipeline,
+}
+
+/// Where to draw the flame.
+pub struct Target<'a> {
+    pub view: &'a wgpu::TextureView,
+    pub format: TextureFormat,
+    /// Top left corner of the region of `view` to draw into.
+    pub origin: [u32; 2],
+    /// Size of the region of `view` to draw into.
+    pub size: PhysicalSize<u32>,
 }
 
 pub fn data(db: &dyn Postprocesser, (): ()) -> PtrRc<Data> {
@@ -23,99 +72,393 @@ pub fn data(db: &dyn Postprocesser, (): ()) -> PtrRc<Data> {
     let queue = db.queue(());
     let data = db.data(());
 
-    let shader = device.create_shader_module(ShaderModuleDescriptor {
-        label: Some("postprocess.wgsl"),
-        source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("../shaders/postprocess.wgsl"))),
+    let shader = shader_module(
+        &device,
+        "postprocess.wgsl",
+        include_str!("../shaders/postprocess.wgsl"),
+    );
+
+    let curve_texture = create_curve_texture(&device, "curve_texture");
+    write_curve(&queue, &curve_texture, &identity_curve());
+    let curve_view = curve_texture.create_view(&TextureViewDescriptor::default());
+    let contrast_texture = create_curve_texture(&device, "contrast_texture");
+    write_curve(
+        &queue,
+        &contrast_texture,
+        &contrast_curve(&IDENTITY_CONTRAST),
+    );
+    let contrast_view = contrast_texture.create_view(&TextureViewDescriptor::default());
+    // Shared by the gradient and the R32Float curves, so only filters when the curves can be filtered.
+    let float_filtering = db.float_filtering(());
+    let (sampler_binding, filter) = float_sampling(float_filtering);
+    let gradient_sampler = device.create_sampler(&SamplerDescriptor {
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        address_mode_w: AddressMode::ClampToEdge,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter: filter, // TODO: mip map gradient
+        ..Default::default()
+    });
+
+    let gradient_bind_group_layout =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(sampler_binding),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float {
+                            filterable: float_filtering,
+                        },
+                        view_dimension: TextureViewDimension::D1,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float {
+                            filterable: float_filtering,
+                        },
+                        view_dimension: TextureViewDimension::D1,
+                    },
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("postprocess pipeline"),
+        bind_group_layouts: &[
+            &data.accumulation_bind_group_layout,
+            &gradient_bind_group_layout,
+        ],
+        push_constant_ranges: &[],
     });
 
-    let gradient_bytes = include_bytes!("../images/gradient.png");
-    let gradient_image = image::load_from_memory(gradient_bytes).unwrap();
-    let gradient_rgba = gradient_image.as_rgba8().unwrap();
+    Data {
+        gradient_bind_group_layout,
+        gradient_sampler,
+        curve_view,
+        params: device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("postprocess params"),
+            size: PARAMS_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }),
+        curve_texture,
+        curve: Mutex::new(identity_curve()),
+        contrast_view,
+        contrast_texture,
+        contrast_points: Mutex::new(IDENTITY_CONTRAST.to_vec()),
+        quad: MeshData::new(&device, &build_quad(), "Quad Vertex Buffer"),
+        shader,
+        pipeline_layout,
+    }
+    .into()
+}
+
+/// Rows of the default palette.
+const DEFAULT_PALETTE_ROWS: u32 = 64;
+
+/// The built in gradient, shifting hue with the color coordinate.
+fn default_palette() -> image::RgbaImage {
+    let gradient = image::load_from_memory(include_bytes!("../images/gradient.png"))
+        .unwrap()
+        .to_rgba8();
+    image::RgbaImage::from_fn(gradient.width(), DEFAULT_PALETTE_ROWS, |x, y| {
+        let f = y as f32 / (DEFAULT_PALETTE_ROWS - 1) as f32;
+        let [r, g, b, a] = gradient.get_pixel(x, 0).0;
+        let shifted = [g, b, r];
+        let mut out = [r, g, b, a];
+        for (c, s) in out.iter_mut().zip(shifted) {
+            *c = (f32::from(*c) * (1.0 - f) + f32::from(s) * f).round() as u8;
+        }
+        image::Rgba(out)
+    })
+}
+
+/// Where the palette comes from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PaletteSource {
+    Default,
+    /// Path of a 2D palette image (x is density, y is color).
+    Image(String),
+    /// 1D gradient (ex: from a flam3 file), used for all colors.
+    Colors(Vec<[u8; 3]>),
+}
+
+/// Width of the gradients built from `AccessiblePalette::STOPS`.
+const ACCESSIBLE_PALETTE_WIDTH: usize = 256;
+
+/// Built in gradients which stay distinguishable under common color vision deficiencies:
+/// their lightness increases steadily, and they avoid relying on red versus green.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessiblePalette {
+    Viridis,
+    /// Designed to look nearly the same with and without red-green color blindness.
+    Cividis,
+    /// Black through blue to orange and yellow (from the Okabe-Ito colors).
+    BlueOrange,
+}
+
+impl AccessiblePalette {
+    pub const ALL: [AccessiblePalette; 3] = [
+        AccessiblePalette::Viridis,
+        AccessiblePalette::Cividis,
+        AccessiblePalette::BlueOrange,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AccessiblePalette::Viridis => "Viridis",
+            AccessiblePalette::Cividis => "Cividis",
+            AccessiblePalette::BlueOrange => "Blue Orange",
+        }
+    }
+
+    /// Evenly spaced colors the gradient passes through, darkest first.
+    fn stops(self) -> &'static [[u8; 3]] {
+        match self {
+            AccessiblePalette::Viridis => &[
+                [0x44, 0x01, 0x54],
+                [0x47, 0x2d, 0x7b],
+                [0x3b, 0x52, 0x8b],
+                [0x2c, 0x72, 0x8e],
+                [0x21, 0x91, 0x8c],
+                [0x28, 0xae, 0x80],
+                [0x5e, 0xc9, 0x62],
+                [0xad, 0xdc, 0x30],
+                [0xfd, 0xe7, 0x25],
+            ],
+            AccessiblePalette::Cividis => &[
+                [0x00, 0x22, 0x4e],
+                [0x12, 0x35, 0x70],
+                [0x3b, 0x49, 0x6c],
+                [0x57, 0x5d, 0x6d],
+                [0x70, 0x71, 0x73],
+                [0x8a, 0x87, 0x79],
+                [0xa6, 0x9d, 0x75],
+                [0xc4, 0xb5, 0x6c],
+                [0xe4, 0xcf, 0x5b],
+                [0xfe, 0xe8, 0x38],
+            ],
+            AccessiblePalette::BlueOrange => &[
+                [0x00, 0x00, 0x00],
+                [0x00, 0x72, 0xb2],
+                [0xe6, 0x9f, 0x00],
+                [0xf0, 0xe4, 0x42],
+            ],
+        }
+    }
+
+    /// The gradient, for `PaletteSource::Colors`.
+    pub fn colors(self) -> Vec<[u8; 3]> {
+        let stops = self.stops();
+        let segments = (stops.len() - 1) as f32;
+        (0..ACCESSIBLE_PALETTE_WIDTH)
+            .map(|i| {
+                let t = i as f32 / (ACCESSIBLE_PALETTE_WIDTH - 1) as f32 * segments;
+                let segment = (t as usize).min(stops.len() - 2);
+                let f = t - segment as f32;
+                let (a, b) = (stops[segment], stops[segment + 1]);
+                [0, 1, 2].map(|c| (f32::from(a[c]) * (1.0 - f) + f32::from(b[c]) * f).round() as u8)
+            })
+            .collect()
+    }
+}
+
+/// Color vision deficiencies `Settings::color_vision` can simulate, to check a flame reads well with them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorVision {
+    /// No red cones.
+    Protanopia,
+    /// No green cones (the most common).
+    Deuteranopia,
+    /// No blue cones.
+    Tritanopia,
+}
+
+impl ColorVision {
+    pub const ALL: [ColorVision; 3] = [
+        ColorVision::Protanopia,
+        ColorVision::Deuteranopia,
+        ColorVision::Tritanopia,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorVision::Protanopia => "Protanopia",
+            ColorVision::Deuteranopia => "Deuteranopia",
+            ColorVision::Tritanopia => "Tritanopia",
+        }
+    }
+
+    /// Rows of the matrix mapping linear RGB to how it appears (Machado et al. 2009, at full severity).
+    pub fn matrix(self) -> [[f32; 3]; 3] {
+        match self {
+            ColorVision::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            ColorVision::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            ColorVision::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        }
+    }
+}
+
+/// Rows of the matrix the shader applies to the final colors: simulating `vision`, or the identity.
+fn color_vision_matrix(vision: Option<ColorVision>) -> [[f32; 3]; 3] {
+    vision.map_or(
+        [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        ColorVision::matrix,
+    )
+}
+
+/// Loads or builds the palette image.
+/// Falls back to the default (and logs) if loading fails.
+pub fn palette_image(source: &PaletteSource) -> image::RgbaImage {
+    match source {
+        PaletteSource::Default => default_palette(),
+        PaletteSource::Image(path) => match image::open(path) {
+            Ok(image) => image.to_rgba8(),
+            Err(e) => {
+                log::warn!("Failed to load palette {path}: {e}");
+                default_palette()
+            }
+        },
+        PaletteSource::Colors(colors) if !colors.is_empty() => {
+            image::RgbaImage::from_fn(colors.len() as u32, 1, |x, _| {
+                let [r, g, b] = colors[x as usize];
+                image::Rgba([r, g, b, 255])
+            })
+        }
+        PaletteSource::Colors(_) => default_palette(),
+    }
+}
+
+/// Row of `palette` closest to the color coordinate `color`.
+pub fn palette_row(palette: &image::RgbaImage, color: f32) -> u32 {
+    let last = palette.height() - 1;
+    ((color * last as f32).round() as u32).min(last)
+}
+
+pub fn palette(db: &dyn Postprocesser, source: PaletteSource) -> PtrRc<Palette> {
+    let device = db.device(());
+    let data = db.postprocess_data(());
 
-    use image::GenericImageView;
-    let dimensions = gradient_image.dimensions();
+    let image = db.palette_image(source);
 
     let texture_size = wgpu::Extent3d {
-        width: dimensions.0,
-        height: dimensions.1,
+        width: image.width(),
+        height: image.height(),
         depth_or_array_layers: 1,
     };
 
-    let gradient_texture = device.create_texture(&TextureDescriptor {
+    let texture = device.create_texture(&TextureDescriptor {
         size: texture_size,
         mip_level_count: 1,
         sample_count: 1,
-        dimension: wgpu::TextureDimension::D1,
+        dimension: wgpu::TextureDimension::D2,
         format: TextureFormat::Rgba8UnormSrgb,
         usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
         label: Some("gradient_texture"),
-        view_formats: &vec![],
+        view_formats: &[],
     });
 
-    queue.write_texture(
+    db.queue(()).write_texture(
         wgpu::ImageCopyTexture {
-            texture: &gradient_texture,
+            texture: &texture,
             mip_level: 0,
             origin: wgpu::Origin3d::ZERO,
             aspect: TextureAspect::All,
         },
-        gradient_rgba,
+        &image,
         wgpu::ImageDataLayout {
             offset: 0,
-            bytes_per_row: Some(4 * dimensions.0),
-            rows_per_image: Some(dimensions.1),
+            bytes_per_row: Some(4 * image.width()),
+            rows_per_image: Some(image.height()),
         },
         texture_size,
     );
 
-    let gradient_texture_view = gradient_texture.create_view(&TextureViewDescriptor::default());
-    let gradient_sampler = device.create_sampler(&SamplerDescriptor {
-        address_mode_u: AddressMode::ClampToEdge,
-        address_mode_v: AddressMode::ClampToEdge,
-        address_mode_w: AddressMode::ClampToEdge,
-        mag_filter: FilterMode::Linear,
-        min_filter: FilterMode::Linear,
-        mipmap_filter: FilterMode::Linear, // TODO: mip map gradient
-        ..Default::default()
-    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
 
-    let gradient_bind_group_layout =
-        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+    Palette {
+        bind_group: device.create_bind_group(&BindGroupDescriptor {
+            layout: &data.gradient_bind_group_layout,
             entries: &[
-                BindGroupLayoutEntry {
+                BindGroupEntry {
                     binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Texture {
-                        multisampled: false,
-                        sample_type: TextureSampleType::Float { filterable: true },
-                        view_dimension: TextureViewDimension::D1,
-                    },
-                    count: None,
+                    resource: BindingResource::TextureView(&view),
                 },
-                BindGroupLayoutEntry {
+                BindGroupEntry {
                     binding: 1,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
+                    resource: BindingResource::Sampler(&data.gradient_sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&data.curve_view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: data.params.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&data.contrast_view),
                 },
             ],
             label: None,
-        });
+        }),
+    }
+    .into()
+}
 
-    let gradient_bind_group = device.create_bind_group(&BindGroupDescriptor {
-        layout: &gradient_bind_group_layout,
-        entries: &[
-            BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::TextureView(&gradient_texture_view),
-            },
-            BindGroupEntry {
-                binding: 1,
-                resource: BindingResource::Sampler(&gradient_sampler),
-            },
-        ],
-        label: None,
-    });
+pub fn pipelines(db: &dyn Postprocesser, format: TextureFormat) -> PtrRc<Pipelines> {
+    let device = db.device(());
+    let data = db.postprocess_data(());
+    let shader = &data.shader;
 
     let blend_replace = wgpu::BlendComponent {
         src_factor: wgpu::BlendFactor::One,
@@ -128,77 +471,252 @@ pub fn data(db: &dyn Postprocesser, (): ()) -> PtrRc<Data> {
         alpha: blend_replace,
     };
 
-    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-        label: Some("postprocess pipeline"),
-        bind_group_layouts: &[
-            &data.accumulation_bind_group_layout,
-            &gradient_bind_group_layout,
-        ],
-        push_constant_ranges: &[],
-    });
+    let make_pipeline = |label, blend| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&data.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: mem::size_of::<Vertex>() as u64,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                // Extended range formats can display brighter than SDR white.
+                entry_point: if format == TextureFormat::Rgba16Float {
+                    "fs_main_hdr"
+                } else {
+                    "fs_main"
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    };
 
-    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("postprocess"),
-        layout: Some(&pipeline_layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
-            buffers: &[wgpu::VertexBufferLayout {
-                array_stride: 2 * 2 * 4,
-                step_mode: wgpu::VertexStepMode::Vertex,
-                attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2],
-            }],
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
-            targets: &[Some(wgpu::ColorTargetState {
-                format: *db.swapchain_format(()),
-                blend: Some(blend_state_replace),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
+    Pipelines {
+        pipeline: make_pipeline("postprocess", blend_state_replace),
+        transparent_pipeline: make_pipeline(
+            "postprocess transparent",
+            wgpu::BlendState::ALPHA_BLENDING,
+        ),
+        fade_pipeline: make_pipeline("postprocess fade", {
+            let fade = wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Constant,
+                dst_factor: wgpu::BlendFactor::OneMinusConstant,
+                operation: wgpu::BlendOperation::Add,
+            };
+            wgpu::BlendState {
+                color: fade,
+                alpha: fade,
+            }
         }),
-        primitive: wgpu::PrimitiveState::default(),
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
-        multiview: None,
-    });
-
-    Data {
-        gradient_bind_group,
-        quad: MeshData::new(&device, &build_quad(), "Quad Vertex Buffer"),
-        pipeline,
     }
     .into()
 }
 
-/// Draws a source accumulation texture into dst with log density coloring
+/// 1D texture holding `CURVE_SIZE` curve entries, written by `write_curve`.
+fn create_curve_texture(device: &wgpu::Device, label: &str) -> wgpu::Texture {
+    device.create_texture(&TextureDescriptor {
+        size: wgpu::Extent3d {
+            width: CURVE_SIZE as u32,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D1,
+        format: TextureFormat::R32Float,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        label: Some(label),
+        view_formats: &[],
+    })
+}
+
+fn write_curve(queue: &wgpu::Queue, texture: &wgpu::Texture, curve: &[f32]) {
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        bytemuck::cast_slice(curve),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * CURVE_SIZE as u32),
+            rows_per_image: Some(1),
+        },
+        wgpu::Extent3d {
+            width: CURVE_SIZE as u32,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+/// Replaces the tone curve. `None` restores the plain log mapping.
+/// Returns true if the curve changed: changes too small to see (see `curve_changed`) are skipped.
+pub fn set_curve(db: &dyn Postprocesser, curve: Option<&[f32]>) -> bool {
+    let data = db.postprocess_data(());
+    let identity;
+    let curve = match curve {
+        Some(curve) => curve,
+        None => {
+            identity = identity_curve();
+            &identity
+        }
+    };
+    let mut current = data.curve.lock().unwrap();
+    if !curve_changed(&current, curve) {
+        return false;
+    }
+    write_curve(&db.queue(()), &data.curve_texture, curve);
+    *current = curve.to_vec();
+    true
+}
+
+/// Draws a source accumulation texture into dst with log density coloring.
+/// If `load`, dst is not cleared first (ex: it contains a background).
+/// If `transparent`, zero density areas are transparent.
+/// If `opacity` is less than 1, the flame is instead blended over dst by it (ex: to cross-fade, which requires `load`).
 pub fn render(
     db: &dyn Postprocesser,
     encoder: &mut wgpu::CommandEncoder,
     src: &wgpu::BindGroup,
-    dst: &wgpu::TextureView,
+    target: &Target,
+    load: bool,
+    transparent: bool,
+    opacity: f32,
 ) {
     let data = db.postprocess_data(());
+    let pipelines = db.postprocess_pipelines(target.format);
+    let config = db.config(());
+    let palette = db.palette(config.palette);
+    let mut params = [0u8; PARAMS_SIZE as usize];
+    params[..4].copy_from_slice(&config.palette_color.to_le_bytes());
+    params[4..8].copy_from_slice(&config.highlight_rolloff.to_le_bytes());
+    // A mat3x3 is stored as 3 columns, each padded to 16 bytes.
+    let matrix = color_vision_matrix(config.color_vision);
+    for (row, values) in matrix.iter().enumerate() {
+        for (column, value) in values.iter().enumerate() {
+            let offset = 16 + column * 16 + row * 4;
+            params[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+    db.queue(()).write_buffer(&data.params, 0, &params);
+    {
+        let mut points = data.contrast_points.lock().unwrap();
+        if *points != config.tone_curve {
+            write_curve(
+                &db.queue(()),
+                &data.contrast_texture,
+                &contrast_curve(&config.tone_curve),
+            );
+            *points = config.tone_curve.clone();
+        }
+    }
 
-    let mut postprocess_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-        label: Some("Postprocess render pass"),
-        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-            view: dst,
-            resolve_target: None,
-            ops: wgpu::Operations {
-                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                store: wgpu::StoreOp::Store,
-            },
-        })],
-        depth_stencil_attachment: None,
-        occlusion_query_set: None,
-        timestamp_writes: None,
-    });
+    let mut postprocess_pass = begin_render_pass(
+        encoder,
+        &wgpu::RenderPassDescriptor {
+            label: Some("Postprocess render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: if load {
+                        wgpu::LoadOp::Load
+                    } else {
+                        wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+                    },
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        },
+    );
 
-    postprocess_pass.set_pipeline(&data.pipeline);
+    let size = target.size;
+    postprocess_pass.set_viewport(
+        target.origin[0] as f32,
+        target.origin[1] as f32,
+        size.width as f32,
+        size.height as f32,
+        0.0,
+        1.0,
+    );
+    if opacity < 1.0 {
+        postprocess_pass.set_pipeline(&pipelines.fade_pipeline);
+        let opacity = f64::from(opacity);
+        postprocess_pass.set_blend_constant(wgpu::Color {
+            r: opacity,
+            g: opacity,
+            b: opacity,
+            a: opacity,
+        });
+    } else if transparent {
+        postprocess_pass.set_pipeline(&pipelines.transparent_pipeline);
+    } else {
+        postprocess_pass.set_pipeline(&pipelines.pipeline);
+    }
     postprocess_pass.set_bind_group(0, src, &[]);
-    postprocess_pass.set_bind_group(1, &data.gradient_bind_group, &[]);
+    postprocess_pass.set_bind_group(1, &palette.bind_group, &[]);
     postprocess_pass.set_vertex_buffer(0, data.quad.buffer.slice(..));
     postprocess_pass.draw(0..(data.quad.count), 0..1);
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::postprocess::{color_vision_matrix, AccessiblePalette, ColorVision};
+
+    fn luminance(c: [u8; 3]) -> f32 {
+        0.2126 * f32::from(c[0]) + 0.7152 * f32::from(c[1]) + 0.0722 * f32::from(c[2])
+    }
+
+    #[test]
+    fn accessible_palettes() {
+        for palette in AccessiblePalette::ALL {
+            let colors = palette.colors();
+            assert_eq!(colors.len(), 256);
+            assert_eq!(colors[0], palette.stops()[0]);
+            assert_eq!(colors[255], *palette.stops().last().unwrap());
+            // Readable without hue: the brightness never decreases.
+            for pair in colors.windows(2) {
+                assert!(
+                    luminance(pair[1]) >= luminance(pair[0]) - 0.5,
+                    "{}",
+                    palette.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn color_vision() {
+        for vision in ColorVision::ALL {
+            // White and grays are unchanged.
+            for row in vision.matrix() {
+                assert!(
+                    (row.iter().sum::<f32>() - 1.0).abs() < 1e-3,
+                    "{}",
+                    vision.name()
+                );
+            }
+        }
+        assert_eq!(color_vision_matrix(None)[1], [0.0, 1.0, 0.0]);
+    }
+}
@@ -1,7 +1,25 @@
 //! Rendering helpers used by multiple rendering stages
 
 use bytemuck::Pod;
-use wgpu::{util::DeviceExt, Buffer, Device};
+use std::sync::atomic::{AtomicU32, Ordering};
+use wgpu::{util::DeviceExt, Buffer, Device, ShaderModule, ShaderModuleDescriptor, ShaderSource};
+
+/// Render passes begun since the last `take_pass_count`.
+static PASS_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// `encoder.begin_render_pass`, but counted for `take_pass_count`.
+pub fn begin_render_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    descriptor: &wgpu::RenderPassDescriptor<'a, '_>,
+) -> wgpu::RenderPass<'a> {
+    PASS_COUNT.fetch_add(1, Ordering::Relaxed);
+    encoder.begin_render_pass(descriptor)
+}
+
+/// Number of render passes begun (through `begin_render_pass`) since the last call.
+pub fn take_pass_count() -> u32 {
+    PASS_COUNT.swap(0, Ordering::Relaxed)
+}
 
 #[derive(Debug)]
 pub struct MeshData {
@@ -21,3 +39,95 @@ impl MeshData {
         }
     }
 }
+
+/// Sampler binding type and filter for sampling R32Float textures:
+/// linear if the device can filter them (see `Inputs::float_filtering`), otherwise nearest.
+pub fn float_sampling(filtering: bool) -> (wgpu::SamplerBindingType, wgpu::FilterMode) {
+    if filtering {
+        (
+            wgpu::SamplerBindingType::Filtering,
+            wgpu::FilterMode::Linear,
+        )
+    } else {
+        (
+            wgpu::SamplerBindingType::NonFiltering,
+            wgpu::FilterMode::Nearest,
+        )
+    }
+}
+
+/// Bytes per row for copying a texture `width` pixels wide into a buffer, padded to the required alignment.
+pub fn padded_bytes_per_row(width: u32, bytes_per_pixel: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * bytes_per_pixel).div_ceil(align) * align
+}
+
+/// Snippets shaders can share with a `//!include <name>` line.
+const INCLUDES: &[(&str, &str)] = &[("quad.wgsl", include_str!("../shaders/include/quad.wgsl"))];
+
+/// Replaces each `//!include <name>` line in `source` with the named snippet (expanded the same way).
+/// Each snippet is only included once, so snippets can include what they depend on without duplicate definitions.
+/// Panics if a snippet is missing: shaders are part of the build, so this is a bug.
+pub fn preprocess(source: &str, includes: &[(&str, &str)]) -> String {
+    fn expand<'a>(
+        source: &'a str,
+        includes: &[(&'a str, &'a str)],
+        included: &mut Vec<&'a str>,
+        out: &mut String,
+    ) {
+        for line in source.lines() {
+            match line.trim().strip_prefix("//!include ") {
+                Some(name) => {
+                    let name = name.trim();
+                    if !included.contains(&name) {
+                        let (name, snippet) = includes
+                            .iter()
+                            .find(|(n, _)| *n == name)
+                            .unwrap_or_else(|| panic!("Missing shader include {name}"));
+                        included.push(name);
+                        expand(snippet, includes, included, out);
+                    }
+                }
+                None => {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    let mut out = String::new();
+    expand(source, includes, &mut vec![], &mut out);
+    out
+}
+
+/// Creates a shader module from WGSL `source`, after expanding its includes (see `preprocess`).
+pub fn shader_module(device: &Device, label: &str, source: &str) -> ShaderModule {
+    device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(preprocess(source, INCLUDES).into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render_common::{preprocess, INCLUDES};
+
+    #[test]
+    fn includes() {
+        let includes = [("a", "fn a() {}"), ("b", "//!include a\nfn b() {}")];
+        assert_eq!(
+            preprocess("//!include b\n  //!include a\nfn c() {}", &includes),
+            "fn a() {}\nfn b() {}\nfn c() {}\n"
+        );
+        assert_eq!(preprocess("fn c() {}", &includes), "fn c() {}\n");
+        for (_, snippet) in INCLUDES {
+            preprocess(snippet, INCLUDES);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn missing_include() {
+        preprocess("//!include missing", &[]);
+    }
+}
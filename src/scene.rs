@@ -0,0 +1,206 @@
+//! Project files: the flame and how it is drawn, as versioned JSON.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{accumulate::Accumulation, histogram::ToneMap, postprocess::PaletteSource};
+
+/// Written by `Scene::save`. Older versions are migrated when loaded.
+/// Version 1 had no camera center or rotation, version 2 had no post transforms, and version 3 had no Möbius transforms.
+pub const VERSION: u32 = 4;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    /// flam3's coefs (see `flame::affine_from_coefs`).
+    pub coefs: [f64; 6],
+    pub opacity: f64,
+    /// Coefs of the transform applied after `coefs`, if any.
+    pub post: Option<[f64; 6]>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub version: u32,
+    pub transforms: Vec<Transform>,
+    /// Coefs of the transform applied to the output only.
+    pub final_transform: Option<[f64; 6]>,
+    /// If set, the flame is drawn from these Möbius transforms instead of `transforms`,
+    /// as `[a, b, c, d]`, each `[re, im]` (see `mobius::Mobius::from_coefs`).
+    pub mobius: Option<Vec<[[f64; 2]; 4]>>,
+    pub palette: PaletteSource,
+    pub palette_color: f32,
+    pub tone_map: ToneMap,
+    pub accumulation: Accumulation,
+    /// Magnification of the view, about `camera_center`.
+    pub zoom: f64,
+    pub camera_center: [f64; 2],
+    /// Radians, counterclockwise.
+    pub camera_rotation: f64,
+    pub background_image: Option<String>,
+    pub transparent_background: bool,
+}
+
+/// Upgrades a scene of any supported version to `VERSION`.
+fn migrate(mut value: Value) -> Result<Value, String> {
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or("Missing scene version")?;
+    if version > u64::from(VERSION) {
+        return Err(format!(
+            "Scene version {version} is newer than supported ({VERSION})"
+        ));
+    }
+    let object = value.as_object_mut().ok_or("Scene is not an object")?;
+    if version < 2 {
+        object.insert("camera_center".to_owned(), json!([0.0, 0.0]));
+        object.insert("camera_rotation".to_owned(), json!(0.0));
+    }
+    if version < 3 {
+        for transform in object
+            .get_mut("transforms")
+            .and_then(Value::as_array_mut)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(transform) = transform.as_object_mut() {
+                transform.insert("post".to_owned(), Value::Null);
+            }
+        }
+    }
+    if version < 4 {
+        object.insert("mobius".to_owned(), Value::Null);
+    }
+    object.insert("version".to_owned(), json!(VERSION));
+    Ok(value)
+}
+
+impl Scene {
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        serde_json::from_value(migrate(value)?).map_err(|e| e.to_string())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("scenes are always serializable")
+    }
+
+    /// Like `to_json`, but on one line, for sharing as text.
+    pub fn to_compact_json(&self) -> String {
+        serde_json::to_string(self).expect("scenes are always serializable")
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        Self::from_json(&text)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_json()).map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accumulate::Accumulation,
+        histogram::ToneMap,
+        mobius::Mobius,
+        postprocess::PaletteSource,
+        scene::{Scene, Transform, VERSION},
+        ui::Settings,
+    };
+    use na::{Affine2, Complex, Matrix3, Vector2};
+
+    #[test]
+    fn round_trip() {
+        let mut settings = Settings::default();
+        settings.zoom = 2.0;
+        settings.camera_rotation = 0.5;
+        settings.set_post_transform(
+            1,
+            Some(Affine2::from_matrix_unchecked(
+                Matrix3::new_nonuniform_scaling(&Vector2::new(0.5, 2.0)),
+            )),
+        );
+        let scene = settings.to_scene();
+        assert_eq!(Scene::from_json(&scene.to_json()), Ok(scene.clone()));
+        let mut loaded = Settings::default();
+        loaded.apply_scene(&scene).unwrap();
+        assert_eq!(loaded.get_state(), settings.get_state());
+        assert_eq!(loaded.to_scene(), scene);
+    }
+
+    #[test]
+    fn mobius_round_trip() {
+        let mut settings = Settings::default();
+        settings.mobius = Some(vec![
+            Mobius::new(
+                Complex::new(0.5, 0.1),
+                Complex::new(0.3, 0.0),
+                Complex::new(0.2, -0.1),
+                Complex::new(1.0, 0.0),
+            ),
+            Mobius::identity(),
+        ]);
+        let scene = settings.to_scene();
+        assert_eq!(Scene::from_json(&scene.to_json()), Ok(scene.clone()));
+        let mut loaded = Settings::default();
+        loaded.apply_scene(&scene).unwrap();
+        assert_eq!(loaded.mobius, settings.mobius);
+
+        // Scenes without Möbius transforms switch back to the affine ones.
+        loaded.apply_scene(&Settings::default().to_scene()).unwrap();
+        assert_eq!(loaded.mobius, None);
+
+        let empty = Scene {
+            mobius: Some(vec![]),
+            ..scene.clone()
+        };
+        assert!(loaded.apply_scene(&empty).is_err());
+        let mut infinite = scene;
+        infinite.mobius.as_mut().unwrap()[0][1][0] = f64::INFINITY;
+        assert!(loaded.apply_scene(&infinite).is_err());
+    }
+
+    #[test]
+    fn migrate_v1() {
+        // As saved before cameras and post transforms were added.
+        let v1 = r#"{
+            "version": 1,
+            "transforms": [{"coefs": [0.5, 0, 0, 0.5, 0, 0], "opacity": 1}],
+            "final_transform": null,
+            "palette": {"Image": "palette.png"},
+            "palette_color": 0.25,
+            "tone_map": "HistogramEq",
+            "accumulation": "Log",
+            "zoom": 2.0,
+            "background_image": null,
+            "transparent_background": false
+        }"#;
+        assert_eq!(
+            Scene::from_json(v1),
+            Ok(Scene {
+                version: VERSION,
+                transforms: vec![Transform {
+                    coefs: [0.5, 0.0, 0.0, 0.5, 0.0, 0.0],
+                    opacity: 1.0,
+                    post: None,
+                }],
+                final_transform: None,
+                mobius: None,
+                palette: PaletteSource::Image("palette.png".to_owned()),
+                palette_color: 0.25,
+                tone_map: ToneMap::HistogramEq,
+                accumulation: Accumulation::Log,
+                zoom: 2.0,
+                camera_center: [0.0, 0.0],
+                camera_rotation: 0.0,
+                background_image: None,
+                transparent_background: false,
+            })
+        );
+        assert!(Scene::from_json(r#"{"version": 5}"#).is_err());
+        assert!(Scene::from_json(r#"{"transforms": []}"#).is_err());
+    }
+}
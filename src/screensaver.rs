@@ -0,0 +1,159 @@
+//! Ambient display: drifts the polygon's scale and rotation along a smooth random walk,
+//! and every so often morphs into a new random flame and back.
+
+use na::Affine2;
+use winit::event::WindowEvent;
+
+use crate::{
+    rng::Rng,
+    ui::{Settings, MAX_POLYGON_SCALE},
+};
+
+/// The same seed always drifts the same way.
+const SEED: u64 = 0;
+/// Seconds from the start of one morph to a random flame to the next.
+const MORPH_PERIOD: f64 = 40.0;
+/// Seconds each morph (to or from a random flame) takes.
+const MORPH_SECONDS: f64 = 6.0;
+/// Seconds a random flame is shown before morphing back to the polygon.
+const HOLD_SECONDS: f64 = 10.0;
+/// Seconds between random values of the walk, which eases between them.
+const DRIFT_PERIOD: f64 = 8.0;
+/// Furthest the walk moves the polygon scale.
+const SCALE_DRIFT: f64 = 0.15;
+/// Average rotation of the polygon, in radians per second.
+const ROTATION_RATE: f64 = 0.05;
+/// Input sooner than this after starting doesn't stop the screensaver (ex: releasing the button which started it).
+const GRACE_SECONDS: f64 = 1.0;
+
+fn smoothstep(x: f64) -> f64 {
+    x * x * (3.0 - 2.0 * x)
+}
+
+/// Smooth noise in [-1, 1]: random values at whole numbers, eased between.
+fn noise(seed: u64, t: f64) -> f64 {
+    let knot = |k: f64| {
+        let mut rng = Rng::new(seed ^ (k as i64 as u64).wrapping_mul(0x9E3779B97F4A7C15));
+        rng.next_f64() * 2.0 - 1.0
+    };
+    let k = t.floor();
+    knot(k) + (knot(k + 1.0) - knot(k)) * smoothstep(t - k)
+}
+
+/// Which random flame to morph towards `t` seconds in, and how far (0 to 1).
+/// Each period drifts on its own, then morphs to its random flame, holds it, and morphs back.
+pub fn morph(t: f64) -> (u64, f64) {
+    let index = (t / MORPH_PERIOD).floor();
+    let phase = t - index * MORPH_PERIOD - (MORPH_PERIOD - 2.0 * MORPH_SECONDS - HOLD_SECONDS);
+    let amount = if phase < MORPH_SECONDS {
+        phase / MORPH_SECONDS
+    } else {
+        (2.0 * MORPH_SECONDS + HOLD_SECONDS - phase) / MORPH_SECONDS
+    };
+    (index as u64, smoothstep(amount.clamp(0.0, 1.0)))
+}
+
+pub struct Screensaver {
+    /// Settings from before the screensaver started, restored by `stop`.
+    base: Settings,
+    started: wasm_timer::Instant,
+}
+
+impl Screensaver {
+    pub fn new(base: Settings) -> Self {
+        Self {
+            base,
+            started: wasm_timer::Instant::now(),
+        }
+    }
+
+    /// Settings to draw `t` seconds in: the polygon from the base settings (with its scale and rotation drifting),
+    /// possibly partway morphed into a random flame with the same number of transforms.
+    pub fn at(&self, t: f64) -> Settings {
+        let mut settings = self.base.clone();
+        let scale = self.base.polygon_scale() + SCALE_DRIFT * noise(SEED, t / DRIFT_PERIOD);
+        settings.set_polygon_scale(scale.clamp(-MAX_POLYGON_SCALE, MAX_POLYGON_SCALE));
+        settings.set_rotation(
+            self.base.rotation() + (ROTATION_RATE * t + noise(SEED + 1, t / DRIFT_PERIOD)) as f32,
+        );
+        let (index, amount) = morph(t);
+        if amount > 0.0 {
+            let mut random = self.base.clone();
+            random.randomize(SEED.wrapping_add(index));
+            let (from, to) = (settings.get_state(), random.get_state());
+            settings.set_transforms(
+                from.transforms()
+                    .iter()
+                    .zip(to.transforms())
+                    .map(|(a, b)| {
+                        Affine2::from_matrix_unchecked(
+                            a.matrix() * (1.0 - amount) + b.matrix() * amount,
+                        )
+                    })
+                    .collect(),
+            );
+        }
+        settings
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.at(self.started.elapsed().as_secs_f64())
+    }
+
+    /// If `event` is input which should stop the screensaver.
+    pub fn interrupted_by(&self, event: &WindowEvent) -> bool {
+        self.started.elapsed().as_secs_f64() > GRACE_SECONDS
+            && matches!(
+                event,
+                WindowEvent::KeyboardInput { .. }
+                    | WindowEvent::MouseInput { .. }
+                    | WindowEvent::MouseWheel { .. }
+                    | WindowEvent::CursorMoved { .. }
+                    | WindowEvent::Touch(_)
+            )
+    }
+
+    /// The settings from before the screensaver started.
+    pub fn stop(self) -> Settings {
+        let mut settings = self.base;
+        settings.screensaver = false;
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        screensaver::{morph, Screensaver, HOLD_SECONDS, MORPH_PERIOD, MORPH_SECONDS},
+        ui::Settings,
+    };
+
+    #[test]
+    fn morphs() {
+        assert_eq!(morph(0.0), (0, 0.0));
+        let start = MORPH_PERIOD - 2.0 * MORPH_SECONDS - HOLD_SECONDS;
+        assert_eq!(morph(start), (0, 0.0));
+        assert_eq!(morph(start + MORPH_SECONDS / 2.0), (0, 0.5));
+        assert_eq!(morph(start + MORPH_SECONDS + HOLD_SECONDS / 2.0), (0, 1.0));
+        assert_eq!(morph(MORPH_PERIOD + 1.0), (1, 0.0));
+    }
+
+    #[test]
+    fn deterministic() {
+        let screensaver = Screensaver::new(Settings::default());
+        for t in [0.0, 3.0, 30.0, 100.0] {
+            let settings = screensaver.at(t);
+            assert_eq!(settings, screensaver.at(t));
+            assert_eq!(
+                settings.get_state().transforms().len(),
+                Settings::default().n
+            );
+        }
+        // Drifting is smooth.
+        let a = screensaver.at(5.0).get_state();
+        let b = screensaver.at(5.01).get_state();
+        for (a, b) in a.transforms().iter().zip(b.transforms()) {
+            assert!((a.matrix() - b.matrix()).norm() < 0.01);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Gallery mode: cycles through a directory of scenes, cross-fading from each to the next.
+
+use crate::{scene::Scene, ui::Settings};
+
+/// Scenes shown at some point in the slideshow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub current: usize,
+    /// Scene fading in over `current`, and its opacity.
+    pub next: Option<(usize, f32)>,
+}
+
+/// What to show `elapsed` seconds into a slideshow of `len` scenes:
+/// each scene is shown alone for `dwell` seconds, then the next fades in over `fade` seconds.
+pub fn frame(len: usize, elapsed: f64, dwell: f64, fade: f64) -> Frame {
+    let period = dwell + fade;
+    if len < 2 || period <= 0.0 {
+        return Frame {
+            current: 0,
+            next: None,
+        };
+    }
+    let current = (elapsed / period) as usize % len;
+    let t = elapsed % period - dwell;
+    Frame {
+        current,
+        next: (t > 0.0).then(|| ((current + 1) % len, (t / fade) as f32)),
+    }
+}
+
+pub struct Slideshow {
+    scenes: Vec<Scene>,
+    started: wasm_timer::Instant,
+    /// Scene last applied to the settings by `update`.
+    shown: Option<usize>,
+}
+
+impl Slideshow {
+    /// Loads every `.json` scene in `dir`, in file name order.
+    /// Files which fail to load (or are invalid scenes) are skipped and logged. Fails if there are no scenes.
+    pub fn load(dir: &str) -> Result<Self, String> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .map_err(|e| format!("{dir}: {e}"))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|e| e == "json"))
+            .collect();
+        paths.sort();
+        let scenes: Vec<Scene> = paths
+            .iter()
+            .map(|path| -> Result<Scene, String> {
+                let scene = Scene::load(&path.to_string_lossy())?;
+                Settings::default().apply_scene(&scene)?;
+                Ok(scene)
+            })
+            .filter_map(|scene| match scene {
+                Ok(scene) => Some(scene),
+                Err(e) => {
+                    log::warn!("Skipping scene: {e}");
+                    None
+                }
+            })
+            .collect();
+        if scenes.is_empty() {
+            return Err(format!("No scenes in {dir}"));
+        }
+        Ok(Self {
+            scenes,
+            started: wasm_timer::Instant::now(),
+            shown: None,
+        })
+    }
+
+    /// Applies the current scene to `settings` (when it changes),
+    /// and returns the settings for the scene fading in (if any) and its opacity.
+    pub fn update(
+        &mut self,
+        settings: &mut Settings,
+        dwell: f64,
+        fade: f64,
+    ) -> Option<(Settings, f32)> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let frame = frame(self.scenes.len(), elapsed, dwell, fade);
+        // Scenes were checked when loaded, so applying them can't fail.
+        if self.shown != Some(frame.current) {
+            settings
+                .apply_scene(&self.scenes[frame.current])
+                .expect("valid scene");
+            self.shown = Some(frame.current);
+        }
+        frame.next.map(|(next, opacity)| {
+            let mut next_settings = settings.clone();
+            next_settings
+                .apply_scene(&self.scenes[next])
+                .expect("valid scene");
+            (next_settings, opacity)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slideshow::{frame, Frame};
+
+    #[test]
+    fn frames() {
+        let at = |elapsed| frame(3, elapsed, 4.0, 1.0);
+        assert_eq!(
+            at(1.0),
+            Frame {
+                current: 0,
+                next: None
+            }
+        );
+        assert_eq!(
+            at(4.5),
+            Frame {
+                current: 0,
+                next: Some((1, 0.5))
+            }
+        );
+        assert_eq!(
+            at(14.25),
+            Frame {
+                current: 2,
+                next: Some((0, 0.25))
+            }
+        );
+        assert_eq!(at(15.0).current, 0);
+        // A single scene never fades.
+        assert_eq!(frame(1, 4.5, 4.0, 1.0).next, None);
+    }
+}
@@ -0,0 +1,357 @@
+//! Downsampling of supersampled exports, with a selectable filter kernel.
+
+use bytemuck::{Pod, Zeroable};
+use std::mem;
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayoutEntry, BindingResource, BindingType,
+    PipelineLayoutDescriptor, ShaderStages, TextureFormat, TextureSampleType, TextureViewDimension,
+};
+
+use crate::{
+    mesh::{build_quad, Vertex},
+    render_common::{begin_render_pass, shader_module, MeshData},
+    util_types::PtrRc,
+    wgpu_render::Postprocesser,
+};
+
+/// Largest supported supersample factor (in each direction).
+pub const MAX_FACTOR: u32 = 4;
+
+/// Most kernel taps (in each direction) the resolve shader supports.
+const MAX_TAPS: usize = 64;
+
+/// Format of the textures resolved from and into.
+pub const FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+/// The resolve shader's `Params`. Its `weights` are an array of `vec4`, so start 16 byte aligned.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Params {
+    offset: [u32; 2],
+    factor: u32,
+    taps: u32,
+    margin: u32,
+    padding: [u32; 3],
+    weights: [f32; MAX_TAPS],
+}
+
+// Implemented by hand since the checks `bytemuck`'s derives generate trip dead code lints on newer compilers.
+// SAFETY: this is `repr(C)` and only contains 4 byte fields, and the size check below ensures there is no padding.
+unsafe impl Zeroable for Params {}
+unsafe impl Pod for Params {}
+const _: () = assert!(mem::size_of::<Params>() == (8 + MAX_TAPS) * 4);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownsampleFilter {
+    /// Averages the source pixels within each output pixel: fast, but aliases.
+    Box,
+    Triangle,
+    /// Sharp, with little aliasing: best for final renders.
+    Lanczos3,
+    Gaussian,
+}
+
+impl DownsampleFilter {
+    pub const ALL: [DownsampleFilter; 4] = [
+        DownsampleFilter::Box,
+        DownsampleFilter::Triangle,
+        DownsampleFilter::Lanczos3,
+        DownsampleFilter::Gaussian,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DownsampleFilter::Box => "Box",
+            DownsampleFilter::Triangle => "Triangle",
+            DownsampleFilter::Lanczos3 => "Lanczos3",
+            DownsampleFilter::Gaussian => "Gaussian",
+        }
+    }
+
+    /// Distance, in output pixels, beyond which `weight` is zero.
+    pub fn radius(self) -> f64 {
+        match self {
+            DownsampleFilter::Box => 0.5,
+            DownsampleFilter::Triangle => 1.0,
+            DownsampleFilter::Lanczos3 => 3.0,
+            DownsampleFilter::Gaussian => 3.0 * GAUSSIAN_SIGMA,
+        }
+    }
+
+    /// Unnormalized weight of a sample `x` output pixels from the center of an output pixel.
+    pub fn weight(self, x: f64) -> f64 {
+        let x = x.abs();
+        if x >= self.radius() {
+            return 0.0;
+        }
+        match self {
+            DownsampleFilter::Box => 1.0,
+            DownsampleFilter::Triangle => 1.0 - x,
+            DownsampleFilter::Lanczos3 => sinc(x) * sinc(x / 3.0),
+            DownsampleFilter::Gaussian => (-x * x / (2.0 * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp(),
+        }
+    }
+
+    /// Source pixels beyond each side of an output pixel's footprint which contribute to it,
+    /// when downsampling by `factor`.
+    pub fn margin(self, factor: u32) -> u32 {
+        ((self.radius() - 0.5) * f64::from(factor)).ceil().max(0.0) as u32
+    }
+
+    /// Normalized separable weights for downsampling by `factor`.
+    /// Tap `i` is the source pixel `i - margin` after the start of the output pixel's footprint.
+    pub fn weights(self, factor: u32) -> Vec<f32> {
+        let margin = self.margin(factor);
+        let factor = f64::from(factor);
+        let weights: Vec<f64> = (0..factor as u32 + 2 * margin)
+            .map(|i| self.weight((f64::from(i) - f64::from(margin) + 0.5) / factor - 0.5))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        weights.iter().map(|w| (w / total) as f32).collect()
+    }
+}
+
+/// Standard deviation of the Gaussian filter, in output pixels.
+const GAUSSIAN_SIGMA: f64 = 0.5;
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f64::consts::PI;
+        x.sin() / x
+    }
+}
+
+/// Device dependant, but otherwise constant data.
+#[derive(Debug)]
+pub struct Data {
+    layout: wgpu::BindGroupLayout,
+    quad: MeshData,
+    pipeline: wgpu::RenderPipeline,
+}
+
+pub fn data(db: &dyn Postprocesser, (): ()) -> PtrRc<Data> {
+    let device = db.device(());
+
+    let shader = shader_module(
+        &device,
+        "resolve.wgsl",
+        include_str!("../shaders/resolve.wgsl"),
+    );
+
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+        label: Some("resolve"),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("resolve pipeline"),
+        bind_group_layouts: &[&layout],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("resolve"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: mem::size_of::<Vertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2],
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: FORMAT,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    Data {
+        layout,
+        quad: MeshData::new(&device, &build_quad(), "Resolve Quad Vertex Buffer"),
+        pipeline,
+    }
+    .into()
+}
+
+/// Downsamples `src` by `factor` with `filter` into all of `dst`.
+/// `offset` is the pixel in `src` where the footprint of `dst`'s first pixel starts.
+/// Filtering happens in linear space, since both are `FORMAT`.
+pub fn resolve(
+    db: &dyn Postprocesser,
+    encoder: &mut wgpu::CommandEncoder,
+    src: &wgpu::TextureView,
+    dst: &wgpu::TextureView,
+    offset: [u32; 2],
+    factor: u32,
+    filter: DownsampleFilter,
+) {
+    let device = db.device(());
+    let data = db.supersample_data(());
+
+    let weights = filter.weights(factor);
+    assert!(
+        weights.len() <= MAX_TAPS,
+        "too many taps: {}",
+        weights.len()
+    );
+    let mut params = Params {
+        offset,
+        factor,
+        taps: weights.len() as u32,
+        margin: filter.margin(factor),
+        ..Zeroable::zeroed()
+    };
+    params.weights[..weights.len()].copy_from_slice(&weights);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("resolve params"),
+        size: mem::size_of::<Params>() as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    db.queue(())
+        .write_buffer(&buffer, 0, bytemuck::bytes_of(&params));
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        layout: &data.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(src),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: buffer.as_entire_binding(),
+            },
+        ],
+        label: None,
+    });
+
+    let mut pass = begin_render_pass(
+        encoder,
+        &wgpu::RenderPassDescriptor {
+            label: Some("Resolve render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: dst,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        },
+    );
+    pass.set_pipeline(&data.pipeline);
+    pass.set_bind_group(0, &bind_group, &[]);
+    pass.set_vertex_buffer(0, data.quad.buffer.slice(..));
+    pass.draw(0..(data.quad.count), 0..1);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::supersample::{DownsampleFilter, Params, MAX_FACTOR, MAX_TAPS};
+    use bytemuck::Zeroable;
+
+    /// Downsamples `src` by `factor` with the same taps as the resolve shader, clamping at the edges.
+    fn downsample(src: &[f64], factor: u32, filter: DownsampleFilter) -> Vec<f64> {
+        let weights = filter.weights(factor);
+        let margin = filter.margin(factor) as i64;
+        (0..src.len() / factor as usize)
+            .map(|x| {
+                let start = (x * factor as usize) as i64 - margin;
+                weights
+                    .iter()
+                    .enumerate()
+                    .map(|(i, w)| {
+                        let p = (start + i as i64).clamp(0, src.len() as i64 - 1);
+                        src[p as usize] * f64::from(*w)
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Must match `Params` in resolve.wgsl: the weights start at byte 32.
+    #[test]
+    fn params_layout() {
+        let mut params = Params::zeroed();
+        params.weights[0] = 1.0;
+        let bytes = bytemuck::bytes_of(&params);
+        assert_eq!(bytes.len(), 32 + MAX_TAPS * 4);
+        assert_eq!(bytes[32..36], 1.0f32.to_le_bytes());
+    }
+
+    #[test]
+    fn weights() {
+        for filter in DownsampleFilter::ALL {
+            for factor in 1..=MAX_FACTOR {
+                let weights = filter.weights(factor);
+                assert!(weights.len() <= MAX_TAPS);
+                let total: f32 = weights.iter().sum();
+                assert!((total - 1.0).abs() < 1e-5, "{filter:?} {factor}");
+                for (a, b) in weights.iter().zip(weights.iter().rev()) {
+                    assert!((a - b).abs() < 1e-6, "{filter:?} {factor}");
+                }
+            }
+        }
+        assert_eq!(DownsampleFilter::Box.weights(2), vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn aliasing() {
+        // 0.75 cycles per output pixel: above the output's Nyquist frequency,
+        // so anything which survives downsampling is aliasing.
+        let factor = 4;
+        let src: Vec<f64> = (0..1024)
+            .map(|i| (std::f64::consts::TAU * 0.75 * (f64::from(i) + 0.5) / 4.0).sin())
+            .collect();
+        let energy = |filter| {
+            let out = downsample(&src, factor, filter);
+            // Skip the edges, where clamping adds its own error.
+            let interior = &out[8..out.len() - 8];
+            interior.iter().map(|v| v * v).sum::<f64>() / interior.len() as f64
+        };
+        let box_energy = energy(DownsampleFilter::Box);
+        let lanczos_energy = energy(DownsampleFilter::Lanczos3);
+        assert!(
+            lanczos_energy < box_energy * 0.5,
+            "{lanczos_energy} vs {box_energy}"
+        );
+    }
+}
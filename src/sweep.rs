@@ -0,0 +1,164 @@
+//! Exporting an animated GIF of one parameter swept over a range (ex: the polygon rotating through a full turn).
+//! Each frame is rendered with `wgpu_render::render_to_image`, so like other exports this is not supported on the web.
+//! Only GIF is supported: the `image` crate can not encode animated WebP.
+
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, Frame,
+};
+use std::{f64::consts::TAU, fs::File, io::BufWriter};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    supersample,
+    ui::Settings,
+    wgpu_render::{self, DatabaseStruct, Inputs, Inputs2},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parameter {
+    /// The polygon mode rotation, in radians. Only affects polygon mode.
+    Rotation,
+    /// The polygon mode scale (switching to polygon mode).
+    Scale,
+    /// Rotation of the whole flame, in radians.
+    CameraRotation,
+    Zoom,
+}
+
+impl Parameter {
+    pub const ALL: [Parameter; 4] = [
+        Parameter::Rotation,
+        Parameter::Scale,
+        Parameter::CameraRotation,
+        Parameter::Zoom,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Parameter::Rotation => "Rotation",
+            Parameter::Scale => "Scale",
+            Parameter::CameraRotation => "Camera Rotation",
+            Parameter::Zoom => "Zoom",
+        }
+    }
+
+    /// Range to sweep when this parameter is picked: a full turn for rotations, so the animation loops.
+    pub fn default_range(self) -> (f64, f64) {
+        match self {
+            Parameter::Rotation | Parameter::CameraRotation => (0.0, TAU),
+            Parameter::Scale => (0.3, 0.6),
+            Parameter::Zoom => (1.0, 4.0),
+        }
+    }
+
+    pub fn apply(self, settings: &mut Settings, value: f64) {
+        match self {
+            Parameter::Rotation => settings.set_rotation(value as f32),
+            Parameter::Scale => settings.set_polygon_scale(value),
+            Parameter::CameraRotation => settings.camera_rotation = value,
+            Parameter::Zoom => settings.zoom = value,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sweep {
+    pub parameter: Parameter,
+    pub from: f64,
+    pub to: f64,
+    pub frames: u32,
+    pub fps: u32,
+}
+
+impl Sweep {
+    pub fn default() -> Self {
+        let (from, to) = Parameter::Rotation.default_range();
+        Self {
+            parameter: Parameter::Rotation,
+            from,
+            to,
+            frames: 60,
+            fps: 30,
+        }
+    }
+
+    /// Value of the parameter for each frame, evenly spaced from `from` towards `to`.
+    /// `to` itself is excluded, so sweeping a full turn loops without repeating a frame.
+    pub fn values(&self) -> Vec<f64> {
+        (0..self.frames)
+            .map(|i| self.from + (self.to - self.from) * f64::from(i) / f64::from(self.frames))
+            .collect()
+    }
+}
+
+/// Renders `settings` with `sweep` applied to each frame, and saves them to `path` as a looping GIF.
+/// Uses its own database (sharing `db`'s device), so the live view's cached state is left alone.
+pub fn export(
+    db: &DatabaseStruct,
+    settings: &Settings,
+    sweep: &Sweep,
+    size: PhysicalSize<u32>,
+    path: &str,
+) -> Result<(), String> {
+    let mut sweep_db = DatabaseStruct::new(
+        db.device(()),
+        db.queue(()),
+        supersample::FORMAT,
+        size,
+        settings.clone(),
+    );
+    sweep_db.set_float_filtering_with_durability(
+        (),
+        db.float_filtering(()),
+        salsa::Durability::HIGH,
+    );
+    let delay = Delay::from_numer_denom_ms(1000, sweep.fps.max(1));
+    let mut frames = vec![];
+    for value in sweep.values() {
+        let mut frame = settings.clone();
+        sweep.parameter.apply(&mut frame, value);
+        sweep_db.set_settings(frame);
+        let image = wgpu_render::render_to_image(&sweep_db, size).map_err(|e| e.to_string());
+        sweep_db.release_passes();
+        frames.push(Frame::from_parts(image?, 0, 0, delay));
+    }
+
+    let file = File::create(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut encoder = GifEncoder::new(BufWriter::new(file));
+    encoder
+        .set_repeat(Repeat::Infinite)
+        .map_err(|e| format!("{path}: {e}"))?;
+    encoder
+        .encode_frames(frames)
+        .map_err(|e| format!("{path}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        sweep::{Parameter, Sweep},
+        ui::Settings,
+    };
+
+    #[test]
+    fn values() {
+        let sweep = Sweep {
+            parameter: Parameter::Zoom,
+            from: 1.0,
+            to: 2.0,
+            frames: 4,
+            fps: 10,
+        };
+        assert_eq!(sweep.values(), vec![1.0, 1.25, 1.5, 1.75]);
+        assert!(Sweep { frames: 0, ..sweep }.values().is_empty());
+
+        let mut settings = Settings::default();
+        Parameter::Zoom.apply(&mut settings, 1.5);
+        assert_eq!(settings.zoom, 1.5);
+        Parameter::Rotation.apply(&mut settings, 0.5);
+        assert_eq!(settings.rotation(), 0.5);
+        Parameter::Scale.apply(&mut settings, 0.4);
+        assert_eq!(settings.polygon_scale(), 0.4);
+    }
+}
@@ -0,0 +1,109 @@
+//! Classic iterated function systems, for use as starting points in the non-polygon editor.
+
+use na::Affine2;
+
+use crate::flame::affine_from_coefs;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Template {
+    BarnsleyFern,
+    Dragon,
+    LevyC,
+    Koch,
+}
+
+impl Template {
+    pub const ALL: [Template; 4] = [
+        Template::BarnsleyFern,
+        Template::Dragon,
+        Template::LevyC,
+        Template::Koch,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Template::BarnsleyFern => "Barnsley Fern",
+            Template::Dragon => "Dragon Curve",
+            Template::LevyC => "Lévy C Curve",
+            Template::Koch => "Koch Curve",
+        }
+    }
+
+    /// The transforms, tabulated as flam3 coefs (see `affine_from_coefs`).
+    pub fn transforms(self) -> Vec<Affine2<f64>> {
+        let third = 1.0 / 3.0;
+        let (sin60, cos60) = (std::f64::consts::FRAC_PI_3).sin_cos();
+        match self {
+            Template::BarnsleyFern => vec![
+                affine_from_coefs([0.0, 0.0, 0.0, 0.16, 0.0, 0.0]),
+                affine_from_coefs([0.85, -0.04, 0.04, 0.85, 0.0, 1.6]),
+                affine_from_coefs([0.2, 0.23, -0.26, 0.22, 0.0, 1.6]),
+                affine_from_coefs([-0.15, 0.26, 0.28, 0.24, 0.0, 0.44]),
+            ],
+            Template::Dragon => vec![
+                affine_from_coefs([0.5, 0.5, -0.5, 0.5, 0.0, 0.0]),
+                affine_from_coefs([-0.5, 0.5, -0.5, -0.5, 1.0, 0.0]),
+            ],
+            Template::LevyC => vec![
+                affine_from_coefs([0.5, 0.5, -0.5, 0.5, 0.0, 0.0]),
+                affine_from_coefs([0.5, -0.5, 0.5, 0.5, 0.5, 0.5]),
+            ],
+            Template::Koch => vec![
+                affine_from_coefs([third, 0.0, 0.0, third, 0.0, 0.0]),
+                affine_from_coefs([
+                    third * cos60,
+                    third * sin60,
+                    -third * sin60,
+                    third * cos60,
+                    third,
+                    0.0,
+                ]),
+                affine_from_coefs([
+                    third * cos60,
+                    -third * sin60,
+                    third * sin60,
+                    third * cos60,
+                    0.5,
+                    third * sin60,
+                ]),
+                affine_from_coefs([third, 0.0, 0.0, third, 2.0 * third, 0.0]),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flame::{BoundedState, Root};
+    use crate::geometry::Bounds;
+    use crate::templates::Template;
+    use na::Point2;
+
+    #[test]
+    fn finite_bounds() {
+        for template in Template::ALL {
+            let root = Root::new(template.transforms());
+            assert!(root.expanding_transforms().is_empty(), "{template:?}");
+            let (b, _levels) = root.get_state().get_bounds_adaptive(10, None);
+            assert!(!b.is_infinite(), "{template:?}");
+            assert!(
+                b.min
+                    .coords
+                    .iter()
+                    .chain(b.max.coords.iter())
+                    .all(|x| x.is_finite()),
+                "{template:?}"
+            );
+            assert!(b.width() > 0.0 && b.height() > 0.0, "{template:?}");
+        }
+    }
+
+    /// The fern's main transform, as usually tabulated: (x, y) -> (0.85 x + 0.04 y, -0.04 x + 0.85 y + 1.6).
+    #[test]
+    fn fern_orientation() {
+        let t = Template::BarnsleyFern.transforms()[1];
+        assert_eq!(t * Point2::new(0.0, 0.0), Point2::new(0.0, 1.6));
+        assert_eq!(t * Point2::new(1.0, 0.0), Point2::new(0.85, 1.6 - 0.04));
+        assert_eq!(t * Point2::new(0.0, 1.0), Point2::new(0.04, 1.6 + 0.85));
+    }
+}
@@ -0,0 +1,124 @@
+//! GPU timestamp queries for profiling the stages of a frame, when supported by the device.
+
+use std::sync::{Arc, Mutex};
+use wgpu::{BufferAsyncError, Device};
+
+use crate::{histogram::ReadbackState, util_types::PtrRc, wgpu_render::Postprocesser};
+
+/// Points in the frame where timestamps are written. Each stage is timed between consecutive marks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mark {
+    Start,
+    Accumulated,
+    Postprocessed,
+    Rendered,
+    Ui,
+}
+
+const MARKS: u32 = 5;
+
+/// Names of the stages between each pair of consecutive marks.
+pub const STAGES: [&str; MARKS as usize - 1] = ["Accumulate", "Postprocess", "Other", "UI"];
+
+#[derive(Debug)]
+pub struct Timer {
+    query_set: wgpu::QuerySet,
+    /// Queries are resolved into this every frame, then copied into `readback` when it is not in use.
+    resolve: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+    state: Mutex<ReadbackState>,
+    mapped: Arc<Mutex<Option<Result<(), BufferAsyncError>>>>,
+}
+
+/// None if the device does not support timestamp queries.
+pub fn timer(db: &dyn Postprocesser, (): ()) -> Option<PtrRc<Timer>> {
+    let device = db.device(());
+    if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+        return None;
+    }
+    let size = u64::from(MARKS) * wgpu::QUERY_SIZE as u64;
+    Some(
+        Timer {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("frame timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: MARKS,
+            }),
+            resolve: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("timestamp resolve"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("timestamp readback"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            period: db.queue(()).get_timestamp_period(),
+            state: Mutex::new(ReadbackState::Idle),
+            mapped: Arc::new(Mutex::new(None)),
+        }
+        .into(),
+    )
+}
+
+impl Timer {
+    pub fn mark(&self, encoder: &mut wgpu::CommandEncoder, mark: Mark) {
+        encoder.write_timestamp(&self.query_set, mark as u32);
+    }
+
+    /// Call after the last mark of the frame.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(&self.query_set, 0..MARKS, &self.resolve, 0);
+        let mut state = self.state.lock().unwrap();
+        if *state == ReadbackState::Idle {
+            encoder.copy_buffer_to_buffer(&self.resolve, 0, &self.readback, 0, self.resolve.size());
+            *state = ReadbackState::Copied;
+        }
+    }
+
+    /// Must be called after the encoder passed to `resolve` was submitted.
+    /// Returns the milliseconds spent in each of `STAGES` if a previous readback has completed.
+    pub fn after_submit(&self, device: &Device) -> Option<Vec<f64>> {
+        device.poll(wgpu::Maintain::Poll);
+        let mut state = self.state.lock().unwrap();
+        let mut stages = None;
+        if *state == ReadbackState::Mapping {
+            let result = self.mapped.lock().unwrap().take();
+            match result {
+                None => {}
+                Some(Ok(())) => {
+                    {
+                        let bytes = self.readback.slice(..).get_mapped_range();
+                        let ticks: &[u64] = bytemuck::cast_slice(&bytes);
+                        stages = Some(
+                            ticks
+                                .windows(2)
+                                .map(|w| {
+                                    w[1].wrapping_sub(w[0]) as f64 * f64::from(self.period) / 1e6
+                                })
+                                .collect(),
+                        );
+                    }
+                    self.readback.unmap();
+                    *state = ReadbackState::Idle;
+                }
+                Some(Err(_)) => *state = ReadbackState::Idle,
+            }
+        }
+        if *state == ReadbackState::Copied {
+            let mapped = self.mapped.clone();
+            self.readback
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    *mapped.lock().unwrap() = Some(result);
+                });
+            *state = ReadbackState::Mapping;
+        }
+        stages
+    }
+}
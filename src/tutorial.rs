@@ -0,0 +1,194 @@
+//! Guided overlay explaining the main controls, shown on first launch until dismissed.
+
+/// A control the tutorial explains, in the order they are explained.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Points,
+    Polygon,
+    Transforms,
+}
+
+impl Step {
+    pub const ALL: [Step; 3] = [Step::Points, Step::Polygon, Step::Transforms];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Step::Points => "Points",
+            Step::Polygon => "Polygon",
+            Step::Transforms => "Transforms",
+        }
+    }
+
+    pub fn text(self) -> &'static str {
+        match self {
+            Step::Points => {
+                "The number of transforms. The flame is made of this many shrunken copies of itself, \
+                 each of which is made of copies, and so on."
+            }
+            Step::Polygon => {
+                "When checked, the transforms are arranged around a regular polygon, \
+                 controlled by just a rotation and scale. Uncheck it to edit each transform."
+            }
+            Step::Transforms => {
+                "Each transform is an offset followed by where it moves the X and Y axes. \
+                 Drag the values here, or select a transform with Tab and move it with the arrow keys."
+            }
+        }
+    }
+}
+
+/// Which step (if any) is shown, and where the controls it explains were last drawn.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tutorial {
+    step: Option<usize>,
+    anchors: [Option<egui::Rect>; Step::ALL.len()],
+}
+
+impl Tutorial {
+    /// Starts at the first step, unless a previous session already dismissed it.
+    pub fn first_launch() -> Self {
+        let mut tutorial = Self::default();
+        if !seen() {
+            tutorial.restart();
+        }
+        tutorial
+    }
+
+    pub fn restart(&mut self) {
+        self.step = Some(0);
+    }
+
+    pub fn current(&self) -> Option<Step> {
+        Step::ALL.get(self.step?).copied()
+    }
+
+    /// Moves to the next step, dismissing the tutorial after the last.
+    pub fn next(&mut self) {
+        match self.step {
+            Some(i) if i + 1 < Step::ALL.len() => self.step = Some(i + 1),
+            Some(_) => self.dismiss(),
+            None => {}
+        }
+    }
+
+    /// Hides the tutorial, and stops it being shown on future launches.
+    pub fn dismiss(&mut self) {
+        self.step = None;
+        mark_seen();
+    }
+
+    /// Records where the control for `step` was drawn this frame, so the overlay can point at it.
+    pub fn anchor(&mut self, step: Step, rect: egui::Rect) {
+        let i = Step::ALL.iter().position(|s| *s == step).unwrap();
+        self.anchors[i] = Some(rect);
+    }
+
+    /// Draws the current step (if any): its control outlined, with an explanation beside it.
+    /// Must be called after the controls have been anchored.
+    pub fn show(&mut self, ctx: &egui::Context) {
+        let (Some(i), Some(step)) = (self.step, self.current()) else {
+            return;
+        };
+        let anchor = self.anchors[i];
+        let color = egui::Color32::from_rgb(255, 200, 0);
+        let mut area = egui::Area::new(egui::Id::new("Tutorial")).order(egui::Order::Foreground);
+        area = match anchor {
+            // To the left of the control (which is in the settings panel on the right).
+            Some(rect) => area
+                .pivot(egui::Align2::RIGHT_CENTER)
+                .fixed_pos(rect.left_center() - egui::vec2(24.0, 0.0)),
+            None => area
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .movable(false),
+        };
+        let callout = area.show(ctx, |ui| {
+            egui::Frame::popup(ui.style())
+                .stroke(egui::Stroke::new(2.0, color))
+                .show(ui, |ui| {
+                    ui.set_max_width(280.0);
+                    ui.heading(step.title());
+                    ui.label(step.text());
+                    ui.horizontal(|ui| {
+                        let last = i + 1 == Step::ALL.len();
+                        if ui.button(if last { "Done" } else { "Next" }).clicked() {
+                            self.next();
+                        }
+                        if !last && ui.button("Skip Tutorial").clicked() {
+                            self.dismiss();
+                        }
+                        ui.label(format!("{}/{}", i + 1, Step::ALL.len()));
+                    });
+                });
+        });
+        if let Some(rect) = anchor {
+            let painter = ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("Tutorial Highlight"),
+            ));
+            let stroke = egui::Stroke::new(2.0, color);
+            painter.rect_stroke(rect.expand(3.0), 3.0, stroke);
+            painter.arrow(
+                callout.response.rect.right_center(),
+                rect.left_center() - callout.response.rect.right_center() - egui::vec2(4.0, 0.0),
+                stroke,
+            );
+        }
+    }
+}
+
+/// Saved in the working directory, like the window state.
+#[cfg(not(target_arch = "wasm32"))]
+const PATH: &str = "rusty-flame-tutorial.txt";
+
+#[cfg(target_arch = "wasm32")]
+const STORAGE_KEY: &str = "rusty-flame-tutorial";
+
+#[cfg(not(target_arch = "wasm32"))]
+fn seen() -> bool {
+    std::path::Path::new(PATH).exists()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn mark_seen() {
+    if let Err(e) = std::fs::write(PATH, "seen\n") {
+        log::warn!("Failed to save tutorial state to {PATH}: {e}");
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn seen() -> bool {
+    local_storage().is_some_and(|storage| storage.get_item(STORAGE_KEY).ok().flatten().is_some())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn mark_seen() {
+    if let Some(storage) = local_storage() {
+        if storage.set_item(STORAGE_KEY, "seen").is_err() {
+            log::warn!("Failed to save tutorial state to local storage");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tutorial::{Step, Tutorial};
+
+    #[test]
+    fn steps() {
+        let mut tutorial = Tutorial::default();
+        assert_eq!(tutorial.current(), None);
+        tutorial.next();
+        assert_eq!(tutorial.current(), None);
+        tutorial.restart();
+        for step in &Step::ALL[..Step::ALL.len() - 1] {
+            assert_eq!(tutorial.current(), Some(*step));
+            tutorial.next();
+        }
+        assert_eq!(tutorial.current(), Some(Step::Transforms));
+    }
+}
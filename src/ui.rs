@@ -1,17 +1,292 @@
-use crate::flame::Root;
+use crate::{
+    accumulate::Accumulation,
+    bloom::Bloom,
+    commands,
+    flam3::parse_palette,
+    flame::{
+        affine_from_coefs, affine_to_coefs, expansion_factor, ContractionReport, Root, State,
+        Symmetry,
+    },
+    geometry::{self, Bounds},
+    histogram::{self, ToneMap, IDENTITY_CONTRAST},
+    mobius::Mobius,
+    orbit_trap::Trap,
+    plan::{max_levels, Plan, SizeRounding, DEFAULT_MIN_PASS_SIZE},
+    postprocess::{self, AccessiblePalette, ColorVision, PaletteSource},
+    rng::Rng,
+    scene::{self, Scene},
+    supersample::{DownsampleFilter, MAX_FACTOR},
+    sweep::{self, Sweep},
+    templates::Template,
+    timing,
+    tutorial::{Step, Tutorial},
+};
 use egui::Ui;
-use na::{Affine2, Point2, Rotation2, SMatrix, Similarity2, Translation2, Vector2};
+use na::{Affine2, Matrix3, Point2, Rotation2, SMatrix, Translation2, Vector2};
+use winit::dpi::PhysicalSize;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Settings {
     pub busy_loop: bool,
-    pub auto_passes: bool,
-    pub passes: u32, // TODO: make this work.
+    /// If set, busy looping redraws at most this many times per second, to save power.
+    /// The present mode can limit the frame rate further (ex: to the display's refresh rate with vsync).
+    pub fps_cap: Option<u32>,
     pub n: usize,
+    pub tone_map: ToneMap,
+    /// If set, the log tone map adapts to the flame's densities (see `histogram::exposure_curve`),
+    /// with this many stops of exposure compensation.
+    pub auto_exposure: Option<f32>,
+    /// Control points (`[x, y]`, sorted by x) of the contrast curve applied after `tone_map` (see `histogram::contrast_curve`).
+    /// The first and last are at x = 0 and x = 1.
+    pub tone_curve: Vec<[f32; 2]>,
+    /// Portion of the top of the gradient the densest areas are softly compressed into (see `histogram::highlight_rolloff`),
+    /// so bright cores keep their color instead of washing out. 0 is the hard mapping.
+    pub highlight_rolloff: f32,
+    /// If set, the output simulates how it looks with this color vision deficiency.
+    pub color_vision: Option<ColorVision>,
+    pub accumulation: Accumulation,
+    /// If set, exports are colored by this orbit trap instead of by density.
+    pub orbit_trap: Option<Trap>,
+    /// If set, the smallest pass draws Gaussian splats with this standard deviation (as a fraction of the quad) instead of solid quads.
+    pub splat_size: Option<f32>,
+    /// Use an extended range swapchain format if supported.
+    pub hdr: bool,
+    pub oriented_bounds: bool,
+    /// Size of the settings panel's text and controls, on top of the window's scale factor.
+    pub ui_scale: f32,
+    /// Magnification of the main view, about `camera_center`.
+    pub zoom: f64,
+    /// Point the view is centered on, in the unzoomed view's normalized device coordinates.
+    pub camera_center: [f64; 2],
+    /// Counterclockwise rotation (radians) of the flame's output, about the center of its bounds.
+    /// The output bounds (and so buffer sizes) are fit to the rotated flame.
+    pub camera_rotation: f64,
+    /// If set, only this part of the output bounds (as fractions, see `Rect::sub_rect`) is drawn, filling the view.
+    pub crop: Option<geometry::Rect>,
+    /// The crop is being dragged out on the flame.
+    crop_drawing: bool,
+    /// Where the crop drag started, as a fraction of the output bounds.
+    crop_start: Option<Point2<f64>>,
+    /// When zoomed in, show the whole flame in a corner.
+    pub minimap: bool,
+    /// Outline the flame's convex hull.
+    pub show_hull: bool,
+    /// Outline the bounds of each level of the flame, nested inside each other.
+    pub show_level_bounds: bool,
+    /// If set, density accumulates across frames, with the previous frame's density multiplied by this.
+    pub feedback_decay: Option<f32>,
+    /// If set, bright areas glow (see `bloom`). Only applied to the live view, not exports.
+    pub bloom: Option<Bloom>,
+    /// Width / height of the pixels of exported images (ex: for anamorphic video), which squeezes the flame to match.
+    /// The preview always has square pixels.
+    pub pixel_aspect: f64,
+    /// If set, outlines the largest area of this aspect ratio (width / height) centered in the view, for framing exports.
+    safe_zone: Option<f64>,
+    /// If set, the preview is letter boxed to this aspect ratio (width / height), and exports keep it,
+    /// so the preview matches what is exported.
+    pub aspect_lock: Option<f64>,
+    pub palette: PaletteSource,
+    /// Path being edited in the UI, applied to `palette` when loaded.
+    palette_path: String,
+    /// Color coordinate (palette row) used for the whole flame.
+    pub palette_color: f32,
+    /// Path of an image to draw behind the flame.
+    pub background_image: Option<String>,
+    /// Path being edited in the UI, applied to `background_image` when loaded.
+    background_path: String,
+    /// Make zero density areas transparent, letting the background image show through.
+    pub transparent_background: bool,
+    /// Path of an image to compare the flame against.
+    pub reference_image: Option<String>,
+    /// Path being edited in the UI, applied to `reference_image` when loaded.
+    reference_path: String,
+    /// Show the difference from `reference_image`.
+    pub compare: bool,
+    /// How much of the difference to show when comparing: 0 is just the flame, 1 just the difference.
+    pub compare_blend: f32,
+    /// Upper limit for the adaptively chosen number of levels used when computing bounds.
+    pub max_bounds_levels: u32,
+    /// If set, only this many transforms per level are used while solving for the bounds (faster for large `n`).
+    pub bounds_samples: Option<usize>,
+    pub size_rounding: SizeRounding,
+    /// If set, accumulation passes are sized for this output size regardless of the actual one.
+    pub locked_resolution: Option<PhysicalSize<u32>>,
+    /// Accumulation passes smaller than this (scaled up with `n`) are not recursed into.
+    pub min_pass_size: u32,
+    /// If set, overrides `min_pass_size` to use this many accumulation passes (where possible).
+    pub pass_depth: Option<u32>,
+    /// If set, draws exactly this many levels in a single pass (see `plan::Inputs::levels`). Changed by `step_level`.
+    pub step_levels: Option<u32>,
+    /// If set, accumulation passes draw levels targeting this many quads per pixel (see `plan::Inputs::samples_per_pixel`).
+    pub samples_per_pixel: Option<f64>,
+    /// Exports are rendered at this many times their size (in each direction), then downsampled.
+    pub supersample: u32,
+    /// Filter used to downsample supersampled exports.
+    pub downsample_filter: DownsampleFilter,
     polygon: bool,
+    /// If set, the transforms are augmented with copies to give the flame this symmetry (see `Root::with_symmetry`).
+    pub symmetry: Option<Symmetry>,
     scale: f64,
+    /// If set, polygon mode points use this scale across (tangential to) the polygon, and `scale` only along their radius.
+    scale_y: Option<f64>,
+    /// Per point replacements for `scale` in polygon mode.
+    scale_overrides: Vec<Option<f64>>,
     rotation: f32,
     points: Vec<Point>,
+    /// Per point density multiplier. Missing entries are 1.
+    opacities: Vec<f64>,
+    /// Per point transform applied after it (flam3's "post" affine). Missing entries are None.
+    post_transforms: Vec<Option<Point>>,
+    /// If set, the flame is drawn from these Möbius transforms instead of the affine ones (see `mobius`).
+    /// The final transform and camera still apply.
+    pub mobius: Option<Vec<Mobius>>,
+    /// Applied to the output only: not one of the numbered points.
+    final_transform: Point,
+    final_enabled: bool,
+    /// Transform highlighted in the editor and preview, and moved by `nudge_selected`.
+    selected: Option<usize>,
+    /// Text edited in the UI, applied with `apply_params` when pasted.
+    params_text: String,
+    /// Why the last paste failed, if it did.
+    params_error: Option<String>,
+    /// Path of the scene file saved and loaded from the UI.
+    scene_path: String,
+    /// Why the last scene save or load failed, if it did.
+    scene_error: Option<String>,
+    /// Cycle through the scenes in `slideshow_dir` (see `slideshow`).
+    pub slideshow: bool,
+    pub slideshow_dir: String,
+    /// Seconds each scene is shown before the next starts fading in.
+    pub slideshow_dwell: f64,
+    /// Seconds the cross-fade between scenes takes.
+    pub slideshow_fade: f64,
+    /// Why the slideshow failed to start, if it did.
+    pub slideshow_error: Option<String>,
+    /// Drift the flame with no UI shown, until there is any input (see `screensaver`).
+    pub screensaver: bool,
+    /// Depth of the tree drawn by `ifs_graph`.
+    graph_levels: u32,
+    /// Text being typed into the command box.
+    command_text: String,
+    /// Result of the last command.
+    command_result: Option<Result<String, String>>,
+    /// Guided overlay for first time users. Inactive by default (see `Tutorial::first_launch`).
+    pub tutorial: Tutorial,
+}
+
+/// Bundles of the settings trading speed for quality (see `apply`), from fastest to best looking.
+/// These only change how accurately the flame is drawn, not how it looks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quality {
+    /// For fast interaction.
+    Draft,
+    Normal,
+    High,
+    /// For final renders: slow.
+    Export,
+}
+
+impl Quality {
+    pub const ALL: [Quality; 4] = [
+        Quality::Draft,
+        Quality::Normal,
+        Quality::High,
+        Quality::Export,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Quality::Draft => "Draft",
+            Quality::Normal => "Normal",
+            Quality::High => "High",
+            Quality::Export => "Export",
+        }
+    }
+
+    /// Sets the settings this preset controls.
+    pub fn apply(self, setting: &mut Settings) {
+        let (min_pass_size, max_bounds_levels, bounds_samples, size_rounding) = match self {
+            Quality::Draft => (64, 3, Some(16), SizeRounding::MultipleOf8),
+            Quality::Normal => (DEFAULT_MIN_PASS_SIZE, 5, None, SizeRounding::MultipleOf8),
+            Quality::High => (8, 7, None, SizeRounding::Exact),
+            Quality::Export => (4, 10, None, SizeRounding::Exact),
+        };
+        // Box is fast for previews, Lanczos is sharp without aliasing for final renders.
+        let (supersample, downsample_filter) = match self {
+            Quality::Draft | Quality::Normal => (1, DownsampleFilter::Box),
+            Quality::High => (2, DownsampleFilter::Lanczos3),
+            Quality::Export => (3, DownsampleFilter::Lanczos3),
+        };
+        setting.min_pass_size = min_pass_size;
+        setting.max_bounds_levels = max_bounds_levels;
+        setting.bounds_samples = bounds_samples;
+        setting.size_rounding = size_rounding;
+        setting.supersample = supersample;
+        setting.downsample_filter = downsample_filter;
+        setting.pass_depth = None;
+    }
+
+    /// The preset `setting` matches, if any.
+    pub fn of(setting: &Settings) -> Option<Quality> {
+        Quality::ALL.into_iter().find(|quality| {
+            let mut preset = setting.clone();
+            quality.apply(&mut preset);
+            preset == *setting
+        })
+    }
+}
+
+/// Distance `nudge_selected` moves per step.
+pub const NUDGE_STEP: f64 = 0.01;
+
+/// Most transforms the UI allows.
+const MAX_TRANSFORMS: usize = 12;
+
+/// Polygon scales (in either direction) beyond this are disallowed by the UI.
+pub const MAX_POLYGON_SCALE: f64 = 0.8;
+
+/// Largest order (copies per transform, doubled when mirrored) of symmetry the UI allows.
+const MAX_SYMMETRY: u32 = 12;
+
+/// Change in polygon scale per line scrolled by `scroll_polygon`.
+const SCROLL_SCALE_STEP: f64 = 0.005;
+
+/// Change in polygon rotation (radians) per line scrolled by `scroll_polygon`.
+const SCROLL_ROTATION_STEP: f32 = std::f32::consts::PI / 180.0;
+
+const SELECTED_COLOR: egui::Color32 = egui::Color32::WHITE;
+
+/// Range of overall UI scale (window scale factor times `Settings::ui_scale`) the default keeps to.
+const DEFAULT_EFFECTIVE_SCALE: std::ops::RangeInclusive<f64> = 1.0..=2.5;
+
+/// `ui_scale` for a window with `scale_factor`, which keeps the panel readable but not huge.
+pub fn default_ui_scale(scale_factor: f64) -> f32 {
+    let effective = scale_factor.clamp(
+        *DEFAULT_EFFECTIVE_SCALE.start(),
+        *DEFAULT_EFFECTIVE_SCALE.end(),
+    );
+    (effective / scale_factor) as f32
+}
+
+/// The default style with text and spacing scaled by `scale`.
+// egui's pixels per point can't be used for this, since egui_winit_platform maps input using the window's scale factor.
+pub fn scaled_style(scale: f32) -> egui::Style {
+    let mut style = egui::Style::default();
+    for font in style.text_styles.values_mut() {
+        font.size *= scale;
+    }
+    let spacing = &mut style.spacing;
+    spacing.item_spacing *= scale;
+    spacing.button_padding *= scale;
+    spacing.interact_size *= scale;
+    spacing.indent *= scale;
+    spacing.slider_width *= scale;
+    spacing.combo_width *= scale;
+    spacing.text_edit_width *= scale;
+    spacing.icon_width *= scale;
+    spacing.icon_width_inner *= scale;
+    spacing.icon_spacing *= scale;
+    style
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -19,47 +294,1131 @@ pub struct Point {
     data: Affine2<f64>,
 }
 
-pub fn update(ctx: &egui::Context, setting: &mut Settings, frame_time: f64) {
-    egui::SidePanel::right("Settings").show(ctx, |ui| {
+/// Request to save the flame as an image, which may be larger than the window.
+pub struct Export {
+    pub width: u32,
+    pub height: u32,
+    pub path: String,
+    /// Set by the UI when the export button is clicked.
+    pub requested: bool,
+    /// Accumulate on the CPU in f64 instead of on the GPU in f32. Much slower.
+    pub high_precision: bool,
+    /// Save the density before tone mapping as an OpenEXR file, instead of the final image.
+    pub raw_density: bool,
+    /// If set, save an animated GIF of this sweep instead of a single image.
+    pub sweep: Option<Sweep>,
+}
+
+impl Default for Export {
+    fn default() -> Self {
+        Self {
+            width: 3840,
+            height: 2160,
+            path: "flame.png".to_owned(),
+            requested: false,
+            high_precision: false,
+            raw_density: false,
+            sweep: None,
+        }
+    }
+}
+
+/// Information about the renderer to display.
+pub struct Stats<'a> {
+    /// Seconds per frame: the interval between frames, which vsync and the FPS cap can limit.
+    pub frame_time: f64,
+    /// CPU seconds per frame spent recording and submitting the flame's render commands (not the UI),
+    /// which vsync does not affect.
+    pub render_time: f64,
+    pub points_per_frame: u64,
+    /// Render passes begun for the last frame.
+    pub render_passes: u32,
+    /// Description of the graphics adapter in use.
+    pub adapter: &'a str,
+    /// If the window supports an HDR (extended range) format.
+    pub hdr_supported: bool,
+    /// Shown under the adapter, ex: when a more capable backend could be available.
+    pub backend_notice: Option<&'a str>,
+    /// Shown under the adapter when it lacks a capability, so the flame is rendered at reduced quality.
+    pub quality_notice: Option<&'a str>,
+    pub plan: Plan,
+    /// Width / height of the flame's uncropped output bounds.
+    pub output_aspect: f64,
+    /// If `Settings::show_hull`, the flame's convex hull, as fractions of the (cropped) output bounds with y up.
+    pub hull: Option<Vec<Point2<f64>>>,
+    /// If `Settings::show_level_bounds`, the corners of each level's bounds, in the same coordinates as `hull`.
+    pub level_bounds: Option<Vec<Vec<Point2<f64>>>>,
+    /// Whether the flame contracts, memoized by the database.
+    pub contraction: ContractionReport,
+    /// The current palette.
+    pub palette: &'a image::RgbaImage,
+    /// GPU milliseconds for each of `timing::STAGES`, if available.
+    pub gpu_timings: Option<&'a [f64]>,
+}
+
+/// If rendering (`render_time` seconds, the slower of CPU and GPU) takes most of each frame's `frame_time`,
+/// so is what limits the frame rate, rather than waiting for vsync or the FPS cap.
+fn render_bound(frame_time: f64, render_time: f64) -> bool {
+    render_time > 0.8 * frame_time
+}
+
+/// Draws the settings panel, returning its width in points.
+pub fn update(
+    ctx: &egui::Context,
+    setting: &mut Settings,
+    show_ui: &mut bool,
+    export: &mut Export,
+    stats: &Stats,
+) -> f32 {
+    let frame_time = stats.frame_time;
+    let panel = egui::SidePanel::right("Settings").show(ctx, |ui| {
+        if ui.button("Hide UI (F11)").clicked() {
+            *show_ui = false;
+        }
+        ui.label("F to toggle full screen");
+        if ui.button("Show Tutorial").clicked() {
+            setting.tutorial.restart();
+        }
+        ui.label(format!("Adapter: {}", stats.adapter));
+        for notice in [stats.backend_notice, stats.quality_notice]
+            .into_iter()
+            .flatten()
+        {
+            ui.colored_label(egui::Color32::YELLOW, notice);
+        }
+        ui.add(egui::Slider::new(&mut setting.ui_scale, 0.5..=3.0).text("UI Scale"));
         ui.checkbox(&mut setting.busy_loop, "Busy Loop");
         if setting.busy_loop {
+            ui.horizontal(|ui| {
+                ui.label("FPS Cap:");
+                for cap in [30, 60, 120] {
+                    ui.radio_value(&mut setting.fps_cap, Some(cap), cap.to_string());
+                }
+                ui.radio_value(&mut setting.fps_cap, None, "Uncapped");
+            });
             ui.label(format!("FPS: {:.0}", 1.0 / frame_time));
-            ui.label(format!("Frame Time: {:.3}ms", frame_time * 1000.0));
+            ui.label(format!("Frame Interval: {:.3}ms", frame_time * 1000.0));
+            ui.label(format!("Render CPU: {:.3}ms", stats.render_time * 1000.0));
+            // The flame's stages: all but the UI.
+            let gpu_render = stats
+                .gpu_timings
+                .map(|timings| timings[..timings.len() - 1].iter().sum::<f64>() / 1000.0);
+            if let Some(seconds) = gpu_render {
+                ui.label(format!("Render GPU: {:.3}ms", seconds * 1000.0));
+            }
+            let render_time =
+                gpu_render.map_or(stats.render_time, |gpu| gpu.max(stats.render_time));
+            if render_bound(frame_time, render_time) {
+                ui.label("Render bound");
+            } else {
+                ui.label("Waiting (vsync or FPS cap): rendering has headroom");
+            }
+            if let Some(timings) = stats.gpu_timings {
+                for (stage, ms) in timing::STAGES.iter().zip(timings) {
+                    ui.label(format!("GPU {stage}: {ms:.3}ms"));
+                }
+            }
+            ui.label(format!(
+                "Points/s: {:.3e}",
+                stats.points_per_frame as f64 / frame_time
+            ));
+        }
+        ui.label(format!("Render passes per frame: {}", stats.render_passes));
+        ui.collapsing("Passes", |ui| {
+            for pass in &stats.plan.passes {
+                let size = pass.key.resolution;
+                ui.label(format!(
+                    "{}x{}: {} levels",
+                    size.width, size.height, pass.levels
+                ));
+            }
+            ui.label(format!("Total levels: {}", stats.plan.levels()));
+            ui.label(format!("Depth: {}", stats.plan.passes.len()));
+            pass_graph(ui, &stats.plan);
+            ui.horizontal(|ui| {
+                let mut fixed = setting.pass_depth.is_some();
+                ui.checkbox(&mut fixed, "Override Depth");
+                match (fixed, &mut setting.pass_depth) {
+                    (true, Some(depth)) => {
+                        ui.add(egui::Slider::new(depth, 1..=12));
+                    }
+                    (true, None) => setting.pass_depth = Some(stats.plan.passes.len() as u32),
+                    (false, _) => setting.pass_depth = None,
+                }
+            });
+            ui.horizontal(|ui| {
+                let mut targeted = setting.samples_per_pixel.is_some();
+                ui.checkbox(&mut targeted, "Target Density");
+                match (targeted, &mut setting.samples_per_pixel) {
+                    (true, Some(samples)) => {
+                        ui.add(
+                            egui::Slider::new(samples, 0.01..=100.0)
+                                .logarithmic(true)
+                                .suffix(" quads/pixel"),
+                        );
+                    }
+                    (true, None) => setting.samples_per_pixel = Some(1.0),
+                    (false, _) => setting.samples_per_pixel = None,
+                }
+            });
+            ui.horizontal(|ui| {
+                let most_levels = max_levels(setting.transform_count());
+                let mut stepping = setting.step_levels.is_some();
+                ui.checkbox(&mut stepping, "Step Levels (L)");
+                match (stepping, &mut setting.step_levels) {
+                    (true, Some(levels)) => {
+                        ui.add(egui::Slider::new(levels, 0..=most_levels));
+                    }
+                    (true, None) => setting.step_levels = Some(0),
+                    (false, _) => setting.step_levels = None,
+                }
+            });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Tone Map:");
+            ui.radio_value(&mut setting.tone_map, ToneMap::Log, "Log");
+            ui.radio_value(&mut setting.tone_map, ToneMap::HistogramEq, "Histogram Eq");
+            ui.radio_value(&mut setting.tone_map, ToneMap::Linear, "Linear");
+        });
+        if setting.tone_map == ToneMap::Log {
+            ui.horizontal(|ui| {
+                let mut auto = setting.auto_exposure.is_some();
+                ui.checkbox(&mut auto, "Auto Exposure");
+                match (auto, &mut setting.auto_exposure) {
+                    (true, Some(compensation)) => {
+                        ui.add(egui::Slider::new(compensation, -8.0..=8.0).text("stops"));
+                    }
+                    (true, None) => setting.auto_exposure = Some(0.0),
+                    (false, _) => setting.auto_exposure = None,
+                }
+            });
+        }
+        ui.add(
+            egui::Slider::new(&mut setting.highlight_rolloff, 0.0..=1.0).text("Highlight Rolloff"),
+        );
+        ui.collapsing("Contrast Curve", |ui| {
+            ui.label(
+                "Drag points to move them, double click to add one, and right click to remove one.",
+            );
+            curve_editor(ui, &mut setting.tone_curve);
+            if ui.button("Reset").clicked() {
+                setting.tone_curve = IDENTITY_CONTRAST.to_vec();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Accumulation:");
+            ui.radio_value(&mut setting.accumulation, Accumulation::Linear, "Linear");
+            ui.radio_value(&mut setting.accumulation, Accumulation::Log, "Log");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Coloring:");
+            ui.radio_value(&mut setting.orbit_trap, None, "Density");
+            ui.radio_value(&mut setting.orbit_trap, Some(Trap::Point), "Point Trap");
+            ui.radio_value(&mut setting.orbit_trap, Some(Trap::Line), "Line Trap");
+            ui.radio_value(&mut setting.orbit_trap, Some(Trap::Circle), "Circle Trap");
+        });
+        ui.horizontal(|ui| {
+            let mut splats = setting.splat_size.is_some();
+            ui.checkbox(&mut splats, "Gaussian Splats");
+            match (splats, setting.splat_size) {
+                (true, None) => setting.splat_size = Some(0.2),
+                (false, Some(_)) => setting.splat_size = None,
+                _ => {}
+            }
+            if let Some(size) = &mut setting.splat_size {
+                ui.add(egui::Slider::new(size, 0.05..=0.5).text("Size"));
+            }
+        });
+        if setting.orbit_trap.is_some() {
+            ui.label("Orbit traps are only shown in (CPU) exports");
+        }
+        if stats.hdr_supported {
+            ui.checkbox(&mut setting.hdr, "HDR");
+        }
+        ui.checkbox(&mut setting.oriented_bounds, "Oriented Bounds");
+        ui.checkbox(&mut setting.show_hull, "Show Hull");
+        ui.checkbox(&mut setting.show_level_bounds, "Show Level Bounds");
+        ui.collapsing("Camera", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Zoom:");
+                ui.add(egui::Slider::new(&mut setting.zoom, 1.0..=16.0).logarithmic(true));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Center:");
+                for v in &mut setting.camera_center {
+                    ui.add(egui::DragValue::new(v).speed(0.01).clamp_range(-1.0..=1.0));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Rotation:");
+                let mut rotation = setting.camera_rotation as f32;
+                ui.drag_angle(&mut rotation);
+                setting.camera_rotation = f64::from(rotation);
+            });
+            ui.label("Ctrl + drag on the flame to rotate");
+            if ui.button("Reset").clicked() {
+                setting.zoom = 1.0;
+                setting.camera_center = [0.0, 0.0];
+                setting.camera_rotation = 0.0;
+            }
+        });
+        if setting.zoom != 1.0 {
+            ui.checkbox(&mut setting.minimap, "Minimap");
+        }
+        ui.horizontal(|ui| {
+            let mut trails = setting.feedback_decay.is_some();
+            ui.checkbox(&mut trails, "Trails");
+            match (trails, setting.feedback_decay) {
+                (true, None) => setting.feedback_decay = Some(0.9),
+                (false, Some(_)) => setting.feedback_decay = None,
+                _ => {}
+            }
+            if let Some(decay) = &mut setting.feedback_decay {
+                ui.add(egui::Slider::new(decay, 0.0..=0.99).text("Decay"));
+            }
+        });
+        let mut bloom = setting.bloom.is_some();
+        ui.checkbox(&mut bloom, "Bloom");
+        match (bloom, &mut setting.bloom) {
+            (true, Some(bloom)) => {
+                ui.add(egui::Slider::new(&mut bloom.threshold, 0.0..=1.0).text("Threshold"));
+                ui.add(egui::Slider::new(&mut bloom.intensity, 0.0..=4.0).text("Intensity"));
+                ui.add(
+                    egui::Slider::new(&mut bloom.radius, 2.0..=64.0)
+                        .logarithmic(true)
+                        .text("Radius"),
+                );
+            }
+            (true, None) => setting.bloom = Some(Bloom::default()),
+            (false, _) => setting.bloom = None,
+        }
+        ui.horizontal(|ui| {
+            ui.label("Crop:");
+            if ui.button("Draw").clicked() {
+                setting.crop = None;
+                setting.crop_drawing = true;
+            }
+            if ui.button("Clear").clicked() {
+                setting.crop = None;
+                setting.crop_drawing = false;
+            }
+            if setting.crop_drawing {
+                ui.label("Drag over the flame");
+            }
+        });
+        ui.horizontal(|ui| {
+            let mut enabled = setting.safe_zone.is_some();
+            ui.checkbox(&mut enabled, "Safe Zone");
+            match (enabled, setting.safe_zone) {
+                (true, None) => setting.safe_zone = Some(16.0 / 9.0),
+                (false, Some(_)) => setting.safe_zone = None,
+                _ => {}
+            }
+            if let Some(ratio) = &mut setting.safe_zone {
+                for (label, value) in [("16:9", 16.0 / 9.0), ("4:3", 4.0 / 3.0), ("1:1", 1.0)] {
+                    if ui.selectable_label(*ratio == value, label).clicked() {
+                        *ratio = value;
+                    }
+                }
+                ui.add(
+                    egui::DragValue::new(ratio)
+                        .speed(0.01)
+                        .clamp_range(0.1..=10.0),
+                );
+            }
+        });
+        ui.horizontal(|ui| {
+            let mut enabled = setting.aspect_lock.is_some();
+            ui.checkbox(&mut enabled, "Lock Aspect");
+            match (enabled, setting.aspect_lock) {
+                (true, None) => {
+                    setting.aspect_lock = Some(
+                        f64::from(export.width) * setting.pixel_aspect / f64::from(export.height),
+                    )
+                }
+                (false, Some(_)) => setting.aspect_lock = None,
+                _ => {}
+            }
+            if let Some(ratio) = &mut setting.aspect_lock {
+                for (label, value) in [("16:9", 16.0 / 9.0), ("4:5", 4.0 / 5.0), ("1:1", 1.0)] {
+                    if ui.selectable_label(*ratio == value, label).clicked() {
+                        *ratio = value;
+                    }
+                }
+                ui.add(
+                    egui::DragValue::new(ratio)
+                        .speed(0.01)
+                        .clamp_range(0.1..=10.0),
+                );
+            }
+        });
+        ui.collapsing("Palette", |ui| {
+            ui.text_edit_singleline(&mut setting.palette_path);
+            ui.horizontal(|ui| {
+                if ui.button("Load").clicked() {
+                    setting.palette = PaletteSource::Image(setting.palette_path.clone());
+                }
+                if ui.button("Default").clicked() {
+                    setting.palette = PaletteSource::Default;
+                }
+            });
+            // Only the palette is used: the rest of the file is ignored.
+            if ui.button("Import palette only (.flame)").clicked() {
+                match std::fs::read_to_string(&setting.palette_path) {
+                    Ok(xml) => match parse_palette(&xml) {
+                        Some(colors) => setting.palette = PaletteSource::Colors(colors),
+                        None => log::warn!("No palette found in {}", setting.palette_path),
+                    },
+                    Err(e) => log::warn!("Failed to read {}: {e}", setting.palette_path),
+                }
+            }
+            ui.horizontal(|ui| {
+                ui.label("Color Blind Friendly:");
+                for palette in AccessiblePalette::ALL {
+                    if ui.button(palette.name()).clicked() {
+                        setting.palette = PaletteSource::Colors(palette.colors());
+                    }
+                }
+            });
+            ui.add(egui::Slider::new(&mut setting.palette_color, 0.0..=1.0).text("Color"));
+            palette_strip(ui, stats.palette, setting.palette_color);
+            ui.horizontal(|ui| {
+                ui.label("Simulate:");
+                ui.radio_value(&mut setting.color_vision, None, "Normal");
+                for vision in ColorVision::ALL {
+                    ui.radio_value(&mut setting.color_vision, Some(vision), vision.name());
+                }
+            });
+        });
+        ui.collapsing("Background", |ui| {
+            ui.text_edit_singleline(&mut setting.background_path);
+            ui.horizontal(|ui| {
+                if ui.button("Load").clicked() {
+                    setting.background_image = Some(setting.background_path.clone());
+                }
+                if ui.button("Clear").clicked() {
+                    setting.background_image = None;
+                }
+            });
+            ui.checkbox(
+                &mut setting.transparent_background,
+                "Transparent Background",
+            );
+        });
+        ui.collapsing("Share", |ui| {
+            ui.text_edit_singleline(&mut setting.params_text);
+            ui.horizontal(|ui| {
+                if ui.button("Copy").clicked() {
+                    setting.params_text = setting.to_params();
+                    ui.output_mut(|o| o.copied_text = setting.params_text.clone());
+                }
+                if ui.button("Paste").clicked() {
+                    let text = setting.params_text.clone();
+                    setting.params_error = setting.apply_params(&text).err();
+                }
+            });
+            if let Some(error) = &setting.params_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+        ui.collapsing("Scene", |ui| {
+            ui.text_edit_singleline(&mut setting.scene_path);
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    setting.scene_error = setting.to_scene().save(&setting.scene_path).err();
+                }
+                if ui.button("Load").clicked() {
+                    setting.scene_error = Scene::load(&setting.scene_path)
+                        .and_then(|scene| setting.apply_scene(&scene))
+                        .err();
+                }
+            });
+            if let Some(error) = &setting.scene_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+        if ui
+            .button("Screensaver")
+            .on_hover_text("Slowly drifts the flame with the UI hidden. Any input stops it.")
+            .clicked()
+        {
+            setting.screensaver = true;
+        }
+        ui.collapsing("Slideshow", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Directory:");
+                ui.text_edit_singleline(&mut setting.slideshow_dir);
+            });
+            ui.add(
+                egui::Slider::new(&mut setting.slideshow_dwell, 1.0..=120.0)
+                    .logarithmic(true)
+                    .text("Dwell (s)"),
+            );
+            ui.add(egui::Slider::new(&mut setting.slideshow_fade, 0.0..=10.0).text("Fade (s)"));
+            if ui.checkbox(&mut setting.slideshow, "Play").changed() {
+                setting.slideshow_error = None;
+            }
+            if let Some(error) = &setting.slideshow_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+        ui.collapsing("Compare to Reference", |ui| {
+            ui.text_edit_singleline(&mut setting.reference_path);
+            ui.horizontal(|ui| {
+                if ui.button("Load").clicked() {
+                    setting.reference_image = Some(setting.reference_path.clone());
+                    setting.compare = true;
+                }
+                if ui.button("Clear").clicked() {
+                    setting.reference_image = None;
+                }
+            });
+            if setting.reference_image.is_some() {
+                ui.checkbox(&mut setting.compare, "Compare to reference");
+                ui.add(egui::Slider::new(&mut setting.compare_blend, 0.0..=1.0).text("Difference"));
+            }
+        });
+        // Exporting blocks on the GPU, which is not possible on the web.
+        if cfg!(not(target_arch = "wasm32")) {
+            ui.collapsing("Export", |ui| {
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut export.width).clamp_range(1..=65536));
+                    ui.label("x");
+                    match setting.aspect_lock {
+                        Some(ratio) => {
+                            export.height =
+                                locked_height(export.width, ratio, setting.pixel_aspect);
+                            ui.label(export.height.to_string());
+                        }
+                        None => {
+                            ui.add(egui::DragValue::new(&mut export.height).clamp_range(1..=65536));
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Pixel Aspect:");
+                    ui.add(
+                        egui::DragValue::new(&mut setting.pixel_aspect)
+                            .clamp_range(0.25..=4.0)
+                            .speed(0.001),
+                    );
+                });
+                ui.text_edit_singleline(&mut export.path);
+                let mut format_changed = ui
+                    .checkbox(&mut export.raw_density, "Raw density (EXR)")
+                    .changed();
+                if !export.raw_density {
+                    let mut enabled = export.sweep.is_some();
+                    format_changed |= ui.checkbox(&mut enabled, "Animate (GIF)").changed();
+                    match (enabled, &export.sweep) {
+                        (true, None) => export.sweep = Some(Sweep::default()),
+                        (false, Some(_)) => export.sweep = None,
+                        _ => {}
+                    }
+                    match &mut export.sweep {
+                        Some(sweep) => sweep_editor(ui, sweep),
+                        None => {
+                            ui.checkbox(&mut export.high_precision, "High precision (CPU)");
+                        }
+                    }
+                }
+                if format_changed {
+                    let extension = match (export.raw_density, &export.sweep) {
+                        (true, _) => "exr",
+                        (false, Some(_)) => "gif",
+                        (false, None) => "png",
+                    };
+                    export.path = std::path::Path::new(&export.path)
+                        .with_extension(extension)
+                        .to_string_lossy()
+                        .into_owned();
+                }
+                if ui.button("Save").clicked() {
+                    export.requested = true;
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.label("Quality:");
+            let current = Quality::of(setting);
+            egui::ComboBox::from_id_source("quality")
+                .selected_text(current.map_or("Custom", Quality::name))
+                .show_ui(ui, |ui| {
+                    for quality in Quality::ALL {
+                        if ui
+                            .selectable_label(current == Some(quality), quality.name())
+                            .clicked()
+                        {
+                            quality.apply(setting);
+                        }
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Pass Size:");
+            ui.radio_value(
+                &mut setting.size_rounding,
+                SizeRounding::MultipleOf8,
+                "Multiple of 8",
+            );
+            ui.radio_value(&mut setting.size_rounding, SizeRounding::Exact, "Exact");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Export Supersample:");
+            ui.add(egui::DragValue::new(&mut setting.supersample).clamp_range(1..=MAX_FACTOR));
+            ui.add_enabled_ui(setting.supersample > 1, |ui| {
+                egui::ComboBox::from_id_source("downsample filter")
+                    .selected_text(setting.downsample_filter.name())
+                    .show_ui(ui, |ui| {
+                        for filter in DownsampleFilter::ALL {
+                            ui.selectable_value(
+                                &mut setting.downsample_filter,
+                                filter,
+                                filter.name(),
+                            );
+                        }
+                    });
+            });
+        });
+        ui.horizontal(|ui| {
+            let mut locked = setting.locked_resolution.is_some();
+            ui.checkbox(&mut locked, "Lock Resolution");
+            match (locked, &mut setting.locked_resolution) {
+                (true, Some(size)) => {
+                    ui.add(egui::DragValue::new(&mut size.width).clamp_range(1..=65536));
+                    ui.label("x");
+                    ui.add(egui::DragValue::new(&mut size.height).clamp_range(1..=65536));
+                }
+                (true, None) => {
+                    setting.locked_resolution = stats.plan.passes.first().map(|p| p.key.resolution)
+                }
+                (false, _) => setting.locked_resolution = None,
+            }
+        });
+        ui.add(
+            egui::Slider::new(&mut setting.min_pass_size, 1..=256)
+                .logarithmic(true)
+                .text("Min Pass Size"),
+        );
+        ui.label("Max Bounds Levels:");
+        ui.add(egui::Slider::new(&mut setting.max_bounds_levels, 1..=10));
+        ui.horizontal(|ui| {
+            let mut sample = setting.bounds_samples.is_some();
+            ui.checkbox(&mut sample, "Sample Bounds");
+            match (sample, &mut setting.bounds_samples) {
+                (true, Some(samples)) => {
+                    ui.add(egui::Slider::new(samples, 1..=256));
+                }
+                (true, None) => setting.bounds_samples = Some(16),
+                (false, _) => setting.bounds_samples = None,
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Command:");
+            let response = ui.text_edit_singleline(&mut setting.command_text);
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                let line = std::mem::take(&mut setting.command_text);
+                setting.command_result = Some(commands::execute(setting, &line));
+                response.request_focus();
+            }
+        });
+        match &setting.command_result {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            None => {}
+        }
+        ui.collapsing("Möbius Transforms", |ui| {
+            let mut enabled = setting.mobius.is_some();
+            ui.checkbox(&mut enabled, "Enabled");
+            match (enabled, &mut setting.mobius) {
+                (true, Some(maps)) => {
+                    ui.label("z -> (az + b) / (cz + d)");
+                    let n = maps.len();
+                    // Applied after the loop, which borrows the maps.
+                    let mut duplicate = None;
+                    let mut remove = None;
+                    for (i, m) in maps.iter_mut().enumerate() {
+                        mobius_editor(ui, m);
+                        ui.horizontal(|ui| {
+                            if ui
+                                .add_enabled(n < MAX_TRANSFORMS, egui::Button::new("Duplicate"))
+                                .clicked()
+                            {
+                                duplicate = Some(i);
+                            }
+                            if ui.add_enabled(n > 1, egui::Button::new("Delete")).clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(i) = duplicate {
+                        maps.insert(i + 1, maps[i]);
+                    }
+                    if let Some(i) = remove {
+                        maps.remove(i);
+                    }
+                }
+                (true, None) => setting.mobius = Some(mobius_gasket()),
+                (false, _) => setting.mobius = None,
+            }
+        });
+        if setting.mobius.is_some() {
+            ui.label(
+                "Drawing the Möbius transforms: the affine transforms below are not used. \
+                Exports are always drawn on the GPU, so without orbit traps.",
+            );
         }
         ui.label("Points:");
-        ui.add(egui::Slider::new(&mut setting.n, 2..=12));
-        ui.checkbox(&mut setting.polygon, "Polygon");
+        let points = ui.add(egui::Slider::new(&mut setting.n, 2..=MAX_TRANSFORMS));
+        setting.tutorial.anchor(Step::Points, points.rect);
+        // Loaded flames and commands can have fewer transforms than the slider allows.
+        match setting.n {
+            0 => {
+                ui.colored_label(egui::Color32::YELLOW, "No transforms: nothing to draw");
+            }
+            1 => {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    "One transform: the flame is just its fixed point",
+                );
+            }
+            _ => {}
+        }
+        let contraction = &stats.contraction;
+        if setting.mobius.is_none() && !contraction.is_contractive() {
+            ui.colored_label(
+                egui::Color32::RED,
+                format!(
+                    "Not contractive (stretches {:.3} per level): the flame has no finite bounds",
+                    contraction.joint_spectral_radius
+                ),
+            );
+        }
+        ui.collapsing("Per Point Opacity", |ui| {
+            setting.opacities.resize(setting.n, 1.0);
+            for (i, opacity) in setting.opacities.iter_mut().enumerate() {
+                ui.add(egui::Slider::new(opacity, 0.0..=1.0).text(format!("{i}")));
+            }
+        });
+        egui::ComboBox::from_label("Template")
+            .selected_text("Choose...")
+            .show_ui(ui, |ui| {
+                for template in Template::ALL {
+                    if ui.selectable_label(false, template.name()).clicked() {
+                        setting.points = template
+                            .transforms()
+                            .into_iter()
+                            .map(|data| Point { data })
+                            .collect();
+                        setting.n = setting.points.len();
+                        setting.polygon = false;
+                    }
+                }
+            });
+        ui.horizontal(|ui| {
+            let mut enabled = setting.symmetry.is_some();
+            ui.checkbox(&mut enabled, "Symmetry");
+            match (enabled, setting.symmetry) {
+                (true, None) => setting.symmetry = Some(Symmetry::Dihedral(3)),
+                (false, Some(_)) => setting.symmetry = None,
+                _ => {}
+            }
+            if let Some(symmetry) = &mut setting.symmetry {
+                let (mut n, mut mirror) = match *symmetry {
+                    Symmetry::Cyclic(n) => (n, false),
+                    Symmetry::Dihedral(n) => (n, true),
+                };
+                ui.add(egui::DragValue::new(&mut n).clamp_range(1..=MAX_SYMMETRY));
+                ui.checkbox(&mut mirror, "Mirror");
+                *symmetry = if mirror {
+                    Symmetry::Dihedral(n)
+                } else {
+                    Symmetry::Cyclic(n)
+                };
+                ui.label(format!("{}{n}", if mirror { "D" } else { "C" }));
+            }
+        });
+        let polygon = ui.checkbox(&mut setting.polygon, "Polygon");
+        setting.tutorial.anchor(Step::Polygon, polygon.rect);
         if setting.polygon {
+            // No transform editors to point at.
+            setting.tutorial.anchor(Step::Transforms, polygon.rect);
             ui.label("Rotation:");
             ui.drag_angle(&mut setting.rotation);
             ui.label("Scale:");
             ui.add(
                 egui::DragValue::new(&mut setting.scale)
-                    .clamp_range(-0.8..=0.8)
+                    .clamp_range(-MAX_POLYGON_SCALE..=MAX_POLYGON_SCALE)
                     .speed(0.0005),
             );
+            if setting.scale < 0.0 {
+                ui.label("Negative scale is a point reflection (a 180° rotation), not a mirror.");
+            }
+            ui.horizontal(|ui| {
+                let mut separate = setting.scale_y.is_some();
+                ui.checkbox(&mut separate, "Separate Y Scale");
+                match (separate, setting.scale_y.as_mut()) {
+                    (true, Some(s)) => {
+                        ui.add(
+                            egui::DragValue::new(s)
+                                .clamp_range(-MAX_POLYGON_SCALE..=MAX_POLYGON_SCALE)
+                                .speed(0.0005),
+                        );
+                    }
+                    (true, None) => setting.scale_y = Some(setting.scale),
+                    (false, _) => setting.scale_y = None,
+                }
+            });
+            ui.collapsing("Per Point Scale", |ui| {
+                setting.scale_overrides.resize(setting.n, None);
+                for (i, scale) in setting.scale_overrides.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        let mut enabled = scale.is_some();
+                        ui.checkbox(&mut enabled, format!("{i}"));
+                        match (enabled, scale.as_mut()) {
+                            (true, Some(s)) => {
+                                ui.add(
+                                    egui::DragValue::new(s)
+                                        .clamp_range(-MAX_POLYGON_SCALE..=MAX_POLYGON_SCALE)
+                                        .speed(0.0005),
+                                );
+                            }
+                            (true, None) => *scale = Some(setting.scale),
+                            (false, _) => *scale = None,
+                        }
+                    });
+                }
+            });
         } else {
             while setting.points.len() < setting.n {
                 setting.points.push(Point {
                     data: get_polygon_point(setting, setting.points.len()),
                 })
             }
-            for p in &mut setting.points[0..setting.n] {
-                affine_editor(ui, p);
+            let selected = setting.selected();
+            let n = setting.n;
+            // Applied after the loop, which borrows the points.
+            let mut duplicate = None;
+            let mut remove = None;
+            for (i, p) in setting.points[0..n].iter_mut().enumerate() {
+                let editor = affine_editor(ui, p, selected == Some(i));
+                if i == 0 {
+                    setting
+                        .tutorial
+                        .anchor(Step::Transforms, editor.response.rect);
+                }
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(n < MAX_TRANSFORMS, egui::Button::new("Duplicate"))
+                        .clicked()
+                    {
+                        duplicate = Some(i);
+                    }
+                    if ui.add_enabled(n > 1, egui::Button::new("Delete")).clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = duplicate {
+                setting.duplicate_transform(i);
+            }
+            if let Some(i) = remove {
+                setting.remove_transform(i);
+            }
+        }
+        ui.collapsing("Post Transforms", |ui| {
+            setting.post_transforms.resize(setting.n, None);
+            let selected = setting.selected();
+            for (i, post) in setting.post_transforms.iter_mut().enumerate() {
+                let mut enabled = post.is_some();
+                ui.checkbox(&mut enabled, format!("{i}"));
+                match (enabled, post.as_mut()) {
+                    (true, Some(p)) => {
+                        affine_editor(ui, p, selected == Some(i));
+                    }
+                    (true, None) => {
+                        *post = Some(Point {
+                            data: Affine2::identity(),
+                        })
+                    }
+                    (false, _) => *post = None,
+                }
+            }
+        });
+        ui.collapsing("Final Transform", |ui| {
+            ui.checkbox(&mut setting.final_enabled, "Enabled");
+            if setting.final_enabled {
+                affine_editor(ui, &mut setting.final_transform, false);
+            }
+        });
+        // Shared by the preview and graph, which show the same transforms.
+        let state = setting.get_state();
+        transform_preview(
+            ui,
+            state.transforms(),
+            setting
+                .final_enabled
+                .then_some(&setting.final_transform.data),
+            setting.selected(),
+        );
+        ui.collapsing("Graph", |ui| {
+            ui.add(egui::Slider::new(&mut setting.graph_levels, 0..=4).text("Levels"));
+            ifs_graph(ui, &state, setting.graph_levels);
+        });
+    });
+    if let Some(ratio) = setting.aspect_lock {
+        letter_box_bars(ctx, ratio);
+    }
+    if let Some(ratio) = setting.safe_zone {
+        safe_zone(ctx, setting, ratio);
+    }
+    if let Some(hull) = &stats.hull {
+        draw_outline(ctx, setting, hull, stats.output_aspect, HULL_COLOR);
+    }
+    if let Some(levels) = &stats.level_bounds {
+        // Fading with depth, so the nesting is visible.
+        for (level, corners) in levels.iter().enumerate() {
+            let alpha = 255 - (level * 160 / levels.len().max(1)) as u8;
+            let color = egui::Color32::from_rgba_unmultiplied(0, 200, 255, alpha);
+            draw_outline(ctx, setting, corners, stats.output_aspect, color);
+        }
+    }
+    if setting.crop_drawing {
+        draw_crop(ctx, setting, stats.output_aspect);
+    } else {
+        rotate_camera(ctx, setting);
+    }
+    setting.tutorial.show(ctx);
+    panel.response.rect.width()
+}
+
+fn to_rect(r: egui::Rect) -> geometry::Rect {
+    geometry::Rect {
+        min: Point2::new(r.min.x as f64, r.min.y as f64),
+        max: Point2::new(r.max.x as f64, r.max.y as f64),
+    }
+}
+
+fn from_rect(r: geometry::Rect) -> egui::Rect {
+    egui::Rect::from_min_max(
+        egui::pos2(r.min.x as f32, r.min.y as f32),
+        egui::pos2(r.max.x as f32, r.max.y as f32),
+    )
+}
+
+/// Lets the user drag out `crop` over the (uncropped) flame, which is letter boxed into the area not covered by panels.
+fn draw_crop(ctx: &egui::Context, setting: &mut Settings, output_aspect: f64) {
+    let flame = geometry::fit_aspect(flame_area(ctx, setting), output_aspect);
+    // Fractions are y up, like the flame, but the screen is y down.
+    let to_fraction = |p: egui::Pos2| {
+        Point2::new(
+            ((p.x as f64 - flame.min.x) / flame.width()).clamp(0.0, 1.0),
+            ((flame.max.y - p.y as f64) / flame.height()).clamp(0.0, 1.0),
+        )
+    };
+    let to_screen = |p: Point2<f64>| {
+        Point2::new(
+            flame.min.x + p.x * flame.width(),
+            flame.max.y - p.y * flame.height(),
+        )
+    };
+
+    let painter = ctx.layer_painter(egui::LayerId::background());
+    let stroke = egui::Stroke::new(1.0, egui::Color32::from_white_alpha(160));
+    painter.rect_stroke(from_rect(flame), 0.0, stroke);
+
+    let (pressed, released, pos) = ctx.input(|i| {
+        (
+            i.pointer.primary_pressed(),
+            i.pointer.primary_released(),
+            i.pointer.interact_pos(),
+        )
+    });
+    let Some(pos) = pos else {
+        return;
+    };
+    if pressed && !ctx.is_pointer_over_area() {
+        setting.crop_start = Some(to_fraction(pos));
+    }
+    if let Some(start) = setting.crop_start {
+        let crop = geometry::Rect::union(
+            &geometry::Rect::point(start),
+            &geometry::Rect::point(to_fraction(pos)),
+        );
+        let screen = geometry::Rect::union(
+            &geometry::Rect::point(to_screen(crop.min)),
+            &geometry::Rect::point(to_screen(crop.max)),
+        );
+        painter.rect_stroke(from_rect(screen), 0.0, stroke);
+        if released {
+            if crop.width() > 0.0 && crop.height() > 0.0 {
+                setting.crop = Some(crop);
             }
+            setting.crop_start = None;
+            setting.crop_drawing = false;
         }
+    }
+}
+
+/// Color `draw_outline` draws the convex hull with.
+const HULL_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 200, 0);
+
+/// Outlines the polygon `outline` (as fractions of the cropped output bounds) where the flame is drawn: the cropped output bounds are
+/// letter boxed into the area not covered by panels, then zoomed about the camera center.
+fn draw_outline(
+    ctx: &egui::Context,
+    setting: &Settings,
+    outline: &[Point2<f64>],
+    output_aspect: f64,
+    color: egui::Color32,
+) {
+    let aspect = setting.crop.map_or(output_aspect, |crop| {
+        output_aspect * crop.width() / crop.height()
+    });
+    let flame = geometry::fit_aspect(flame_area(ctx, setting), aspect);
+    let center = na::center(&flame.min, &flame.max);
+    let [x, y] = setting.camera_center;
+    let to_screen = |p: &Point2<f64>| {
+        // Normalized device coordinates (y up) of the unzoomed view, then zoomed.
+        let ndc = (p.coords * 2.0 - Vector2::new(1.0 + x, 1.0 + y)) * setting.zoom;
+        egui::pos2(
+            (center.x + ndc.x * flame.width() / 2.0) as f32,
+            (center.y - ndc.y * flame.height() / 2.0) as f32,
+        )
+    };
+    ctx.layer_painter(egui::LayerId::background())
+        .add(egui::Shape::closed_line(
+            outline.iter().map(to_screen).collect(),
+            egui::Stroke::new(1.0, color),
+        ));
+}
+
+/// Rotates the camera while the primary button is dragged over the flame with ctrl (or cmd) held.
+fn rotate_camera(ctx: &egui::Context, setting: &mut Settings) {
+    let (dragging, command, delta) = ctx.input(|i| {
+        (
+            i.pointer.primary_down(),
+            i.modifiers.command,
+            i.pointer.delta(),
+        )
+    });
+    if dragging && command && !ctx.is_using_pointer() && !ctx.is_pointer_over_area() {
+        setting.camera_rotation =
+            (setting.camera_rotation + f64::from(delta.x) * 0.01) % std::f64::consts::TAU;
+    }
+}
+
+/// Where the flame is drawn: the area not covered by panels, letter boxed to `aspect_lock` if set.
+fn flame_area(ctx: &egui::Context, setting: &Settings) -> geometry::Rect {
+    let available = to_rect(ctx.available_rect());
+    setting
+        .aspect_lock
+        .map_or(available, |ratio| geometry::fit_aspect(available, ratio))
+}
+
+/// Covers the area not covered by panels outside of the largest centered rect with `aspect_ratio`.
+fn letter_box_bars(ctx: &egui::Context, aspect_ratio: f64) {
+    let available = ctx.available_rect();
+    let inner = from_rect(geometry::fit_aspect(to_rect(available), aspect_ratio));
+    let painter = ctx.layer_painter(egui::LayerId::background());
+    for bar in [
+        egui::Rect::from_min_max(available.min, egui::pos2(available.max.x, inner.min.y)),
+        egui::Rect::from_min_max(egui::pos2(available.min.x, inner.max.y), available.max),
+        egui::Rect::from_min_max(available.min, egui::pos2(inner.min.x, available.max.y)),
+        egui::Rect::from_min_max(egui::pos2(inner.max.x, available.min.y), available.max),
+    ] {
+        if bar.is_positive() {
+            painter.rect_filled(bar, 0.0, egui::Color32::BLACK);
+        }
+    }
+}
+
+/// Which parameter an animated export sweeps, over what range, and its frame count and rate.
+fn sweep_editor(ui: &mut Ui, sweep: &mut Sweep) {
+    ui.horizontal(|ui| {
+        ui.label("Sweep:");
+        egui::ComboBox::from_id_source("sweep parameter")
+            .selected_text(sweep.parameter.name())
+            .show_ui(ui, |ui| {
+                for parameter in sweep::Parameter::ALL {
+                    if ui
+                        .selectable_label(sweep.parameter == parameter, parameter.name())
+                        .clicked()
+                    {
+                        sweep.parameter = parameter;
+                        (sweep.from, sweep.to) = parameter.default_range();
+                    }
+                }
+            });
+    });
+    ui.horizontal(|ui| {
+        ui.label("From:");
+        ui.add(egui::DragValue::new(&mut sweep.from).speed(0.01));
+        ui.label("To:");
+        ui.add(egui::DragValue::new(&mut sweep.to).speed(0.01));
+    });
+    ui.horizontal(|ui| {
+        ui.add(
+            egui::DragValue::new(&mut sweep.frames)
+                .clamp_range(1..=1000)
+                .suffix(" frames"),
+        );
+        ui.add(
+            egui::DragValue::new(&mut sweep.fps)
+                .clamp_range(1..=100)
+                .suffix(" fps"),
+        );
+    });
+}
+
+/// Height of a `width` pixel wide export which displays with `aspect_ratio` (width / height)
+/// when its pixels are `pixel_aspect` times as wide as they are tall.
+fn locked_height(width: u32, aspect_ratio: f64, pixel_aspect: f64) -> u32 {
+    ((f64::from(width) * pixel_aspect / aspect_ratio).round() as u32).clamp(1, 65536)
+}
+
+/// Outlines the largest centered rect with `aspect_ratio` in the area the flame is drawn.
+fn safe_zone(ctx: &egui::Context, setting: &Settings, aspect_ratio: f64) {
+    let zone = geometry::fit_aspect(flame_area(ctx, setting), aspect_ratio);
+    ctx.layer_painter(egui::LayerId::background()).rect_stroke(
+        from_rect(zone),
+        0.0,
+        egui::Stroke::new(1.0, egui::Color32::from_white_alpha(160)),
+    );
+}
+
+/// Full window message for when the flame can not be rendered.
+pub fn error_screen(ctx: &egui::Context, message: &str) {
+    egui::CentralPanel::default().show(ctx, |ui| {
+        ui.heading("Unable to render");
+        ui.label(message);
     });
 }
 
-fn affine_editor(ui: &mut Ui, p: &mut Point) -> egui::InnerResponse<()> {
+fn affine_editor(ui: &mut Ui, p: &mut Point, selected: bool) -> egui::InnerResponse<()> {
     let mut translation = p.data.transform_point(&Point2::new(0.0, 0.0)) - Point2::new(0.0, 0.0);
     let mut x = p.data.transform_vector(&Vector2::new(1.0, 0.0));
     let mut y = p.data.transform_vector(&Vector2::new(0.0, 1.0));
 
-    let response = ui.group(|ui: &mut Ui| {
+    let expansion = expansion_factor(&p.data);
+    let mut frame = egui::Frame::group(ui.style());
+    if selected {
+        frame = frame.stroke(egui::Stroke::new(2.0, SELECTED_COLOR));
+    }
+    let response = frame.show(ui, |ui: &mut Ui| {
         vec_editor(ui, &mut translation);
         vec_editor(ui, &mut x);
         vec_editor(ui, &mut y);
+        if expansion >= 1.0 {
+            ui.colored_label(
+                egui::Color32::RED,
+                format!("Expanding (max stretch {expansion:.3})"),
+            );
+        }
     });
     // TODO: better way to construct this.
     let m: SMatrix<f64, 3, 3> = SMatrix::from_columns(&[
@@ -71,6 +1430,40 @@ fn affine_editor(ui: &mut Ui, p: &mut Point) -> egui::InnerResponse<()> {
     response
 }
 
+/// Edits the coefficients of `z -> (az + b) / (cz + d)`.
+fn mobius_editor(ui: &mut Ui, m: &mut Mobius) -> egui::InnerResponse<()> {
+    egui::Frame::group(ui.style()).show(ui, |ui: &mut Ui| {
+        for (name, z) in [
+            ("a", &mut m.a),
+            ("b", &mut m.b),
+            ("c", &mut m.c),
+            ("d", &mut m.d),
+        ] {
+            ui.horizontal(|ui: &mut Ui| {
+                ui.label(format!("{name}:"));
+                ui.add(
+                    egui::DragValue::new(&mut z.re)
+                        .clamp_range(-2.0..=2.0)
+                        .speed(0.001),
+                );
+                ui.label("+");
+                ui.add(
+                    egui::DragValue::new(&mut z.im)
+                        .clamp_range(-2.0..=2.0)
+                        .speed(0.001),
+                );
+                ui.label("i");
+            });
+        }
+        if m.determinant().norm_sqr() == 0.0 {
+            ui.colored_label(
+                egui::Color32::RED,
+                "Degenerate (ad = bc): maps everything to one point",
+            );
+        }
+    })
+}
+
 fn vec_editor(ui: &mut Ui, p: &mut Vector2<f64>) -> egui::InnerResponse<()> {
     ui.horizontal(|ui: &mut Ui| {
         ui.label("X:");
@@ -88,39 +1481,942 @@ fn vec_editor(ui: &mut Ui, p: &mut Vector2<f64>) -> egui::InnerResponse<()> {
     })
 }
 
+/// Draws where each transform maps an asymmetric marker, making reflections and rotations visible.
+/// The final transform, if any, is drawn in a distinct color, and the selected transform is highlighted.
+fn transform_preview(
+    ui: &mut Ui,
+    transforms: &[Affine2<f64>],
+    final_transform: Option<&Affine2<f64>>,
+    selected: Option<usize>,
+) {
+    const EXTENT: f64 = 1.5;
+    let marker: [&[Point2<f64>]; 2] = [
+        &[
+            Point2::new(-0.3, -0.5),
+            Point2::new(-0.3, 0.5),
+            Point2::new(0.3, 0.5),
+        ],
+        &[Point2::new(-0.3, 0.0), Point2::new(0.1, 0.0)],
+    ];
+    let (response, painter) = ui.allocate_painter(egui::vec2(120.0, 120.0), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_stroke(rect, 0.0, ui.visuals().widgets.noninteractive.bg_stroke);
+    let to_screen = |p: Point2<f64>| {
+        egui::pos2(
+            rect.center().x + (p.x / EXTENT) as f32 * rect.width() / 2.0,
+            // Screen space y points down.
+            rect.center().y - (p.y / EXTENT) as f32 * rect.height() / 2.0,
+        )
+    };
+    let draw = |t: &Affine2<f64>, width: f32, color: egui::Color32| {
+        for line in marker {
+            painter.add(egui::Shape::line(
+                line.iter()
+                    .map(|p| to_screen(t.transform_point(p)))
+                    .collect(),
+                egui::Stroke::new(width, color),
+            ));
+        }
+    };
+    draw(&Affine2::identity(), 1.5, egui::Color32::DARK_GRAY);
+    for t in transforms {
+        draw(t, 1.5, egui::Color32::LIGHT_BLUE);
+    }
+    if let Some(t) = final_transform {
+        draw(t, 1.5, egui::Color32::GOLD);
+    }
+    // Drawn last so it is on top.
+    if let Some(t) = selected.and_then(|i| transforms.get(i)) {
+        draw(t, 2.5, SELECTED_COLOR);
+    }
+}
+
+/// Draws the row of `palette` used for `color`, from low density (left) to high.
+fn palette_strip(ui: &mut Ui, palette: &image::RgbaImage, color: f32) {
+    let (response, painter) =
+        ui.allocate_painter(egui::vec2(ui.available_width(), 16.0), egui::Sense::hover());
+    let rect = response.rect;
+    let row = postprocess::palette_row(palette, color);
+    let width = rect.width() / palette.width() as f32;
+    for x in 0..palette.width() {
+        let [r, g, b, _] = palette.get_pixel(x, row).0;
+        let left = rect.left() + x as f32 * width;
+        painter.rect_filled(
+            // Overlap the next column slightly so there are no gaps.
+            egui::Rect::from_min_max(
+                egui::pos2(left, rect.top()),
+                egui::pos2(left + width + 0.5, rect.bottom()),
+            ),
+            0.0,
+            egui::Color32::from_rgb(r, g, b),
+        );
+    }
+}
+
+/// Index of the point in `points` drawn closest to `pos`, if any are close enough to grab.
+fn grabbed_point(
+    points: &[[f32; 2]],
+    pos: egui::Pos2,
+    to_screen: impl Fn([f32; 2]) -> egui::Pos2,
+) -> Option<usize> {
+    const GRAB_RADIUS: f32 = 8.0;
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (i, to_screen(*p).distance(pos)))
+        .filter(|(_, d)| *d < GRAB_RADIUS)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+}
+
+/// Edits the control points of a contrast curve, drawn from low density (left) to high.
+/// The end points stay on the left and right edges, and points can't be dragged past their neighbors.
+fn curve_editor(ui: &mut Ui, points: &mut Vec<[f32; 2]>) {
+    if points.len() < 2 {
+        *points = IDENTITY_CONTRAST.to_vec();
+    }
+    let size = ui.available_width().min(200.0);
+    let (response, painter) =
+        ui.allocate_painter(egui::vec2(size, size), egui::Sense::click_and_drag());
+    let rect = response.rect;
+    let to_screen = |[x, y]: [f32; 2]| {
+        // Screen space y points down.
+        egui::pos2(
+            rect.left() + x * rect.width(),
+            rect.bottom() - y * rect.height(),
+        )
+    };
+    let from_screen = |p: egui::Pos2| {
+        [
+            ((p.x - rect.left()) / rect.width()).clamp(0.0, 1.0),
+            ((rect.bottom() - p.y) / rect.height()).clamp(0.0, 1.0),
+        ]
+    };
+
+    if let Some(pos) = response.interact_pointer_pos() {
+        let last = points.len() - 1;
+        if response.drag_started() {
+            let grabbed = grabbed_point(points, pos, to_screen);
+            ui.data_mut(|d| d.insert_temp(response.id, grabbed));
+        }
+        let dragged = ui.data(|d| d.get_temp::<Option<usize>>(response.id).flatten());
+        if let (true, Some(i)) = (response.dragged(), dragged.filter(|i| *i <= last)) {
+            let [x, y] = from_screen(pos);
+            let x = match i {
+                0 => 0.0,
+                i if i == last => 1.0,
+                i => x.clamp(points[i - 1][0], points[i + 1][0]),
+            };
+            points[i] = [x, y];
+        } else if response.double_clicked() {
+            let [x, y] = from_screen(pos);
+            let i = points.partition_point(|p| p[0] < x);
+            if i > 0 && i <= last {
+                points.insert(i, [x, y]);
+            }
+        } else if response.secondary_clicked() {
+            if let Some(i) = grabbed_point(points, pos, to_screen).filter(|i| (1..last).contains(i))
+            {
+                points.remove(i);
+            }
+        }
+    }
+
+    painter.rect_stroke(rect, 0.0, ui.visuals().widgets.noninteractive.bg_stroke);
+    painter.line_segment(
+        [to_screen([0.0, 0.0]), to_screen([1.0, 1.0])],
+        egui::Stroke::new(1.0, egui::Color32::DARK_GRAY),
+    );
+    const SEGMENTS: usize = 64;
+    let curve = histogram::contrast_curve(points);
+    painter.add(egui::Shape::line(
+        (0..=SEGMENTS)
+            .map(|i| {
+                let x = i as f32 / SEGMENTS as f32;
+                to_screen([x, histogram::lookup(&curve, x)])
+            })
+            .collect(),
+        egui::Stroke::new(1.5, egui::Color32::LIGHT_BLUE),
+    ));
+    for p in points.iter() {
+        painter.circle_filled(to_screen(*p), 4.0, ui.visuals().text_color());
+    }
+}
+
+/// Draws the accumulation passes as a pyramid, largest first: each pass is a box sized by its resolution,
+/// with an arrow to the smaller pass it samples. The last pass draws solid quads.
+fn pass_graph(ui: &mut Ui, plan: &Plan) {
+    const ROW_HEIGHT: f32 = 36.0;
+    const BOX_HEIGHT: f32 = 24.0;
+    const MIN_BOX_WIDTH: f32 = 120.0;
+    let Some(largest) = plan.passes.first() else {
+        return;
+    };
+    let width = ui.available_width().max(MIN_BOX_WIDTH);
+    let (response, painter) = ui.allocate_painter(
+        egui::vec2(width, ROW_HEIGHT * plan.passes.len() as f32),
+        egui::Sense::hover(),
+    );
+    let rect = response.rect;
+    let font = egui::TextStyle::Small.resolve(ui.style());
+    let stroke = ui.visuals().widgets.noninteractive.fg_stroke;
+    let largest_width = largest.key.resolution.width.max(1) as f32;
+    let mut above: Option<egui::Pos2> = None;
+    for (i, pass) in plan.passes.iter().enumerate() {
+        let size = pass.key.resolution;
+        let pass_rect = egui::Rect::from_center_size(
+            egui::pos2(
+                rect.center().x,
+                rect.top() + ROW_HEIGHT * i as f32 + BOX_HEIGHT / 2.0,
+            ),
+            egui::vec2(
+                (width * size.width as f32 / largest_width).clamp(MIN_BOX_WIDTH, width),
+                BOX_HEIGHT,
+            ),
+        );
+        painter.rect_stroke(pass_rect, 2.0, stroke);
+        let quads = if pass.smaller.is_none() {
+            ", quads"
+        } else {
+            ""
+        };
+        painter.text(
+            pass_rect.center(),
+            egui::Align2::CENTER_CENTER,
+            format!(
+                "{}x{}: {} levels{quads}",
+                size.width, size.height, pass.levels
+            ),
+            font.clone(),
+            ui.visuals().text_color(),
+        );
+        if let Some(from) = above {
+            painter.arrow(from, pass_rect.center_top() - from, stroke);
+        }
+        above = Some(pass_rect.center_bottom());
+    }
+}
+
+/// Most nodes `ifs_graph` draws on one level.
+const GRAPH_MAX_NODES: usize = 256;
+
+/// Draws the expansion of the IFS as a tree, with each transform in its own color.
+/// Level `k` has the `n^k` states from `process_levels(k)`, and the children of a state are it followed by each transform.
+/// Node size shows how much the state shrinks the flame.
+fn ifs_graph(ui: &mut Ui, root: &Root, levels: u32) {
+    let n = root.transforms().len();
+    if n == 0 {
+        return;
+    }
+    let levels = (0..=levels)
+        .take_while(|l| n.checked_pow(*l).is_some_and(|c| c <= GRAPH_MAX_NODES))
+        .last()
+        .unwrap_or(0);
+    let color = |i: usize| -> egui::Color32 {
+        egui::ecolor::Hsva::new(i as f32 / n as f32, 0.7, 0.9, 1.0).into()
+    };
+
+    const ROW_HEIGHT: f32 = 40.0;
+    let width = ui.available_width().max(120.0);
+    let (response, painter) = ui.allocate_painter(
+        egui::vec2(width, ROW_HEIGHT * (levels as f32 + 0.5)),
+        egui::Sense::hover(),
+    );
+    let rect = response.rect;
+    let position = |level: u32, k: usize| {
+        let count = n.pow(level) as f32;
+        egui::pos2(
+            rect.left() + (k as f32 + 0.5) / count * rect.width(),
+            rect.top() + ROW_HEIGHT * (level as f32 + 0.25),
+        )
+    };
+
+    let state = root.get_state();
+    for level in 0..=levels {
+        let mut stretches = vec![];
+        state.process_levels(level, &mut |s| stretches.push(expansion_factor(&s.mat)));
+        for (k, stretch) in stretches.into_iter().enumerate() {
+            let p = position(level, k);
+            if level > 0 {
+                painter.line_segment(
+                    [position(level - 1, k / n), p],
+                    egui::Stroke::new(1.0, color(k % n)),
+                );
+            }
+            let fill = if level == 0 {
+                egui::Color32::DARK_GRAY
+            } else {
+                color(k % n)
+            };
+            painter.circle_filled(p, (6.0 * stretch as f32).clamp(1.0, 6.0), fill);
+        }
+    }
+
+    for (i, t) in root.transforms().iter().enumerate() {
+        let m = t.matrix();
+        let rotation = m[(1, 0)].atan2(m[(0, 0)]).to_degrees();
+        ui.colored_label(
+            color(i),
+            format!(
+                "{i}: scale {:.3}, rotation {rotation:.1}°",
+                expansion_factor(t)
+            ),
+        );
+    }
+}
+
 fn get_polygon_point(setting: &Settings, i: usize) -> Affine2<f64> {
-    let sm = Similarity2::from_scaling(setting.scale);
+    let scale = setting
+        .scale_overrides
+        .get(i)
+        .copied()
+        .flatten()
+        .unwrap_or(setting.scale);
+    let angle = Rotation2::new(std::f64::consts::PI * 2.0 * i as f64 / setting.n as f64);
+    // Built directly from a matrix since Similarity2 does not support zero or non-uniform scale.
+    let sm: Affine2<f64> = match setting.scale_y {
+        None => Affine2::from_matrix_unchecked(Matrix3::new_scaling(scale)),
+        // Scaled in the point's own frame (x along its radius), so all points get the same shape.
+        Some(scale_y) => {
+            let local = Matrix3::new_nonuniform_scaling(&Vector2::new(scale, scale_y));
+            let to_global = angle.to_homogeneous();
+            Affine2::from_matrix_unchecked(to_global * local * to_global.transpose())
+        }
+    };
 
-    let offset = Rotation2::new(std::f64::consts::PI * 2.0 * i as f64 / setting.n as f64)
-        * Point2::new(1.0, 0.0);
-    na::convert::<_, Affine2<f64>>(sm * Translation2::new(offset.x, offset.y))
-        * Rotation2::new(setting.rotation as f64)
+    let offset = angle * Point2::new(1.0, 0.0);
+    sm * Translation2::new(offset.x, offset.y) * Rotation2::new(setting.rotation as f64)
 }
 
-impl Settings {
-    pub fn default() -> Self {
+/// Three Möbius transforms, each shrinking the unit disk into a disk touching its center and edge,
+/// a third of a turn apart: a Sierpinski gasket with curved sides. The default when enabling Möbius transforms.
+fn mobius_gasket() -> Vec<Mobius> {
+    let c = |re: f64, im: f64| na::Complex::new(re, im);
+    // Maps the unit disk to the disk from 0 to 0.8 on the real axis.
+    let shrink = Mobius::new(c(0.5, 0.0), c(0.5, 0.0), c(0.25, 0.0), c(1.0, 0.0));
+    (0..3)
+        .map(|i| {
+            let angle = std::f64::consts::TAU * f64::from(i) / 3.0;
+            let turn = |angle: f64| {
+                Mobius::new(
+                    c(angle.cos(), angle.sin()),
+                    c(0.0, 0.0),
+                    c(0.0, 0.0),
+                    c(1.0, 0.0),
+                )
+            };
+            turn(angle).compose(&shrink.compose(&turn(-angle)))
+        })
+        .collect()
+}
+
+impl Default for Settings {
+    fn default() -> Self {
         Self {
             n: 5,
             scale: 0.5,
+            scale_y: None,
+            scale_overrides: vec![],
             rotation: 0.1,
             busy_loop: false,
+            fps_cap: None,
             polygon: true,
-            auto_passes: true,
-            passes: 10,
+            symmetry: None,
+            tone_map: ToneMap::Log,
+            auto_exposure: None,
+            tone_curve: IDENTITY_CONTRAST.to_vec(),
+            highlight_rolloff: 0.0,
+            color_vision: None,
+            accumulation: Accumulation::Linear,
+            orbit_trap: None,
+            splat_size: None,
+            hdr: false,
+            oriented_bounds: false,
+            ui_scale: 1.0,
+            zoom: 1.0,
+            camera_center: [0.0, 0.0],
+            camera_rotation: 0.0,
+            crop: None,
+            crop_drawing: false,
+            crop_start: None,
+            minimap: true,
+            show_hull: false,
+            show_level_bounds: false,
+            feedback_decay: None,
+            bloom: None,
+            pixel_aspect: 1.0,
+            safe_zone: None,
+            aspect_lock: None,
+            palette: PaletteSource::Default,
+            palette_path: String::new(),
+            palette_color: 0.0,
+            background_image: None,
+            background_path: String::new(),
+            transparent_background: false,
+            reference_image: None,
+            reference_path: String::new(),
+            compare: false,
+            compare_blend: 1.0,
+            max_bounds_levels: 5,
+            bounds_samples: None,
+            size_rounding: SizeRounding::MultipleOf8,
+            locked_resolution: None,
+            min_pass_size: DEFAULT_MIN_PASS_SIZE,
+            supersample: 1,
+            downsample_filter: DownsampleFilter::Box,
+            pass_depth: None,
+            step_levels: None,
+            samples_per_pixel: None,
             points: vec![],
+            opacities: vec![],
+            post_transforms: vec![],
+            mobius: None,
+            final_transform: Point {
+                data: Affine2::identity(),
+            },
+            final_enabled: false,
+            selected: None,
+            params_text: String::new(),
+            params_error: None,
+            scene_path: "flame.json".to_owned(),
+            scene_error: None,
+            slideshow: false,
+            slideshow_dir: "scenes".to_owned(),
+            slideshow_dwell: 10.0,
+            slideshow_fade: 2.0,
+            slideshow_error: None,
+            screensaver: false,
+            graph_levels: 2,
+            command_text: String::new(),
+            command_result: None,
+            tutorial: Tutorial::default(),
         }
     }
+}
+
+impl Settings {
+    fn selected(&self) -> Option<usize> {
+        self.selected.filter(|i| *i < self.n)
+    }
+
+    /// Selects the next (or previous) transform, wrapping around.
+    /// Without any transforms, nothing is selected.
+    pub fn cycle_selection(&mut self, backwards: bool) {
+        let n = self.n;
+        if n == 0 {
+            self.selected = None;
+            return;
+        }
+        self.selected = Some(match (self.selected(), backwards) {
+            (None, false) => 0,
+            (None, true) => n - 1,
+            (Some(i), false) => (i + 1) % n,
+            (Some(i), true) => (i + n - 1) % n,
+        });
+    }
+
+    /// Sets the number of points, adding new ones (from the polygon) if needed.
+    pub fn set_n(&mut self, n: usize) {
+        self.n = n;
+        while self.points.len() < n {
+            self.points.push(Point {
+                data: get_polygon_point(self, self.points.len()),
+            })
+        }
+    }
+
+    /// Inserts a copy of transform `i` (with its opacity and post transform) right after it, and selects the copy.
+    /// Leaves polygon mode, keeping the current transforms.
+    pub fn duplicate_transform(&mut self, i: usize) {
+        if i >= self.n {
+            return;
+        }
+        self.make_explicit();
+        self.points.insert(i + 1, self.points[i].clone());
+        self.opacities.insert(i + 1, self.opacities[i]);
+        self.post_transforms
+            .insert(i + 1, self.post_transforms[i].clone());
+        self.scale_overrides.insert(i + 1, self.scale_overrides[i]);
+        self.n += 1;
+        self.selected = Some(i + 1);
+    }
+
+    /// Removes transform `i` (with its opacity and post transform), unlike reducing `n` which removes the last.
+    /// Leaves polygon mode, keeping the current transforms.
+    pub fn remove_transform(&mut self, i: usize) {
+        if i >= self.n {
+            return;
+        }
+        self.make_explicit();
+        self.points.remove(i);
+        self.opacities.remove(i);
+        self.post_transforms.remove(i);
+        self.scale_overrides.remove(i);
+        self.n -= 1;
+        // Keep the same transform selected.
+        self.selected = match self.selected {
+            Some(selected) if selected == i => None,
+            Some(selected) if selected > i => Some(selected - 1),
+            selected => selected,
+        };
+    }
+
+    /// Leaves polygon mode (keeping the current transforms), and fills in every per point list up to `n`.
+    fn make_explicit(&mut self) {
+        for i in 0..self.n {
+            let data = self.transform(i);
+            match self.points.get_mut(i) {
+                Some(p) => p.data = data,
+                None => self.points.push(Point { data }),
+            }
+        }
+        self.polygon = false;
+        let n = self.n;
+        self.opacities.resize(self.opacities.len().max(n), 1.0);
+        self.post_transforms
+            .resize(self.post_transforms.len().max(n), None);
+        self.scale_overrides
+            .resize(self.scale_overrides.len().max(n), None);
+    }
+
+    /// Switches to polygon mode with a uniform `scale`.
+    pub fn set_polygon_scale(&mut self, scale: f64) {
+        self.polygon = true;
+        self.scale = scale;
+        self.scale_y = None;
+    }
+
+    pub fn polygon_scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Sets the polygon mode rotation, in radians.
+    pub fn set_rotation(&mut self, rotation: f32) {
+        self.rotation = rotation;
+    }
+
+    /// The polygon mode rotation, in radians.
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    /// Replaces the transforms (leaving polygon mode), keeping their opacities.
+    pub fn set_transforms(&mut self, transforms: Vec<Affine2<f64>>) {
+        self.polygon = false;
+        self.n = transforms.len();
+        self.points = transforms.into_iter().map(|data| Point { data }).collect();
+    }
+
+    /// Adjusts polygon mode from the mouse wheel: `lines` scrolled change the scale (clamped like the UI),
+    /// or the rotation if `rotate`. Does nothing outside of polygon mode.
+    pub fn scroll_polygon(&mut self, lines: f32, rotate: bool) {
+        if !self.polygon {
+            return;
+        }
+        if rotate {
+            self.rotation += lines * SCROLL_ROTATION_STEP;
+        } else {
+            self.scale = (self.scale + f64::from(lines) * SCROLL_SCALE_STEP)
+                .clamp(-MAX_POLYGON_SCALE, MAX_POLYGON_SCALE);
+        }
+    }
+
+    /// Number of transforms drawn: the Möbius transforms if set, otherwise the points' transforms with their symmetric copies.
+    /// Each level multiplies the quads drawn by this, so it limits the levels a pass can draw.
+    pub fn transform_count(&self) -> usize {
+        match &self.mobius {
+            Some(maps) => maps.len(),
+            None => self.get_state().transforms().len(),
+        }
+    }
+
+    /// Draws one more (or with `backwards`, one fewer) level than before, in a single pass,
+    /// starting from just the seed rect (level 0). See `step_levels`.
+    pub fn step_level(&mut self, backwards: bool) {
+        self.step_levels = Some(match (self.step_levels, backwards) {
+            (None, _) => 0,
+            (Some(levels), false) => u32::min(levels + 1, max_levels(self.transform_count())),
+            (Some(levels), true) => levels.saturating_sub(1),
+        });
+    }
+
+    /// Replaces the points with random contractive transforms. The same seed always gives the same transforms.
+    pub fn randomize(&mut self, seed: u64) {
+        let mut rng = Rng::new(seed);
+        let mut random = || rng.next_f64();
+        self.polygon = false;
+        self.points = (0..self.n)
+            .map(|_| {
+                let scale = Vector2::new(0.2 + 0.5 * random(), 0.2 + 0.5 * random());
+                let rotation = Rotation2::new(std::f64::consts::TAU * random());
+                let offset = Translation2::new(2.0 * random() - 1.0, 2.0 * random() - 1.0);
+                Point {
+                    data: Affine2::from_matrix_unchecked(
+                        offset.to_homogeneous()
+                            * rotation.to_homogeneous()
+                            * Matrix3::new_nonuniform_scaling(&scale),
+                    ),
+                }
+            })
+            .collect();
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selected = None;
+    }
+
+    /// Moves the selected transform by `NUDGE_STEP` times `steps`.
+    /// Polygon mode transforms are generated, so can not be moved individually.
+    pub fn nudge_selected(&mut self, steps: Vector2<f64>) {
+        if self.polygon {
+            return;
+        }
+        if let Some(p) = self.selected().and_then(|i| self.points.get_mut(i)) {
+            let m = p.data.matrix_mut_unchecked();
+            m[(0, 2)] += steps.x * NUDGE_STEP;
+            m[(1, 2)] += steps.y * NUDGE_STEP;
+        }
+    }
+
+    /// The flame and its appearance (see `to_scene`) as one line of JSON, for sharing.
+    pub fn to_params(&self) -> String {
+        self.to_scene().to_compact_json()
+    }
+
+    /// Replaces the flame and its appearance with those from `to_params` text (or any scene file's contents).
+    /// Leaves the settings unchanged if `text` is malformed.
+    pub fn apply_params(&mut self, text: &str) -> Result<(), String> {
+        self.apply_scene(&Scene::from_json(text.trim())?)
+    }
+
+    /// Everything needed to redraw the flame, for saving as a project file.
+    pub fn to_scene(&self) -> Scene {
+        Scene {
+            version: scene::VERSION,
+            transforms: (0..self.n)
+                .map(|i| scene::Transform {
+                    coefs: affine_to_coefs(&self.transform(i)),
+                    opacity: self.opacities.get(i).copied().unwrap_or(1.0),
+                    post: self.post_transform(i).map(|post| affine_to_coefs(&post)),
+                })
+                .collect(),
+            final_transform: self
+                .final_enabled
+                .then(|| affine_to_coefs(&self.final_transform.data)),
+            mobius: self
+                .mobius
+                .as_ref()
+                .map(|maps| maps.iter().map(Mobius::to_coefs).collect()),
+            palette: self.palette.clone(),
+            palette_color: self.palette_color,
+            tone_map: self.tone_map,
+            accumulation: self.accumulation,
+            zoom: self.zoom,
+            camera_center: self.camera_center,
+            camera_rotation: self.camera_rotation,
+            background_image: self.background_image.clone(),
+            transparent_background: self.transparent_background,
+        }
+    }
+
+    /// Replaces the flame and its appearance with those from `scene`.
+    /// Leaves the settings unchanged if `scene` is invalid.
+    pub fn apply_scene(&mut self, scene: &Scene) -> Result<(), String> {
+        if scene.transforms.is_empty() {
+            return Err("No transforms".to_owned());
+        }
+        if scene.mobius.as_ref().is_some_and(Vec::is_empty) {
+            return Err("No Möbius transforms".to_owned());
+        }
+        let finite = scene
+            .transforms
+            .iter()
+            .flat_map(|t| {
+                t.coefs
+                    .iter()
+                    .chain([&t.opacity])
+                    .chain(t.post.iter().flatten())
+            })
+            .chain(scene.final_transform.iter().flatten())
+            .chain(scene.mobius.iter().flatten().flatten().flatten())
+            .chain([&scene.zoom, &scene.camera_rotation])
+            .chain(&scene.camera_center)
+            .all(|v| v.is_finite());
+        if !finite {
+            return Err("Invalid number".to_owned());
+        }
+        self.n = scene.transforms.len();
+        self.points = scene
+            .transforms
+            .iter()
+            .map(|t| Point {
+                data: affine_from_coefs(t.coefs),
+            })
+            .collect();
+        self.opacities = scene.transforms.iter().map(|t| t.opacity).collect();
+        self.post_transforms = scene
+            .transforms
+            .iter()
+            .map(|t| {
+                t.post.map(|coefs| Point {
+                    data: affine_from_coefs(coefs),
+                })
+            })
+            .collect();
+        self.mobius = scene
+            .mobius
+            .as_ref()
+            .map(|maps| maps.iter().copied().map(Mobius::from_coefs).collect());
+        self.polygon = false;
+        self.final_enabled = scene.final_transform.is_some();
+        if let Some(coefs) = scene.final_transform {
+            self.final_transform = Point {
+                data: affine_from_coefs(coefs),
+            };
+        }
+        self.palette = scene.palette.clone();
+        self.palette_color = scene.palette_color;
+        self.tone_map = scene.tone_map;
+        self.accumulation = scene.accumulation;
+        self.zoom = scene.zoom;
+        self.camera_center = scene.camera_center;
+        self.camera_rotation = scene.camera_rotation;
+        self.background_image = scene.background_image.clone();
+        self.transparent_background = scene.transparent_background;
+        Ok(())
+    }
+
+    fn transform(&self, i: usize) -> Affine2<f64> {
+        if self.polygon {
+            get_polygon_point(self, i)
+        } else {
+            self.points[i].data
+        }
+    }
+
+    /// The post transform of point `i`, if it has one.
+    fn post_transform(&self, i: usize) -> Option<Affine2<f64>> {
+        self.post_transforms
+            .get(i)
+            .and_then(|post| post.as_ref().map(|post| post.data))
+    }
+
+    /// Sets (or with None, removes) the post transform of point `i`.
+    pub fn set_post_transform(&mut self, i: usize, post: Option<Affine2<f64>>) {
+        if self.post_transforms.len() <= i {
+            self.post_transforms.resize(i + 1, None);
+        }
+        self.post_transforms[i] = post.map(|data| Point { data });
+    }
+
+    /// Called by the UI every frame (and by `wgpu_render::root` when the settings change),
+    /// so builds the transforms with their post transforms applied in one pass, without intermediate allocations.
     pub fn get_state(&self) -> Root {
         let va = (0..self.n)
-            .map(|i| {
-                if self.polygon {
-                    get_polygon_point(self, i)
-                } else {
-                    self.points.get(i).unwrap().data
-                }
+            .map(|i| match self.post_transform(i) {
+                Some(post) => post * self.transform(i),
+                None => self.transform(i),
             })
             .collect::<Vec<Affine2<f64>>>();
+        let opacities = (0..self.n)
+            .map(|i| self.opacities.get(i).copied().unwrap_or(1.0))
+            .collect();
 
-        Root::new(va)
+        let root = Root::with_opacities(va, opacities);
+        match self.symmetry {
+            Some(symmetry) => root.with_symmetry(symmetry),
+            None => root,
+        }
+        .with_final_transform(self.final_enabled.then_some(self.final_transform.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        flame::{BoundedState, Symmetry},
+        geometry::Bounds,
+        mobius::{contains_attractor, MobiusState},
+        plan::max_levels,
+        ui::{locked_height, mobius_gasket, render_bound, Quality, Settings},
+    };
+    use na::{Affine2, Complex, Matrix3};
+
+    #[test]
+    fn aspect_lock() {
+        assert_eq!(locked_height(3840, 16.0 / 9.0, 1.0), 2160);
+        assert_eq!(locked_height(1080, 4.0 / 5.0, 1.0), 1350);
+        // Anamorphic pixels are twice as wide, so half as many rows keep the shape.
+        assert_eq!(locked_height(1920, 16.0 / 9.0, 2.0), 2160);
+        assert_eq!(locked_height(1, 10.0, 1.0), 1);
+    }
+
+    /// The default Möbius transforms shrink the unit disk into itself, so have finite bounds inside it.
+    #[test]
+    fn mobius_gasket_bounds() {
+        let maps = mobius_gasket();
+        assert!(contains_attractor(&maps, Complex::new(0.0, 0.0), 1.0));
+        let (bounds, _levels) = MobiusState::new(&maps).get_bounds_adaptive(5, None);
+        assert!(!bounds.is_infinite());
+        assert!(bounds.center.coords.norm() + bounds.radius <= 1.0 + 1e-6);
+    }
+
+    #[test]
+    fn symmetry() {
+        let mut settings = Settings::default();
+        let plain = settings.get_state();
+        settings.symmetry = Some(Symmetry::Dihedral(3));
+        let root = settings.get_state();
+        assert_eq!(root.transforms().len(), plain.transforms().len() * 6);
+        assert_eq!(&root.transforms()[..settings.n], plain.transforms());
+        // Stepping stops at the levels the symmetric copies allow, not just the points.
+        assert_eq!(settings.transform_count(), root.transforms().len());
+        for _ in 0..100 {
+            settings.step_level(false);
+        }
+        assert_eq!(
+            settings.step_levels,
+            Some(max_levels(root.transforms().len()))
+        );
+        assert!(max_levels(root.transforms().len()) < max_levels(settings.n));
+    }
+
+    #[test]
+    fn render_limited() {
+        // 60Hz vsync with a quick render.
+        assert!(!render_bound(1.0 / 60.0, 0.004));
+        // Rendering takes the whole frame.
+        assert!(render_bound(0.03, 0.029));
+    }
+
+    #[test]
+    fn quality_presets() {
+        let mut settings = Settings::default();
+        assert_eq!(Quality::of(&settings), Some(Quality::Normal));
+        for quality in Quality::ALL {
+            quality.apply(&mut settings);
+            assert_eq!(Quality::of(&settings), Some(quality));
+        }
+        settings.max_bounds_levels = 1;
+        assert_eq!(Quality::of(&settings), None);
+    }
+
+    #[test]
+    fn duplicate_and_remove() {
+        let mut settings = Settings::default();
+        let polygon = settings.get_state();
+        settings.set_post_transform(1, Some(Affine2::identity()));
+        settings.opacities = vec![1.0, 0.5, 0.25, 1.0, 1.0];
+        settings.duplicate_transform(1);
+        assert!(!settings.polygon);
+        assert_eq!(settings.n, 6);
+        assert_eq!(settings.selected, Some(2));
+        let state = settings.get_state();
+        let transforms = state.transforms();
+        assert_eq!(transforms[1], transforms[2]);
+        assert_eq!(transforms[2], polygon.transforms()[1]);
+        assert_eq!(transforms[3], polygon.transforms()[2]);
+        assert_eq!(&settings.opacities[..4], &[1.0, 0.5, 0.5, 0.25]);
+        assert!(settings.post_transform(2).is_some());
+        assert!(settings.post_transform(3).is_none());
+
+        settings.remove_transform(0);
+        assert_eq!(settings.n, 5);
+        assert_eq!(settings.selected, Some(1));
+        assert_eq!(
+            settings.get_state().transforms()[2],
+            polygon.transforms()[2]
+        );
+        assert_eq!(&settings.opacities[..3], &[0.5, 0.5, 0.25]);
+        settings.remove_transform(1);
+        assert_eq!(settings.selected, None);
+        // Out of range does nothing.
+        settings.duplicate_transform(5);
+        settings.remove_transform(5);
+        assert_eq!(settings.n, 4);
+    }
+
+    #[test]
+    fn cycle_selection() {
+        let mut settings = Settings::default();
+        settings.cycle_selection(true);
+        assert_eq!(settings.selected, Some(settings.n - 1));
+        settings.cycle_selection(false);
+        assert_eq!(settings.selected, Some(0));
+        settings.set_n(0);
+        settings.cycle_selection(true);
+        assert_eq!(settings.selected, None);
+        settings.cycle_selection(false);
+        assert_eq!(settings.selected, None);
+    }
+
+    #[test]
+    fn scroll_polygon() {
+        let mut settings = Settings::default();
+        settings.scroll_polygon(2.0, false);
+        assert!((settings.scale - 0.51).abs() < 1e-9);
+        settings.scroll_polygon(1000.0, false);
+        assert_eq!(settings.scale, 0.8);
+        let rotation = settings.rotation;
+        settings.scroll_polygon(-90.0, true);
+        assert!((settings.rotation - (rotation - std::f32::consts::FRAC_PI_2)).abs() < 1e-5);
+        assert_eq!(settings.scale, 0.8);
+
+        // Explicit transforms are left alone.
+        settings.polygon = false;
+        settings.scroll_polygon(-1000.0, false);
+        assert_eq!(settings.scale, 0.8);
+    }
+
+    #[test]
+    fn step_level() {
+        let mut settings = Settings::default();
+        settings.step_level(true);
+        assert_eq!(settings.step_levels, Some(0));
+        settings.step_level(true);
+        assert_eq!(settings.step_levels, Some(0));
+        settings.step_level(false);
+        settings.step_level(false);
+        assert_eq!(settings.step_levels, Some(2));
+        settings.step_level(true);
+        assert_eq!(settings.step_levels, Some(1));
+    }
+
+    #[test]
+    fn params_round_trip() {
+        let mut settings = Settings {
+            final_enabled: true,
+            ..Settings::default()
+        };
+        settings.set_post_transform(
+            2,
+            Some(Affine2::from_matrix_unchecked(Matrix3::new_scaling(0.5))),
+        );
+        let params = settings.to_params();
+        let mut pasted = Settings::default();
+        pasted.apply_params(&params).unwrap();
+        assert_eq!(pasted.get_state(), settings.get_state());
+        assert_eq!(pasted.to_params(), params);
+    }
+
+    #[test]
+    fn params_malformed() {
+        let mut settings = Settings::default();
+        let before = settings.clone();
+        let params = settings.to_params();
+        let mut empty = settings.to_scene();
+        empty.transforms.clear();
+        for text in [
+            "",
+            "1 0 0 0 1 0 1",
+            "rusty-flame:1 0 0 0 1 0 1",
+            r#"{"version": 3}"#,
+            &params[..params.len() / 2],
+            &params.replacen(r#""opacity":"#, r#""opacity":"most","unused":"#, 1),
+            &empty.to_compact_json(),
+        ] {
+            assert!(settings.apply_params(text).is_err(), "{text}");
+        }
+        assert_eq!(settings, before);
     }
 }
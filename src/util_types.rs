@@ -1,14 +1,21 @@
 //! Misc wrapper types for providing Clone, Debug and Eq
 
-use std::{ops::Deref, rc::Rc};
+use std::ops::Deref;
 
-#[derive(Debug, Clone)]
+/// Reference counted pointer used for objects shared between queries: Arc with the "sync" feature, otherwise Rc.
+#[cfg(not(feature = "sync"))]
+pub use std::rc::Rc as Shared;
+#[cfg(feature = "sync")]
+pub use std::sync::Arc as Shared;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 /// Wrapper that provides Debug.
+/// Comparison and hashing are forwarded to `T` (when it supports them), so this works as a salsa input.
 pub struct DebugIt<T>(pub T);
 
 #[derive(Debug)]
-/// Rc with pointer semantics (reference equality)
-pub struct PtrRc<T>(Rc<T>);
+/// Rc (or Arc, see `Shared`) with pointer semantics (reference equality)
+pub struct PtrRc<T>(Shared<T>);
 
 // TODO: why does derive clone not work for this?
 impl<T> Clone for PtrRc<T> {
@@ -19,7 +26,7 @@ impl<T> Clone for PtrRc<T> {
 
 impl<T> PartialEq for PtrRc<T> {
     fn eq(&self, other: &Self) -> bool {
-        Rc::ptr_eq(&self.0, &other.0)
+        Shared::ptr_eq(&self.0, &other.0)
     }
 }
 
@@ -43,6 +50,21 @@ impl<T> Eq for PtrRc<T> {}
 
 impl<T> From<T> for PtrRc<T> {
     fn from(t: T) -> Self {
-        PtrRc(Rc::new(t))
+        PtrRc(Shared::new(t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util_types::{DebugIt, PtrRc};
+
+    #[test]
+    fn equality() {
+        // `DebugIt` compares values, and `PtrRc` pointers.
+        assert_eq!(DebugIt(1), DebugIt(1));
+        assert_ne!(DebugIt(1), DebugIt(2));
+        let a = PtrRc::from(1);
+        assert_eq!(a, a.clone());
+        assert_ne!(a, PtrRc::from(1));
     }
 }
@@ -1,16 +1,21 @@
-use std::rc::Rc;
+use na::{Affine2, Matrix3, Vector2};
 use wgpu::{Device, Queue, TextureFormat, TextureViewDescriptor};
 use winit::dpi::PhysicalSize;
 
 use crate::{
-    accumulate::{self, AccumulateStorage, Accumulator},
-    flame::Root,
-    postprocess, ui,
-    util_types::{DebugIt, PtrRc},
+    accumulate::{self, AccumulateStorage, Accumulator, ViewTransform},
+    background, bloom, compare,
+    error::RenderError,
+    feedback,
+    flame::{ContractionReport, Root},
+    histogram::{self, ToneMap},
+    plan, postprocess, render_common, supersample, timing, ui,
+    util_types::{DebugIt, PtrRc, Shared},
 };
 
 #[salsa::query_group(InputStorage2)]
 pub trait Inputs2: salsa::Database {
+    /// Size of the region of the window the flame is drawn into.
     #[salsa::input]
     fn window_size(&self, key: ()) -> PhysicalSize<u32>;
 
@@ -18,32 +23,141 @@ pub trait Inputs2: salsa::Database {
     fn swapchain_format(&self, key: ()) -> DebugIt<TextureFormat>;
 
     #[salsa::input]
-    fn queue(&self, key: ()) -> Rc<Queue>;
+    fn queue(&self, key: ()) -> Shared<Queue>;
 }
 
 #[salsa::query_group(InputStorage)]
 pub trait Inputs: salsa::Database {
     #[salsa::input]
-    fn device(&self, key: ()) -> Rc<Device>;
+    fn device(&self, key: ()) -> Shared<Device>;
 
     #[salsa::input]
     fn config(&self, key: ()) -> ui::Settings;
+
+    /// If the device can filter R32Float textures. If not, they are sampled nearest only, at reduced quality.
+    #[salsa::input]
+    fn float_filtering(&self, key: ()) -> bool;
 }
 
 #[salsa::query_group(RendererStorage)]
 pub trait Renderer: Inputs {
     fn root(&self, key: ()) -> Root;
+    /// `Root::contraction_report` of `root`, which is too slow to recompute every UI frame.
+    fn contraction(&self, key: ()) -> ContractionReport;
 }
 
 #[salsa::query_group(PostprocesserStorage)]
 pub trait Postprocesser: Accumulator + Inputs2 {
     fn postprocess_data(&self, key: ()) -> PtrRc<postprocess::Data>;
+    fn postprocess_pipelines(&self, key: TextureFormat) -> PtrRc<postprocess::Pipelines>;
+    /// Palette loaded from the path, or the default palette.
+    fn palette(&self, key: postprocess::PaletteSource) -> PtrRc<postprocess::Palette>;
+    /// The palette's colors, loaded or built on the CPU.
+    fn palette_image(&self, key: postprocess::PaletteSource) -> PtrRc<image::RgbaImage>;
+    fn readback(&self, key: PhysicalSize<u32>) -> PtrRc<histogram::Readback>;
+    fn background_data(&self, key: TextureFormat) -> PtrRc<background::Data>;
+    /// Background image loaded from the path.
+    fn background(&self, key: String) -> Option<PtrRc<background::Background>>;
+    fn compare_data(&self, key: TextureFormat) -> PtrRc<compare::Data>;
+    fn compare_target(&self, key: PhysicalSize<u32>) -> PtrRc<compare::Target>;
+    /// Reference image loaded from the path.
+    fn reference(&self, key: String) -> Option<PtrRc<compare::Reference>>;
+    fn feedback_data(&self, key: ()) -> PtrRc<feedback::Data>;
+    fn trails(&self, key: PhysicalSize<u32>) -> PtrRc<feedback::Trails>;
+    fn timer(&self, key: ()) -> Option<PtrRc<timing::Timer>>;
+    fn supersample_data(&self, key: ()) -> PtrRc<supersample::Data>;
+    fn bloom_data(&self, key: TextureFormat) -> PtrRc<bloom::Data>;
+    fn bloom_target(&self, key: PhysicalSize<u32>) -> PtrRc<bloom::Target>;
 }
 
 fn postprocess_data(db: &dyn Postprocesser, (): ()) -> PtrRc<postprocess::Data> {
     postprocess::data(db, ())
 }
 
+fn postprocess_pipelines(
+    db: &dyn Postprocesser,
+    format: TextureFormat,
+) -> PtrRc<postprocess::Pipelines> {
+    postprocess::pipelines(db, format)
+}
+
+fn palette(
+    db: &dyn Postprocesser,
+    source: postprocess::PaletteSource,
+) -> PtrRc<postprocess::Palette> {
+    postprocess::palette(db, source)
+}
+
+fn readback(db: &dyn Postprocesser, size: PhysicalSize<u32>) -> PtrRc<histogram::Readback> {
+    histogram::readback(db, size)
+}
+
+fn background_data(db: &dyn Postprocesser, format: TextureFormat) -> PtrRc<background::Data> {
+    background::data(db, format)
+}
+
+fn background(db: &dyn Postprocesser, path: String) -> Option<PtrRc<background::Background>> {
+    background::load(db, &path)
+}
+
+fn compare_data(db: &dyn Postprocesser, format: TextureFormat) -> PtrRc<compare::Data> {
+    compare::data(db, format)
+}
+
+fn compare_target(db: &dyn Postprocesser, size: PhysicalSize<u32>) -> PtrRc<compare::Target> {
+    compare::target(db, size)
+}
+
+fn reference(db: &dyn Postprocesser, path: String) -> Option<PtrRc<compare::Reference>> {
+    compare::load_reference(db, &path)
+}
+
+fn feedback_data(db: &dyn Postprocesser, (): ()) -> PtrRc<feedback::Data> {
+    feedback::data(db, ())
+}
+
+fn trails(db: &dyn Postprocesser, size: PhysicalSize<u32>) -> PtrRc<feedback::Trails> {
+    feedback::trails(db, size)
+}
+
+fn timer(db: &dyn Postprocesser, (): ()) -> Option<PtrRc<timing::Timer>> {
+    timing::timer(db, ())
+}
+
+fn supersample_data(db: &dyn Postprocesser, (): ()) -> PtrRc<supersample::Data> {
+    supersample::data(db, ())
+}
+
+fn bloom_data(db: &dyn Postprocesser, format: TextureFormat) -> PtrRc<bloom::Data> {
+    bloom::data(db, format)
+}
+
+fn bloom_target(db: &dyn Postprocesser, size: PhysicalSize<u32>) -> PtrRc<bloom::Target> {
+    bloom::target(db, size)
+}
+
+/// Writes a timestamp, if supported.
+fn mark(db: &DatabaseStruct, encoder: &mut wgpu::CommandEncoder, mark: timing::Mark) {
+    if let Some(timer) = db.timer(()) {
+        timer.mark(encoder, mark);
+    }
+}
+
+/// Records the timestamp after the UI is drawn, and resolves the frame's timestamps.
+pub fn finish_timing(db: &DatabaseStruct, encoder: &mut wgpu::CommandEncoder) {
+    if let Some(timer) = db.timer(()) {
+        timer.mark(encoder, timing::Mark::Ui);
+        timer.resolve(encoder);
+    }
+}
+
+fn palette_image(
+    _db: &dyn Postprocesser,
+    source: postprocess::PaletteSource,
+) -> PtrRc<image::RgbaImage> {
+    postprocess::palette_image(&source).into()
+}
+
 #[salsa::database(
     RendererStorage,
     InputStorage,
@@ -56,27 +170,657 @@ pub struct DatabaseStruct {
     storage: salsa::Storage<Self>,
 }
 
-impl salsa::Database for DatabaseStruct {}
+impl salsa::Database for DatabaseStruct {
+    fn salsa_event(&self, event: salsa::Event) {
+        // Log which queries are recomputed, to check caching.
+        if let salsa::EventKind::WillExecute { database_key } = event.kind {
+            log::trace!("{:?}", database_key.debug(self));
+        }
+    }
+}
+
+/// Entry points for embedding: rendering with a device and queue owned by the caller, into their textures.
+impl DatabaseStruct {
+    /// A database which renders `settings` with the caller's `device` and `queue` into textures of `format`,
+    /// drawing the flame into the `size` region at their origin.
+    ///
+    /// The device and queue are shared rather than borrowed since the GPU resources cached here are created from them,
+    /// and must outlive any one call to `render`.
+    /// The device is assumed to filter R32Float textures: if it can not, call `set_float_filtering` before rendering.
+    pub fn new(
+        device: Shared<Device>,
+        queue: Shared<Queue>,
+        format: TextureFormat,
+        size: PhysicalSize<u32>,
+        settings: ui::Settings,
+    ) -> Self {
+        let mut db = DatabaseStruct::default();
+        db.set_device_with_durability((), device, salsa::Durability::HIGH);
+        db.set_queue_with_durability((), queue, salsa::Durability::HIGH);
+        db.set_swapchain_format_with_durability((), DebugIt(format), salsa::Durability::HIGH);
+        db.set_window_size_with_durability((), size, salsa::Durability::MEDIUM);
+        db.set_float_filtering_with_durability((), true, salsa::Durability::HIGH);
+        db.set_config((), settings);
+        db
+    }
+
+    /// Only invalidates cached queries (like `root`) if `settings` changed,
+    /// so calling this every frame with the same settings does not rebuild anything.
+    pub fn set_settings(&mut self, settings: ui::Settings) {
+        if self.config(()) != settings {
+            self.set_config((), settings);
+        }
+    }
+
+    /// Only invalidates cached passes if `size` changed.
+    pub fn set_target_size(&mut self, size: PhysicalSize<u32>) {
+        if self.window_size(()) != size {
+            self.set_window_size_with_durability((), size, salsa::Durability::MEDIUM);
+        }
+    }
+
+    /// Only invalidates cached pipelines if `format` changed.
+    pub fn set_target_format(&mut self, format: TextureFormat) {
+        if *self.swapchain_format(()) != format {
+            self.set_swapchain_format_with_durability((), DebugIt(format), salsa::Durability::HIGH);
+        }
+    }
+
+    /// Frees every cached accumulation pass (with its textures and instance buffers), ex: the full resolution tiles
+    /// `render_to_image` and `render_density` leave cached, which would otherwise stay alive after the export.
+    /// Bumps the revision so none of the passes count as used, so the on screen passes are rebuilt by the next frame,
+    /// which is cheap next to an export. Pipelines and meshes are kept.
+    pub fn release_passes(&mut self) {
+        self.storage
+            .salsa_runtime_mut()
+            .synthetic_write(salsa::Durability::LOW);
+        let outdated = salsa::SweepStrategy::discard_outdated();
+        accumulate::PassQuery.in_db(self).sweep(outdated);
+        accumulate::InstanceQuery.in_db(self).sweep(outdated);
+        accumulate::VertexCountQuery.in_db(self).sweep(outdated);
+    }
+
+    /// Compiles the pipelines rendering the current config uses, for the database's own format and `formats`,
+    /// so changing settings which keep using them (ex: dragging a slider which changes the pass count) does not stall a frame.
+    /// Compilation is synchronous, so this only moves the stall to the call:
+    /// enabling a feature (ex: bloom) or exporting later still compiles its pipelines on first use.
+    /// These only depend on the device, float filtering and target format, so stay cached.
+    pub fn prebuild_pipelines(&self, formats: &[TextureFormat]) {
+        let config = self.config(());
+        accumulate::prebuild_pipelines(self);
+        if config.feedback_decay.is_some() {
+            self.feedback_data(());
+        }
+        let own = *self.swapchain_format(());
+        for format in std::iter::once(&own).chain(formats) {
+            self.postprocess_pipelines(*format);
+            if config.background_image.is_some() {
+                self.background_data(*format);
+            }
+            if config.bloom.is_some() {
+                self.bloom_data(*format);
+            }
+            if config.compare && config.reference_image.is_some() {
+                self.compare_data(*format);
+            }
+        }
+    }
+}
+
+// Each thread can have its own database, sharing the device and queue.
+#[cfg(feature = "sync")]
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<DatabaseStruct>();
+};
 
 fn root(db: &dyn Renderer, (): ()) -> Root {
     db.config(()).get_state()
 }
 
-pub fn render(
+fn contraction(db: &dyn Renderer, (): ()) -> ContractionReport {
+    db.root(()).contraction_report()
+}
+
+/// Origin and size of the part of the target the flame is drawn into: all of `window_size`, or if `aspect_lock` is set,
+/// the largest centered part with that aspect ratio (the rest is covered by letter box bars).
+fn flame_region(db: &DatabaseStruct) -> ([u32; 2], PhysicalSize<u32>) {
+    let size = db.window_size(());
+    match db.config(()).aspect_lock {
+        None => ([0, 0], size),
+        Some(aspect) => {
+            let width = (f64::from(size.height) * aspect)
+                .round()
+                .clamp(1.0, f64::from(size.width)) as u32;
+            let height = (f64::from(size.width) / aspect)
+                .round()
+                .clamp(1.0, f64::from(size.height)) as u32;
+            (
+                [(size.width - width) / 2, (size.height - height) / 2],
+                PhysicalSize::new(width, height),
+            )
+        }
+    }
+}
+
+/// Key for the pass which is drawn to the screen.
+fn root_pass_key(db: &DatabaseStruct) -> accumulate::PassKey {
+    accumulate::PassKey {
+        resolution: flame_region(db).1,
+        filter: false,
+        axis_aligned: true,
+        tile: None,
+        view: view_transform(&db.config(())),
+        depth: 0,
+    }
+}
+
+/// Zoom applied to the main view.
+fn view_transform(config: &ui::Settings) -> Option<ViewTransform> {
+    if config.zoom == 1.0 && config.camera_center == [0.0, 0.0] {
+        None
+    } else {
+        let [x, y] = config.camera_center;
+        Some(ViewTransform::new(&Affine2::from_matrix_unchecked(
+            Matrix3::new_nonuniform_scaling(&Vector2::new(config.zoom, config.zoom))
+                * Matrix3::new_translation(&Vector2::new(-x, -y)),
+        )))
+    }
+}
+
+/// Where and how to draw the flame.
+pub struct View<'a> {
+    pub target: postprocess::Target<'a>,
+    /// Applied after fitting the flame into the target region.
+    pub transform: Option<ViewTransform>,
+    /// Draw over the existing contents of the target instead of clearing it.
+    pub load: bool,
+    /// Make zero density areas transparent.
+    pub transparent: bool,
+    /// Blend the flame over the target's contents by this, if less than 1 (see `postprocess::render`).
+    pub opacity: f32,
+    /// If set, accumulate into the persistent trails texture, fading its previous contents by this factor.
+    pub feedback: Option<f32>,
+    /// Write the `Accumulated` and `Postprocessed` timestamps.
+    pub timed: bool,
+}
+
+/// Density texture a view was drawn from.
+pub enum Density {
+    Pass(PtrRc<accumulate::Pass>),
+    Trails(PtrRc<feedback::Trails>),
+}
+
+impl Density {
+    pub fn texture(&self) -> &wgpu::Texture {
+        match self {
+            Density::Pass(pass) => pass.texture(),
+            Density::Trails(trails) => &trails.texture,
+        }
+    }
+}
+
+/// Draws the flame into a region of a target.
+/// Returns the density that was drawn from.
+pub fn render_view(
     db: &DatabaseStruct,
-    frame: &wgpu::SurfaceTexture,
-    mut encoder: &mut wgpu::CommandEncoder,
-) {
+    encoder: &mut wgpu::CommandEncoder,
+    view: &View,
+) -> Density {
     let accumulate = db.pass(accumulate::PassKey {
-        resolution: db.window_size(()),
+        resolution: view.target.size,
         filter: false,
+        axis_aligned: true,
+        tile: None,
+        view: view.transform,
+        depth: 0,
     });
-    let bind_group = accumulate.render(db, &mut encoder);
+    let bind_group = accumulate.render(db, encoder);
+    let density = match view.feedback {
+        Some(decay) => {
+            let trails = db.trails(view.target.size);
+            feedback::render(db, encoder, bind_group, &trails, decay);
+            Density::Trails(trails)
+        }
+        None => Density::Pass(accumulate.clone()),
+    };
+    if view.timed {
+        mark(db, encoder, timing::Mark::Accumulated);
+    }
+    let bind_group = match &density {
+        Density::Pass(_) => bind_group,
+        Density::Trails(trails) => &trails.bind_group,
+    };
     postprocess::render(
         db,
-        &mut encoder,
+        encoder,
         bind_group,
-        &frame.texture.create_view(&TextureViewDescriptor::default()),
+        &view.target,
+        view.load,
+        view.transparent,
+        view.opacity,
+    );
+    if view.timed {
+        mark(db, encoder, timing::Mark::Postprocessed);
+    }
+    density
+}
+
+/// Width / height of the flame's output bounds, before cropping.
+pub fn output_aspect(db: &DatabaseStruct) -> f64 {
+    let bounds = accumulate::output_bounds(db);
+    bounds.width() / bounds.height()
+}
+
+/// Convex hull of the flame as drawn (after the final transform and camera rotation),
+/// as fractions of the (cropped) output bounds with y up.
+pub fn hull_overlay(db: &DatabaseStruct) -> Vec<na::Point2<f64>> {
+    overlay_points(db, db.hull(()).iter().copied())
+}
+
+/// Corners of the bounds of each level of the flame (see `BoundedState::level_bounds`) as drawn,
+/// in the same coordinates as `hull_overlay`. Level 0 (the seed) first.
+pub fn level_bounds_overlay(db: &DatabaseStruct) -> Vec<Vec<na::Point2<f64>>> {
+    db.level_bounds(())
+        .iter()
+        .map(|bounds| overlay_points(db, bounds.corners().into_iter()))
+        .collect()
+}
+
+/// Maps `points` (before the final transform) to where they are drawn,
+/// as fractions of the (cropped) output bounds with y up.
+fn overlay_points(
+    db: &DatabaseStruct,
+    points: impl Iterator<Item = na::Point2<f64>>,
+) -> Vec<na::Point2<f64>> {
+    let output = accumulate::output_bounds(db);
+    let rect = db
+        .config(())
+        .crop
+        .map_or(output, |crop| output.sub_rect(&crop));
+    let transform = accumulate::output_transform(db);
+    points
+        .map(|p| {
+            let p = transform * p;
+            na::Point2::new(
+                (p.x - rect.min.x) / rect.width(),
+                (p.y - rect.min.y) / rect.height(),
+            )
+        })
+        .collect()
+}
+
+/// The passes used to draw to the screen, largest first.
+pub fn plan(db: &DatabaseStruct) -> plan::Plan {
+    plan::plan(&accumulate::plan_inputs(db), root_pass_key(db))
+}
+
+/// Number of IFS points (vertices) rendered per frame.
+pub fn points_per_frame(db: &DatabaseStruct) -> u64 {
+    db.vertex_count(root_pass_key(db))
+}
+
+/// Draws the flame (with its background, comparison and minimap) into `view`,
+/// which may be a swap chain texture or any other texture of the database's format (ex: one layer of a larger render graph).
+/// Must be followed by `after_submit` once `encoder` is submitted.
+pub fn render(db: &DatabaseStruct, view: &wgpu::TextureView, encoder: &mut wgpu::CommandEncoder) {
+    // The background, if any, is included in the accumulate timing.
+    mark(db, encoder, timing::Mark::Start);
+    let config = db.config(());
+    let size = db.window_size(());
+    let (origin, region) = flame_region(db);
+    let format = *db.swapchain_format(());
+    let reference = match &config.reference_image {
+        Some(path) if config.compare => db.reference(path.clone()),
+        _ => None,
+    };
+    // When comparing or blooming, the flame is drawn into an intermediate texture instead of directly to the screen.
+    let compare_target = reference.as_ref().map(|_| db.compare_target(size));
+    let bloomed_view = compare_target.as_ref().map_or(view, |target| &target.view);
+    let bloom_target = config.bloom.map(|_| db.bloom_target(size));
+    let flame_view = bloom_target
+        .as_ref()
+        .map_or(bloomed_view, |target| target.view());
+    let background = config
+        .background_image
+        .clone()
+        .and_then(|path| db.background(path));
+    if let Some(background) = &background {
+        background::render(db, encoder, background, flame_view);
+    }
+    let density = render_view(
+        db,
+        encoder,
+        &View {
+            target: postprocess::Target {
+                view: flame_view,
+                format,
+                origin,
+                size: region,
+            },
+            transform: view_transform(&config),
+            load: background.is_some(),
+            transparent: config.transparent_background,
+            opacity: 1.0,
+            feedback: config.feedback_decay,
+            timed: true,
+        },
     );
+    if histogram::needs_readback(config.tone_map, config.auto_exposure) {
+        db.readback(region).copy(encoder, density.texture());
+    }
+    if let (Some(bloom), Some(target)) = (&config.bloom, &bloom_target) {
+        bloom::render(db, encoder, target, bloom, bloomed_view);
+    }
+    if let (Some(reference), Some(target)) = (&reference, &compare_target) {
+        compare::render(db, encoder, target, reference, view, config.compare_blend);
+    }
+
+    // The whole flame in the corner, while zoomed in (or moved).
+    if config.minimap && view_transform(&config).is_some() {
+        const MARGIN: u32 = 8;
+        let minimap = PhysicalSize::new(region.width / 4, region.height / 4);
+        if minimap.width > 0 && minimap.height > 0 {
+            render_view(
+                db,
+                encoder,
+                &View {
+                    target: postprocess::Target {
+                        view,
+                        format,
+                        origin: [
+                            origin[0] + region.width.saturating_sub(minimap.width + MARGIN),
+                            origin[1] + region.height.saturating_sub(minimap.height + MARGIN),
+                        ],
+                        size: minimap,
+                    },
+                    transform: None,
+                    load: true,
+                    transparent: false,
+                    opacity: 1.0,
+                    feedback: None,
+                    timed: false,
+                },
+            );
+        }
+    }
+    mark(db, encoder, timing::Mark::Rendered);
     // TODO: debug option to draw intermediate texture to screen at actual resolution
 }
+
+/// Blends this flame by `opacity` over what `render` (from another database) drew into `target`, to cross-fade between them.
+/// Only the flame is drawn: the background, comparison and minimap come from `render`.
+/// Like `render`, must be followed by `after_submit`.
+pub fn render_fade(
+    db: &DatabaseStruct,
+    target: &wgpu::TextureView,
+    encoder: &mut wgpu::CommandEncoder,
+    opacity: f32,
+) {
+    let config = db.config(());
+    let (origin, size) = flame_region(db);
+    let density = render_view(
+        db,
+        encoder,
+        &View {
+            target: postprocess::Target {
+                view: target,
+                format: *db.swapchain_format(()),
+                origin,
+                size,
+            },
+            transform: view_transform(&config),
+            load: true,
+            transparent: false,
+            opacity,
+            feedback: None,
+            timed: false,
+        },
+    );
+    if histogram::needs_readback(config.tone_map, config.auto_exposure) {
+        db.readback(size).copy(encoder, density.texture());
+    }
+}
+
+/// Splits an output of `size` into tiles no larger than `max_tile`, returning each tile's top left corner and size.
+fn tiles(size: PhysicalSize<u32>, max_tile: u32) -> Vec<([u32; 2], PhysicalSize<u32>)> {
+    (0..size.height)
+        .step_by(max_tile as usize)
+        .flat_map(|y| {
+            (0..size.width).step_by(max_tile as usize).map(move |x| {
+                (
+                    [x, y],
+                    PhysicalSize::new(
+                        u32::min(max_tile, size.width - x),
+                        u32::min(max_tile, size.height - y),
+                    ),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Accumulation pass for one tile of a `full` size output, with its top left corner at `min` (y down).
+fn tile_pass(
+    db: &DatabaseStruct,
+    full: PhysicalSize<u32>,
+    min: [u32; 2],
+    tile_size: PhysicalSize<u32>,
+) -> PtrRc<accumulate::Pass> {
+    db.pass(accumulate::PassKey {
+        resolution: tile_size,
+        filter: false,
+        axis_aligned: true,
+        tile: Some(accumulate::Tile {
+            full,
+            // Tiles are y up.
+            min: [min[0], full.height - min[1] - tile_size.height],
+        }),
+        view: None,
+        depth: 0,
+    })
+}
+
+/// Copies `texture` (of `size`, with `bytes_per_pixel`) to the CPU after submitting `encoder`, blocking until done.
+/// Returns the rows of the texture, without padding.
+fn read_texture(
+    db: &DatabaseStruct,
+    mut encoder: wgpu::CommandEncoder,
+    texture: &wgpu::Texture,
+    size: PhysicalSize<u32>,
+    bytes_per_pixel: u32,
+) -> Result<Vec<u8>, RenderError> {
+    let device = db.device(());
+    let padded_bytes_per_row = render_common::padded_bytes_per_row(size.width, bytes_per_pixel);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("export tile readback"),
+        size: u64::from(padded_bytes_per_row) * u64::from(size.height),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(size.height),
+            },
+        },
+        wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+    );
+    db.queue(()).submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        // Only fails if the receiver was dropped, in which case the result is not needed.
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    if !matches!(receiver.try_recv(), Ok(Ok(()))) {
+        return Err(RenderError::BufferMap);
+    }
+    let data = slice.get_mapped_range();
+    let bytes_per_row = (size.width * bytes_per_pixel) as usize;
+    Ok(data
+        .chunks(padded_bytes_per_row as usize)
+        .flat_map(|row| &row[..bytes_per_row])
+        .copied()
+        .collect())
+}
+
+/// Renders the flame into a new image of the given size, blocking until done.
+/// Outputs larger than the maximum texture size are rendered in tiles.
+/// If the config's `supersample` is above 1, each tile is rendered that much larger, then downsampled.
+/// Not supported on the web, where blocking on the GPU is not possible.
+pub fn render_to_image(
+    db: &DatabaseStruct,
+    size: PhysicalSize<u32>,
+) -> Result<image::RgbaImage, RenderError> {
+    let device = db.device(());
+    let config = db.config(());
+    let format = supersample::FORMAT;
+    let factor = config.supersample.clamp(1, supersample::MAX_FACTOR);
+    let filter = config.downsample_filter;
+    // Source pixels needed around each tile by the downsample filter.
+    let margin = if factor > 1 { filter.margin(factor) } else { 0 };
+    let max_tile = (device.limits().max_texture_dimension_2d - 2 * margin) / factor;
+    let full = PhysicalSize::new(size.width * factor, size.height * factor);
+    let mut image = image::RgbaImage::new(size.width, size.height);
+    let image_bytes_per_row = size.width as usize * 4;
+
+    let create_texture = |label, size: PhysicalSize<u32>, usage| {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | usage,
+            view_formats: &[],
+        })
+    };
+
+    for ([x, y], tile_size) in tiles(size, max_tile) {
+        let texture = create_texture("export tile", tile_size, wgpu::TextureUsages::COPY_SRC);
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+        // Region of the supersampled image rendered for this tile.
+        let source_min = [
+            (x * factor).saturating_sub(margin),
+            (y * factor).saturating_sub(margin),
+        ];
+        let source_size = PhysicalSize::new(
+            u32::min((x + tile_size.width) * factor + margin, full.width) - source_min[0],
+            u32::min((y + tile_size.height) * factor + margin, full.height) - source_min[1],
+        );
+        let pass = tile_pass(db, full, source_min, source_size);
+        let bind_group = pass.render(db, &mut encoder);
+        let source = (factor > 1).then(|| {
+            create_texture(
+                "export supersampled tile",
+                source_size,
+                wgpu::TextureUsages::TEXTURE_BINDING,
+            )
+            .create_view(&TextureViewDescriptor::default())
+        });
+        postprocess::render(
+            db,
+            &mut encoder,
+            bind_group,
+            &postprocess::Target {
+                view: source.as_ref().unwrap_or(&view),
+                format,
+                origin: [0, 0],
+                size: source_size,
+            },
+            false,
+            false,
+            1.0,
+        );
+        if let Some(source) = &source {
+            supersample::resolve(
+                db,
+                &mut encoder,
+                source,
+                &view,
+                [x * factor - source_min[0], y * factor - source_min[1]],
+                factor,
+                filter,
+            );
+        }
+
+        let data = read_texture(db, encoder, &texture, tile_size, 4)?;
+        let tile_bytes_per_row = tile_size.width as usize * 4;
+        let pixels: &mut [u8] = &mut image;
+        for (row, bytes) in data.chunks(tile_bytes_per_row).enumerate() {
+            let start = (y as usize + row) * image_bytes_per_row + x as usize * 4;
+            pixels[start..start + tile_bytes_per_row].copy_from_slice(bytes);
+        }
+    }
+    Ok(image)
+}
+
+/// Renders the flame's raw (not tone mapped) density at the given size, blocking until done.
+/// Returns rows of densities, from the top.
+/// Like `render_to_image`, large outputs are rendered in tiles, and this is not supported on the web.
+pub fn render_density(
+    db: &DatabaseStruct,
+    size: PhysicalSize<u32>,
+) -> Result<Vec<f32>, RenderError> {
+    let device = db.device(());
+    let mut density = vec![0.0; size.width as usize * size.height as usize];
+
+    for ([x, y], tile_size) in tiles(size, device.limits().max_texture_dimension_2d) {
+        let pass = tile_pass(db, size, [x, y], tile_size);
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        pass.render(db, &mut encoder);
+        let data = read_texture(db, encoder, pass.texture(), tile_size, 4)?;
+        let tile: Vec<f32> = data
+            .chunks_exact(4)
+            .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+            .collect();
+        // The accumulation texture is y up.
+        for (row, values) in tile.chunks(tile_size.width as usize).rev().enumerate() {
+            let start = (y as usize + row) * size.width as usize + x as usize;
+            density[start..start + values.len()].copy_from_slice(values);
+        }
+    }
+    Ok(density)
+}
+
+/// Must be called after the encoder passed to `render` has been submitted.
+/// Returns true if another frame should be rendered: the tone curve changed, or a readback for it is still in flight.
+/// Once the densities stop changing, so does the curve, and this returns false.
+pub fn after_submit(db: &DatabaseStruct) -> bool {
+    let config = db.config(());
+    match config.tone_map {
+        ToneMap::Log if config.auto_exposure.is_none() => postprocess::set_curve(db, None),
+        tone_map => {
+            let readback = db.readback(flame_region(db).1);
+            let changed = readback
+                .after_submit(&db.device(()), tone_map, config.auto_exposure)
+                .is_some_and(|curve| postprocess::set_curve(db, Some(&curve)));
+            changed || readback.in_flight()
+        }
+    }
+}
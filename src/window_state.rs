@@ -0,0 +1,132 @@
+//! Remembers the window's size and position between sessions (desktop only).
+
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    monitor::MonitorHandle,
+    window::Window,
+};
+
+/// Saved in the working directory, like exported images.
+const PATH: &str = "rusty-flame-window.txt";
+
+/// Smallest size a restored window is given (if the monitor is that large), so it stays usable.
+const MIN_SIZE: PhysicalSize<u32> = PhysicalSize::new(320, 240);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowState {
+    pub size: PhysicalSize<u32>,
+    pub position: PhysicalPosition<i32>,
+}
+
+impl WindowState {
+    /// The window's state, or None if it can't be restored from (ex: when minimized, it has no size).
+    pub fn of(window: &Window) -> Option<Self> {
+        let size = window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return None;
+        }
+        Some(Self {
+            size,
+            position: window.outer_position().ok()?,
+        })
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut values = text.split_whitespace();
+        let mut next = || values.next()?.parse::<i64>().ok();
+        let (width, height, x, y) = (next()?, next()?, next()?, next()?);
+        Some(Self {
+            size: PhysicalSize::new(width.try_into().ok()?, height.try_into().ok()?),
+            position: PhysicalPosition::new(x.try_into().ok()?, y.try_into().ok()?),
+        })
+    }
+
+    fn format(&self) -> String {
+        format!(
+            "{} {} {} {}\n",
+            self.size.width, self.size.height, self.position.x, self.position.y
+        )
+    }
+
+    /// Resizes and moves this so it fits on `monitor`.
+    pub fn clamp_to(&self, monitor: &MonitorHandle) -> Self {
+        self.clamp(monitor.position(), monitor.size())
+    }
+
+    /// Resizes (to at least `MIN_SIZE`) and moves this so it fits in the area at `origin` of size `available`.
+    fn clamp(&self, origin: PhysicalPosition<i32>, available: PhysicalSize<u32>) -> Self {
+        let size = PhysicalSize::new(
+            self.size
+                .width
+                .clamp(MIN_SIZE.width.min(available.width), available.width),
+            self.size
+                .height
+                .clamp(MIN_SIZE.height.min(available.height), available.height),
+        );
+        let max_x = origin.x + (available.width - size.width) as i32;
+        let max_y = origin.y + (available.height - size.height) as i32;
+        Self {
+            size,
+            position: PhysicalPosition::new(
+                self.position.x.clamp(origin.x, max_x),
+                self.position.y.clamp(origin.y, max_y),
+            ),
+        }
+    }
+}
+
+/// The last saved state, if any.
+pub fn load() -> Option<WindowState> {
+    WindowState::parse(&std::fs::read_to_string(PATH).ok()?)
+}
+
+pub fn save(window: &Window) {
+    if let Some(state) = WindowState::of(window) {
+        if let Err(e) = std::fs::write(PATH, state.format()) {
+            log::warn!("Failed to save window state to {PATH}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::window_state::WindowState;
+    use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+    #[test]
+    fn round_trip() {
+        let state = WindowState {
+            size: PhysicalSize::new(1280, 720),
+            position: PhysicalPosition::new(-10, 20),
+        };
+        assert_eq!(WindowState::parse(&state.format()), Some(state));
+        assert_eq!(WindowState::parse("1280 720"), None);
+        assert_eq!(WindowState::parse("-1 720 0 0"), None);
+    }
+
+    #[test]
+    fn clamp() {
+        let origin = PhysicalPosition::new(100, 0);
+        let available = PhysicalSize::new(1920, 1080);
+        let state = |width, height, x, y| WindowState {
+            size: PhysicalSize::new(width, height),
+            position: PhysicalPosition::new(x, y),
+        };
+        assert_eq!(
+            state(1280, 720, 200, 50).clamp(origin, available),
+            state(1280, 720, 200, 50)
+        );
+        assert_eq!(
+            state(0, 0, 0, 2000).clamp(origin, available),
+            state(320, 240, 100, 840)
+        );
+        assert_eq!(
+            state(4000, 100, 0, 0).clamp(origin, available),
+            state(1920, 240, 100, 0)
+        );
+        assert_eq!(
+            state(1280, 720, 0, 0).clamp(origin, PhysicalSize::new(200, 100)),
+            state(200, 100, 100, 0)
+        );
+    }
+}